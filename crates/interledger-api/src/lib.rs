@@ -9,14 +9,16 @@ extern crate serde_json;
 use bytes::Bytes;
 use futures::Future;
 use interledger_http::{HttpAccount, HttpStore};
-use interledger_ildcp::IldcpAccount;
+use interledger_ildcp::{AssetScaleError, AssetScaleRange, IldcpAccount};
 use interledger_packet::Address;
-use interledger_router::RouterStore;
+use interledger_router::{RouteEntry, RouterStore};
 use interledger_service::{Account as AccountTrait, IncomingService, OutgoingService};
-use interledger_service_util::{BalanceStore, ExchangeRateStore};
-use interledger_settlement::{SettlementAccount, SettlementApi, SettlementStore};
-use serde::Serialize;
-use std::str;
+use interledger_service_util::{resolve_secret, BalanceStore, ExchangeRateStore, SecretError};
+use interledger_settlement::{
+    PendingSettlementStore, SettlementAccount, SettlementApi, SettlementStore, SettlementWebhook,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str, time::Duration};
 use tower_web::{net::ConnectionStream, ServiceBuilder};
 
 mod routes;
@@ -48,10 +50,66 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         prefix: String,
         account_id: <Self::Account as AccountTrait>::AccountId,
     ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    fn delete_static_route(&self, prefix: String) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Replace the routing table with the given routes, for example ones exported with
+    /// `RouterStore::export_routes`. The entries have already been checked with
+    /// `validate_route_entries`, but this should fail if any of the accounts do not exist.
+    fn import_routes(
+        &self,
+        routes: Vec<RouteEntry<<Self::Account as AccountTrait>::AccountId>>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Enable or disable the account, and return the updated account.
+    fn set_account_enabled(
+        &self,
+        account_id: <Self::Account as AccountTrait>::AccountId,
+        enabled: bool,
+    ) -> Box<dyn Future<Item = Self::Account, Error = ()> + Send>;
+
+    /// Delete the account along with its balance, incoming credentials and any routes through it.
+    fn delete_account(
+        &self,
+        account_id: <Self::Account as AccountTrait>::AccountId,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Load the static routes and accounts that were last created from the node's config.
+    fn get_applied_config(
+        &self,
+    ) -> Box<
+        dyn Future<Item = AppliedConfig<<Self::Account as AccountTrait>::AccountId>, Error = ()>
+            + Send,
+    >;
+
+    /// Save the static routes and accounts that were created from the node's config,
+    /// so that the next config reload, even after a restart, knows what to remove.
+    fn set_applied_config(
+        &self,
+        config: AppliedConfig<<Self::Account as AccountTrait>::AccountId>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+}
+
+/// The static routes and accounts that were created from a node's config
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedConfig<AccountId> {
+    /// Static routes, keyed by prefix
+    pub static_routes: HashMap<String, AccountId>,
+    /// Ids of the accounts created from the config, keyed by ILP address
+    pub accounts: HashMap<String, AccountId>,
+}
+
+impl<AccountId> Default for AppliedConfig<AccountId> {
+    fn default() -> Self {
+        AppliedConfig {
+            static_routes: HashMap::new(),
+            accounts: HashMap::new(),
+        }
+    }
 }
 
 /// The Account type for the RedisStore.
-#[derive(Debug, Extract, Response, Clone)]
+#[derive(Debug, Extract, Response, Clone, Deserialize)]
 pub struct AccountDetails {
     pub ilp_address: Address,
     pub asset_code: String,
@@ -74,9 +132,61 @@ pub struct AccountDetails {
     pub round_trip_time: Option<u64>,
     pub amount_per_minute_limit: Option<u64>,
     pub packets_per_minute_limit: Option<u32>,
+    /// The largest Prepare data, in bytes, the account may send. Unlimited if not set.
+    pub max_prepare_data_len: Option<usize>,
     pub settlement_engine_url: Option<String>,
     pub settlement_engine_asset_scale: Option<u8>,
+    /// Asset scale of the amounts the settlement engine reports as received, if it differs from `settlement_engine_asset_scale`
+    pub settlement_engine_incoming_asset_scale: Option<u8>,
+    /// Asset scale of the amounts the settlement engine is asked to send, if it differs from `settlement_engine_asset_scale`
+    pub settlement_engine_outgoing_asset_scale: Option<u8>,
     pub settlement_engine_ilp_address: Option<Address>,
+    /// Secret the settlement engine must send as a bearer token when calling the settlement API about this account
+    pub settlement_engine_auth_token: Option<String>,
+    /// Hex-encoded fulfillment of the execution condition for messages between this account's
+    /// and the node's settlement engines, if the peers agreed on a non-standard one
+    pub settlement_engine_peer_protocol_fulfillment: Option<String>,
+    /// How long, in milliseconds, messages to this account's settlement engine are valid for,
+    /// if it needs a different timeout than the node's `peer_protocol_expiry`
+    pub settlement_engine_message_timeout: Option<u64>,
+    /// Accounts are enabled unless this is set to `false`
+    pub enabled: Option<bool>,
+}
+
+impl AccountDetails {
+    /// Replace credentials that reference an environment variable or secret file
+    /// (for example `${ENV:PEER_TOKEN}`) with the secret's value.
+    /// See `interledger_service_util::resolve_secret` for the supported formats.
+    pub fn resolve_secrets(mut self) -> Result<Self, SecretError> {
+        self.http_incoming_token = resolve_optional_secret(self.http_incoming_token)?;
+        self.http_outgoing_token = resolve_optional_secret(self.http_outgoing_token)?;
+        self.btp_incoming_token = resolve_optional_secret(self.btp_incoming_token)?;
+        self.btp_uri = resolve_optional_secret(self.btp_uri)?;
+        self.settlement_engine_auth_token =
+            resolve_optional_secret(self.settlement_engine_auth_token)?;
+        Ok(self)
+    }
+
+    /// Check that the account's asset scale, and its settlement engine's if one is configured,
+    /// are within the range the node accepts.
+    pub fn check_asset_scales(&self, range: AssetScaleRange) -> Result<(), AssetScaleError> {
+        range.check(self.asset_scale)?;
+        for scale in [
+            self.settlement_engine_asset_scale,
+            self.settlement_engine_incoming_asset_scale,
+            self.settlement_engine_outgoing_asset_scale,
+        ]
+        .iter()
+        .flatten()
+        {
+            range.check(*scale)?;
+        }
+        Ok(())
+    }
+}
+
+fn resolve_optional_secret(value: Option<String>) -> Result<Option<String>, SecretError> {
+    value.map(|value| resolve_secret(&value)).transpose()
 }
 
 pub struct NodeApi<T, S, U> {
@@ -86,6 +196,10 @@ pub struct NodeApi<T, S, U> {
     incoming_handler: S,
     outgoing_handler: U,
     server_secret: Bytes,
+    peer_protocol_expiry: Option<Duration>,
+    ilp_address: Option<Address>,
+    asset_scale_range: AssetScaleRange,
+    settlement_webhook: Option<SettlementWebhook>,
 }
 
 impl<T, S, U, A> NodeApi<T, S, U>
@@ -94,6 +208,7 @@ where
         + HttpStore<Account = A>
         + BalanceStore<Account = A>
         + SettlementStore<Account = A>
+        + PendingSettlementStore<Account = A>
         + RouterStore
         + ExchangeRateStore,
     S: IncomingService<A> + Clone + Send + Sync + 'static,
@@ -121,6 +236,10 @@ where
             incoming_handler,
             server_secret,
             outgoing_handler,
+            peer_protocol_expiry: None,
+            ilp_address: None,
+            asset_scale_range: AssetScaleRange::default(),
+            settlement_webhook: None,
         }
     }
 
@@ -129,6 +248,30 @@ where
         self
     }
 
+    /// Set how long peer protocol messages sent by the node, such as settlement engine messages, are valid for
+    pub fn peer_protocol_expiry(&mut self, expiry: Duration) -> &mut Self {
+        self.peer_protocol_expiry = Some(expiry);
+        self
+    }
+
+    /// Set the address of the node, so that routes that would send packets straight back to it are rejected
+    pub fn ilp_address(&mut self, ilp_address: Address) -> &mut Self {
+        self.ilp_address = Some(ilp_address);
+        self
+    }
+
+    /// Set the asset scales that new accounts and incoming settlements may use
+    pub fn asset_scale_range(&mut self, range: AssetScaleRange) -> &mut Self {
+        self.asset_scale_range = range;
+        self
+    }
+
+    /// Set the webhook to notify once an incoming settlement has been credited to an account
+    pub fn settlement_webhook(&mut self, webhook: SettlementWebhook) -> &mut Self {
+        self.settlement_webhook = Some(webhook);
+        self
+    }
+
     pub fn serve<I>(&self, incoming: I) -> impl Future<Item = (), Error = ()>
     where
         I: ConnectionStream,
@@ -150,18 +293,32 @@ where
                 }
                 spsp
             })
-            .resource(SettlementApi::new(
-                self.store.clone(),
-                self.outgoing_handler.clone(),
-            ))
-            .resource(AccountsApi::new(
-                self.admin_api_token.clone(),
-                self.store.clone(),
-            ))
-            .resource(SettingsApi::new(
-                self.admin_api_token.clone(),
-                self.store.clone(),
-            ))
+            .resource({
+                let mut settlement =
+                    SettlementApi::new(self.store.clone(), self.outgoing_handler.clone());
+                if let Some(expiry) = self.peer_protocol_expiry {
+                    settlement.peer_protocol_expiry(expiry);
+                }
+                settlement.asset_scale_range(self.asset_scale_range);
+                if let Some(webhook) = &self.settlement_webhook {
+                    settlement.settlement_webhook(webhook.clone());
+                }
+                settlement
+            })
+            .resource({
+                let mut accounts =
+                    AccountsApi::new(self.admin_api_token.clone(), self.store.clone());
+                accounts.asset_scale_range(self.asset_scale_range);
+                accounts
+            })
+            .resource({
+                let mut settings =
+                    SettingsApi::new(self.admin_api_token.clone(), self.store.clone());
+                if let Some(ilp_address) = &self.ilp_address {
+                    settings.ilp_address(ilp_address.clone());
+                }
+                settings
+            })
             .serve(incoming)
     }
 }
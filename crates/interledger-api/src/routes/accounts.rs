@@ -5,8 +5,10 @@ use futures::{
 };
 use hyper::Response;
 use interledger_http::{HttpAccount, HttpStore};
+use interledger_ildcp::{AssetScaleRange, IldcpAccount};
 use interledger_service::Account;
 use interledger_service_util::BalanceStore;
+use interledger_settlement::{PendingSettlementStore, SettlementAccount, SettlementClient};
 use serde::Serialize;
 use serde_json::Value;
 use std::str::FromStr;
@@ -27,24 +29,39 @@ struct BalanceResponse {
     balance: String,
 }
 
+#[derive(Extract, Debug)]
+struct AccountStatus {
+    enabled: bool,
+}
+
 pub struct AccountsApi<T> {
     store: T,
     admin_api_token: String,
+    settlement_client: SettlementClient,
+    asset_scale_range: AssetScaleRange,
 }
 
 impl_web! {
     impl<T, A> AccountsApi<T>
-    where T: NodeStore<Account = A> + HttpStore<Account = A> + BalanceStore<Account = A>,
-    A: Account + HttpAccount + Serialize + 'static,
+    where T: NodeStore<Account = A> + HttpStore<Account = A> + BalanceStore<Account = A> + PendingSettlementStore<Account = A>,
+    A: Account + HttpAccount + IldcpAccount + SettlementAccount + Serialize + Send + Sync + 'static,
 
     {
         pub fn new(admin_api_token: String, store: T) -> Self {
             AccountsApi {
                 store,
                 admin_api_token,
+                settlement_client: SettlementClient::new(),
+                asset_scale_range: AssetScaleRange::default(),
             }
         }
 
+        /// Set the asset scales that new accounts may use
+        pub fn asset_scale_range(&mut self, range: AssetScaleRange) -> &mut Self {
+            self.asset_scale_range = range;
+            self
+        }
+
         fn is_admin(&self, authorization: &str) -> bool {
             authorization[BEARER_TOKEN_START..] == self.admin_api_token
         }
@@ -63,7 +80,12 @@ impl_web! {
         fn post_accounts(&self, body: AccountDetails, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
             // TODO don't allow accounts to be overwritten
             // TODO try connecting to that account's websocket server if it has a btp_uri
+            let asset_scales = body.check_asset_scales(self.asset_scale_range).map_err(|err| {
+                error!("Cannot create account: {}", err);
+                Response::builder().status(400).body(()).unwrap()
+            });
             self.validate_admin(authorization)
+                .and_then(move |store| result(asset_scales).map(move |_| store))
                 .and_then(move |store| store.insert_account(body)
                 .and_then(|account| Ok(json!(account)))
                 .map_err(|_| Response::builder().status(500).body(()).unwrap()))
@@ -110,6 +132,18 @@ impl_web! {
                 })
         }
 
+        #[put("/accounts/:id/enabled")]
+        #[content_type("application/json")]
+        fn put_account_enabled(&self, id: String, body: AccountStatus, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+            let parsed_id: Result<A::AccountId, ()> = A::AccountId::from_str(&id).map_err(|_| error!("Invalid id"));
+            self.validate_admin(authorization)
+                .and_then(move |store| result(parsed_id)
+                    .map_err(|_| Response::builder().status(400).body(()).unwrap())
+                    .and_then(move |id| store.set_account_enabled(id, body.enabled)
+                        .map_err(|_| Response::builder().status(404).body(()).unwrap())))
+                .and_then(|account| Ok(json!(account)))
+        }
+
         // TODO should this be combined into the account record?
         #[get("/accounts/:id/balance")]
         #[content_type("application/json")]
@@ -141,5 +175,34 @@ impl_web! {
                         .map_err(|_| Response::builder().status(404).body(()).unwrap()))
                 })
         }
+
+        #[post("/accounts/:id/settlement/trigger")]
+        #[content_type("application/json")]
+        fn trigger_settlement(&self, id: String, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+            let settlement_client = self.settlement_client.clone();
+            let parsed_id: Result<A::AccountId, ()> = A::AccountId::from_str(&id).map_err(|_| error!("Invalid id"));
+            self.validate_admin(authorization)
+                .and_then(move |store| result(parsed_id)
+                    .map_err(|_| Response::builder().status(400).body(()).unwrap())
+                    .and_then(move |id| store.get_accounts(vec![id])
+                        .map_err(|_| Response::builder().status(404).body(()).unwrap())
+                        .and_then(move |mut accounts| {
+                            let account = accounts.remove(0);
+                            // Without a settlement engine there is nothing the node could retry
+                            if account.settlement_engine_details().is_none() {
+                                debug!("Cannot trigger a settlement for account {} because it does not have a settlement engine configured", account.id());
+                                return Either::A(err(Response::builder().status(400).body(()).unwrap()));
+                            }
+                            Either::B(settlement_client.settle_outstanding_balance(store, account)
+                                .map_err(|_| Response::builder().status(502).body(()).unwrap()))
+                        })))
+                .and_then(|amount| {
+                    if amount > 0 {
+                        Ok(json!({ "settled": true, "amount": amount.to_string() }))
+                    } else {
+                        Ok(json!({ "settled": false, "amount": "0", "message": "Nothing to settle" }))
+                    }
+                })
+        }
     }
 }
@@ -1,15 +1,18 @@
 use crate::{NodeStore, BEARER_TOKEN_START};
 use futures::{
-    future::{err, ok},
+    future::{err, ok, Either},
     Future,
 };
 use hyper::Response;
-use interledger_router::RouterStore;
-use interledger_service::Account;
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
+use interledger_router::{check_route_loop, validate_route_entries, RouteEntry, RouterStore};
+use interledger_service::{Account, AccountStore};
 use interledger_service_util::ExchangeRateStore;
+use serde::Deserialize;
+use serde_json::Value;
 use std::{
     collections::HashMap,
-    iter::FromIterator,
     str::{self, FromStr},
 };
 
@@ -30,24 +33,82 @@ struct Rates(HashMap<String, f64>);
 #[web(status = "200")]
 struct Routes(HashMap<String, String>);
 
+#[derive(Deserialize, Debug)]
+struct RouteEntryDetails {
+    prefix: String,
+    account_id: String,
+    #[serde(default)]
+    cost: u32,
+}
+
+#[derive(Extract, Debug)]
+struct RouteEntries(Vec<RouteEntryDetails>);
+
 pub struct SettingsApi<T> {
     store: T,
     admin_api_token: String,
+    ilp_address: Option<Address>,
+}
+
+/// Reject routes that would send packets straight back to this node, if its address is known
+fn check_route_loops<T, A>(
+    store: T,
+    routes: Vec<(String, A::AccountId)>,
+    own_address: Option<Address>,
+) -> impl Future<Item = T, Error = Response<()>>
+where
+    T: AccountStore<Account = A>,
+    A: Account + IldcpAccount,
+{
+    let own_address = match own_address {
+        Some(own_address) if !routes.is_empty() => own_address,
+        _ => return Either::A(ok(store)),
+    };
+    let account_ids = routes
+        .iter()
+        .map(|(_prefix, account_id)| *account_id)
+        .collect();
+    Either::B(
+        store
+            .get_accounts(account_ids)
+            .map_err(|_| {
+                error!("Error loading the next hop accounts of routes");
+                Response::builder().status(404).body(()).unwrap()
+            })
+            .and_then(move |accounts| {
+                for ((prefix, _account_id), account) in routes.iter().zip(accounts.iter()) {
+                    if let Err(message) =
+                        check_route_loop(prefix, account.client_address(), &own_address)
+                    {
+                        error!("Cannot set route: {}", message);
+                        return Err(Response::builder().status(400).body(()).unwrap());
+                    }
+                }
+                Ok(store)
+            }),
+    )
 }
 
 impl_web! {
     impl<T, A> SettingsApi<T>
-    where T: NodeStore<Account = A> + RouterStore + ExchangeRateStore,
-    A: Account + 'static,
+    where T: NodeStore<Account = A> + RouterStore + AccountStore<Account = A> + ExchangeRateStore,
+    A: Account + IldcpAccount + 'static,
 
     {
         pub fn new(admin_api_token: String, store: T) -> Self {
             SettingsApi {
                 store,
                 admin_api_token,
+                ilp_address: None,
             }
         }
 
+        /// Set the address of this node, so that routes that would send packets straight back to it are rejected
+        pub fn ilp_address(&mut self, ilp_address: Address) -> &mut Self {
+            self.ilp_address = Some(ilp_address);
+            self
+        }
+
         fn validate_admin(&self, authorization: String) -> impl Future<Item = T, Error = Response<()>> {
             if authorization[BEARER_TOKEN_START..] == self.admin_api_token {
                 ok(self.store.clone())
@@ -82,21 +143,64 @@ impl_web! {
 
         #[get("/routes")]
         #[content_type("application/json")]
-        fn get_routes(&self) -> impl Future<Item = Routes, Error = Response<()>> {
-            ok(Routes(HashMap::from_iter(self.store.routing_table()
-                .into_iter()
-                .filter_map(|(address, account)| {
-                    if let Ok(address) = str::from_utf8(address.as_ref()) {
-                        Some((address.to_string(), account.to_string()))
-                    } else {
-                        None
+        fn get_routes(&self, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+            self.validate_admin(authorization)
+                .and_then(|store| {
+                    let routes: Vec<Value> = store.export_routes()
+                        .into_iter()
+                        .map(|route| json!({
+                            "prefix": route.prefix,
+                            "account_id": route.account_id.to_string(),
+                            "cost": route.cost,
+                        }))
+                        .collect();
+                    Ok(Value::Array(routes))
+                })
+        }
+
+        #[put("/routes")]
+        #[content_type("application/json")]
+        fn put_routes(&self, body: RouteEntries, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
+            let ilp_address = self.ilp_address.clone();
+            self.validate_admin(authorization)
+                .and_then(move |store| {
+                    let mut routes: Vec<RouteEntry<A::AccountId>> = Vec::with_capacity(body.0.len());
+                    for route in body.0 {
+                        if let Ok(account_id) = A::AccountId::from_str(route.account_id.as_str()) {
+                            routes.push(RouteEntry {
+                                prefix: route.prefix,
+                                account_id,
+                                cost: route.cost,
+                            });
+                        } else {
+                            error!("Cannot import route for prefix: {} with invalid account id: {}", route.prefix, route.account_id);
+                            return Err(Response::builder().status(400).body(()).unwrap());
+                        }
                     }
-                }))))
+                    if let Err(message) = validate_route_entries(&routes) {
+                        error!("Cannot import routes: {}", message);
+                        return Err(Response::builder().status(400).body(()).unwrap());
+                    }
+                    Ok((store, routes))
+                })
+                .and_then(move |(store, routes)| {
+                    let next_hops = routes.iter().map(|route| (route.prefix.clone(), route.account_id)).collect();
+                    check_route_loops(store, next_hops, ilp_address).map(|store| (store, routes))
+                })
+                .and_then(|(store, routes)| {
+                    store.import_routes(routes)
+                        .and_then(|_| Ok(Success))
+                        .map_err(|err| {
+                            error!("Error importing routes: {:?}", err);
+                            Response::builder().status(500).body(()).unwrap()
+                        })
+                })
         }
 
         #[put("/routes/static")]
         #[content_type("application/json")]
         fn post_static_routes(&self, body: Routes, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
+            let ilp_address = self.ilp_address.clone();
             self.validate_admin(authorization)
                 .and_then(move |store| {
                     let mut routes: HashMap<String, A::AccountId> = HashMap::with_capacity(body.0.len());
@@ -109,6 +213,10 @@ impl_web! {
                     }
                     Ok((store, routes))
                 })
+                .and_then(move |(store, routes)| {
+                    let next_hops = routes.iter().map(|(prefix, account_id)| (prefix.clone(), *account_id)).collect();
+                    check_route_loops(store, next_hops, ilp_address).map(|store| (store, routes))
+                })
                 .and_then(|(store, routes)| {
                     store.set_static_routes(routes)
                     .and_then(|_| Ok(Success))
@@ -122,6 +230,7 @@ impl_web! {
         #[put("/routes/static/:prefix")]
         #[content_type("application/json")]
         fn post_static_route(&self, prefix: String, body: String, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
+            let ilp_address = self.ilp_address.clone();
             self.validate_admin(authorization)
                 .and_then(move |store| {
                     if let Ok(account_id) = A::AccountId::from_str(body.as_str()) {
@@ -131,6 +240,10 @@ impl_web! {
                     }
                 })
                 .and_then(move |(store, account_id)| {
+                    check_route_loops(store, vec![(prefix.clone(), account_id)], ilp_address)
+                        .map(move |store| (store, prefix, account_id))
+                })
+                .and_then(|(store, prefix, account_id)| {
                     store.set_static_route(prefix, account_id)
                     .and_then(|_| Ok(Success))
                         .map_err(|err| {
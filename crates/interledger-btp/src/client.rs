@@ -1,21 +1,37 @@
+use super::errors::BtpUrlError;
 use super::packet::*;
 use super::service::BtpOutgoingService;
+use super::throttle::ReconnectThrottle;
 use super::BtpAccount;
-use futures::{future::join_all, Future, Sink};
+use futures::{
+    future::{join_all, ok, Either},
+    Future, Sink,
+};
 use interledger_service::*;
 use rand::random;
 use std::iter::IntoIterator;
 use tokio_tungstenite::connect_async;
 use tungstenite::Message;
-use url::{ParseError, Url};
+use url::Url;
 
-pub fn parse_btp_url(uri: &str) -> Result<Url, ParseError> {
-    let uri = if uri.starts_with("btp+") {
-        uri.split_at(4).1
-    } else {
-        uri
-    };
-    Url::parse(uri)
+/// Parse a `btp+ws://` or `btp+wss://` URL into the WebSocket URL the client connects to.
+///
+/// Other transports, such as the `btp+http` long-polling used by some older implementations,
+/// are rejected with `BtpUrlError::UnsupportedTransport`.
+pub fn parse_btp_url(uri: &str) -> Result<Url, BtpUrlError> {
+    let url = Url::parse(uri)?;
+    let scheme = url.scheme();
+    match scheme.trim_start_matches("btp+") {
+        "ws" | "wss" => {
+            let uri = if uri.starts_with("btp+") {
+                uri.split_at(4).1
+            } else {
+                uri
+            };
+            Ok(Url::parse(uri)?)
+        }
+        _ => Err(BtpUrlError::UnsupportedTransport(scheme.to_string())),
+    }
 }
 
 /// Create a BtpOutgoingService wrapping BTP connections to the accounts specified.
@@ -26,6 +42,40 @@ pub fn connect_client<A, S>(
     error_on_unavailable: bool,
     next_outgoing: S,
 ) -> impl Future<Item = BtpOutgoingService<S, A>, Error = ()>
+where
+    S: OutgoingService<A> + Clone + 'static,
+    A: BtpAccount + 'static,
+{
+    connect_accounts(accounts, error_on_unavailable, next_outgoing, None)
+}
+
+/// Like `connect_client`, but each connection waits for the given `ReconnectThrottle`
+/// before connecting, so that connections to many peers are spread out over time.
+/// The same throttle should be shared by everything that (re)connects to peers.
+pub fn connect_client_with_throttle<A, S>(
+    accounts: Vec<A>,
+    error_on_unavailable: bool,
+    next_outgoing: S,
+    throttle: ReconnectThrottle,
+) -> impl Future<Item = BtpOutgoingService<S, A>, Error = ()>
+where
+    S: OutgoingService<A> + Clone + 'static,
+    A: BtpAccount + 'static,
+{
+    connect_accounts(
+        accounts,
+        error_on_unavailable,
+        next_outgoing,
+        Some(throttle),
+    )
+}
+
+fn connect_accounts<A, S>(
+    accounts: Vec<A>,
+    error_on_unavailable: bool,
+    next_outgoing: S,
+    throttle: Option<ReconnectThrottle>,
+) -> impl Future<Item = BtpOutgoingService<S, A>, Error = ()>
 where
     S: OutgoingService<A> + Clone + 'static,
     A: BtpAccount + 'static,
@@ -43,57 +93,63 @@ where
             .get_btp_token()
             .map(|s| s.to_vec())
             .unwrap_or_default();
-        debug!("Connecting to {}", url);
-        connect_async(url.clone())
-            .map_err(move |err| {
-                error!(
-                    "Error connecting to WebSocket server for account: {} {:?}",
-                    account_id, err
-                )
-            })
-            .and_then(move |(connection, _)| {
-                trace!(
-                    "Connected to account {} (URI: {}), sending auth packet",
-                    account_id,
-                    url
-                );
-                // Send BTP authentication
-                let auth_packet = Message::Binary(
-                    BtpPacket::Message(BtpMessage {
-                        request_id: random(),
-                        protocol_data: vec![
-                            ProtocolData {
-                                protocol_name: String::from("auth"),
-                                content_type: ContentType::ApplicationOctetStream,
-                                data: vec![],
-                            },
-                            ProtocolData {
-                                protocol_name: String::from("auth_token"),
-                                content_type: ContentType::TextPlainUtf8,
-                                data: token,
-                            },
-                        ],
-                    })
-                    .to_bytes(),
-                );
+        let wait = match throttle {
+            Some(ref throttle) => Either::A(throttle.wait()),
+            None => Either::B(ok(())),
+        };
+        wait.and_then(move |_| {
+            debug!("Connecting to {}", url);
+            connect_async(url.clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error connecting to WebSocket server for account: {} {:?}",
+                        account_id, err
+                    )
+                })
+                .and_then(move |(connection, _)| {
+                    trace!(
+                        "Connected to account {} (URI: {}), sending auth packet",
+                        account_id,
+                        url
+                    );
+                    // Send BTP authentication
+                    let auth_packet = Message::Binary(
+                        BtpPacket::Message(BtpMessage {
+                            request_id: random(),
+                            protocol_data: vec![
+                                ProtocolData {
+                                    protocol_name: String::from("auth"),
+                                    content_type: ContentType::ApplicationOctetStream,
+                                    data: vec![],
+                                },
+                                ProtocolData {
+                                    protocol_name: String::from("auth_token"),
+                                    content_type: ContentType::TextPlainUtf8,
+                                    data: token,
+                                },
+                            ],
+                        })
+                        .to_bytes(),
+                    );
 
-                connection
-                    .send(auth_packet)
-                    .map_err(move |_| error!("Error sending auth packet on connection: {}", url))
-            })
-            .then(move |result| match result {
-                Ok(connection) => {
-                    debug!("Connected to account {}'s server", account.id());
-                    Ok(Some((account, connection)))
-                }
-                Err(_) => {
-                    if error_on_unavailable {
-                        Err(())
-                    } else {
-                        Ok(None)
-                    }
+                    connection.send(auth_packet).map_err(move |_| {
+                        error!("Error sending auth packet on connection: {}", url)
+                    })
+                })
+        })
+        .then(move |result| match result {
+            Ok(connection) => {
+                debug!("Connected to account {}'s server", account.id());
+                Ok(Some((account, connection)))
+            }
+            Err(_) => {
+                if error_on_unavailable {
+                    Err(())
+                } else {
+                    Ok(None)
                 }
-            })
+            }
+        })
     }))
     .and_then(|connections| {
         let service = BtpOutgoingService::new(next_outgoing);
@@ -104,3 +160,58 @@ where
         Ok(service)
     })
 }
+
+#[cfg(test)]
+mod parse_btp_url {
+    use super::*;
+
+    #[test]
+    fn parses_btp_ws() {
+        let url = parse_btp_url("btp+ws://:token@example.com:7768/btp").unwrap();
+        assert_eq!(url.as_str(), "ws://:token@example.com:7768/btp");
+    }
+
+    #[test]
+    fn parses_btp_wss() {
+        let url = parse_btp_url("btp+wss://example.com/btp").unwrap();
+        assert_eq!(url.as_str(), "wss://example.com/btp");
+    }
+
+    #[test]
+    fn parses_plain_websocket_urls() {
+        assert_eq!(
+            parse_btp_url("ws://example.com").unwrap().as_str(),
+            "ws://example.com/"
+        );
+        assert_eq!(
+            parse_btp_url("wss://example.com").unwrap().as_str(),
+            "wss://example.com/"
+        );
+    }
+
+    #[test]
+    fn rejects_btp_http() {
+        for uri in &["btp+http://example.com/btp", "btp+https://example.com/btp"] {
+            match parse_btp_url(uri) {
+                Err(BtpUrlError::UnsupportedTransport(scheme)) => {
+                    assert_eq!(scheme, uri.split("://").next().unwrap())
+                }
+                other => panic!("Expected unsupported transport error, got: {:?}", other),
+            }
+        }
+        assert_eq!(
+            parse_btp_url("btp+http://example.com")
+                .unwrap_err()
+                .to_string(),
+            "Unsupported BTP transport: btp+http (only btp+ws and btp+wss are supported)"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_url() {
+        match parse_btp_url("btp+ws//example.com") {
+            Err(BtpUrlError::Parse(_)) => {}
+            other => panic!("Expected parse error, got: {:?}", other),
+        }
+    }
+}
@@ -41,3 +41,17 @@ quick_error! {
         }
     }
 }
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum BtpUrlError {
+        Parse(err: url::ParseError) {
+            from()
+            display("Invalid BTP URL: {}", err)
+            cause(err)
+        }
+        UnsupportedTransport(scheme: String) {
+            display("Unsupported BTP transport: {} (only btp+ws and btp+wss are supported)", scheme)
+        }
+    }
+}
@@ -24,10 +24,13 @@ mod oer;
 mod packet;
 mod server;
 mod service;
+mod throttle;
 
-pub use self::client::{connect_client, parse_btp_url};
+pub use self::client::{connect_client, connect_client_with_throttle, parse_btp_url};
+pub use self::errors::BtpUrlError;
 pub use self::server::{create_open_signup_server, create_server};
 pub use self::service::{BtpOutgoingService, BtpService};
+pub use self::throttle::ReconnectThrottle;
 use interledger_packet::Address;
 
 pub trait BtpAccount: Account {
@@ -76,16 +79,31 @@ pub trait BtpOpenSignupStore {
 
 #[cfg(test)]
 mod client_server {
+    use super::packet::*;
     use super::*;
-    use futures::future::{err, ok, result};
-    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use bytes::BytesMut;
+    use futures::{
+        future::{err, ok, result},
+        Sink, Stream,
+    };
+    use interledger_packet::{
+        Address, ErrorCode, Fulfill, FulfillBuilder, Packet, PacketType as IlpPacketType,
+        PrepareBuilder, Reject, RejectBuilder,
+    };
     use interledger_service::*;
     use std::str::FromStr;
     use std::{
-        sync::Arc,
-        time::{Duration, SystemTime},
+        convert::TryFrom,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime},
     };
     use tokio::runtime::Runtime;
+    use tokio_timer::Delay;
+    use tokio_tungstenite::connect_async;
+    use tungstenite::Message;
 
     #[derive(Clone, Debug)]
     pub struct TestAccount {
@@ -263,6 +281,7 @@ mod client_server {
                         data: b"test data",
                     }
                     .build(),
+                    correlation_id: None,
                 })
                 .map_err(|reject| println!("Packet was rejected: {:?}", reject))
                 .and_then(move |_| {
@@ -272,4 +291,136 @@ mod client_server {
         });
         runtime.block_on(client).unwrap();
     }
+
+    #[test]
+    fn rejects_reused_request_id() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let server_store = TestStore {
+            accounts: Arc::new(vec![TestAccount {
+                id: 0,
+                btp_incoming_token: Some("test_auth_token".to_string()),
+                btp_outgoing_token: None,
+                btp_uri: None,
+            }]),
+        };
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handled_clone = handled.clone();
+        let server = create_server(
+            "127.0.0.1:12346".parse().unwrap(),
+            server_store,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+        )
+        .and_then(move |btp_server| {
+            btp_server.handle_incoming(incoming_service_fn(move |_| {
+                handled_clone.fetch_add(1, Ordering::SeqCst);
+                // Keep the first request pending until the duplicate has arrived
+                Delay::new(Instant::now() + Duration::from_millis(100)).then(
+                    |_| -> Result<Fulfill, Reject> {
+                        Ok(FulfillBuilder {
+                            fulfillment: &[0; 32],
+                            data: b"test data",
+                        }
+                        .build())
+                    },
+                )
+            }));
+            Ok(())
+        });
+        runtime.spawn(server);
+
+        let auth = BtpPacket::Message(BtpMessage {
+            request_id: 1,
+            protocol_data: vec![
+                ProtocolData {
+                    protocol_name: String::from("auth"),
+                    content_type: ContentType::ApplicationOctetStream,
+                    data: vec![],
+                },
+                ProtocolData {
+                    protocol_name: String::from("auth_token"),
+                    content_type: ContentType::TextPlainUtf8,
+                    data: b"test_auth_token".to_vec(),
+                },
+            ],
+        });
+        let prepare = |amount| {
+            let prepare = PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount,
+                execution_condition: &[0; 32],
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: &[],
+            }
+            .build();
+            Message::binary(
+                BtpPacket::Message(BtpMessage {
+                    request_id: 7,
+                    protocol_data: vec![ProtocolData {
+                        protocol_name: String::from("ilp"),
+                        content_type: ContentType::ApplicationOctetStream,
+                        data: BytesMut::from(prepare).to_vec(),
+                    }],
+                })
+                .to_bytes(),
+            )
+        };
+
+        let client = connect_async(Url::parse("ws://127.0.0.1:12346").unwrap())
+            .map_err(|err| panic!("Error connecting: {:?}", err))
+            .and_then(move |(connection, _)| {
+                connection
+                    .send(Message::binary(auth.to_bytes()))
+                    .and_then(move |connection| connection.send(prepare(100)))
+                    .and_then(move |connection| connection.send(prepare(200)))
+                    .and_then(|connection| {
+                        // Skip the auth response
+                        connection
+                            .filter(Message::is_binary)
+                            .skip(1)
+                            .take(2)
+                            .collect()
+                    })
+                    .map_err(|err| panic!("WebSocket error: {:?}", err))
+            });
+        let responses: Vec<(u32, Vec<u8>)> = runtime
+            .block_on(client)
+            .unwrap()
+            .into_iter()
+            .map(|message| {
+                let data = message.into_data();
+                let (request_id, mut protocol_data) = parse_protocol_data(&data).unwrap();
+                (
+                    request_id,
+                    protocol_data.next().unwrap().unwrap().data.to_vec(),
+                )
+            })
+            .collect();
+
+        assert_eq!(responses.len(), 2);
+        // The duplicate is rejected right away and the original is still fulfilled
+        assert_eq!(responses[0].0, 7);
+        assert_eq!(
+            IlpPacketType::try_from(&responses[0].1[..]).unwrap(),
+            IlpPacketType::Reject
+        );
+        assert!(responses[0]
+            .1
+            .windows(24)
+            .any(|window| window == b"Duplicate BTP request ID"));
+        assert_eq!(responses[1].0, 7);
+        match Packet::try_from(BytesMut::from(responses[1].1.clone())).unwrap() {
+            Packet::Fulfill(fulfill) => assert_eq!(fulfill.data(), b"test data"),
+            other => panic!("Expected Fulfill for original request, got: {:?}", other),
+        }
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
 }
@@ -122,6 +122,115 @@ where
     }
 }
 
+/// A protocol data entry that borrows its name and data from the buffer it was parsed from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProtocolDataRef<'a> {
+    pub protocol_name: &'a str,
+    pub content_type: ContentType,
+    pub data: &'a [u8],
+}
+
+/// Iterator over the protocol data entries of a BTP Message or Response.
+///
+/// Each entry is only parsed when the iterator reaches it, and nothing is copied,
+/// so consumers can skip entries they are not interested in cheaply.
+#[derive(Debug, Clone)]
+pub struct ProtocolDataIter<'a> {
+    buf: &'a [u8],
+    remaining: u64,
+}
+
+impl<'a> Iterator for ProtocolDataIter<'a> {
+    type Item = Result<ProtocolDataRef<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let entry = read_protocol_data_ref(&mut self.buf);
+        if entry.is_err() {
+            // Entries after a malformed one cannot be located
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
+fn read_protocol_data_ref<'a>(buf: &mut &'a [u8]) -> Result<ProtocolDataRef<'a>, ParseError> {
+    let protocol_name = str::from_utf8(take_var_octet_string(buf)?)?;
+    let content_type = ContentType::from(take(buf, 1)?[0]);
+    let data = take_var_octet_string(buf)?;
+    Ok(ProtocolDataRef {
+        protocol_name,
+        content_type,
+        data,
+    })
+}
+
+fn take<'a>(buf: &mut &'a [u8], length: usize) -> Result<&'a [u8], ParseError> {
+    if buf.len() < length {
+        return Err(ParseError::InvalidPacket(format!(
+            "Expected {} more bytes but only {} remain",
+            length,
+            buf.len()
+        )));
+    }
+    let (taken, rest) = buf.split_at(length);
+    *buf = rest;
+    Ok(taken)
+}
+
+fn take_var_octet_string<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], ParseError> {
+    let length = take(buf, 1)?[0];
+    let length = if length & 0x80 != 0 {
+        let length_prefix = take(buf, usize::from(length & 0x7f))?;
+        bytes_to_u64(length_prefix)? as usize
+    } else {
+        usize::from(length)
+    };
+    take(buf, length)
+}
+
+fn bytes_to_u64(bytes: &[u8]) -> Result<u64, ParseError> {
+    if bytes.len() > 8 {
+        return Err(ParseError::InvalidPacket(format!(
+            "Length of {} bytes is too large",
+            bytes.len()
+        )));
+    }
+    Ok(bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+/// Parse the request ID of a BTP Message or Response and return an iterator over its protocol data
+/// that borrows from the given buffer, rather than copying every entry like `from_bytes` does.
+pub fn parse_protocol_data(bytes: &[u8]) -> Result<(u32, ProtocolDataIter<'_>), ParseError> {
+    let mut buf = bytes;
+    let packet_type = take(&mut buf, 1)?[0];
+    match PacketType::from(packet_type) {
+        PacketType::Message | PacketType::Response => {}
+        _ => {
+            return Err(ParseError::InvalidPacket(format!(
+                "Cannot read protocol data from packet of type {}",
+                packet_type
+            )));
+        }
+    }
+    let request_id = take(&mut buf, 4)?;
+    let request_id = bytes_to_u64(request_id)? as u32;
+    let mut contents = take_var_octet_string(&mut buf)?;
+    let remaining = bytes_to_u64(take_var_octet_string(&mut contents)?)?;
+    Ok((
+        request_id,
+        ProtocolDataIter {
+            buf: contents,
+            remaining,
+        },
+    ))
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct BtpMessage {
     pub request_id: u32,
@@ -297,6 +406,97 @@ mod tests {
         }
     }
 
+    mod protocol_data_iter {
+        use super::*;
+
+        #[test]
+        fn borrows_entries_from_message() {
+            let message = BtpMessage {
+                request_id: 7,
+                protocol_data: vec![
+                    ProtocolData {
+                        protocol_name: String::from("auth"),
+                        content_type: ContentType::ApplicationOctetStream,
+                        data: Vec::new(),
+                    },
+                    ProtocolData {
+                        protocol_name: String::from("ilp"),
+                        content_type: ContentType::ApplicationOctetStream,
+                        data: vec![0xab; 300],
+                    },
+                    ProtocolData {
+                        protocol_name: String::from("text"),
+                        content_type: ContentType::TextPlainUtf8,
+                        data: Vec::from("hello"),
+                    },
+                ],
+            };
+            let bytes = message.to_bytes();
+
+            let (request_id, entries) = parse_protocol_data(&bytes).unwrap();
+            assert_eq!(request_id, 7);
+            let entries: Vec<ProtocolDataRef> = entries.map(Result::unwrap).collect();
+            assert_eq!(entries.len(), 3);
+            assert_eq!(entries[0].protocol_name, "auth");
+            assert!(entries[0].data.is_empty());
+            assert_eq!(entries[1].protocol_name, "ilp");
+            assert_eq!(entries[1].data, &[0xab; 300][..]);
+            assert_eq!(entries[2].protocol_name, "text");
+            assert_eq!(entries[2].content_type, ContentType::TextPlainUtf8);
+            assert_eq!(entries[2].data, b"hello");
+
+            // The entries point into the original buffer rather than copies of it
+            let buffer = bytes.as_ptr_range();
+            assert!(buffer.contains(&entries[1].data.as_ptr()));
+            assert!(buffer.contains(&entries[2].protocol_name.as_ptr()));
+        }
+
+        #[test]
+        fn can_skip_to_entry() {
+            let (_, mut entries) = parse_protocol_data(&MESSAGE_SERIALIZED).unwrap();
+            let text = entries
+                .find(|entry| entry.as_ref().unwrap().protocol_name == "text")
+                .unwrap()
+                .unwrap();
+            assert_eq!(text.data, b"hello");
+            assert!(entries.next().is_none());
+        }
+
+        #[test]
+        fn errors_on_truncated_entry() {
+            let truncated = &MESSAGE_SERIALIZED[..MESSAGE_SERIALIZED.len() - 2];
+            // The outer length prefix no longer matches
+            assert!(parse_protocol_data(truncated).is_err());
+
+            let mut inner_truncated = MESSAGE_SERIALIZED.clone();
+            // Claim the second entry's data is longer than it is
+            let last = inner_truncated.len() - 6;
+            inner_truncated[last] = 0x09;
+            let (_, entries) = parse_protocol_data(&inner_truncated).unwrap();
+            let entries: Vec<_> = entries.collect();
+            assert!(entries[0].is_ok());
+            assert!(entries[1].is_err());
+        }
+
+        #[test]
+        fn rejects_error_packets() {
+            let error = BtpError {
+                request_id: 1,
+                code: String::from("F00"),
+                name: String::from("NotAcceptedError"),
+                triggered_at: Utc::now(),
+                data: String::new(),
+                protocol_data: Vec::new(),
+            };
+            assert!(parse_protocol_data(&error.to_bytes()).is_err());
+        }
+
+        lazy_static! {
+            static ref MESSAGE_SERIALIZED: Vec<u8> =
+                hex::decode("060000000217010204746573740002ffff0474657874010568656c6c6f").unwrap();
+        }
+    }
+
     mod btp_response {
         use super::*;
 
@@ -227,18 +227,16 @@ where
 
 fn parse_auth(ws_packet: Option<Message>) -> Option<Auth> {
     if let Some(Message::Binary(message)) = ws_packet {
-        if let Ok(message) = BtpMessage::from_bytes(&message) {
-            let request_id = message.request_id;
+        if let Ok((request_id, protocol_data)) = parse_protocol_data(&message) {
             let mut username: Option<String> = None;
             let mut token: Option<String> = None;
-            for protocol_data in message.protocol_data.iter() {
-                match (
-                    protocol_data.protocol_name.as_ref(),
-                    !protocol_data.data.is_empty(),
-                ) {
-                    ("auth_token", _) => token = String::from_utf8(protocol_data.data.clone()).ok(),
+            for protocol_data in protocol_data.filter_map(Result::ok) {
+                match (protocol_data.protocol_name, !protocol_data.data.is_empty()) {
+                    ("auth_token", _) => {
+                        token = String::from_utf8(protocol_data.data.to_vec()).ok()
+                    }
                     ("auth_username", true) => {
-                        username = String::from_utf8(protocol_data.data.clone()).ok()
+                        username = String::from_utf8(protocol_data.data.to_vec()).ok()
                     }
                     _ => {}
                 }
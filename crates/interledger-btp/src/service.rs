@@ -6,7 +6,7 @@ use futures::{
     sync::oneshot,
     Future, Sink, Stream,
 };
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use interledger_packet::{ErrorCode, Fulfill, Packet, Prepare, Reject, RejectBuilder};
 use interledger_service::*;
 use parking_lot::{Mutex, RwLock};
@@ -14,7 +14,6 @@ use rand::random;
 use std::{
     convert::TryFrom,
     io::{Error as IoError, ErrorKind},
-    iter::IntoIterator,
     marker::PhantomData,
     sync::Arc,
     time::Duration,
@@ -31,6 +30,8 @@ const PING_INTERVAL: u64 = 30; // seconds
 type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 type IlpResultChannel = oneshot::Sender<Result<Fulfill, Reject>>;
 type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
+// Request IDs are only unique per connection, so pending requests are keyed by the account as well
+type RequestKey<A> = (<A as Account>::AccountId, u32);
 
 /// A container for BTP/WebSocket connections that implements OutgoingService
 /// for sending outgoing ILP Prepare packets over one of the connected BTP connections.
@@ -38,8 +39,9 @@ type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
 pub struct BtpOutgoingService<O, A: Account> {
     // TODO support multiple connections per account
     connections: Arc<RwLock<HashMap<A::AccountId, UnboundedSender<Message>>>>,
-    pending_outgoing: Arc<Mutex<HashMap<u32, IlpResultChannel>>>,
+    pending_outgoing: Arc<Mutex<HashMap<RequestKey<A>, IlpResultChannel>>>,
     pending_incoming: Arc<Mutex<Option<IncomingRequestBuffer<A>>>>,
+    pending_incoming_ids: Arc<Mutex<HashSet<RequestKey<A>>>>,
     incoming_sender: UnboundedSender<(A, u32, Prepare)>,
     next: O,
     close_all_connections: Arc<Mutex<Option<Trigger>>>,
@@ -58,6 +60,7 @@ where
             connections: Arc::new(RwLock::new(HashMap::new())),
             pending_outgoing: Arc::new(Mutex::new(HashMap::new())),
             pending_incoming: Arc::new(Mutex::new(Some(incoming_receiver))),
+            pending_incoming_ids: Arc::new(Mutex::new(HashSet::new())),
             incoming_sender,
             next,
             close_all_connections: Arc::new(Mutex::new(Some(close_all_connections))),
@@ -125,6 +128,7 @@ where
         // Set up a listener to handle incoming packets from the WebSocket connection
         // TODO do we need all this cloning?
         let pending_requests = self.pending_outgoing.clone();
+        let pending_incoming_ids = self.pending_incoming_ids.clone();
         let incoming_sender = self.incoming_sender.clone();
         let tx_clone = tx.clone();
         let handle_incoming = stream.map_err(move |err| error!("Error reading from WebSocket stream for account {}: {:?}", account_id, err)).for_each(move |message| {
@@ -133,12 +137,24 @@ where
               match parse_ilp_packet(message) {
                 Ok((request_id, Packet::Prepare(prepare))) => {
                     trace!("Got incoming Prepare packet on request ID: {} {:?}", request_id, prepare);
+                    if !pending_incoming_ids.lock().insert((account_id, request_id)) {
+                        // The peer would not be able to tell which response was for which request
+                        warn!("Account {} reused request ID {} while the original request is still pending, rejecting the new request", account_id, request_id);
+                        let reject = RejectBuilder {
+                            code: ErrorCode::F00_BAD_REQUEST,
+                            message: b"Duplicate BTP request ID",
+                            triggered_by: None,
+                            data: &[],
+                        }.build();
+                        return tx_clone.unbounded_send(ilp_packet_to_ws_message(request_id, Packet::Reject(reject)))
+                            .map_err(|err| error!("Error sending Reject for duplicate request ID: {:?}", err));
+                    }
                     incoming_sender.clone().unbounded_send((account.clone(), request_id, prepare))
                         .map_err(|err| error!("Unable to buffer incoming request: {:?}", err))
                 },
                 Ok((request_id, Packet::Fulfill(fulfill))) => {
                   trace!("Got fulfill response to request id {}", request_id);
-                  if let Some(channel) = (*pending_requests.lock()).remove(&request_id) {
+                  if let Some(channel) = (*pending_requests.lock()).remove(&(account_id, request_id)) {
                     channel.send(Ok(fulfill)).map_err(|fulfill| error!("Error forwarding Fulfill packet back to the Future that sent the Prepare: {:?}", fulfill))
                   } else {
                     warn!("Got Fulfill packet that does not match an outgoing Prepare we sent: {:?}", fulfill);
@@ -147,7 +163,7 @@ where
                 }
                 Ok((request_id, Packet::Reject(reject))) => {
                   trace!("Got reject response to request id {}", request_id);
-                  if let Some(channel) = (*pending_requests.lock()).remove(&request_id) {
+                  if let Some(channel) = (*pending_requests.lock()).remove(&(account_id, request_id)) {
                     channel.send(Err(reject)).map_err(|reject| error!("Error forwarding Reject packet back to the Future that sent the Prepare: {:?}", reject))
                   } else {
                     warn!("Got Reject packet that does not match an outgoing Prepare we sent: {:?}", reject);
@@ -204,6 +220,7 @@ where
         // all Prepare packets from the buffer, handle them, and send the responses back
         let mut incoming_handler_clone = incoming_handler.clone();
         let connections_clone = self.connections.clone();
+        let pending_incoming_ids = self.pending_incoming_ids.clone();
         let handle_pending_incoming = self
             .pending_incoming
             .lock()
@@ -212,9 +229,11 @@ where
             .for_each(move |(account, request_id, prepare)| {
                 let account_id = account.id();
                 let connections_clone = connections_clone.clone();
+                let pending_incoming_ids = pending_incoming_ids.clone();
                 let request = IncomingRequest {
                     from: account,
                     prepare,
+                    correlation_id: None,
                 };
                 trace!(
                     "Handling incoming request {} from account {}",
@@ -224,6 +243,9 @@ where
                 incoming_handler_clone
                     .handle_request(request)
                     .then(move |result| {
+                        pending_incoming_ids
+                            .lock()
+                            .remove(&(account_id, request_id));
                         let packet = match result {
                             Ok(fulfill) => Packet::Fulfill(fulfill),
                             Err(reject) => Packet::Reject(reject),
@@ -272,7 +294,20 @@ where
     fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
         let account_id = request.to.id();
         if let Some(connection) = (*self.connections.read()).get(&account_id) {
-            let request_id = random::<u32>();
+            // Register the request before sending it so the response cannot arrive first,
+            // and make sure we don't reuse an ID that is still pending on this connection
+            let (sender, receiver) = oneshot::channel();
+            let request_id = {
+                let mut pending_outgoing = self.pending_outgoing.lock();
+                let request_id = loop {
+                    let request_id = random::<u32>();
+                    if !pending_outgoing.contains_key(&(account_id, request_id)) {
+                        break request_id;
+                    }
+                };
+                pending_outgoing.insert((account_id, request_id), sender);
+                request_id
+            };
 
             // Clone the trigger so that the connections stay open until we've
             // gotten the response to our outgoing request
@@ -289,8 +324,6 @@ where
                 Packet::Prepare(request.prepare),
             )) {
                 Ok(_) => {
-                    let (sender, receiver) = oneshot::channel();
-                    (*self.pending_outgoing.lock()).insert(request_id, sender);
                     Box::new(
                         receiver
                             .then(move |result| {
@@ -320,6 +353,7 @@ where
                     )
                 }
                 Err(send_error) => {
+                    (*self.pending_outgoing.lock()).remove(&(account_id, request_id));
                     error!(
                         "Error sending websocket message for request {} to account {}: {:?}",
                         request_id, account_id, send_error
@@ -380,31 +414,25 @@ where
 
 fn parse_ilp_packet(message: Message) -> Result<(u32, Packet), ()> {
     if let Message::Binary(data) = message {
-        let (request_id, ilp_data) = match BtpPacket::from_bytes(&data) {
-            Ok(BtpPacket::Message(message)) => {
-                let ilp_data = message
-                    .protocol_data
-                    .into_iter()
-                    .find(|proto| proto.protocol_name == "ilp")
-                    .ok_or(())?
-                    .data;
-                (message.request_id, ilp_data)
-            }
-            Ok(BtpPacket::Response(response)) => {
-                let ilp_data = response
-                    .protocol_data
-                    .into_iter()
-                    .find(|proto| proto.protocol_name == "ilp")
+        let (request_id, ilp_data) = match parse_protocol_data(&data) {
+            Ok((request_id, mut protocol_data)) => {
+                // Skip over other entries without copying them
+                let ilp_data = protocol_data
+                    .find(|entry| match entry {
+                        Ok(entry) => entry.protocol_name == "ilp",
+                        Err(_) => true,
+                    })
                     .ok_or(())?
+                    .map_err(|err| error!("Error parsing BTP protocol data: {:?}", err))?
                     .data;
-                (response.request_id, ilp_data)
-            }
-            Ok(BtpPacket::Error(error)) => {
-                error!("Got BTP error: {:?}", error);
-                return Err(());
+                (request_id, ilp_data)
             }
             Err(err) => {
-                error!("Error parsing BTP packet: {:?}", err);
+                if let Ok(error) = BtpError::from_bytes(&data) {
+                    error!("Got BTP error: {:?}", error);
+                } else {
+                    error!("Error parsing BTP packet: {:?}", err);
+                }
                 return Err(());
             }
         };
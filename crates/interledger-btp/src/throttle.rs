@@ -0,0 +1,91 @@
+use futures::Future;
+use parking_lot::Mutex;
+use std::{
+    cmp::max,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+/// Spreads BTP (re)connection attempts across all of a connector's peers.
+///
+/// Each connection backs off on its own, but when a shared upstream restarts every
+/// connection to it drops at the same moment and would otherwise reconnect at once.
+/// Clones of a `ReconnectThrottle` share one schedule, which lets at most one
+/// connection attempt start per `interval`; the others wait for the next free slot.
+#[derive(Clone)]
+pub struct ReconnectThrottle {
+    interval: Duration,
+    next_slot: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ReconnectThrottle {
+    pub fn new(interval: Duration) -> Self {
+        ReconnectThrottle {
+            interval,
+            next_slot: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Reserve the next free slot and return when the connection attempt may start
+    fn reserve(&self) -> Instant {
+        let now = Instant::now();
+        let mut next_slot = self.next_slot.lock();
+        let slot = match *next_slot {
+            Some(next) => max(next, now),
+            None => now,
+        };
+        *next_slot = Some(slot + self.interval);
+        slot
+    }
+
+    /// Returns a future that resolves once this connection may try to (re)connect
+    pub fn wait(&self) -> impl Future<Item = (), Error = ()> {
+        let slot = self.reserve();
+        trace!(
+            "Delaying connection attempt by {:?} to spread out reconnections",
+            slot.saturating_duration_since(Instant::now())
+        );
+        Delay::new(slot).map_err(|err| error!("Error waiting to reconnect: {:?}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn spreads_simultaneous_attempts() {
+        let throttle = ReconnectThrottle::new(Duration::from_millis(20));
+        let start = Instant::now();
+        // Many connections drop at the same time and all try to reconnect
+        let attempts: Vec<_> = (0..10)
+            .map(|_| throttle.wait().map(|_| Instant::now()))
+            .collect();
+        let mut times = Runtime::new()
+            .unwrap()
+            .block_on(join_all(attempts))
+            .unwrap();
+        times.sort();
+
+        // One attempt may start every 20ms, so only the first five can start within 100ms
+        let early = times
+            .iter()
+            .filter(|time| **time - start < Duration::from_millis(100))
+            .count();
+        assert!(early <= 5);
+        assert!(times[9] - start >= Duration::from_millis(180));
+    }
+
+    #[test]
+    fn does_not_delay_after_quiet_period() {
+        let throttle = ReconnectThrottle::new(Duration::from_millis(20));
+        throttle.reserve();
+        std::thread::sleep(Duration::from_millis(30));
+        let now = Instant::now();
+        assert!(throttle.reserve() >= now);
+        assert!(throttle.reserve() - now >= Duration::from_millis(20));
+    }
+}
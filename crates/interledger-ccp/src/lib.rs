@@ -15,7 +15,7 @@ extern crate log;
 extern crate lazy_static;
 
 use bytes::Bytes;
-use futures::Future;
+use futures::{future::ok, Future};
 use hashbrown::HashMap;
 use interledger_ildcp::IldcpAccount;
 use interledger_service::Account;
@@ -29,6 +29,8 @@ mod server;
 #[cfg(test)]
 mod test_helpers;
 
+pub use packet::Route;
+pub use routing_table::{MergePolicy, MergeReport, RoutingTable};
 pub use server::{CcpRouteManager, CcpRouteManagerBuilder};
 
 #[repr(u8)]
@@ -79,8 +81,8 @@ pub trait CcpRoutingAccount: Account + IldcpAccount {
 }
 
 // key = Bytes, key should be Address -- TODO
-type Route<T> = HashMap<Bytes, T>;
-type LocalAndConfiguredRoutes<T> = (Route<T>, Route<T>);
+type Routes<T> = HashMap<Bytes, T>;
+type LocalAndConfiguredRoutes<T> = (Routes<T>, Routes<T>);
 
 pub trait RouteManagerStore: Clone {
     type Account: CcpRoutingAccount;
@@ -102,4 +104,15 @@ pub trait RouteManagerStore: Clone {
         &mut self,
         routes: impl IntoIterator<Item = (Bytes, Self::Account)>,
     ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Save the other next hops for the prefixes that several accounts announced equally good
+    /// routes for, replacing the ones saved before. These are returned by
+    /// `RouterStore::equal_cost_routes` for a `Router` with equal-cost multipath enabled.
+    /// By default, they are ignored.
+    fn set_equal_cost_routes(
+        &mut self,
+        _routes: HashMap<Bytes, Vec<<Self::Account as Account>::AccountId>>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(ok(()))
+    }
 }
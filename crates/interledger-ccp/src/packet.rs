@@ -205,6 +205,18 @@ pub struct Route {
     pub(crate) props: Vec<RouteProp>,
 }
 
+impl Route {
+    /// A route to the prefix through the given path of connectors, with no properties
+    pub fn new(prefix: Bytes, path: Vec<Bytes>) -> Self {
+        Route {
+            prefix,
+            path,
+            auth: [0; 32],
+            props: Vec::new(),
+        }
+    }
+}
+
 impl TryFrom<&mut &[u8]> for Route {
     type Error = ParseError;
 
@@ -2,6 +2,7 @@ use crate::packet::{Route, RouteUpdateRequest};
 use bytes::Bytes;
 use hashbrown::HashMap;
 use hex;
+use interledger_service::Account;
 use ring::rand::{SecureRandom, SystemRandom};
 use std::iter::FromIterator;
 
@@ -39,6 +40,33 @@ impl<T> PrefixMap<T> {
     }
 }
 
+/// How to resolve a prefix that has a route in both tables when merging routing tables
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergePolicy {
+    /// Keep the route with the shorter path, keeping the existing route if they are the same length
+    PreferLowerCost,
+    /// Always keep the existing route
+    PreferExisting,
+    /// Always use the route from the table being merged in
+    PreferNew,
+}
+
+/// The prefixes affected by merging one routing table into another.
+/// Each list is sorted so that the report is deterministic.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeReport {
+    /// Prefixes that were only in the table being merged in
+    pub added: Vec<Bytes>,
+    /// Conflicting prefixes where the route from the table being merged in was used
+    pub replaced: Vec<Bytes>,
+    /// Conflicting prefixes where the existing route was kept
+    pub kept: Vec<Bytes>,
+    /// Prefixes whose route in the table being merged in had the same next hop and path
+    /// as the existing one, so the existing route was kept
+    pub unchanged: Vec<Bytes>,
+}
+
 /// The routing table is identified by an ID (a UUID in array form) and an "epoch".
 /// When an Interledger node reloads, it will generate a new UUID for its routing table.
 /// Each update applied increments the epoch number, so it acts as a version tracker.
@@ -109,6 +137,14 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// Iterate over the prefixes in the table with their next hop and route
+    pub fn routes(&self) -> impl Iterator<Item = (&Bytes, &A, &Route)> {
+        self.prefix_map
+            .map
+            .iter()
+            .map(|(prefix, (account, route))| (prefix, account, route))
+    }
+
     pub fn get_simplified_table(&self) -> HashMap<Bytes, A> {
         HashMap::from_iter(
             self.prefix_map
@@ -118,12 +154,64 @@ where
         )
     }
 
-    /// Handle a CCP Route Update Request from the peer this table represents
+    /// Merge the routes from another table into this one, resolving prefixes that
+    /// have a route in both tables according to the given policy.
+    /// The ID and epoch of this table are not changed.
+    pub fn merge(&mut self, other: RoutingTable<A>, policy: MergePolicy) -> MergeReport
+    where
+        A: Account,
+    {
+        let mut report = MergeReport::default();
+        for (prefix, (account, route)) in other.prefix_map.map {
+            let use_new = match self.prefix_map.map.get(&prefix) {
+                None => {
+                    report.added.push(prefix.clone());
+                    true
+                }
+                Some((existing_account, existing_route))
+                    if existing_account.id() == account.id()
+                        && existing_route.path == route.path =>
+                {
+                    report.unchanged.push(prefix.clone());
+                    false
+                }
+                Some((_, existing_route)) => {
+                    let use_new = match policy {
+                        MergePolicy::PreferLowerCost => {
+                            route.path.len() < existing_route.path.len()
+                        }
+                        MergePolicy::PreferExisting => false,
+                        MergePolicy::PreferNew => true,
+                    };
+                    if use_new {
+                        report.replaced.push(prefix.clone());
+                    } else {
+                        report.kept.push(prefix.clone());
+                    }
+                    use_new
+                }
+            };
+            if use_new {
+                self.set_route(prefix, account, route);
+            }
+        }
+        report.added.sort();
+        report.replaced.sort();
+        report.kept.sort();
+        report.unchanged.sort();
+        report
+    }
+
+    /// Handle a CCP Route Update Request from the peer this table represents.
+    /// Returns the prefixes whose route was added, withdrawn, or changed.
     pub fn handle_update_request(
         &mut self,
         account: A,
         request: RouteUpdateRequest,
-    ) -> Result<Vec<Bytes>, String> {
+    ) -> Result<Vec<Bytes>, String>
+    where
+        A: Account,
+    {
         if self.id != request.routing_table_id {
             debug!(
                 "Saw new routing table. Old ID: {}, new ID: {}",
@@ -167,12 +255,15 @@ where
             }
         }
 
+        // Routes for prefixes we already had are replaced, so those are changed too,
+        // unless the peer announced the same path again
+        let mut new_routes = RoutingTable::new(self.id);
         for route in request.new_routes.into_iter() {
-            let prefix = route.prefix.clone();
-            if self.add_route(account.clone(), route) {
-                changed_prefixes.push(prefix);
-            }
+            new_routes.add_route(account.clone(), route);
         }
+        let report = self.merge(new_routes, MergePolicy::PreferNew);
+        changed_prefixes.extend(report.added);
+        changed_prefixes.extend(report.replaced);
 
         self.epoch = request.to_epoch_index;
         trace!(
@@ -310,4 +401,135 @@ mod table {
         assert_eq!(simplified.get(&b"example.one"[..]).unwrap().id, 1);
         assert_eq!(simplified.get(&b"example.two"[..]).unwrap().id, 2);
     }
+
+    fn route_with_path(prefix: &str, path_len: usize) -> Route {
+        Route {
+            prefix: Bytes::from(prefix),
+            path: vec![Bytes::from("example.hop"); path_len],
+            props: Vec::new(),
+            auth: [0; 32],
+        }
+    }
+
+    /// Existing table has example.a (path 2) and example.b (path 1),
+    /// the other has example.a (path 1), example.b (path 3), and example.c (path 1)
+    fn overlapping_tables() -> (RoutingTable<TestAccount>, RoutingTable<TestAccount>) {
+        let existing_account = TestAccount::new(1, "example.existing");
+        let mut existing = RoutingTable::new([0; 16]);
+        existing.add_route(existing_account.clone(), route_with_path("example.a", 2));
+        existing.add_route(existing_account, route_with_path("example.b", 1));
+
+        let new_account = TestAccount::new(2, "example.new");
+        let mut other = RoutingTable::new([1; 16]);
+        other.add_route(new_account.clone(), route_with_path("example.a", 1));
+        other.add_route(new_account.clone(), route_with_path("example.b", 3));
+        other.add_route(new_account, route_with_path("example.c", 1));
+        (existing, other)
+    }
+
+    fn next_hops(table: &RoutingTable<TestAccount>) -> Vec<u64> {
+        ["example.a", "example.b", "example.c"]
+            .iter()
+            .map(|prefix| table.get_route(prefix.as_bytes()).unwrap().0.id)
+            .collect()
+    }
+
+    #[test]
+    fn merge_prefers_lower_cost() {
+        let (mut existing, other) = overlapping_tables();
+        let report = existing.merge(other, MergePolicy::PreferLowerCost);
+        assert_eq!(
+            report,
+            MergeReport {
+                added: vec![Bytes::from("example.c")],
+                replaced: vec![Bytes::from("example.a")],
+                kept: vec![Bytes::from("example.b")],
+                unchanged: Vec::new(),
+            }
+        );
+        assert_eq!(next_hops(&existing), vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn merge_prefers_existing() {
+        let (mut existing, other) = overlapping_tables();
+        let report = existing.merge(other, MergePolicy::PreferExisting);
+        assert_eq!(
+            report,
+            MergeReport {
+                added: vec![Bytes::from("example.c")],
+                replaced: Vec::new(),
+                kept: vec![Bytes::from("example.a"), Bytes::from("example.b")],
+                unchanged: Vec::new(),
+            }
+        );
+        assert_eq!(next_hops(&existing), vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn merge_prefers_new() {
+        let (mut existing, other) = overlapping_tables();
+        let report = existing.merge(other, MergePolicy::PreferNew);
+        assert_eq!(
+            report,
+            MergeReport {
+                added: vec![Bytes::from("example.c")],
+                replaced: vec![Bytes::from("example.a"), Bytes::from("example.b")],
+                kept: Vec::new(),
+                unchanged: Vec::new(),
+            }
+        );
+        assert_eq!(next_hops(&existing), vec![2, 2, 2]);
+        assert_eq!(existing.id(), [0; 16]);
+    }
+
+    #[test]
+    fn merge_reports_same_route_as_unchanged() {
+        let account = TestAccount::new(1, "example.existing");
+        let mut existing = RoutingTable::new([0; 16]);
+        existing.add_route(account.clone(), route_with_path("example.a", 1));
+        existing.add_route(account.clone(), route_with_path("example.b", 1));
+        let mut other = RoutingTable::new([1; 16]);
+        // Same next hop and path
+        other.add_route(account.clone(), route_with_path("example.a", 1));
+        // Same next hop, different path
+        other.add_route(account, route_with_path("example.b", 2));
+
+        let report = existing.merge(other, MergePolicy::PreferNew);
+        assert_eq!(
+            report,
+            MergeReport {
+                added: Vec::new(),
+                replaced: vec![Bytes::from("example.b")],
+                kept: Vec::new(),
+                unchanged: vec![Bytes::from("example.a")],
+            }
+        );
+    }
+
+    #[test]
+    fn reannounced_routes_are_not_changed() {
+        let mut table = RoutingTable::new(UPDATE_REQUEST_COMPLEX.routing_table_id);
+        table.epoch = 46;
+        let request = UPDATE_REQUEST_COMPLEX.clone();
+        let updated_routes = table
+            .handle_update_request(ROUTING_ACCOUNT.clone(), request.clone())
+            .unwrap();
+        assert_eq!(
+            updated_routes,
+            vec![
+                Bytes::from("example.prefix1"),
+                Bytes::from("example.prefix2")
+            ]
+        );
+
+        let mut again = request;
+        again.from_epoch_index = 50;
+        again.to_epoch_index = 52;
+        again.withdrawn_routes = Vec::new();
+        let updated_routes = table
+            .handle_update_request(ROUTING_ACCOUNT.clone(), again)
+            .unwrap();
+        assert!(updated_routes.is_empty());
+    }
 }
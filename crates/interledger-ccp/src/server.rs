@@ -103,6 +103,7 @@ where
             last_epoch_updates_sent_for: Arc::new(Mutex::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
+            equal_cost_routes: Arc::new(RwLock::new(HashMap::new())),
         };
 
         if self.spawn_tasks {
@@ -144,6 +145,8 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// Updates from peers are applied to our local_table if they are better than the
     /// existing best route and if they do not attempt to overwrite configured routes.
     incoming_tables: Arc<RwLock<HashMap<A::AccountId, RoutingTable<A>>>>,
+    /// The other next hops for prefixes with several equally good routes, as last saved to the Store
+    equal_cost_routes: Arc<RwLock<HashMap<Bytes, Vec<A::AccountId>>>>,
     store: S,
     /// If true, tasks will be spawned to process Route Update Requests and respond
     /// to Route Control Requests. If false, the response to the incoming request
@@ -273,7 +276,10 @@ where
             .new_routes
             .into_iter()
             .filter(|route| {
-                if !route.prefix.starts_with(&self.global_prefix) {
+                if !is_routable_prefix(&route.prefix) {
+                    warn!("Got route for a non-routable address scheme: {:?}", route);
+                    false
+                } else if !route.prefix.starts_with(&self.global_prefix) {
                     warn!("Got route for a different global prefix: {:?}", route);
                     false
                 } else if route.prefix.len() <= self.global_prefix.len() {
@@ -413,6 +419,7 @@ where
                 to: account,
                 original_amount: prepare.amount(),
                 prepare,
+                correlation_id: None,
             })
             .then(move |result| {
                 if let Err(err) = result {
@@ -442,6 +449,7 @@ where
         let incoming_tables = self.incoming_tables.clone();
         let ilp_address = self.ilp_address.clone();
         let global_prefix = self.global_prefix.clone();
+        let equal_cost_routes = self.equal_cost_routes.clone();
         let mut store = self.store.clone();
 
         self.store.get_local_and_configured_routes().and_then(
@@ -489,7 +497,7 @@ where
                 };
 
                 // Update the local and forwarding tables
-                if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
+                let routes_saved = if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
                     let mut local_table = local_table.write();
                     let mut forwarding_table = forwarding_table.write();
                     let mut forwarding_table_updates = forwarding_table_updates.write();
@@ -509,6 +517,8 @@ where
                         if route.prefix.starts_with(&global_prefix[..])
                             // Don't advertise the global prefix
                             && route.prefix != global_prefix
+                            // Don't advertise peer., self. or local. routes
+                            && is_routable_prefix(&route.prefix)
                             // Don't advertise completely local routes because advertising our own
                             // prefix will make sure we get packets sent to them
                             && !(route.prefix.starts_with(ilp_address.as_ref()) && route.path.is_empty())
@@ -539,7 +549,45 @@ where
                 } else {
                     // The routing table hasn't changed
                     Either::B(ok(()))
-                }
+                };
+
+                // Other peers may have announced routes as good as the best ones even if the
+                // best routes did not change, so check every route in the local table
+                let new_equal_cost_routes: HashMap<Bytes, Vec<A::AccountId>> = {
+                    let local_table = local_table.read();
+                    let incoming_tables = incoming_tables.read();
+                    local_table
+                        .routes()
+                        .filter_map(|(prefix, account, route)| {
+                            let next_hops = get_equal_cost_next_hops(&incoming_tables, account, route);
+                            if next_hops.is_empty() {
+                                None
+                            } else {
+                                Some((prefix.clone(), next_hops))
+                            }
+                        })
+                        .collect()
+                };
+                let equal_cost_routes_changed = {
+                    let mut equal_cost_routes = equal_cost_routes.write();
+                    if *equal_cost_routes != new_equal_cost_routes {
+                        *equal_cost_routes = new_equal_cost_routes.clone();
+                        true
+                    } else {
+                        false
+                    }
+                };
+                routes_saved.and_then(move |_| {
+                    if equal_cost_routes_changed {
+                        trace!(
+                            "Saving equal-cost routes for {} prefixes",
+                            new_equal_cost_routes.len()
+                        );
+                        Either::A(store.set_equal_cost_routes(new_equal_cost_routes))
+                    } else {
+                        Either::B(ok(()))
+                    }
+                })
             },
         )
     }
@@ -588,6 +636,7 @@ where
                                     to: account,
                                     original_amount: prepare.amount(),
                                     prepare: prepare.clone(),
+                                    correlation_id: None,
                                 })
                                 .map_err(move |err| {
                                     warn!(
@@ -697,6 +746,7 @@ where
                 to: account,
                 original_amount: prepare.amount(),
                 prepare,
+                correlation_id: None,
             })
             .and_then(|_| Ok(()))
             .then(move |result| {
@@ -711,6 +761,12 @@ where
     }
 }
 
+fn is_routable_prefix(prefix: &[u8]) -> bool {
+    AddressScheme::from_prefix(prefix)
+        .map(AddressScheme::is_routable)
+        .unwrap_or(false)
+}
+
 fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     local_routes: &HashMap<Bytes, A>,
     configured_routes: &HashMap<Bytes, A>,
@@ -775,6 +831,33 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     }
 }
 
+/// Find the other accounts that announced a route for the same prefix that is as good as
+/// the best one: from an account with the same routing relation and with a path of the same length.
+/// Configured and local routes have no equal-cost alternatives.
+fn get_equal_cost_next_hops<A: CcpRoutingAccount>(
+    incoming_tables: &HashMap<A::AccountId, RoutingTable<A>>,
+    best_account: &A,
+    best_route: &Route,
+) -> Vec<A::AccountId> {
+    if best_route.path.is_empty() {
+        return Vec::new();
+    }
+    let mut next_hops: Vec<A::AccountId> = incoming_tables
+        .values()
+        .filter_map(|incoming_table| incoming_table.get_route(&best_route.prefix))
+        .filter(|(account, route)| {
+            account.id() != best_account.id()
+                && account.routing_relation() == best_account.routing_relation()
+                && route.prefix == best_route.prefix
+                && route.path.len() == best_route.path.len()
+        })
+        .map(|(account, _route)| account.id())
+        .collect();
+    next_hops.sort_by_key(|account_id| account_id.to_string());
+    next_hops.dedup();
+    next_hops
+}
+
 impl<I, O, S, A> IncomingService<A> for CcpRouteManager<I, O, S, A>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
@@ -907,6 +990,81 @@ mod ranking_routes {
         let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.z");
         assert!(best_route.is_none());
     }
+
+    fn peer_table(
+        account: &TestAccount,
+        prefix: &str,
+        path_len: usize,
+    ) -> RoutingTable<TestAccount> {
+        let mut table = RoutingTable::default();
+        table.add_route(
+            account.clone(),
+            Route::new(
+                Bytes::from(prefix),
+                vec![Bytes::from("example.hop"); path_len],
+            ),
+        );
+        table
+    }
+
+    #[test]
+    fn finds_equal_cost_next_hops() {
+        let peer_1 = TestAccount::new(1, "example.peer1");
+        let peer_2 = TestAccount::new(2, "example.peer2");
+        let peer_3 = TestAccount::new(3, "example.peer3");
+        let mut child = TestAccount::new(4, "example.child");
+        child.relation = RoutingRelation::Child;
+        let incoming = HashMap::from_iter(vec![
+            (1, peer_table(&peer_1, "example.f", 1)),
+            (2, peer_table(&peer_2, "example.f", 1)),
+            // Longer path
+            (3, peer_table(&peer_3, "example.f", 2)),
+            // Different routing relation
+            (4, peer_table(&child, "example.f", 1)),
+        ]);
+
+        let (best_account, best_route) =
+            get_best_route_for_prefix(&HashMap::new(), &HashMap::new(), &incoming, b"example.f")
+                .unwrap();
+        assert_eq!(best_account.id(), 4);
+        assert!(get_equal_cost_next_hops(&incoming, &best_account, &best_route).is_empty());
+
+        let route = incoming[&1].get_route(b"example.f").unwrap().1.clone();
+        assert_eq!(
+            get_equal_cost_next_hops(&incoming, &peer_1, &route),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn configured_routes_have_no_equal_cost_next_hops() {
+        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.a");
+        let (account, route) = best_route.unwrap();
+        assert!(get_equal_cost_next_hops(&INCOMING, &account, &route).is_empty());
+    }
+
+    #[test]
+    fn saves_equal_cost_routes_to_store() {
+        let service = test_service();
+        let peer_1 = TestAccount::new(1, "example.peer1");
+        let peer_2 = TestAccount::new(2, "example.peer2");
+        service.incoming_tables.write().extend(vec![
+            (1, peer_table(&peer_1, "example.f", 1)),
+            (2, peer_table(&peer_2, "example.f", 1)),
+        ]);
+        service
+            .update_best_routes(Some(vec![Bytes::from("example.f")]))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            service.store.routes.lock()[&Bytes::from("example.f")].id(),
+            1
+        );
+        assert_eq!(
+            *service.store.equal_cost_routes.lock(),
+            HashMap::from_iter(vec![(Bytes::from("example.f"), vec![2])])
+        );
+    }
 }
 
 #[cfg(test)]
@@ -923,6 +1081,7 @@ mod handle_route_control_request {
             .handle_request(IncomingRequest {
                 prepare: CONTROL_REQUEST.to_prepare(),
                 from: ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -934,6 +1093,7 @@ mod handle_route_control_request {
             .handle_request(IncomingRequest {
                 prepare: CONTROL_REQUEST.to_prepare(),
                 from: NON_ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -956,6 +1116,7 @@ mod handle_route_control_request {
                 }
                 .build(),
                 from: ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -980,6 +1141,7 @@ mod handle_route_control_request {
                     features: Vec::new(),
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1007,6 +1169,7 @@ mod handle_route_control_request {
                     features: Vec::new(),
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1043,6 +1206,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 prepare: update.to_prepare(),
                 from: ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1054,6 +1218,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 prepare: UPDATE_REQUEST_SIMPLE.to_prepare(),
                 from: NON_ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -1076,6 +1241,7 @@ mod handle_route_update_request {
                 }
                 .build(),
                 from: ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -1096,6 +1262,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 prepare: update.to_prepare(),
                 from: ROUTING_ACCOUNT.clone(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1144,6 +1311,21 @@ mod handle_route_update_request {
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
 
+    #[test]
+    fn filters_routes_for_non_routable_schemes() {
+        let mut service = test_service();
+        service.global_prefix = Bytes::from("local.");
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.new_routes.push(Route {
+            prefix: Bytes::from("local.foo"),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        });
+        let request = service.filter_routes(request);
+        assert!(request.new_routes.is_empty());
+    }
+
     #[test]
     fn filters_routing_loops() {
         let service = test_service();
@@ -1179,6 +1361,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1210,6 +1393,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1257,6 +1441,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1288,6 +1473,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1305,6 +1491,7 @@ mod handle_route_update_request {
                     withdrawn_routes: vec![Bytes::from("example.prefix2")],
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1333,6 +1520,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request1.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1346,6 +1534,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request2.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap_err();
@@ -1372,6 +1561,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1384,6 +1574,7 @@ mod handle_route_update_request {
             .handle_request(IncomingRequest {
                 from: ROUTING_ACCOUNT.clone(),
                 prepare: request.to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap_err();
@@ -1548,6 +1739,7 @@ mod send_route_updates {
                     withdrawn_routes: Vec::new(),
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1591,6 +1783,7 @@ mod send_route_updates {
                     withdrawn_routes: Vec::new(),
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -1608,6 +1801,7 @@ mod send_route_updates {
                     withdrawn_routes: vec![Bytes::from("example.remote")],
                 }
                 .to_prepare(),
+                correlation_id: None,
             })
             .wait()
             .unwrap();
@@ -96,6 +96,7 @@ pub struct TestStore {
     pub local: HashMap<Bytes, TestAccount>,
     pub configured: HashMap<Bytes, TestAccount>,
     pub routes: Arc<Mutex<HashMap<Bytes, TestAccount>>>,
+    pub equal_cost_routes: Arc<Mutex<HashMap<Bytes, Vec<u64>>>>,
 }
 
 impl TestStore {
@@ -104,6 +105,7 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            equal_cost_routes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -115,6 +117,7 @@ impl TestStore {
             local,
             configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            equal_cost_routes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -170,6 +173,14 @@ impl RouteManagerStore for TestStore {
         *self.routes.lock() = HashMap::from_iter(routes.into_iter());
         Box::new(ok(()))
     }
+
+    fn set_equal_cost_routes(
+        &mut self,
+        routes: HashMap<Bytes, Vec<u64>>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        *self.equal_cost_routes.lock() = routes;
+        Box::new(ok(()))
+    }
 }
 
 pub fn test_service() -> CcpRouteManager<
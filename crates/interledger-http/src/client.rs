@@ -1,22 +1,46 @@
-use super::{HttpAccount, HttpStore};
+use super::{gzip, HttpAccount, HttpStore};
 use bytes::BytesMut;
 use futures::{future::result, Future, Stream};
 use interledger_packet::{ErrorCode, Fulfill, Packet, Reject, RejectBuilder};
 use interledger_service::*;
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING},
     r#async::{Chunk, Client, ClientBuilder, Response as HttpResponse},
+    StatusCode,
+};
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
-use std::{convert::TryFrom, marker::PhantomData, sync::Arc, time::Duration};
 
 #[derive(Clone)]
-pub struct HttpClientService<S, O, A> {
+pub struct HttpClientService<S, O, A: Account> {
     client: Client,
     store: Arc<S>,
     next: O,
+    compression: bool,
+    /// The accounts whose servers said they accept gzip-compressed requests
+    gzip_accounts: Arc<RwLock<HashSet<A::AccountId>>>,
     account_type: PhantomData<A>,
 }
 
+fn build_client(gzip: bool) -> Client {
+    let mut headers = HeaderMap::with_capacity(2);
+    headers.insert(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    ClientBuilder::new()
+        .default_headers(headers)
+        .gzip(gzip)
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap()
+}
+
 impl<S, O, A> HttpClientService<S, O, A>
 where
     S: HttpStore,
@@ -24,31 +48,31 @@ where
     A: HttpAccount,
 {
     pub fn new(store: S, next: O) -> Self {
-        let mut headers = HeaderMap::with_capacity(2);
-        headers.insert(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("application/octet-stream"),
-        );
-        let client = ClientBuilder::new()
-            .default_headers(headers)
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
-
         HttpClientService {
-            client,
+            client: build_client(false),
             store: Arc::new(store),
             next,
+            compression: false,
+            gzip_accounts: Arc::new(RwLock::new(HashSet::new())),
             account_type: PhantomData,
         }
     }
+
+    /// Ask peers for gzip-compressed responses with `Accept-Encoding: gzip`, and compress the
+    /// requests to peers whose responses say they accept gzip. Requests to other peers, and
+    /// the first request to each peer, are sent uncompressed. Disabled by default.
+    pub fn compression(&mut self, enabled: bool) -> &mut Self {
+        self.compression = enabled;
+        self.client = build_client(enabled);
+        self
+    }
 }
 
 impl<S, O, A> OutgoingService<A> for HttpClientService<S, O, A>
 where
     S: HttpStore,
     O: OutgoingService<A>,
-    A: HttpAccount,
+    A: HttpAccount + 'static,
 {
     type Future = BoxedIlpFuture;
 
@@ -60,14 +84,25 @@ where
                 request.to.id(),
                 url.as_str()
             );
+            let account_id = request.to.id();
+            let compression = self.compression;
+            let compress_request =
+                compression && self.gzip_accounts.read().unwrap().contains(&account_id);
+            let gzip_accounts = self.gzip_accounts.clone();
+            let mut builder = self.client.post(url.clone()).header(
+                "authorization",
+                format!("Bearer {}", request.to.get_http_auth_token().unwrap_or("")),
+            );
+            let prepare = BytesMut::from(request.prepare);
+            builder = if compress_request {
+                builder
+                    .header(CONTENT_ENCODING, "gzip")
+                    .body(gzip::compress(&prepare[..]))
+            } else {
+                builder.body(prepare.freeze())
+            };
             Box::new(
-                self.client
-                    .post(url.clone())
-                    .header(
-                        "authorization",
-                        format!("Bearer {}", request.to.get_http_auth_token().unwrap_or("")),
-                    )
-                    .body(BytesMut::from(request.prepare).freeze())
+                builder
                     .send()
                     .map_err(|err| {
                         error!("Error sending HTTP request: {:?}", err);
@@ -79,6 +114,20 @@ where
                         }
                         .build()
                     })
+                    .map(move |response| {
+                        if compression {
+                            // Only compress requests to peers that said they accept them
+                            let accepts_gzip = gzip::accepts_gzip(response.headers())
+                                && response.status() != StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                            let mut gzip_accounts = gzip_accounts.write().unwrap();
+                            if accepts_gzip {
+                                gzip_accounts.insert(account_id);
+                            } else {
+                                gzip_accounts.remove(&account_id);
+                            }
+                        }
+                        response
+                    })
                     .and_then(parse_packet_from_response),
             )
         } else {
@@ -139,3 +188,138 @@ fn parse_packet_from_response(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HttpServerService;
+    use futures::future::ok;
+    use hyper::{service::service_fn, Body, Request, Server};
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        url: Url,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            1
+        }
+    }
+
+    impl HttpAccount for TestAccount {
+        fn get_http_url(&self) -> Option<&Url> {
+            Some(&self.url)
+        }
+
+        fn get_http_auth_token(&self) -> Option<&str> {
+            Some("token")
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore {
+        account: TestAccount,
+    }
+
+    impl HttpStore for TestStore {
+        type Account = TestAccount;
+
+        fn get_account_from_http_token(
+            &self,
+            _token: &str,
+        ) -> Box<dyn Future<Item = TestAccount, Error = ()> + Send> {
+            Box::new(ok(self.account.clone()))
+        }
+    }
+
+    #[test]
+    fn round_trips_compressed_packets() {
+        let mut runtime = Runtime::new().unwrap();
+        let account = TestAccount {
+            url: Url::parse("http://127.0.0.1").unwrap(),
+        };
+        let store = TestStore {
+            account: account.clone(),
+        };
+
+        let received_data = Arc::new(Mutex::new(Vec::new()));
+        let received_data_clone = received_data.clone();
+        let mut server_service = HttpServerService::new(
+            incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+                received_data_clone
+                    .lock()
+                    .unwrap()
+                    .push(request.prepare.data().to_vec());
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"fulfilled",
+                }
+                .build())
+            }),
+            store.clone(),
+        );
+        server_service.compression(true);
+        let request_encodings = Arc::new(Mutex::new(Vec::new()));
+        let request_encodings_clone = request_encodings.clone();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(move || {
+            let mut server_service = server_service.clone();
+            let request_encodings = request_encodings_clone.clone();
+            service_fn(move |request: Request<Body>| {
+                request_encodings.lock().unwrap().push(
+                    gzip::content_encoding(request.headers()).map(|encoding| encoding.to_string()),
+                );
+                server_service.handle_http_request(request)
+            })
+        });
+        let url = format!("http://127.0.0.1:{}/ilp", server.local_addr().port());
+        runtime.spawn(server.map_err(|err| panic!("Server error: {:?}", err)));
+
+        let account = TestAccount {
+            url: Url::parse(&url).unwrap(),
+        };
+        let mut client = HttpClientService::new(
+            store,
+            outgoing_service_fn(|_| -> Result<Fulfill, Reject> { unreachable!() }),
+        );
+        client.compression(true);
+        for _ in 0..2 {
+            let fulfill = runtime
+                .block_on(
+                    client.send_request(OutgoingRequest {
+                        from: account.clone(),
+                        to: account.clone(),
+                        original_amount: 100,
+                        prepare: PrepareBuilder {
+                            destination: Address::from_str("example.destination").unwrap(),
+                            amount: 100,
+                            expires_at: SystemTime::now() + Duration::from_secs(30),
+                            execution_condition: &[0; 32],
+                            data: &[1; 1000],
+                        }
+                        .build(),
+                        correlation_id: None,
+                    }),
+                )
+                .unwrap();
+            assert_eq!(fulfill.data(), b"fulfilled");
+        }
+
+        // The first request is sent uncompressed, until the server says it accepts gzip
+        assert_eq!(
+            *request_encodings.lock().unwrap(),
+            vec![None, Some("gzip".to_string())]
+        );
+        assert_eq!(*received_data.lock().unwrap(), vec![vec![1; 1000]; 2]);
+    }
+}
@@ -0,0 +1,72 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use http::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING};
+use std::io::{self, Read, Write};
+
+/// Compress an ILP packet for a request or response body with `Content-Encoding: gzip`
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len()), Compression::default());
+    // Writing to a Vec cannot fail
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompress a gzip-encoded body, failing if it is larger than `max_size` once decompressed
+pub fn decompress(bytes: &[u8], max_size: Option<usize>) -> io::Result<Vec<u8>> {
+    let limit = max_size
+        .map(|max_size| max_size as u64 + 1)
+        .unwrap_or(u64::MAX);
+    let mut decompressed = Vec::with_capacity(bytes.len());
+    GzDecoder::new(bytes)
+        .take(limit)
+        .read_to_end(&mut decompressed)?;
+    match max_size {
+        Some(max_size) if decompressed.len() > max_size => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Decompressed body is too large",
+        )),
+        _ => Ok(decompressed),
+    }
+}
+
+/// Whether the `Accept-Encoding` header includes gzip
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| encoding.split(';').next().unwrap_or_default().trim() == "gzip")
+}
+
+/// The `Content-Encoding` of a body, if it is not the identity encoding
+pub fn content_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap_or_default().trim())
+        .filter(|encoding| !encoding.is_empty() && *encoding != "identity")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+
+    #[test]
+    fn round_trips_bytes() {
+        let bytes = vec![7; 1000];
+        let compressed = compress(&bytes[..]);
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed[..], Some(1000)).unwrap(), bytes);
+        assert!(decompress(&compressed[..], Some(999)).is_err());
+    }
+
+    #[test]
+    fn parses_accept_encoding() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_gzip(&headers));
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("br, gzip;q=0.8"));
+        assert!(accepts_gzip(&headers));
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        assert!(!accepts_gzip(&headers));
+    }
+}
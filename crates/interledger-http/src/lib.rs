@@ -11,6 +11,7 @@ use interledger_service::Account;
 use url::Url;
 
 mod client;
+mod gzip;
 mod server;
 
 /// Originally from [interledger-relay](https://github.com/coilhq/interledger-relay/blob/master/crates/interledger-relay/src/combinators/limit_stream.rs).
@@ -1,12 +1,16 @@
+use super::gzip;
 use super::limit_stream::LimitStream;
 use super::HttpStore;
 use bytes::BytesMut;
 use futures::{
-    future::{err, Either},
+    future::{err, ok, Either},
     Future, Stream,
 };
 use hyper::{
-    body::Body, header::AUTHORIZATION, service::Service as HttpService, Error, Request, Response,
+    body::Body,
+    header::{ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING},
+    service::Service as HttpService,
+    Error, Request, Response,
 };
 use interledger_packet::{Fulfill, Prepare, Reject};
 use interledger_service::*;
@@ -22,6 +26,7 @@ const BEARER_TOKEN_START: usize = 7;
 pub struct HttpServerService<S, T> {
     next: S,
     store: T,
+    compression: bool,
 }
 
 impl<S, T> HttpServerService<S, T>
@@ -30,7 +35,20 @@ where
     T: HttpStore,
 {
     pub fn new(next: S, store: T) -> Self {
-        HttpServerService { next, store }
+        HttpServerService {
+            next,
+            store,
+            compression: false,
+        }
+    }
+
+    /// Accept gzip-compressed requests, and compress responses to clients that send
+    /// `Accept-Encoding: gzip`. Responses include `Accept-Encoding: gzip` so that clients
+    /// know they can compress their requests. Disabled by default, in which case compressed
+    /// requests are rejected with `415 Unsupported Media Type`.
+    pub fn compression(&mut self, enabled: bool) -> &mut Self {
+        self.compression = enabled;
+        self
     }
 
     // TODO support certificate-based authentication
@@ -65,9 +83,11 @@ where
         request: Request<Body>,
     ) -> impl Future<Item = Response<Body>, Error = Error> {
         let mut next = self.next.clone();
+        let compression = self.compression;
+        let compress_response = compression && gzip::accepts_gzip(request.headers());
         self.check_authorization(&request)
-            .and_then(|from_account| {
-                parse_prepare_from_request(request, Some(MAX_MESSAGE_SIZE)).and_then(
+            .and_then(move |from_account| {
+                parse_prepare_from_request(request, Some(MAX_MESSAGE_SIZE), compression).and_then(
                     move |prepare| {
                         trace!(
                             "Got incoming ILP over HTTP packet from account: {}",
@@ -77,8 +97,15 @@ where
                         next.handle_request(IncomingRequest {
                             from: from_account,
                             prepare,
+                            correlation_id: None,
+                        })
+                        .then(move |result| {
+                            ok(ilp_response_to_http_response(
+                                result,
+                                compression,
+                                compress_response,
+                            ))
                         })
-                        .then(ilp_response_to_http_response)
                     },
                 )
             })
@@ -108,37 +135,70 @@ where
 fn parse_prepare_from_request(
     request: Request<Body>,
     max_message_size: Option<usize>,
+    accept_gzip: bool,
 ) -> impl Future<Item = Prepare, Error = Response<Body>> + 'static {
-    LimitStream::new(max_message_size, request.into_body())
-        .concat2()
-        .map_err(|err| {
-            eprintln!("Concatenating stream failed: {:?}", err);
-            Response::builder().status(500).body(Body::empty()).unwrap()
-        })
-        .and_then(|body| {
-            let bytes = body.into_bytes().try_mut().unwrap_or_else(|bytes| {
-                debug!("Copying bytes from incoming HTTP request into Prepare packet");
-                BytesMut::from(bytes)
-            });
-            Prepare::try_from(bytes).map_err(|err| {
-                eprintln!("Parsing prepare packet failed: {:?}", err);
-                Response::builder().status(400).body(Body::empty()).unwrap()
+    let gzipped = match gzip::content_encoding(request.headers()) {
+        None => false,
+        Some("gzip") if accept_gzip => true,
+        Some(encoding) => {
+            debug!("Rejecting request with unsupported encoding: {}", encoding);
+            return Either::A(err(Response::builder()
+                .status(415)
+                .body(Body::empty())
+                .unwrap()));
+        }
+    };
+    Either::B(
+        LimitStream::new(max_message_size, request.into_body())
+            .concat2()
+            .map_err(|err| {
+                eprintln!("Concatenating stream failed: {:?}", err);
+                Response::builder().status(500).body(Body::empty()).unwrap()
             })
-        })
+            .and_then(move |body| {
+                let bytes = if gzipped {
+                    gzip::decompress(&body[..], max_message_size)
+                        .map(BytesMut::from)
+                        .map_err(|err| {
+                            debug!("Decompressing request body failed: {:?}", err);
+                            Response::builder().status(400).body(Body::empty()).unwrap()
+                        })?
+                } else {
+                    body.into_bytes().try_mut().unwrap_or_else(|bytes| {
+                        debug!("Copying bytes from incoming HTTP request into Prepare packet");
+                        BytesMut::from(bytes)
+                    })
+                };
+                Prepare::try_from(bytes).map_err(|err| {
+                    eprintln!("Parsing prepare packet failed: {:?}", err);
+                    Response::builder().status(400).body(Body::empty()).unwrap()
+                })
+            }),
+    )
 }
 
 fn ilp_response_to_http_response(
     result: Result<Fulfill, Reject>,
-) -> Result<Response<Body>, Response<Body>> {
+    compression: bool,
+    compress_response: bool,
+) -> Response<Body> {
     let bytes: BytesMut = match result {
         Ok(fulfill) => fulfill.into(),
         Err(reject) => reject.into(),
     };
-    Ok(Response::builder()
+    let mut response = Response::builder();
+    response
         .status(200)
-        .header("content-type", "application/octet-stream")
-        .body(bytes.freeze().into())
-        .unwrap())
+        .header("content-type", "application/octet-stream");
+    if compression {
+        response.header(ACCEPT_ENCODING, "gzip");
+    }
+    if compress_response {
+        response.header(CONTENT_ENCODING, "gzip");
+        response.body(gzip::compress(&bytes[..]).into()).unwrap()
+    } else {
+        response.body(bytes.freeze().into()).unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -227,7 +287,7 @@ mod test_limit_stream {
             .body(body)
             .unwrap();
 
-        parse_prepare_from_request(request, max_message_size).wait()
+        parse_prepare_from_request(request, max_message_size, false).wait()
     }
 
     fn get_millis_from_unix_epoch(system_time: SystemTime) -> u128 {
@@ -0,0 +1,165 @@
+use std::{error::Error, fmt};
+
+/// The smallest asset scale accepted unless another range is configured
+pub const DEFAULT_MIN_ASSET_SCALE: u8 = 0;
+/// The largest asset scale accepted unless another range is configured.
+/// Amounts are `u64`s, so at larger scales even one whole unit of an asset
+/// would be close to overflowing when converting between scales.
+pub const DEFAULT_MAX_ASSET_SCALE: u8 = 18;
+
+/// The asset scales that accounts and settlements are allowed to use
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssetScaleRange {
+    min: u8,
+    max: u8,
+}
+
+impl Default for AssetScaleRange {
+    fn default() -> Self {
+        AssetScaleRange {
+            min: DEFAULT_MIN_ASSET_SCALE,
+            max: DEFAULT_MAX_ASSET_SCALE,
+        }
+    }
+}
+
+impl AssetScaleRange {
+    /// Returns `None` if `min` is larger than `max`
+    pub fn new(min: u8, max: u8) -> Option<Self> {
+        if min <= max {
+            Some(AssetScaleRange { min, max })
+        } else {
+            None
+        }
+    }
+
+    /// The smallest accepted scale
+    pub fn min(&self) -> u8 {
+        self.min
+    }
+
+    /// The largest accepted scale
+    pub fn max(&self) -> u8 {
+        self.max
+    }
+
+    /// Check that the scale is within the range (inclusive)
+    pub fn check(&self, scale: u8) -> Result<(), AssetScaleError> {
+        if scale >= self.min && scale <= self.max {
+            Ok(())
+        } else {
+            Err(AssetScaleError {
+                scale,
+                range: *self,
+            })
+        }
+    }
+}
+
+/// An asset scale outside of the accepted `AssetScaleRange`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssetScaleError {
+    pub scale: u8,
+    pub range: AssetScaleRange,
+}
+
+impl fmt::Display for AssetScaleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Asset scale {} is outside the accepted range of {} to {}",
+            self.scale, self.range.min, self.range.max
+        )
+    }
+}
+
+impl Error for AssetScaleError {}
+
+/// Convert an amount from one asset scale to another, rounding down if it cannot be represented
+/// exactly in the new scale. Returns `None` if the converted amount does not fit in a `u64`.
+pub fn convert_scale(amount: u64, from_scale: u8, to_scale: u8) -> Option<u64> {
+    if to_scale >= from_scale {
+        if amount == 0 {
+            return Some(0);
+        }
+        10u64
+            .checked_pow(u32::from(to_scale - from_scale))
+            .and_then(|factor| amount.checked_mul(factor))
+    } else {
+        // A divisor that does not fit in a u64 is larger than any amount
+        Some(
+            10u64
+                .checked_pow(u32::from(from_scale - to_scale))
+                .map(|divisor| amount / divisor)
+                .unwrap_or(0),
+        )
+    }
+}
+
+/// Scale an exchange rate between two assets so that it converts amounts in `from_scale`
+/// directly to amounts in `to_scale`
+pub fn scale_rate(rate: f64, from_scale: u8, to_scale: u8) -> f64 {
+    if to_scale >= from_scale {
+        rate * 10f64.powf(f64::from(to_scale - from_scale))
+    } else {
+        rate / 10f64.powf(f64::from(from_scale - to_scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_scales_in_default_range() {
+        assert!(AssetScaleRange::default().check(0).is_ok());
+        assert!(AssetScaleRange::default().check(18).is_ok());
+    }
+
+    #[test]
+    fn converts_to_the_same_scale() {
+        assert_eq!(convert_scale(12345, 9, 9), Some(12345));
+        assert_eq!(convert_scale(u64::MAX, 0, 0), Some(u64::MAX));
+    }
+
+    #[test]
+    fn scales_down_with_remainder() {
+        assert_eq!(convert_scale(1299, 3, 1), Some(12));
+        assert_eq!(convert_scale(999, 3, 0), Some(0));
+        assert_eq!(convert_scale(u64::MAX, 30, 0), Some(0));
+    }
+
+    #[test]
+    fn returns_none_on_overflow() {
+        assert_eq!(convert_scale(123, 2, 5), Some(123_000));
+        assert_eq!(convert_scale(u64::MAX, 0, 1), None);
+        assert_eq!(convert_scale(2, 0, 19), None);
+        // 10^20 does not fit in a u64 at all
+        assert_eq!(convert_scale(1, 0, 20), None);
+        assert_eq!(convert_scale(0, 0, 30), Some(0));
+    }
+
+    #[test]
+    fn scales_rates() {
+        assert_eq!(scale_rate(1.5, 0, 3), 1500.0);
+        assert_eq!(scale_rate(1.5, 3, 0), 0.0015);
+    }
+
+    #[test]
+    fn rejects_scales_outside_range() {
+        let err = AssetScaleRange::default().check(30).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Asset scale 30 is outside the accepted range of 0 to 18"
+        );
+        let range = AssetScaleRange::new(2, 9).unwrap();
+        assert!(range.check(0).is_err());
+        assert!(range.check(9).is_ok());
+    }
+
+    #[test]
+    fn range_must_not_be_empty() {
+        assert!(AssetScaleRange::new(9, 2).is_none());
+        assert!(AssetScaleRange::new(9, 9).is_some());
+    }
+}
@@ -0,0 +1,164 @@
+use super::client::get_ildcp_info;
+use super::packet::IldcpResponse;
+use futures::{
+    future::{ok, Either},
+    Future,
+};
+use interledger_service::*;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+type CacheEntries<A> = HashMap<<A as Account>::AccountId, (IldcpResponse, Instant)>;
+
+/// A cache of the ILDCP info received for each account, so that reconnecting
+/// to a peer does not require querying it again while the info is recent.
+#[derive(Clone)]
+pub struct IldcpCache<A: Account> {
+    entries: Arc<RwLock<CacheEntries<A>>>,
+    ttl: Duration,
+}
+
+impl<A> IldcpCache<A>
+where
+    A: Account,
+{
+    /// Create a cache that keeps each account's info for the given duration
+    pub fn new(ttl: Duration) -> Self {
+        IldcpCache {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// Get the cached info for the account, unless it has expired
+    pub fn get(&self, account_id: A::AccountId) -> Option<IldcpResponse> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(&account_id)
+            .and_then(|(response, fetched_at)| {
+                if fetched_at.elapsed() < self.ttl {
+                    Some(response.clone())
+                } else {
+                    None
+                }
+            })
+    }
+
+    pub fn insert(&self, account_id: A::AccountId, response: IldcpResponse) {
+        self.entries
+            .write()
+            .unwrap()
+            .insert(account_id, (response, Instant::now()));
+    }
+
+    /// Remove the account's info so that the next request queries it again
+    pub fn invalidate(&self, account_id: A::AccountId) {
+        self.entries.write().unwrap().remove(&account_id);
+    }
+
+    /// Get the ILP address and asset details for the account from the cache or,
+    /// if there is no recent info, by sending an ILDCP request (see `get_ildcp_info`).
+    pub fn get_ildcp_info<S>(
+        &self,
+        service: &mut S,
+        account: A,
+    ) -> impl Future<Item = IldcpResponse, Error = ()>
+    where
+        S: IncomingService<A>,
+    {
+        let account_id = account.id();
+        if let Some(response) = self.get(account_id) {
+            trace!("Using cached ILDCP info for account: {}", account_id);
+            return Either::A(ok(response));
+        }
+
+        let cache = self.clone();
+        Either::B(get_ildcp_info(service, account).map(move |response| {
+            cache.insert(account_id, response.clone());
+            response
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::IldcpResponseBuilder;
+    use interledger_packet::{Address, Fulfill};
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread::sleep,
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn counting_service(requests: Arc<AtomicUsize>) -> impl IncomingService<TestAccount> + Clone {
+        incoming_service_fn(move |_request| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            Ok(Fulfill::from(
+                IldcpResponseBuilder {
+                    client_address: &Address::from_str("example.client").unwrap(),
+                    asset_scale: 9,
+                    asset_code: "XYZ",
+                }
+                .build(),
+            ))
+        })
+    }
+
+    #[test]
+    fn reuses_info_within_ttl() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let mut service = counting_service(requests.clone());
+        let cache = IldcpCache::new(Duration::from_secs(60));
+        let first = cache
+            .get_ildcp_info(&mut service, TestAccount)
+            .wait()
+            .unwrap();
+        let second = cache
+            .get_ildcp_info(&mut service, TestAccount)
+            .wait()
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        cache.invalidate(0);
+        cache
+            .get_ildcp_info(&mut service, TestAccount)
+            .wait()
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn queries_again_after_expiry() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let mut service = counting_service(requests.clone());
+        let cache = IldcpCache::new(Duration::from_millis(10));
+        cache
+            .get_ildcp_info(&mut service, TestAccount)
+            .wait()
+            .unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(cache.get(0).is_none());
+        cache
+            .get_ildcp_info(&mut service, TestAccount)
+            .wait()
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,7 +1,7 @@
 use super::packet::*;
 use futures::Future;
 use interledger_service::*;
-use std::convert::TryFrom;
+use std::time::Duration;
 
 /// Get the ILP address and asset details for a given account.
 pub fn get_ildcp_info<S, A>(
@@ -12,21 +12,34 @@ where
     S: IncomingService<A>,
     A: Account,
 {
-    let prepare = IldcpRequest {}.to_prepare();
+    get_ildcp_info_with_expiry(service, account, DEFAULT_ILDCP_EXPIRY)
+}
+
+/// Get the ILP address and asset details for a given account, using the given expiry for the request.
+pub fn get_ildcp_info_with_expiry<S, A>(
+    service: &mut S,
+    account: A,
+    expiry: Duration,
+) -> impl Future<Item = IldcpResponse, Error = ()>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    let prepare = IldcpRequest::new().expiry(expiry).to_prepare();
     service
         .handle_request(IncomingRequest {
             from: account,
             prepare,
+            correlation_id: None,
         })
         .map_err(|err| error!("Error getting ILDCP info: {:?}", err))
         .and_then(|fulfill| {
-            let response =
-                IldcpResponse::try_from(fulfill.into_data().freeze()).map_err(|err| {
-                    error!(
-                        "Unable to parse ILDCP response from fulfill packet: {:?}",
-                        err
-                    );
-                })?;
+            let response = IldcpResponse::try_from_fulfill(&fulfill).map_err(|err| {
+                error!(
+                    "Unable to parse ILDCP response from fulfill packet: {:?}",
+                    err
+                );
+            })?;
             debug!("Got ILDCP response: {:?}", response);
             Ok(response)
         })
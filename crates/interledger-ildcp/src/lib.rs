@@ -12,11 +12,18 @@ extern crate log;
 use interledger_packet::Address;
 use interledger_service::Account;
 
+mod asset_scale;
+mod cache;
 mod client;
 mod packet;
 mod server;
 
-pub use client::get_ildcp_info;
+pub use asset_scale::{
+    convert_scale, scale_rate, AssetScaleError, AssetScaleRange, DEFAULT_MAX_ASSET_SCALE,
+    DEFAULT_MIN_ASSET_SCALE,
+};
+pub use cache::IldcpCache;
+pub use client::{get_ildcp_info, get_ildcp_info_with_expiry};
 pub use packet::*;
 pub use server::IldcpService;
 
@@ -24,4 +31,10 @@ pub trait IldcpAccount: Account {
     fn client_address(&self) -> &Address;
     fn asset_scale(&self) -> u8;
     fn asset_code(&self) -> &str;
+
+    /// Whether the account is a child of this node, in which case ILDCP responses carry the
+    /// connector's own asset, if one is configured on the `IldcpService`. Defaults to `false`.
+    fn is_child(&self) -> bool {
+        false
+    }
 }
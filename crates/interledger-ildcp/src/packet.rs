@@ -2,10 +2,12 @@ use byteorder::ReadBytesExt;
 use bytes::{BufMut, Bytes, BytesMut};
 use interledger_packet::{
     oer::{predict_var_octet_string, BufOerExt, MutBufOerExt},
-    Address, Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder,
+    Address, AddressError, Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder,
+    MAX_ADDRESS_LENGTH,
 };
 use std::{
     convert::TryFrom,
+    error::Error,
     fmt, str,
     str::FromStr,
     time::{Duration, SystemTime},
@@ -18,30 +20,57 @@ static PEER_PROTOCOL_CONDITION: [u8; 32] = [
 ];
 const ASSET_SCALE_LEN: usize = 1;
 
+/// How long peer protocol requests, such as settlement engine messages, are valid for
+/// unless another expiry is configured: 30 seconds.
+pub const DEFAULT_PEER_PROTOCOL_EXPIRY: Duration = Duration::from_secs(30);
+
+/// How long ILDCP requests are valid for unless another expiry is configured: 60 seconds.
+pub const DEFAULT_ILDCP_EXPIRY: Duration = Duration::from_secs(60);
+
 lazy_static! {
-    static ref PEER_PROTOCOL_EXPIRY_DURATION: Duration = Duration::from_secs(60);
     static ref ILDCP_DESTINATION: Address = Address::from_str("peer.config").unwrap();
 }
 
+/// The `peer.config` address that ILDCP requests are sent to
+pub fn ildcp_destination() -> &'static Address {
+    &ILDCP_DESTINATION
+}
+
 pub fn is_ildcp_request(prepare: &Prepare) -> bool {
     prepare.execution_condition() == PEER_PROTOCOL_CONDITION
-        && prepare.destination() == *ILDCP_DESTINATION
+        && prepare.destination() == *ildcp_destination()
 }
 
-#[derive(Debug, Default)]
-pub struct IldcpRequest {}
+#[derive(Debug)]
+pub struct IldcpRequest {
+    expiry: Duration,
+}
+
+impl Default for IldcpRequest {
+    fn default() -> Self {
+        IldcpRequest::new()
+    }
+}
 
 impl IldcpRequest {
     pub fn new() -> Self {
-        IldcpRequest {}
+        IldcpRequest {
+            expiry: DEFAULT_ILDCP_EXPIRY,
+        }
+    }
+
+    /// Set how long the request is valid for (defaults to `DEFAULT_ILDCP_EXPIRY`)
+    pub fn expiry(&mut self, expiry: Duration) -> &mut Self {
+        self.expiry = expiry;
+        self
     }
 
     pub fn to_prepare(&self) -> Prepare {
         PrepareBuilder {
-            destination: (*ILDCP_DESTINATION).clone(),
+            destination: ildcp_destination().clone(),
             amount: 0,
             execution_condition: &PEER_PROTOCOL_CONDITION,
-            expires_at: SystemTime::now() + *PEER_PROTOCOL_EXPIRY_DURATION,
+            expires_at: SystemTime::now() + self.expiry,
             data: &[],
         }
         .build()
@@ -85,6 +114,14 @@ impl TryFrom<Bytes> for IldcpResponse {
         let mut reader = &buffer[..];
         let buffer_len = reader.len();
 
+        // Check the declared length before reading the address so that
+        // implausibly long addresses are rejected without looking any further
+        let address_len = (&reader[..]).read_var_octet_string_length()?;
+        if address_len > MAX_ADDRESS_LENGTH {
+            return Err(ParseError::InvalidAddress(AddressError::InvalidLength(
+                address_len,
+            )));
+        }
         let buf = reader.read_var_octet_string()?;
         let ilp_address = Address::try_from(buf)?;
 
@@ -103,6 +140,17 @@ impl TryFrom<Bytes> for IldcpResponse {
 }
 
 impl IldcpResponse {
+    /// Parse the ILDCP response carried in the data of a Fulfill, checking that the Fulfill
+    /// uses the peer protocol fulfillment.
+    pub fn try_from_fulfill(fulfill: &Fulfill) -> Result<Self, ParseError> {
+        if fulfill.fulfillment() != PEER_PROTOCOL_FULFILLMENT {
+            return Err(ParseError::InvalidPacket(
+                "Fulfill does not use the peer protocol fulfillment".to_string(),
+            ));
+        }
+        IldcpResponse::try_from(Bytes::from(fulfill.data()))
+    }
+
     pub fn client_address(&self) -> Address {
         self.ilp_address.clone()
     }
@@ -138,11 +186,16 @@ pub struct IldcpResponseBuilder<'a> {
 }
 
 impl<'a> IldcpResponseBuilder<'a> {
+    /// The number of bytes the built response will take up in the Fulfill data
+    pub fn data_len(&self) -> usize {
+        ASSET_SCALE_LEN
+            + predict_var_octet_string(self.client_address.len())
+            + predict_var_octet_string(self.asset_code.len())
+    }
+
     pub fn build(&self) -> IldcpResponse {
         let address_size = predict_var_octet_string(self.client_address.len());
-        let asset_code_size = predict_var_octet_string(self.asset_code.len());
-        let buf_size = ASSET_SCALE_LEN + address_size + asset_code_size;
-        let mut buffer = BytesMut::with_capacity(buf_size);
+        let mut buffer = BytesMut::with_capacity(self.data_len());
 
         buffer.put_var_octet_string_length(self.client_address.len());
         buffer.put_slice(self.client_address.as_ref());
@@ -157,4 +210,157 @@ impl<'a> IldcpResponseBuilder<'a> {
             ilp_address: self.client_address.clone(),
         }
     }
+
+    /// Build the response, unless it would be larger than `max_data_len` bytes. This is for
+    /// transports that limit the size of packet data, whose peers would otherwise reject the
+    /// Fulfill without saying why.
+    pub fn build_with_max_len(
+        &self,
+        max_data_len: usize,
+    ) -> Result<IldcpResponse, IldcpResponseTooLarge> {
+        let len = self.data_len();
+        if len > max_data_len {
+            Err(IldcpResponseTooLarge { len, max_data_len })
+        } else {
+            Ok(self.build())
+        }
+    }
+}
+
+/// An ILDCP response that does not fit in the data size allowed by the transport
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IldcpResponseTooLarge {
+    pub len: usize,
+    pub max_data_len: usize,
+}
+
+impl fmt::Display for IldcpResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ILDCP response is {} bytes, more than the maximum data size of {}",
+            self.len, self.max_data_len
+        )
+    }
+}
+
+impl Error for IldcpResponseTooLarge {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ildcp_destination_is_peer_config() {
+        assert_eq!(
+            *ildcp_destination(),
+            Address::from_str("peer.config").unwrap()
+        );
+        assert_eq!(
+            IldcpRequest::new().to_prepare().destination(),
+            *ildcp_destination()
+        );
+    }
+
+    #[test]
+    fn parses_response() {
+        let response = IldcpResponseBuilder {
+            client_address: &Address::from_str("example.client").unwrap(),
+            asset_scale: 9,
+            asset_code: "XYZ",
+        }
+        .build();
+        let parsed = IldcpResponse::try_from(Bytes::from(response.clone())).unwrap();
+        assert_eq!(parsed, response);
+        assert_eq!(parsed.asset_code(), b"XYZ");
+    }
+
+    #[test]
+    fn parses_response_from_fulfill() {
+        let response = IldcpResponseBuilder {
+            client_address: &Address::from_str("example.client").unwrap(),
+            asset_scale: 9,
+            asset_code: "XYZ",
+        }
+        .build();
+        let fulfill = Fulfill::from(response.clone());
+        assert_eq!(IldcpResponse::try_from_fulfill(&fulfill).unwrap(), response);
+    }
+
+    #[test]
+    fn rejects_fulfill_with_wrong_fulfillment() {
+        let response = IldcpResponseBuilder {
+            client_address: &Address::from_str("example.client").unwrap(),
+            asset_scale: 9,
+            asset_code: "XYZ",
+        }
+        .build();
+        let fulfill = FulfillBuilder {
+            fulfillment: &[1; 32],
+            data: &Bytes::from(response)[..],
+        }
+        .build();
+        match IldcpResponse::try_from_fulfill(&fulfill) {
+            Err(ParseError::InvalidPacket(_)) => {}
+            other => panic!("Expected invalid packet error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn request_expires_after_60_seconds_by_default() {
+        let expires_in = IldcpRequest::new()
+            .to_prepare()
+            .expires_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(expires_in <= Duration::from_secs(60));
+        assert!(expires_in > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn request_uses_configured_expiry() {
+        let prepare = IldcpRequest::new()
+            .expiry(Duration::from_secs(5))
+            .to_prepare();
+        let expires_in = prepare
+            .expires_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(expires_in <= Duration::from_secs(5));
+        assert!(expires_in > Duration::from_secs(4));
+    }
+
+    #[test]
+    fn rejects_implausible_address_length() {
+        let mut buffer = BytesMut::new();
+        buffer.put_var_octet_string_length(10000);
+        buffer.put_slice(b"example.client");
+        match IldcpResponse::try_from(buffer.freeze()) {
+            Err(ParseError::InvalidAddress(AddressError::InvalidLength(10000))) => {}
+            other => panic!("Expected invalid address length error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_response_larger_than_max_data_len() {
+        let client_address = Address::from_str(&format!("example.{}", "a".repeat(1000))).unwrap();
+        let builder = IldcpResponseBuilder {
+            client_address: &client_address,
+            asset_scale: 9,
+            asset_code: "XYZ",
+        };
+        assert_eq!(builder.data_len(), builder.build().buffer.len());
+        assert_eq!(
+            builder.build_with_max_len(512).unwrap_err(),
+            IldcpResponseTooLarge {
+                len: 1016,
+                max_data_len: 512,
+            }
+        );
+        assert_eq!(
+            builder.build_with_max_len(512).unwrap_err().to_string(),
+            "ILDCP response is 1016 bytes, more than the maximum data size of 512"
+        );
+        assert!(builder.build_with_max_len(1016).is_ok());
+    }
 }
@@ -1,15 +1,21 @@
 use super::packet::*;
 use super::IldcpAccount;
-use futures::future::ok;
+use futures::future::{err, ok};
 use interledger_packet::*;
 use interledger_service::*;
 use std::marker::PhantomData;
 
 /// A simple service that intercepts incoming ILDCP requests
 /// and responds using the information in the Account struct.
+///
+/// If the connector's own asset is configured with `connector_asset`, responses to child
+/// accounts carry that asset code and scale instead of the ones stored on the account.
+/// If `max_response_len` is set, requests whose response would not fit are rejected.
 #[derive(Clone)]
 pub struct IldcpService<I, A> {
     next: I,
+    connector_asset: Option<(String, u8)>,
+    max_response_len: Option<usize>,
     account_type: PhantomData<A>,
 }
 
@@ -21,9 +27,30 @@ where
     pub fn new(next: I) -> Self {
         IldcpService {
             next,
+            connector_asset: None,
+            max_response_len: None,
             account_type: PhantomData,
         }
     }
+
+    /// Set the asset code and scale of the connector, to be served to child accounts
+    pub fn connector_asset(&mut self, asset_code: String, asset_scale: u8) -> &mut Self {
+        self.connector_asset = Some((asset_code, asset_scale));
+        self
+    }
+
+    /// Set the largest Fulfill data, in bytes, the transport can carry to the requesting account
+    pub fn max_response_len(&mut self, max_data_len: usize) -> &mut Self {
+        self.max_response_len = Some(max_data_len);
+        self
+    }
+
+    /// The asset code and scale of the connector, if configured
+    pub fn get_connector_asset(&self) -> Option<(&str, u8)> {
+        self.connector_asset
+            .as_ref()
+            .map(|(asset_code, asset_scale)| (asset_code.as_str(), *asset_scale))
+    }
 }
 
 impl<I, A> IncomingService<A> for IldcpService<I, A>
@@ -36,13 +63,38 @@ where
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
         if is_ildcp_request(&request.prepare) {
             let from = request.from.client_address();
+            let connector_asset = if request.from.is_child() {
+                self.get_connector_asset()
+            } else {
+                None
+            };
+            let (asset_code, asset_scale) = connector_asset
+                .unwrap_or_else(|| (request.from.asset_code(), request.from.asset_scale()));
             let builder = IldcpResponseBuilder {
                 client_address: &from,
-                asset_code: request.from.asset_code(),
-                asset_scale: request.from.asset_scale(),
+                asset_code,
+                asset_scale,
+            };
+            let response = match self.max_response_len {
+                Some(max_data_len) => match builder.build_with_max_len(max_data_len) {
+                    Ok(response) => response,
+                    Err(too_large) => {
+                        error!(
+                            "Unable to respond to ILDCP request from account {:?}: {}",
+                            from, too_large
+                        );
+                        return Box::new(err(RejectBuilder {
+                            code: ErrorCode::F99_APPLICATION_ERROR,
+                            message: too_large.to_string().as_bytes(),
+                            triggered_by: None,
+                            data: &[],
+                        }
+                        .build()));
+                    }
+                },
+                None => builder.build(),
             };
             debug!("Responding to query for ildcp info by account: {:?}", from);
-            let response = builder.build();
             let fulfill = Fulfill::from(response);
             Box::new(ok(fulfill))
         } else {
@@ -50,3 +102,116 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::Future;
+    use std::{convert::TryFrom, str::FromStr};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        ilp_address: Address,
+        child: bool,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &Address {
+            &self.ilp_address
+        }
+
+        fn asset_scale(&self) -> u8 {
+            2
+        }
+
+        fn asset_code(&self) -> &str {
+            "ABC"
+        }
+
+        fn is_child(&self) -> bool {
+            self.child
+        }
+    }
+
+    fn query_as(service: &mut impl IncomingService<TestAccount>, child: bool) -> IldcpResponse {
+        let fulfill = service
+            .handle_request(IncomingRequest {
+                from: TestAccount {
+                    ilp_address: Address::from_str("example.connector.child").unwrap(),
+                    child,
+                },
+                prepare: IldcpRequest::new().to_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap();
+        IldcpResponse::try_from(Bytes::from(fulfill.data())).unwrap()
+    }
+
+    fn query(service: &mut impl IncomingService<TestAccount>) -> IldcpResponse {
+        query_as(service, true)
+    }
+
+    #[test]
+    fn serves_connector_asset_to_children() {
+        let next = incoming_service_fn(|_| -> Result<Fulfill, Reject> { unreachable!() });
+        let mut service = IldcpService::new(next);
+        let response = query(&mut service);
+        assert_eq!(response.asset_code(), b"ABC");
+        assert_eq!(response.asset_scale(), 2);
+
+        service.connector_asset("XYZ".to_string(), 9);
+        let response = query(&mut service);
+        assert_eq!(
+            response.client_address(),
+            Address::from_str("example.connector.child").unwrap()
+        );
+        assert_eq!(response.asset_code(), b"XYZ");
+        assert_eq!(response.asset_scale(), 9);
+    }
+
+    #[test]
+    fn serves_account_asset_to_peers() {
+        let next = incoming_service_fn(|_| -> Result<Fulfill, Reject> { unreachable!() });
+        let mut service = IldcpService::new(next);
+        service.connector_asset("XYZ".to_string(), 9);
+        let response = query_as(&mut service, false);
+        assert_eq!(response.asset_code(), b"ABC");
+        assert_eq!(response.asset_scale(), 2);
+    }
+
+    #[test]
+    fn rejects_responses_larger_than_max_response_len() {
+        let next = incoming_service_fn(|_| -> Result<Fulfill, Reject> { unreachable!() });
+        let mut service = IldcpService::new(next);
+        service.max_response_len(16);
+        let reject = service
+            .handle_request(IncomingRequest {
+                from: TestAccount {
+                    ilp_address: Address::from_str("example.connector.child").unwrap(),
+                    child: true,
+                },
+                prepare: IldcpRequest::new().to_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(
+            reject.message(),
+            &b"ILDCP response is 29 bytes, more than the maximum data size of 16"[..]
+        );
+
+        service.max_response_len(29);
+        assert_eq!(query(&mut service).asset_code(), b"ABC");
+    }
+}
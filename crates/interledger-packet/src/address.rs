@@ -16,7 +16,8 @@ use std::str::FromStr;
 
 use lazy_static::lazy_static;
 
-const MAX_ADDRESS_LENGTH: usize = 1023;
+/// The maximum length of an ILP address, in bytes
+pub const MAX_ADDRESS_LENGTH: usize = 1023;
 
 #[derive(Debug)]
 pub enum AddressError {
@@ -46,6 +47,53 @@ impl fmt::Display for AddressError {
     }
 }
 
+/// The allocation scheme of an ILP address, i.e. its first segment.
+///
+/// Reference: [Allocation Schemes](https://interledger.org/rfcs/0015-ilp-addresses/#allocation-schemes).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AddressScheme {
+    /// `g.`
+    Global,
+    /// `private.`
+    Private,
+    /// `example.`
+    Example,
+    /// `peer.`
+    Peer,
+    /// `self.`
+    Loopback,
+    /// `test.`, `test1.`, `test2.` or `test3.`
+    Test,
+    /// `local.`
+    Local,
+}
+
+impl AddressScheme {
+    /// Determines the scheme from the first segment of an address or address prefix.
+    pub fn from_prefix(prefix: &[u8]) -> Option<Self> {
+        let scheme = prefix.split(|&b| b == b'.').next().unwrap_or_default();
+        match scheme {
+            b"g" => Some(AddressScheme::Global),
+            b"private" => Some(AddressScheme::Private),
+            b"example" => Some(AddressScheme::Example),
+            b"peer" => Some(AddressScheme::Peer),
+            b"self" => Some(AddressScheme::Loopback),
+            b"test" | b"test1" | b"test2" | b"test3" => Some(AddressScheme::Test),
+            b"local" => Some(AddressScheme::Local),
+            _ => None,
+        }
+    }
+
+    /// Returns false for the `peer.`, `self.` and `local.` schemes, which are only
+    /// meaningful between directly connected nodes or within a single connector and
+    /// must not be advertised to or forwarded on behalf of other nodes.
+    pub fn is_routable(self) -> bool {
+        self != AddressScheme::Peer
+            && self != AddressScheme::Loopback
+            && self != AddressScheme::Local
+    }
+}
+
 /// An ILP address backed by `Bytes`.
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub struct Address(Bytes);
@@ -58,22 +106,27 @@ impl FromStr for Address {
     }
 }
 
+/// Check that the bytes are a valid address, without consulting the address cache
+pub(crate) fn validate_address(bytes: Bytes) -> Result<Address, ParseError> {
+    // https://interledger.org/rfcs/0015-ilp-addresses/#address-requirements
+    if bytes.len() > MAX_ADDRESS_LENGTH {
+        return Err(ParseError::InvalidAddress(AddressError::InvalidLength(
+            bytes.len(),
+        )));
+    }
+
+    if ADDRESS_PATTERN.is_match(str::from_utf8(&bytes)?) {
+        Ok(Address(bytes))
+    } else {
+        Err(ParseError::InvalidAddress(AddressError::InvalidFormat))
+    }
+}
+
 impl TryFrom<Bytes> for Address {
     type Error = ParseError;
 
     fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        // https://interledger.org/rfcs/0015-ilp-addresses/#address-requirements
-        if bytes.len() > MAX_ADDRESS_LENGTH {
-            return Err(ParseError::InvalidAddress(AddressError::InvalidLength(
-                bytes.len(),
-            )));
-        }
-
-        if ADDRESS_PATTERN.is_match(str::from_utf8(&bytes)?) {
-            Ok(Address(bytes))
-        } else {
-            Err(ParseError::InvalidAddress(AddressError::InvalidFormat))
-        }
+        validate_address(bytes)
     }
 }
 
@@ -156,6 +209,50 @@ impl Address {
         }
     }
 
+    /// Returns the number of segments in the ILP Address, without iterating over them
+    pub fn segment_count(&self) -> usize {
+        self.0.iter().filter(|&&b| b == b'.').count() + 1
+    }
+
+    /// Returns the allocation scheme of the ILP Address
+    pub fn scheme(&self) -> AddressScheme {
+        // The address pattern guarantees the first segment is a known scheme
+        AddressScheme::from_prefix(self.0.as_ref()).unwrap()
+    }
+
+    /// Returns whether packets addressed to this ILP Address may be forwarded
+    /// to, or routes for it advertised to, other nodes.
+    pub fn is_routable(&self) -> bool {
+        self.scheme().is_routable()
+    }
+
+    /// Returns whether this ILP Address is hierarchically beneath `ancestor`,
+    /// i.e. it starts with all of the ancestor's segments and has at least one more.
+    pub fn is_child_of(&self, ancestor: &Address) -> bool {
+        self.len() > ancestor.len()
+            && self.0.starts_with(&ancestor.0)
+            && self.0[ancestor.len()] == b'.'
+    }
+
+    /// Returns whether this ILP Address is `prefix` or is hierarchically beneath it.
+    ///
+    /// Unlike comparing the raw bytes, this only matches whole segments, so `g.bank`
+    /// is a prefix of `g.bank.alice` but not of `g.bankx.alice`.
+    pub fn starts_with(&self, prefix: &Address) -> bool {
+        self == prefix || self.is_child_of(prefix)
+    }
+
+    /// Returns the ILP Address without its last segment, or `None` if that would
+    /// leave only the allocation scheme (for example, `test.alice` has no parent).
+    pub fn parent(&self) -> Option<Address> {
+        let last_separator = self.0.iter().rposition(|&b| b == b'.')?;
+        if !self.0[..last_separator].contains(&b'.') {
+            return None;
+        }
+        // Every prefix of a valid address that ends before a separator is itself valid
+        Some(Address(self.0.slice_to(last_separator)))
+    }
+
     /// Suffixes the ILP Address with the provided suffix. Includes a '.' separator
     pub fn with_suffix(&self, suffix: &[u8]) -> Result<Address, ParseError> {
         let new_address_len = self.len() + 1 + suffix.len();
@@ -181,8 +278,10 @@ impl<'de> serde::Deserialize<'de> for Address {
     where
         D: serde::Deserializer<'de>,
     {
-        let string = <&str>::deserialize(deserializer)?;
-        Address::from_str(string).map_err(serde::de::Error::custom)
+        // Use an owned string so addresses can also be read from formats that
+        // can't lend out their strings, such as config files
+        let string = String::deserialize(deserializer)?;
+        Address::from_str(&string).map_err(serde::de::Error::custom)
     }
 }
 
@@ -290,6 +389,42 @@ mod test_address {
         assert!(addr.segments().eq(expected));
     }
 
+    #[test]
+    fn test_scheme() {
+        assert_eq!(
+            Address::from_str("g.alice").unwrap().scheme(),
+            AddressScheme::Global
+        );
+        assert_eq!(
+            Address::from_str("test2.alice").unwrap().scheme(),
+            AddressScheme::Test
+        );
+        assert_eq!(
+            Address::from_str("self.alice").unwrap().scheme(),
+            AddressScheme::Loopback
+        );
+        assert_eq!(
+            Address::from_str("local.foo").unwrap().scheme(),
+            AddressScheme::Local
+        );
+        assert_eq!(
+            AddressScheme::from_prefix(b"local"),
+            Some(AddressScheme::Local)
+        );
+        assert_eq!(AddressScheme::from_prefix(b"what.alice"), None);
+    }
+
+    #[test]
+    fn test_is_routable() {
+        assert!(Address::from_str("g.alice").unwrap().is_routable());
+        assert!(Address::from_str("private.alice").unwrap().is_routable());
+        assert!(Address::from_str("test.alice").unwrap().is_routable());
+        assert!(!Address::from_str("peer.config").unwrap().is_routable());
+        assert!(!Address::from_str("self.alice").unwrap().is_routable());
+        assert!(!Address::from_str("local.foo").unwrap().is_routable());
+        assert!(!Address::from_str("local.foo.bar").unwrap().is_routable());
+    }
+
     #[test]
     fn test_eq() {
         let addr1 = Address::from_str("test.alice.1234.5789").unwrap();
@@ -343,6 +478,66 @@ mod test_address {
         );
     }
 
+    #[test]
+    fn test_is_child_of() {
+        let parent = Address::from_str("test.alice").unwrap();
+        assert!(Address::from_str("test.alice.bob")
+            .unwrap()
+            .is_child_of(&parent));
+        assert!(Address::from_str("test.alice.bob.carl")
+            .unwrap()
+            .is_child_of(&parent));
+        assert!(!parent.is_child_of(&parent));
+        assert!(!Address::from_str("test.alicebob")
+            .unwrap()
+            .is_child_of(&parent));
+        assert!(!Address::from_str("test.bob.alice")
+            .unwrap()
+            .is_child_of(&parent));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn segment_count_matches_segments(
+            address in "(g|private|example|peer|self|test[1-3]?|local)([.][a-zA-Z0-9_~-]{1,16}){1,32}"
+        ) {
+            let address = Address::from_str(&address).unwrap();
+            proptest::prop_assert_eq!(address.segment_count(), address.segments().count());
+        }
+    }
+
+    #[test]
+    fn test_starts_with() {
+        let prefix = Address::from_str("g.bank").unwrap();
+        assert!(prefix.starts_with(&prefix));
+        assert!(Address::from_str("g.bank.alice")
+            .unwrap()
+            .starts_with(&prefix));
+        assert!(!Address::from_str("g.bankx.alice")
+            .unwrap()
+            .starts_with(&prefix));
+        assert!(!Address::from_str("g.bankx").unwrap().starts_with(&prefix));
+        assert!(!Address::from_str("g.ban").unwrap().starts_with(&prefix));
+    }
+
+    #[test]
+    fn test_parent() {
+        assert_eq!(
+            Address::from_str("test.alice.client").unwrap().parent(),
+            Some(Address::from_str("test.alice").unwrap())
+        );
+        assert_eq!(
+            Address::from_str("g.bank.alice.client")
+                .unwrap()
+                .parent()
+                .and_then(|parent| parent.parent()),
+            Some(Address::from_str("g.bank").unwrap())
+        );
+        assert_eq!(Address::from_str("test.alice").unwrap().parent(), None);
+        // A bare scheme such as `test` is not a valid address, so it cannot have a parent either
+        assert!(Address::from_str("test").is_err());
+    }
+
     fn make_address(length: usize) -> Vec<u8> {
         let mut addr = b"test.".to_vec();
         addr.resize(length, b'_');
@@ -0,0 +1,174 @@
+//! An opt-in cache of recently validated ILP addresses.
+
+use crate::address::{validate_address, Address};
+use crate::errors::ParseError;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+struct Entries {
+    /// Each address with the tick it was last used at
+    addresses: HashMap<Bytes, (Address, u64)>,
+    /// The addresses by the tick they were last used at, oldest first
+    last_used: BTreeMap<u64, Bytes>,
+    tick: u64,
+}
+
+/// A least-recently-used cache of addresses that have already been validated, so that
+/// parsing the same address again does not need to match it against the address pattern.
+///
+/// Only valid addresses are cached, and at most `capacity` of them are kept.
+/// Nothing is cached unless a cache is passed in explicitly, for example with
+/// `Prepare::parse_with_address_cache`, so parsing does not use any extra memory by default.
+pub struct AddressCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AddressCache {
+    pub fn new(capacity: usize) -> Self {
+        AddressCache {
+            capacity,
+            entries: Mutex::new(Entries {
+                addresses: HashMap::with_capacity(capacity),
+                last_used: BTreeMap::new(),
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Parse an address, only validating it if it is not in the cache
+    pub fn parse(&self, bytes: &[u8]) -> Result<Address, ParseError> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.tick += 1;
+            let tick = entries.tick;
+            if let Some((address, last_used)) = entries.addresses.get_mut(bytes) {
+                let previous = *last_used;
+                *last_used = tick;
+                let address = address.clone();
+                let key = entries.last_used.remove(&previous).unwrap();
+                entries.last_used.insert(tick, key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(address);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let address = validate_address(Bytes::from(bytes))?;
+        if self.capacity > 0 {
+            let mut entries = self.entries.lock().unwrap();
+            if !entries.addresses.contains_key(bytes) {
+                if entries.addresses.len() >= self.capacity {
+                    let oldest = entries.last_used.keys().next().cloned();
+                    if let Some(oldest) = oldest {
+                        let key = entries.last_used.remove(&oldest).unwrap();
+                        entries.addresses.remove(&key);
+                    }
+                }
+                entries.tick += 1;
+                let tick = entries.tick;
+                let key = address.to_bytes();
+                entries.last_used.insert(tick, key.clone());
+                entries.addresses.insert(key, (address.clone(), tick));
+            }
+        }
+        Ok(address)
+    }
+
+    /// The number of addresses currently cached
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of parsed addresses that were found in the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of parsed addresses that had to be validated
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_repeated_addresses_once() {
+        let cache = AddressCache::new(10);
+        let destinations = ["example.alice", "example.bob", "example.carol"];
+        for _ in 0..1000 {
+            for destination in destinations.iter() {
+                let address = cache.parse(destination.as_bytes()).unwrap();
+                assert_eq!(&*address, *destination);
+            }
+        }
+        assert_eq!(cache.misses(), 3);
+        assert_eq!(cache.hits(), 2997);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = AddressCache::new(2);
+        cache.parse(b"example.a").unwrap();
+        cache.parse(b"example.b").unwrap();
+        cache.parse(b"example.a").unwrap();
+        cache.parse(b"example.c").unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.misses(), 3);
+
+        // example.b was evicted, example.a was not
+        cache.parse(b"example.a").unwrap();
+        assert_eq!(cache.misses(), 3);
+        cache.parse(b"example.b").unwrap();
+        assert_eq!(cache.misses(), 4);
+    }
+
+    #[test]
+    fn does_not_cache_invalid_addresses() {
+        let cache = AddressCache::new(10);
+        assert!(cache.parse(b"invalid address").is_err());
+        assert!(cache.parse(b"invalid address").is_err());
+        assert!(cache.is_empty());
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[test]
+    fn caches_prepare_destinations() {
+        use crate::{Prepare, PrepareBuilder};
+        use bytes::BytesMut;
+        use std::str::FromStr;
+        use std::time::SystemTime;
+
+        let prepare: BytesMut = PrepareBuilder {
+            destination: Address::from_str("example.cached.destination").unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now(),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+        .into();
+        let cache = AddressCache::new(100);
+        for _ in 0..10 {
+            let parsed = Prepare::parse_with_address_cache(prepare.clone(), &cache).unwrap();
+            assert_eq!(&*parsed.destination(), "example.cached.destination");
+        }
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), 9);
+    }
+}
@@ -0,0 +1,173 @@
+//! A `tokio_codec` Decoder and Encoder for reading and writing ILP packets on byte streams.
+
+use crate::errors::ParseError;
+use crate::oer::BufOerExt;
+use crate::packet::Packet;
+use bytes::BytesMut;
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use tokio_codec::{Decoder, Encoder};
+
+/// The largest packet a `PacketCodec` will read, unless configured otherwise.
+/// This leaves room for the envelope and the other fields around 32767 bytes of data.
+pub const DEFAULT_MAX_PACKET_LENGTH: usize = 65535;
+
+/// Splits a stream of bytes into ILP packets, using the length prefix of each packet's
+/// envelope, and writes packets as their serialized bytes.
+///
+/// Packets may arrive in any number of chunks; the decoder only yields a packet
+/// once all of its bytes have been read.
+#[derive(Clone, Debug)]
+pub struct PacketCodec {
+    max_packet_length: usize,
+}
+
+impl PacketCodec {
+    pub fn new() -> Self {
+        PacketCodec {
+            max_packet_length: DEFAULT_MAX_PACKET_LENGTH,
+        }
+    }
+
+    /// Set the largest packet, including its envelope, that will be read.
+    /// Longer packets cause the decoder to return an error instead of buffering them.
+    pub fn max_packet_length(&mut self, max_packet_length: usize) -> &mut Self {
+        self.max_packet_length = max_packet_length;
+        self
+    }
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        PacketCodec::new()
+    }
+}
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = ParseError;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Packet>, ParseError> {
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+
+        // The envelope is the packet type, followed by the contents as a variable-length octet string
+        let mut reader = &buffer[1..];
+        let content_length = match reader.read_var_octet_string_length() {
+            Ok(length) => length,
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let header_length = buffer.len() - reader.len();
+        let packet_length = header_length.saturating_add(content_length);
+        if packet_length > self.max_packet_length {
+            return Err(ParseError::InvalidPacket(format!(
+                "Packet of {} bytes is longer than the maximum of {}",
+                packet_length, self.max_packet_length
+            )));
+        }
+
+        if buffer.len() < packet_length {
+            buffer.reserve(packet_length - buffer.len());
+            return Ok(None);
+        }
+        Packet::try_from(buffer.split_to(packet_length)).map(Some)
+    }
+}
+
+impl Encoder for PacketCodec {
+    type Item = Packet;
+    type Error = ParseError;
+
+    fn encode(&mut self, packet: Packet, buffer: &mut BytesMut) -> Result<(), ParseError> {
+        buffer.extend_from_slice(&BytesMut::from(packet)[..]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{FULFILL, FULFILL_BYTES, PREPARE, PREPARE_BYTES, REJECT, REJECT_BYTES};
+
+    fn all_packet_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PREPARE_BYTES);
+        bytes.extend_from_slice(FULFILL_BYTES);
+        bytes.extend_from_slice(REJECT_BYTES);
+        bytes
+    }
+
+    fn decode_in_chunks(bytes: &[u8], chunk_size: usize) -> Vec<Packet> {
+        let mut codec = PacketCodec::new();
+        let mut buffer = BytesMut::new();
+        let mut packets = Vec::new();
+        for chunk in bytes.chunks(chunk_size) {
+            buffer.extend_from_slice(chunk);
+            while let Some(packet) = codec.decode(&mut buffer).unwrap() {
+                packets.push(packet);
+            }
+        }
+        assert!(buffer.is_empty());
+        packets
+    }
+
+    #[test]
+    fn reassembles_packets_split_across_chunks() {
+        let bytes = all_packet_bytes();
+        let expected = vec![
+            Packet::Prepare(PREPARE.clone()),
+            Packet::Fulfill(FULFILL.clone()),
+            Packet::Reject(REJECT.clone()),
+        ];
+        for chunk_size in &[1, 2, 3, 7, 64, bytes.len()] {
+            assert_eq!(decode_in_chunks(&bytes, *chunk_size), expected);
+        }
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_packet() {
+        let mut codec = PacketCodec::new();
+        let mut buffer = BytesMut::from(&PREPARE_BYTES[..PREPARE_BYTES.len() - 1]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(&PREPARE_BYTES[PREPARE_BYTES.len() - 1..]);
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some(Packet::Prepare(PREPARE.clone()))
+        );
+    }
+
+    #[test]
+    fn rejects_packets_over_max_length() {
+        let mut codec = PacketCodec::new();
+        codec.max_packet_length(100);
+        let mut buffer = BytesMut::from(PREPARE_BYTES);
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_length_prefixes() {
+        for length_prefix in &[0x80, 0x89, 0xff] {
+            let mut codec = PacketCodec::new();
+            let mut buffer = BytesMut::from(&[12, *length_prefix, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+            assert!(codec.decode(&mut buffer).is_err());
+        }
+    }
+
+    #[test]
+    fn encodes_packets() {
+        let mut codec = PacketCodec::new();
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(Packet::Prepare(PREPARE.clone()), &mut buffer)
+            .unwrap();
+        codec
+            .encode(Packet::Fulfill(FULFILL.clone()), &mut buffer)
+            .unwrap();
+        codec
+            .encode(Packet::Reject(REJECT.clone()), &mut buffer)
+            .unwrap();
+        assert_eq!(&buffer[..], &all_packet_bytes()[..]);
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
@@ -48,3 +49,70 @@ quick_error! {
         }
     }
 }
+
+/// A `ParseError` along with the name and byte offset of the packet field
+/// that was being read when it occurred
+#[derive(Debug)]
+pub struct FieldParseError {
+    field: &'static str,
+    offset: usize,
+    error: ParseError,
+}
+
+impl FieldParseError {
+    pub(crate) fn new(field: &'static str, offset: usize, error: ParseError) -> Self {
+        FieldParseError {
+            field,
+            offset,
+            error,
+        }
+    }
+
+    /// The name of the packet field that was being read when parsing failed
+    pub fn field(&self) -> &'static str {
+        self.field
+    }
+
+    /// The byte offset within the packet of the field that failed to parse
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The error that occurred while reading the field
+    pub fn error(&self) -> &ParseError {
+        &self.error
+    }
+}
+
+impl fmt::Display for FieldParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error parsing {} at offset {}: {}",
+            self.field, self.offset, self.error
+        )
+    }
+}
+
+impl std::error::Error for FieldParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<FieldParseError> for ParseError {
+    fn from(error: FieldParseError) -> Self {
+        error.error
+    }
+}
+
+/// Attaches the packet field and offset to the error of a failed read.
+pub(crate) trait FieldResultExt<T> {
+    fn at_field(self, field: &'static str, offset: usize) -> Result<T, FieldParseError>;
+}
+
+impl<T, E: Into<ParseError>> FieldResultExt<T> for Result<T, E> {
+    fn at_field(self, field: &'static str, offset: usize) -> Result<T, FieldParseError> {
+        self.map_err(|err| FieldParseError::new(field, offset, err.into()))
+    }
+}
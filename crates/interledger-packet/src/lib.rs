@@ -3,6 +3,9 @@
 //! Interledger packet serialization/deserialization.
 
 mod address;
+mod address_cache;
+#[cfg(any(feature = "tokio-codec", test))]
+mod codec;
 
 mod error;
 mod errors;
@@ -11,9 +14,12 @@ mod fixtures;
 pub mod oer;
 mod packet;
 
-pub use self::address::{Address, AddressError};
+pub use self::address::{Address, AddressError, AddressScheme, MAX_ADDRESS_LENGTH};
+pub use self::address_cache::AddressCache;
+#[cfg(any(feature = "tokio-codec", test))]
+pub use self::codec::{PacketCodec, DEFAULT_MAX_PACKET_LENGTH};
 pub use self::error::{ErrorClass, ErrorCode};
-pub use self::errors::ParseError;
+pub use self::errors::{FieldParseError, ParseError};
 
 pub use self::packet::MaxPacketAmountDetails;
 pub use self::packet::{Fulfill, Packet, PacketType, Prepare, Reject};
@@ -104,6 +104,12 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
         let length = self.read_u8()?;
         if length & HIGH_BIT != 0 {
             let length_prefix_length = (length & LOWER_SEVEN_BITS) as usize;
+            if length_prefix_length == 0 || length_prefix_length > 8 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "length prefix must be between 1 and 8 bytes",
+                ));
+            }
             // TODO check for canonical length
             Ok(self.read_uint::<BigEndian>(length_prefix_length)? as usize)
         } else {
@@ -117,6 +123,11 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
         let size = self.read_var_octet_string_length()?;
         if size == 0 {
             Err(Error::new(ErrorKind::InvalidData, "zero-length VarUInt"))
+        } else if size > 8 {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                "VarUInt longer than 8 bytes",
+            ))
         } else {
             Ok(self.read_uint::<BigEndian>(size)?)
         }
@@ -260,6 +271,23 @@ mod test_buf_oer_ext {
         );
     }
 
+    #[test]
+    fn test_read_var_octet_string_length_prefix() {
+        let tests: &[Vec<u8>] = &[
+            // The length of the length must be at least 1 byte
+            vec![0x80],
+            // The length must fit in a u64
+            vec![0x89, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09],
+            vec![0xff, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+        ];
+        for buffer in tests {
+            assert_eq!(
+                (&buffer[..]).read_var_octet_string().unwrap_err().kind(),
+                ErrorKind::InvalidData,
+            );
+        }
+    }
+
     #[test]
     fn test_skip() {
         let mut empty = &[][..];
@@ -342,6 +370,11 @@ mod test_buf_oer_ext {
             (vec![0x04], ErrorKind::UnexpectedEof),
             // Enough bytes must be present.
             (vec![0x04, 0x01, 0x02, 0x03], ErrorKind::UnexpectedEof),
+            // VarUInts must fit in a u64.
+            (
+                vec![0x09, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09],
+                ErrorKind::InvalidData,
+            ),
         ];
 
         for (buffer, error_kind) in tests {
@@ -9,8 +9,9 @@ use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{BufMut, BytesMut};
 use chrono::{DateTime, TimeZone, Utc};
 
+use super::errors::{FieldParseError, FieldResultExt};
 use super::oer::{self, BufOerExt, MutBufOerExt};
-use super::{Address, ErrorCode, ParseError};
+use super::{Address, AddressCache, ErrorCode, ParseError};
 use std::convert::TryFrom;
 
 const AMOUNT_LEN: usize = 8;
@@ -136,25 +137,69 @@ impl TryFrom<BytesMut> for Prepare {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
+        Prepare::parse(buffer).map_err(ParseError::from)
+    }
+}
+
+impl Prepare {
+    /// Parse the packet like `try_from` does, but return the name and byte offset
+    /// of the field that could not be parsed along with the error
+    pub fn parse(buffer: BytesMut) -> Result<Self, FieldParseError> {
+        Prepare::parse_destination_with(buffer, None)
+    }
+
+    /// Parse the packet like `parse` does, but look the destination up in the given cache
+    /// so that addresses that were parsed before do not need to be validated again
+    pub fn parse_with_address_cache(
+        buffer: BytesMut,
+        cache: &AddressCache,
+    ) -> Result<Self, FieldParseError> {
+        Prepare::parse_destination_with(buffer, Some(cache))
+    }
+
+    fn parse_destination_with(
+        buffer: BytesMut,
+        cache: Option<&AddressCache>,
+    ) -> Result<Self, FieldParseError> {
         let (content_offset, mut content) = deserialize_envelope(PacketType::Prepare, &buffer)?;
         let content_len = content.len();
-        let amount = content.read_u64::<BigEndian>()?;
+        let amount = content
+            .read_u64::<BigEndian>()
+            .at_field("amount", content_offset)?;
 
+        let expires_at_offset = content_offset + AMOUNT_LEN;
         let mut expires_at = [0x00; 17];
-        content.read_exact(&mut expires_at)?;
-        let expires_at = str::from_utf8(&expires_at[..])?;
-        let expires_at: DateTime<Utc> =
-            Utc.datetime_from_str(&expires_at, INTERLEDGER_TIMESTAMP_FORMAT)?;
+        content
+            .read_exact(&mut expires_at)
+            .at_field("expires_at", expires_at_offset)?;
+        let expires_at =
+            str::from_utf8(&expires_at[..]).at_field("expires_at", expires_at_offset)?;
+        let expires_at: DateTime<Utc> = Utc
+            .datetime_from_str(&expires_at, INTERLEDGER_TIMESTAMP_FORMAT)
+            .at_field("expires_at", expires_at_offset)?;
         let expires_at = SystemTime::from(expires_at);
 
         // Skip execution condition.
-        content.skip(CONDITION_LEN)?;
-
-        let destination = Address::try_from(content.read_var_octet_string()?)?;
+        let condition_offset = expires_at_offset + EXPIRY_LEN;
+        content
+            .skip(CONDITION_LEN)
+            .at_field("execution_condition", condition_offset)?;
+
+        let destination_offset = condition_offset + CONDITION_LEN;
+        let destination = content
+            .read_var_octet_string()
+            .at_field("destination", destination_offset)?;
+        let destination = match cache {
+            Some(cache) => cache.parse(destination),
+            None => Address::try_from(destination),
+        }
+        .at_field("destination", destination_offset)?;
 
         // Skip the data.
         let data_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        content
+            .skip_var_octet_string()
+            .at_field("data", data_offset)?;
 
         Ok(Prepare {
             buffer,
@@ -165,9 +210,7 @@ impl TryFrom<BytesMut> for Prepare {
             data_offset,
         })
     }
-}
 
-impl Prepare {
     #[inline]
     pub fn amount(&self) -> u64 {
         self.amount
@@ -257,13 +300,21 @@ impl fmt::Debug for Prepare {
 }
 
 impl<'a> PrepareBuilder<'a> {
-    pub fn build(&self) -> Prepare {
+    fn content_len(&self) -> usize {
         const STATIC_LEN: usize = AMOUNT_LEN + EXPIRY_LEN + CONDITION_LEN;
         let destination_size = oer::predict_var_octet_string(self.destination.len());
         let data_size = oer::predict_var_octet_string(self.data.len());
-        let content_len = STATIC_LEN + destination_size + data_size;
-        let buf_size = 1 + oer::predict_var_octet_string(content_len);
-        let mut buffer = BytesMut::with_capacity(buf_size);
+        STATIC_LEN + destination_size + data_size
+    }
+
+    /// Returns the length in bytes of the packet this will build.
+    pub fn predicted_len(&self) -> usize {
+        1 + oer::predict_var_octet_string(self.content_len())
+    }
+
+    pub fn build(&self) -> Prepare {
+        let content_len = self.content_len();
+        let mut buffer = BytesMut::with_capacity(self.predicted_len());
 
         buffer.put_u8(PacketType::Prepare as u8);
         buffer.put_var_octet_string_length(content_len);
@@ -281,6 +332,7 @@ impl<'a> PrepareBuilder<'a> {
 
         buffer.put_slice(&self.execution_condition[..]);
         buffer.put_var_octet_string::<&[u8]>(self.destination.as_ref());
+        let data_offset = buffer.len();
         buffer.put_var_octet_string(self.data);
 
         Prepare {
@@ -289,7 +341,7 @@ impl<'a> PrepareBuilder<'a> {
             destination: self.destination.clone(),
             amount: self.amount,
             expires_at: self.expires_at,
-            data_offset: buf_size - data_size,
+            data_offset,
         }
     }
 }
@@ -310,19 +362,29 @@ impl TryFrom<BytesMut> for Fulfill {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
+        Fulfill::parse(buffer).map_err(ParseError::from)
+    }
+}
+
+impl Fulfill {
+    /// Parse the packet like `try_from` does, but return the name and byte offset
+    /// of the field that could not be parsed along with the error
+    pub fn parse(buffer: BytesMut) -> Result<Self, FieldParseError> {
         let (content_offset, mut content) = deserialize_envelope(PacketType::Fulfill, &buffer)?;
 
-        content.skip(FULFILLMENT_LEN)?;
-        content.skip_var_octet_string()?;
+        content
+            .skip(FULFILLMENT_LEN)
+            .at_field("fulfillment", content_offset)?;
+        content
+            .skip_var_octet_string()
+            .at_field("data", content_offset + FULFILLMENT_LEN)?;
 
         Ok(Fulfill {
             buffer,
             content_offset,
         })
     }
-}
 
-impl Fulfill {
     /// The returned value always has a length of 32.
     #[inline]
     pub fn fulfillment(&self) -> &[u8] {
@@ -370,11 +432,18 @@ impl fmt::Debug for Fulfill {
 }
 
 impl<'a> FulfillBuilder<'a> {
+    fn content_len(&self) -> usize {
+        FULFILLMENT_LEN + oer::predict_var_octet_string(self.data.len())
+    }
+
+    /// Returns the length in bytes of the packet this will build.
+    pub fn predicted_len(&self) -> usize {
+        1 + oer::predict_var_octet_string(self.content_len())
+    }
+
     pub fn build(&self) -> Fulfill {
-        let data_size = oer::predict_var_octet_string(self.data.len());
-        let content_len = FULFILLMENT_LEN + data_size;
-        let buf_size = 1 + oer::predict_var_octet_string(content_len);
-        let mut buffer = BytesMut::with_capacity(buf_size);
+        let content_len = self.content_len();
+        let mut buffer = BytesMut::with_capacity(self.predicted_len());
 
         buffer.put_u8(PacketType::Fulfill as u8);
         buffer.put_var_octet_string_length(content_len);
@@ -409,21 +478,38 @@ impl TryFrom<BytesMut> for Reject {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
+        Reject::parse(buffer).map_err(ParseError::from)
+    }
+}
+
+impl Reject {
+    /// Parse the packet like `try_from` does, but return the name and byte offset
+    /// of the field that could not be parsed along with the error
+    pub fn parse(buffer: BytesMut) -> Result<Self, FieldParseError> {
         let (content_offset, mut content) = deserialize_envelope(PacketType::Reject, &buffer)?;
         let content_len = content.len();
 
         let mut code = [0; 3];
-        content.read_exact(&mut code)?;
+        content
+            .read_exact(&mut code)
+            .at_field("code", content_offset)?;
         let code = ErrorCode::new(code);
 
         let triggered_by_offset = content_offset + content_len - content.len();
-        Address::try_from(content.read_var_octet_string()?)?;
+        let triggered_by = content
+            .read_var_octet_string()
+            .at_field("triggered_by", triggered_by_offset)?;
+        Address::try_from(triggered_by).at_field("triggered_by", triggered_by_offset)?;
 
         let message_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        content
+            .skip_var_octet_string()
+            .at_field("message", message_offset)?;
 
         let data_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        content
+            .skip_var_octet_string()
+            .at_field("data", data_offset)?;
 
         Ok(Reject {
             buffer,
@@ -433,9 +519,18 @@ impl TryFrom<BytesMut> for Reject {
             data_offset,
         })
     }
-}
 
-impl Reject {
+    /// Build an `F02 Unreachable` reject, which is returned when there is no route to the destination.
+    pub fn unreachable(triggered_by: &Address, message: &str) -> Self {
+        RejectBuilder {
+            code: ErrorCode::F02_UNREACHABLE,
+            message: message.as_bytes(),
+            triggered_by: Some(triggered_by),
+            data: &[],
+        }
+        .build()
+    }
+
     #[inline]
     pub fn code(&self) -> ErrorCode {
         self.code
@@ -497,33 +592,42 @@ impl fmt::Debug for Reject {
 }
 
 impl<'a> RejectBuilder<'a> {
-    pub fn build(&self) -> Reject {
-        let (trigerred_by_message, len) = match self.triggered_by {
-            Some(ref msg) => (msg.as_ref(), msg.len()),
-            None => {
-                let empty_msg: &[u8] = &[];
-                (empty_msg, 0)
-            }
-        };
-        let triggered_by_size = oer::predict_var_octet_string(len);
+    fn content_len(&self) -> usize {
+        let triggered_by_len = self.triggered_by.map(|address| address.len()).unwrap_or(0);
+        let triggered_by_size = oer::predict_var_octet_string(triggered_by_len);
         let message_size = oer::predict_var_octet_string(self.message.len());
         let data_size = oer::predict_var_octet_string(self.data.len());
-        let content_len = ERROR_CODE_LEN + triggered_by_size + message_size + data_size;
-        let buf_size = 1 + oer::predict_var_octet_string(content_len);
-        let mut buffer = BytesMut::with_capacity(buf_size);
+        ERROR_CODE_LEN + triggered_by_size + message_size + data_size
+    }
+
+    /// Returns the length in bytes of the packet this will build.
+    pub fn predicted_len(&self) -> usize {
+        1 + oer::predict_var_octet_string(self.content_len())
+    }
+
+    pub fn build(&self) -> Reject {
+        let trigerred_by_message: &[u8] = match self.triggered_by {
+            Some(ref msg) => msg.as_ref(),
+            None => &[],
+        };
+        let content_len = self.content_len();
+        let mut buffer = BytesMut::with_capacity(self.predicted_len());
 
         buffer.put_u8(PacketType::Reject as u8);
         buffer.put_var_octet_string_length(content_len);
         buffer.put_slice(&<[u8; 3]>::from(self.code)[..]);
+        let triggered_by_offset = buffer.len();
         buffer.put_var_octet_string::<&[u8]>(trigerred_by_message);
+        let message_offset = buffer.len();
         buffer.put_var_octet_string(self.message);
+        let data_offset = buffer.len();
         buffer.put_var_octet_string(self.data);
         Reject {
             buffer,
             code: self.code,
-            triggered_by_offset: buf_size - data_size - message_size - triggered_by_size,
-            message_offset: buf_size - data_size - message_size,
-            data_offset: buf_size - data_size,
+            triggered_by_offset,
+            message_offset,
+            data_offset,
         }
     }
 }
@@ -531,23 +635,25 @@ impl<'a> RejectBuilder<'a> {
 fn deserialize_envelope(
     packet_type: PacketType,
     mut reader: &[u8],
-) -> Result<(usize, &[u8]), ParseError> {
-    let got_type = reader.read_u8()?;
+) -> Result<(usize, &[u8]), FieldParseError> {
+    let got_type = reader.read_u8().at_field("type", 0)?;
     if got_type == packet_type as u8 {
         let content_offset = 1 + {
             // This could probably be determined a better way...
             let mut peek = &reader[..];
             let before = peek.len();
-            peek.read_var_octet_string_length()?;
+            peek.read_var_octet_string_length()
+                .at_field("contents", 1)?;
             before - peek.len()
         };
-        let content = reader.peek_var_octet_string()?;
+        let content = reader.peek_var_octet_string().at_field("contents", 1)?;
         Ok((content_offset, content))
     } else {
-        Err(ParseError::InvalidPacket(format!(
-            "Unexpected packet type: {:?}",
-            got_type,
-        )))
+        Err(FieldParseError::new(
+            "type",
+            0,
+            ParseError::InvalidPacket(format!("Unexpected packet type: {:?}", got_type)),
+        ))
     }
 }
 
@@ -654,11 +760,59 @@ mod test_prepare {
     use super::*;
     use crate::fixtures::{self, PREPARE, PREPARE_BUILDER, PREPARE_BYTES};
 
+    #[test]
+    fn test_predicted_len() {
+        assert_eq!(
+            PREPARE_BUILDER.predicted_len(),
+            BytesMut::from(PREPARE_BUILDER.build()).len()
+        );
+
+        let data = vec![0; 1000];
+        let builder = PrepareBuilder {
+            data: &data[..],
+            ..PREPARE_BUILDER.clone()
+        };
+        assert_eq!(
+            builder.predicted_len(),
+            BytesMut::from(builder.build()).len()
+        );
+    }
+
     #[test]
     fn test_invalid_address() {
         let mut prep = BytesMut::from(PREPARE_BYTES);
         prep[67] = 42; // convert a byte from the address to a junk character
-        assert!(Prepare::try_from(prep).is_err());
+        let error = Prepare::parse(prep.clone()).unwrap_err();
+        assert_eq!(error.field(), "destination");
+        assert_eq!(error.offset(), 61);
+        match error.error() {
+            ParseError::InvalidAddress(_) => {}
+            other => panic!("Unexpected error: {:?}", other),
+        }
+        match Prepare::try_from(prep).unwrap_err() {
+            ParseError::InvalidAddress(_) => {}
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncated_reports_offset() {
+        // The contents are cut off in the middle of the destination address,
+        // but the length prefix is consistent with the truncated contents
+        let mut prep = BytesMut::from(&[PacketType::Prepare as u8, 0x81, 66][..]);
+        prep.extend_from_slice(&PREPARE_BYTES[4..70]);
+        let error = Prepare::parse(prep).unwrap_err();
+        assert_eq!(error.field(), "destination");
+        assert_eq!(error.offset(), 60);
+
+        // The length prefix claims more bytes than the buffer has
+        let error = Prepare::parse(BytesMut::from(&PREPARE_BYTES[..70])).unwrap_err();
+        assert_eq!(error.field(), "contents");
+        assert_eq!(error.offset(), 1);
+        match Prepare::try_from(BytesMut::from(&PREPARE_BYTES[..70])).unwrap_err() {
+            ParseError::Io(_) => {}
+            other => panic!("Unexpected error: {:?}", other),
+        }
     }
 
     #[test]
@@ -757,7 +911,25 @@ mod test_prepare {
 #[cfg(test)]
 mod test_fulfill {
     use super::*;
-    use crate::fixtures::{self, FULFILL, FULFILL_BYTES};
+    use crate::fixtures::{self, FULFILL, FULFILL_BUILDER, FULFILL_BYTES};
+
+    #[test]
+    fn test_predicted_len() {
+        assert_eq!(
+            FULFILL_BUILDER.predicted_len(),
+            BytesMut::from(FULFILL_BUILDER.build()).len()
+        );
+
+        let data = vec![0; 1000];
+        let builder = FulfillBuilder {
+            data: &data[..],
+            ..FULFILL_BUILDER.clone()
+        };
+        assert_eq!(
+            builder.predicted_len(),
+            BytesMut::from(builder.build()).len()
+        );
+    }
 
     #[test]
     fn test_try_from() {
@@ -814,6 +986,26 @@ mod test_fulfill {
 mod test_reject {
     use super::*;
     use crate::fixtures::{self, REJECT, REJECT_BUILDER, REJECT_BYTES};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_predicted_len() {
+        assert_eq!(
+            REJECT_BUILDER.predicted_len(),
+            BytesMut::from(REJECT_BUILDER.build()).len()
+        );
+
+        let data = vec![0; 1000];
+        let builder = RejectBuilder {
+            data: &data[..],
+            triggered_by: None,
+            ..REJECT_BUILDER.clone()
+        };
+        assert_eq!(
+            builder.predicted_len(),
+            BytesMut::from(builder.build()).len()
+        );
+    }
 
     #[test]
     fn test_try_from() {
@@ -853,6 +1045,16 @@ mod test_reject {
         assert_eq!(REJECT.message(), REJECT_BUILDER.message);
     }
 
+    #[test]
+    fn test_unreachable() {
+        let address = Address::from_str("example.connector").unwrap();
+        let reject = Reject::unreachable(&address, "No route found");
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+        assert_eq!(reject.message(), b"No route found");
+        assert_eq!(reject.triggered_by(), Some(address));
+        assert!(reject.data().is_empty());
+    }
+
     #[test]
     fn test_triggered_by() {
         assert_eq!(REJECT.triggered_by().as_ref(), REJECT_BUILDER.triggered_by);
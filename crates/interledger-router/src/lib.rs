@@ -16,19 +16,188 @@
 extern crate log;
 
 use bytes::Bytes;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use interledger_packet::Address;
 use interledger_service::{Account, AccountStore};
+use std::{str, str::FromStr, sync::Arc};
 
 mod router;
 
-pub use self::router::Router;
+pub use self::router::{RouteInfo, Router};
+
+/// One route in an exported routing table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteEntry<I> {
+    pub prefix: String,
+    pub account_id: I,
+    /// The route's cost, or 0 if the store does not keep track of route costs
+    pub cost: u32,
+}
+
+/// Check that routes can be imported into a routing table: every prefix must be
+/// empty (the catch-all route) or a valid ILP address, and no prefix may appear twice.
+pub fn validate_route_entries<I>(entries: &[RouteEntry<I>]) -> Result<(), String> {
+    let mut prefixes = HashSet::with_capacity(entries.len());
+    for entry in entries {
+        if !entry.prefix.is_empty() && Address::from_str(&entry.prefix).is_err() {
+            return Err(format!("Invalid route prefix: {}", entry.prefix));
+        }
+        if !prefixes.insert(entry.prefix.as_str()) {
+            return Err(format!("Duplicate route prefix: {}", entry.prefix));
+        }
+    }
+    Ok(())
+}
+
+/// Check that a route does not send packets straight back to this node. A route is rejected
+/// if its next hop is this node itself, or if its prefix is this node's own address or one of
+/// its ancestors, because packets for this node would be forwarded to a peer that routes them
+/// back here. The catch-all (empty) prefix is allowed.
+pub fn check_route_loop(
+    prefix: &str,
+    next_hop_address: &Address,
+    own_address: &Address,
+) -> Result<(), String> {
+    if next_hop_address == own_address {
+        return Err(format!(
+            "Route for prefix: {} points back to this node ({})",
+            prefix, own_address
+        ));
+    }
+    if let Ok(prefix_address) = Address::from_str(prefix) {
+        if own_address.starts_with(&prefix_address) {
+            return Err(format!(
+                "Route for prefix: {} includes this node's own address ({}) and would forward its packets to {}",
+                prefix, own_address, next_hop_address
+            ));
+        }
+    }
+    Ok(())
+}
 
 /// A trait for Store implmentations that have ILP routing tables.
 pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
-    /// **Synchronously** return a copy of the routing table.
+    /// **Synchronously** return a snapshot of the routing table.
     /// Note that this is synchronous because it assumes that Stores should
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
-    // TODO avoid using HashMap because it means it'll be cloned a lot
-    fn routing_table(&self) -> HashMap<Bytes, <Self::Account as Account>::AccountId>;
+    /// Stores should replace the whole table when it changes, so that returning it only
+    /// clones the `Arc` rather than the table.
+    fn routing_table(&self) -> Arc<HashMap<Bytes, <Self::Account as Account>::AccountId>>;
+
+    /// **Synchronously** return a snapshot of the additional next hops for any prefixes in
+    /// the routing table that have multiple equally good routes. These are used by a `Router`
+    /// with equal-cost multipath enabled, which spreads packets across all of the
+    /// next hops for a prefix rather than always using the one in the routing table.
+    fn equal_cost_routes(&self) -> Arc<HashMap<Bytes, Vec<<Self::Account as Account>::AccountId>>> {
+        Arc::new(HashMap::new())
+    }
+
+    /// **Synchronously** return the cost of the route for the given routing table prefix,
+    /// for example the length of the path it was advertised with.
+    /// This is only used for diagnostics, such as `Router::resolve`.
+    fn route_cost(&self, _prefix: &[u8]) -> Option<u32> {
+        None
+    }
+
+    /// **Synchronously** choose which of several equally good next hops a packet should be
+    /// forwarded to, for example to prefer one in the same currency as the incoming account.
+    /// `next_hops[0]` is the next hop from the routing table. Returns an index into `next_hops`.
+    /// This is only used when equal-cost multipath is disabled.
+    /// By default, the next hop from the routing table is used.
+    fn select_next_hop(
+        &self,
+        _from: &Self::Account,
+        _amount: u64,
+        _next_hops: &[Self::Account],
+    ) -> usize {
+        0
+    }
+
+    /// **Synchronously** return a copy of the routing table as a list of entries sorted
+    /// by prefix, including the route costs, for example to back it up.
+    fn export_routes(&self) -> Vec<RouteEntry<<Self::Account as Account>::AccountId>> {
+        let mut entries: Vec<_> = self
+            .routing_table()
+            .iter()
+            .map(|(prefix, account_id)| (prefix.clone(), *account_id))
+            .filter_map(|(prefix, account_id)| {
+                let cost = self.route_cost(&prefix[..]).unwrap_or(0);
+                str::from_utf8(&prefix[..]).ok().map(|prefix| RouteEntry {
+                    prefix: prefix.to_string(),
+                    account_id,
+                    cost,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(prefix: &str) -> RouteEntry<u64> {
+        RouteEntry {
+            prefix: prefix.to_string(),
+            account_id: 1,
+            cost: 0,
+        }
+    }
+
+    #[test]
+    fn accepts_catch_all_and_address_prefixes() {
+        assert!(validate_route_entries(&[entry(""), entry("example.alice")]).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_and_duplicate_prefixes() {
+        assert_eq!(
+            validate_route_entries(&[entry("not an address")]).unwrap_err(),
+            "Invalid route prefix: not an address"
+        );
+        assert_eq!(
+            validate_route_entries(&[entry("example.alice"), entry("example.alice")]).unwrap_err(),
+            "Duplicate route prefix: example.alice"
+        );
+    }
+
+    #[test]
+    fn rejects_self_referential_routes() {
+        let own_address = Address::from_str("example.connector").unwrap();
+        assert_eq!(
+            check_route_loop("example.alice", &own_address, &own_address).unwrap_err(),
+            "Route for prefix: example.alice points back to this node (example.connector)"
+        );
+        assert_eq!(
+            check_route_loop(
+                "example.connector",
+                &Address::from_str("example.bob").unwrap(),
+                &own_address
+            )
+            .unwrap_err(),
+            "Route for prefix: example.connector includes this node's own address (example.connector) and would forward its packets to example.bob"
+        );
+    }
+
+    #[test]
+    fn rejects_routes_for_ancestors_of_own_address() {
+        let own_address = Address::from_str("example.connector.child").unwrap();
+        let bob = Address::from_str("example.bob").unwrap();
+        assert!(check_route_loop("example.connector", &bob, &own_address).is_err());
+        // Sibling prefixes do not include the node's address
+        assert!(check_route_loop("example.connector.childx", &bob, &own_address).is_ok());
+        assert!(check_route_loop("example.connectorx", &bob, &own_address).is_ok());
+    }
+
+    #[test]
+    fn accepts_routes_to_other_nodes() {
+        let own_address = Address::from_str("example.connector").unwrap();
+        let bob = Address::from_str("example.bob").unwrap();
+        assert!(check_route_loop("example.bob", &bob, &own_address).is_ok());
+        assert!(check_route_loop("", &bob, &own_address).is_ok());
+        assert!(check_route_loop("example.connector.alice", &bob, &own_address).is_ok());
+    }
 }
@@ -1,9 +1,20 @@
 use super::RouterStore;
-use bytes::Bytes;
-use futures::{future::err, Future};
-use interledger_packet::{ErrorCode, RejectBuilder};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    future::{err, join_all, Either},
+    Future,
+};
+use hashbrown::HashMap;
+use interledger_packet::{Address, AddressCache, ErrorCode, ParseError, Prepare, RejectBuilder};
 use interledger_service::*;
-use std::str;
+use std::{
+    fmt::Display,
+    str,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 /// # Interledger Router
 ///
@@ -26,6 +37,9 @@ use std::str;
 pub struct Router<S, O> {
     store: S,
     next: O,
+    equal_cost_multipath: bool,
+    next_path: Arc<AtomicUsize>,
+    address_cache: Option<Arc<AddressCache>>,
 }
 
 impl<S, O> Router<S, O>
@@ -34,8 +48,109 @@ where
     O: OutgoingService<S::Account>,
 {
     pub fn new(store: S, next: O) -> Self {
-        Router { store, next }
+        Router {
+            store,
+            next,
+            equal_cost_multipath: false,
+            next_path: Arc::new(AtomicUsize::new(0)),
+            address_cache: None,
+        }
+    }
+
+    /// Distribute packets round-robin across all of the next hops the store
+    /// returns from `equal_cost_routes` for the matching prefix.
+    /// By default, the router always uses the next hop in the routing table.
+    pub fn equal_cost_multipath(&mut self, enabled: bool) -> &mut Self {
+        self.equal_cost_multipath = enabled;
+        self
+    }
+
+    /// Cache the destinations of the Prepare packets parsed with `parse_prepare`, so that
+    /// repeated destinations do not need to be validated again. By default, nothing is cached.
+    pub fn address_cache(&mut self, cache: Arc<AddressCache>) -> &mut Self {
+        self.address_cache = Some(cache);
+        self
+    }
+
+    /// Parse a Prepare packet that is about to be routed, using the router's address cache if it has one
+    pub fn parse_prepare(&self, buffer: BytesMut) -> Result<Prepare, ParseError> {
+        match self.address_cache {
+            Some(ref cache) => Prepare::parse_with_address_cache(buffer, cache),
+            None => Prepare::parse(buffer),
+        }
+        .map_err(ParseError::from)
+    }
+
+    /// Look up where a packet to the given destination would be forwarded, without sending one.
+    /// This uses the same longest-prefix match as routing a packet but does not apply
+    /// equal-cost multipath or the store's next hop selection, which depend on the packet.
+    pub fn resolve(
+        &self,
+        destination: &Address,
+    ) -> Option<RouteInfo<<S::Account as Account>::AccountId>> {
+        let routing_table = self.store.routing_table();
+        find_route(&routing_table, destination).map(|(account_id, matched_prefix)| RouteInfo {
+            account_id,
+            cost: self.store.route_cost(&matched_prefix),
+            matched_prefix,
+        })
+    }
+}
+
+/// The route a packet to a given destination would take, as returned by `Router::resolve`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo<I> {
+    /// The ID of the next-hop account
+    pub account_id: I,
+    /// The routing table prefix that matched the destination (empty for a catch-all route)
+    pub matched_prefix: Bytes,
+    /// The cost of the route, if the store knows it
+    pub cost: Option<u32>,
+}
+
+/// Find the longest prefix in the routing table that the destination starts with.
+/// Exact matches are checked first and an empty prefix matches every destination.
+fn find_route<I: Copy + Display>(
+    routing_table: &HashMap<Bytes, I>,
+    destination: &Address,
+) -> Option<(I, Bytes)> {
+    // Check if we have a direct path for that account or if we need to scan
+    // through the routing table
+    let dest: &[u8] = destination.as_ref();
+    if let Some(account_id) = routing_table.get(dest) {
+        trace!(
+            "Found direct route for address: \"{}\". Account: {}",
+            destination,
+            account_id
+        );
+        return Some((*account_id, destination.to_bytes()));
     }
+
+    let mut next_hop = None;
+    let mut matching_prefix = Bytes::new();
+    for (prefix, account_id) in routing_table.iter() {
+        trace!(
+            "Checking route: \"{}\" -> {}",
+            str::from_utf8(&prefix[..]).unwrap_or("<not utf8>"),
+            account_id
+        );
+        // Check if the route prefix matches or is empty (meaning it's a catch-all address)
+        if (prefix.is_empty() || dest.starts_with(&prefix[..]))
+            && prefix.len() >= matching_prefix.len()
+        {
+            next_hop.replace(*account_id);
+            matching_prefix = prefix.clone();
+        }
+    }
+    if let Some(account_id) = next_hop {
+        trace!(
+            "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
+            destination,
+            str::from_utf8(&matching_prefix[..]).unwrap_or("<not utf8>"),
+            account_id,
+        );
+    }
+    next_hop.map(|account_id| (account_id, matching_prefix))
 }
 
 impl<S, O> IncomingService<S::Account> for Router<S, O>
@@ -52,67 +167,103 @@ where
     /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
     fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> Self::Future {
         let destination = request.prepare.destination();
-        let mut next_hop = None;
         let routing_table = self.store.routing_table();
+        if routing_table.is_empty() {
+            error!("Unable to route request because routing table is empty");
+        }
+        let (mut next_hop, matching_prefix) = match find_route(&routing_table, &destination) {
+            Some((account_id, prefix)) => (Some(account_id), prefix),
+            None => (None, Bytes::new()),
+        };
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
-        let dest: &[u8] = destination.as_ref();
-        if let Some(account_id) = routing_table.get(dest) {
-            trace!(
-                "Found direct route for address: \"{}\". Account: {}",
-                destination,
-                account_id
-            );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = Bytes::new();
-            for route in self.store.routing_table() {
-                trace!(
-                    "Checking route: \"{}\" -> {}",
-                    str::from_utf8(&route.0[..]).unwrap_or("<not utf8>"),
-                    route.1
-                );
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (route.0.is_empty() || dest.starts_with(&route.0[..]))
-                    && route.0.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(route.1);
-                    matching_prefix = route.0;
+        // If there are other equally good next hops for this prefix, either distribute packets
+        // across them or let the store pick the most suitable one for this packet
+        let mut candidates = Vec::new();
+        if let Some(account_id) = next_hop {
+            if let Some(other_hops) = self.store.equal_cost_routes().get(&matching_prefix) {
+                candidates.push(account_id);
+                candidates.extend(other_hops.iter().filter(|id| **id != account_id));
+                if self.equal_cost_multipath {
+                    let index = self.next_path.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                    trace!(
+                        "Using path {} of {} equal-cost routes for prefix: \"{}\"",
+                        index + 1,
+                        candidates.len(),
+                        str::from_utf8(&matching_prefix[..]).unwrap_or("<not utf8>"),
+                    );
+                    next_hop = Some(candidates[index]);
+                    candidates.clear();
                 }
             }
-            if let Some(account_id) = next_hop {
-                trace!(
-                    "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
-                    destination,
-                    str::from_utf8(&matching_prefix[..]).unwrap_or("<not utf8>"),
-                    account_id,
-                );
-            }
-        } else {
-            error!("Unable to route request because routing table is empty");
         }
 
         if let Some(account_id) = next_hop {
             let mut next = self.next.clone();
-            Box::new(
-                self.store
-                    .get_accounts(vec![account_id])
-                    .map_err(move |_| {
-                        error!("No record found for account: {}", account_id);
-                        RejectBuilder {
-                            code: ErrorCode::F02_UNREACHABLE,
-                            message: &[],
-                            triggered_by: None,
-                            data: &[],
-                        }
-                        .build()
-                    })
-                    .and_then(move |mut accounts| {
-                        let request = request.into_outgoing(accounts.remove(0));
-                        next.send_request(request)
-                    }),
-            )
+            let store = self.store.clone();
+            let matched_prefix = matching_prefix.clone();
+            let account_ids = if candidates.len() > 1 {
+                candidates
+            } else {
+                vec![account_id]
+            };
+            // Load each candidate separately so that one that cannot be loaded
+            // does not stop the packet from being routed through the others
+            let loads: Vec<_> = account_ids
+                .iter()
+                .map(|account_id| {
+                    let account_id = *account_id;
+                    self.store
+                        .get_accounts(vec![account_id])
+                        .then(move |result| -> Result<_, ()> {
+                            match result {
+                                Ok(mut accounts) => Ok(accounts.pop()),
+                                Err(_) => {
+                                    warn!("No record found for account: {}", account_id);
+                                    Ok(None)
+                                }
+                            }
+                        })
+                })
+                .collect();
+            Box::new(join_all(loads).then(move |result| {
+                let mut accounts: Vec<S::Account> =
+                    result.unwrap_or_default().into_iter().flatten().collect();
+                if accounts.is_empty() {
+                    error!("No record found for accounts: {:?}", account_ids);
+                    return Either::A(err(RejectBuilder {
+                        code: ErrorCode::F02_UNREACHABLE,
+                        message: &[],
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build()));
+                }
+                let index = if accounts.len() > 1 {
+                    let index =
+                        store.select_next_hop(&request.from, request.prepare.amount(), &accounts);
+                    if index > 0 && index < accounts.len() {
+                        trace!(
+                            "Store selected next hop: {} instead of: {}",
+                            accounts[index].id(),
+                            accounts[0].id()
+                        );
+                        index
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                };
+                // Only the addresses are logged, never the packet data
+                debug!(
+                    "Routing packet for: {} via prefix: \"{}\" to account: {}",
+                    request.prepare.destination(),
+                    str::from_utf8(&matched_prefix[..]).unwrap_or("<not utf8>"),
+                    accounts[index].id()
+                );
+                let request = request.into_outgoing(accounts.swap_remove(index));
+                Either::B(next.send_request(request))
+            }))
         } else {
             error!("No route found for request: {:?}", request);
             Box::new(err(RejectBuilder {
@@ -129,10 +280,13 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::RouteEntry;
     use futures::future::ok;
     use hashbrown::HashMap;
-    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_packet::{Address, Fulfill, FulfillBuilder, PrepareBuilder, Reject};
     use interledger_service::outgoing_service_fn;
+    use interledger_service::test_support::{init_test_logger, logged_messages};
+    use log::Level;
     use parking_lot::Mutex;
     use std::iter::FromIterator;
     use std::str::FromStr;
@@ -152,6 +306,9 @@ mod tests {
     #[derive(Clone)]
     struct TestStore {
         routes: HashMap<Bytes, u64>,
+        equal_cost_routes: HashMap<Bytes, Vec<u64>>,
+        preferred_next_hop: Option<u64>,
+        missing_accounts: Vec<u64>,
     }
 
     impl AccountStore for TestStore {
@@ -161,13 +318,44 @@ mod tests {
             &self,
             account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
         ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
-            Box::new(ok(account_ids.into_iter().map(TestAccount).collect()))
+            if account_ids
+                .iter()
+                .any(|id| self.missing_accounts.contains(id))
+            {
+                Box::new(err(()))
+            } else {
+                Box::new(ok(account_ids.into_iter().map(TestAccount).collect()))
+            }
         }
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> HashMap<Bytes, u64> {
-            self.routes.clone()
+        fn routing_table(&self) -> Arc<HashMap<Bytes, u64>> {
+            Arc::new(self.routes.clone())
+        }
+
+        fn equal_cost_routes(&self) -> Arc<HashMap<Bytes, Vec<u64>>> {
+            Arc::new(self.equal_cost_routes.clone())
+        }
+
+        fn route_cost(&self, prefix: &[u8]) -> Option<u32> {
+            // Treat every configured route as a direct peer
+            if self.routes.contains_key(prefix) {
+                Some(1)
+            } else {
+                None
+            }
+        }
+
+        fn select_next_hop(
+            &self,
+            _from: &TestAccount,
+            _amount: u64,
+            next_hops: &[TestAccount],
+        ) -> usize {
+            self.preferred_next_hop
+                .and_then(|preferred| next_hops.iter().position(|account| account.0 == preferred))
+                .unwrap_or(0)
         }
     }
 
@@ -176,6 +364,9 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::new(),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -197,6 +388,7 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -207,6 +399,9 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::from_iter(vec![(Bytes::from("example.other"), 1)].into_iter()),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -228,6 +423,7 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
@@ -240,6 +436,9 @@ mod tests {
                 routes: HashMap::from_iter(
                     vec![(Bytes::from("example.destination"), 1)].into_iter(),
                 ),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -261,6 +460,7 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_ok());
@@ -271,6 +471,9 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::from_iter(vec![(Bytes::from(""), 0)].into_iter()),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -292,6 +495,7 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_ok());
@@ -302,6 +506,9 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)].into_iter()),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -323,6 +530,7 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_ok());
@@ -342,6 +550,9 @@ mod tests {
                     ]
                     .into_iter(),
                 ),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
             },
             outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
                 *to_clone.lock() = Some(request.to.clone());
@@ -365,9 +576,344 @@ mod tests {
                     data: &[],
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, 2);
     }
+
+    #[test]
+    fn logs_chosen_route_at_debug() {
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![
+                    (Bytes::from(""), 0),
+                    (Bytes::from("example.logged"), 7),
+                ]),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        init_test_logger();
+
+        router
+            .handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.logged.bob").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: b"secret payload",
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap();
+
+        let messages: Vec<String> = logged_messages(Level::Debug)
+            .into_iter()
+            .filter(|message| message.contains("example.logged.bob"))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Routing packet for: example.logged.bob via prefix: \"example.logged\" to account: 7"
+                    .to_string()
+            ]
+        );
+        assert!(!messages[0].contains("secret payload"));
+    }
+
+    fn send_to(
+        router: &mut Router<TestStore, impl OutgoingService<TestAccount> + Clone + Send + 'static>,
+    ) {
+        router
+            .handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn exports_sorted_routes_with_costs() {
+        let store = TestStore {
+            routes: HashMap::from_iter(vec![
+                (Bytes::from("example.other"), 2),
+                (Bytes::from(""), 0),
+                (Bytes::from("example.destination"), 1),
+            ]),
+            equal_cost_routes: HashMap::new(),
+            preferred_next_hop: None,
+            missing_accounts: Vec::new(),
+        };
+        assert_eq!(
+            store.export_routes(),
+            vec![
+                RouteEntry {
+                    prefix: "".to_string(),
+                    account_id: 0,
+                    cost: 1,
+                },
+                RouteEntry {
+                    prefix: "example.destination".to_string(),
+                    account_id: 1,
+                    cost: 1,
+                },
+                RouteEntry {
+                    prefix: "example.other".to_string(),
+                    account_id: 2,
+                    cost: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_matching_route() {
+        let router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![
+                    (Bytes::from(""), 0),
+                    (Bytes::from("example.destination"), 1),
+                    (Bytes::from("example.other"), 2),
+                ]),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(|_| -> Result<_, _> { panic!("should not send a packet") }),
+        );
+
+        assert_eq!(
+            router.resolve(&Address::from_str("example.destination.sub").unwrap()),
+            Some(RouteInfo {
+                account_id: 1,
+                matched_prefix: Bytes::from("example.destination"),
+                cost: Some(1),
+            })
+        );
+        assert_eq!(
+            router
+                .resolve(&Address::from_str("example.unknown").unwrap())
+                .map(|route| route.account_id),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn resolves_unmatched_destination() {
+        let router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example.other"), 1)]),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(|_| -> Result<_, _> { panic!("should not send a packet") }),
+        );
+
+        assert!(router
+            .resolve(&Address::from_str("example.destination").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn distributes_across_equal_cost_routes() {
+        let to: Arc<Mutex<Vec<u64>>> = Arc::new(Mutex::new(Vec::new()));
+        let to_clone = to.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)]),
+                equal_cost_routes: HashMap::from_iter(vec![(Bytes::from("example."), vec![1, 2])]),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                to_clone.lock().push(request.to.0);
+
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        router.equal_cost_multipath(true);
+
+        for _ in 0..4 {
+            send_to(&mut router);
+        }
+        assert_eq!(*to.lock(), vec![1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn uses_first_match_without_equal_cost_multipath() {
+        let to: Arc<Mutex<Vec<u64>>> = Arc::new(Mutex::new(Vec::new()));
+        let to_clone = to.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)]),
+                equal_cost_routes: HashMap::from_iter(vec![(Bytes::from("example."), vec![1, 2])]),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                to_clone.lock().push(request.to.0);
+
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        for _ in 0..4 {
+            send_to(&mut router);
+        }
+        assert_eq!(*to.lock(), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn store_selects_among_equal_cost_routes() {
+        let to: Arc<Mutex<Vec<u64>>> = Arc::new(Mutex::new(Vec::new()));
+        let to_clone = to.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)]),
+                equal_cost_routes: HashMap::from_iter(vec![(Bytes::from("example."), vec![1, 2])]),
+                preferred_next_hop: Some(2),
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                to_clone.lock().push(request.to.0);
+
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        for _ in 0..2 {
+            send_to(&mut router);
+        }
+        assert_eq!(*to.lock(), vec![2, 2]);
+    }
+
+    #[test]
+    fn skips_equal_cost_routes_that_cannot_be_loaded() {
+        let to: Arc<Mutex<Vec<u64>>> = Arc::new(Mutex::new(Vec::new()));
+        let to_clone = to.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)]),
+                equal_cost_routes: HashMap::from_iter(vec![(Bytes::from("example."), vec![1, 2])]),
+                preferred_next_hop: None,
+                missing_accounts: vec![1],
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                to_clone.lock().push(request.to.0);
+
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        for _ in 0..2 {
+            send_to(&mut router);
+        }
+        assert_eq!(*to.lock(), vec![2, 2]);
+    }
+
+    #[test]
+    fn rejects_when_no_next_hop_can_be_loaded() {
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example."), 1)]),
+                equal_cost_routes: HashMap::from_iter(vec![(Bytes::from("example."), vec![1, 2])]),
+                preferred_next_hop: None,
+                missing_accounts: vec![1, 2],
+            },
+            outgoing_service_fn(
+                |_: OutgoingRequest<TestAccount>| -> Result<Fulfill, Reject> { unreachable!() },
+            ),
+        );
+
+        let reject = router
+            .handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+    }
+
+    #[test]
+    fn parses_repeated_destinations_with_address_cache() {
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::new(),
+                equal_cost_routes: HashMap::new(),
+                preferred_next_hop: None,
+                missing_accounts: Vec::new(),
+            },
+            outgoing_service_fn(
+                |_: OutgoingRequest<TestAccount>| -> Result<Fulfill, Reject> { unreachable!() },
+            ),
+        );
+        let cache = Arc::new(AddressCache::new(10));
+        router.address_cache(cache.clone());
+        let prepare: BytesMut = PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            execution_condition: &[1; 32],
+            expires_at: UNIX_EPOCH,
+            data: &[],
+        }
+        .build()
+        .into();
+
+        for _ in 0..100 {
+            let parsed = router.parse_prepare(prepare.clone()).unwrap();
+            assert_eq!(&*parsed.destination(), "example.destination");
+        }
+        // The destination was only validated the first time
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), 99);
+    }
 }
@@ -0,0 +1,234 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+pub trait EnabledAccount: Account {
+    /// Whether packets may currently be sent to or received from this account.
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+/// # Account Status Service
+///
+/// Incoming or Outgoing Service responsible for rejecting packets to and from accounts
+/// that have been disabled by the node operator.
+/// Disabling an account only stops new packets; its balance is left untouched so that
+/// any outstanding settlements can still be reconciled and the account can be re-enabled later.
+/// Requires an `EnabledAccount` and _no store_.
+#[derive(Clone)]
+pub struct AccountStatusService<IO, A> {
+    ilp_address: Address,
+    next: IO,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> AccountStatusService<I, A>
+where
+    I: IncomingService<A>,
+    A: EnabledAccount,
+{
+    pub fn incoming(ilp_address: Address, next: I) -> Self {
+        AccountStatusService {
+            ilp_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<O, A> AccountStatusService<O, A>
+where
+    O: OutgoingService<A>,
+    A: EnabledAccount,
+{
+    pub fn outgoing(ilp_address: Address, next: O) -> Self {
+        AccountStatusService {
+            ilp_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<I, A> IncomingService<A> for AccountStatusService<I, A>
+where
+    I: IncomingService<A>,
+    A: EnabledAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. If the account the request came from is disabled, reject it with `T03 Connector Busy`
+    /// 1. Otherwise, pass the request to the next service
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        if request.from.enabled() {
+            Box::new(self.next.handle_request(request))
+        } else {
+            debug!(
+                "Rejecting packet from disabled account {}",
+                request.from.id()
+            );
+            Box::new(err(RejectBuilder {
+                code: ErrorCode::T03_CONNECTOR_BUSY,
+                message: b"Account is disabled",
+                triggered_by: Some(&self.ilp_address),
+                data: &[],
+            }
+            .build()))
+        }
+    }
+}
+
+impl<O, A> OutgoingService<A> for AccountStatusService<O, A>
+where
+    O: OutgoingService<A>,
+    A: EnabledAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. If the account the request is going to is disabled, reject it with `F02 Unreachable`
+    /// 1. Otherwise, pass the request to the next service
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        if request.to.enabled() {
+            Box::new(self.next.send_request(request))
+        } else {
+            debug!("Rejecting packet to disabled account {}", request.to.id());
+            Box::new(err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: b"Account is disabled",
+                triggered_by: Some(&self.ilp_address),
+                data: &[],
+            }
+            .build()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod account_status_tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use std::str::FromStr;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        enabled: Arc<AtomicBool>,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl EnabledAccount for TestAccount {
+        fn enabled(&self) -> bool {
+            self.enabled.load(Ordering::SeqCst)
+        }
+    }
+
+    fn test_account(id: u64, enabled: &Arc<AtomicBool>) -> TestAccount {
+        TestAccount {
+            id,
+            enabled: enabled.clone(),
+        }
+    }
+
+    fn test_prepare() -> interledger_packet::Prepare {
+        PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    fn fulfill() -> interledger_packet::Fulfill {
+        FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    #[test]
+    fn incoming_rejects_disabled_account_until_reenabled() {
+        let enabled = Arc::new(AtomicBool::new(false));
+        let mut service = AccountStatusService::incoming(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| Ok(fulfill())),
+        );
+
+        let reject = service
+            .handle_request(IncomingRequest {
+                from: test_account(1, &enabled),
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T03_CONNECTOR_BUSY);
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+
+        enabled.store(true, Ordering::SeqCst);
+        let result = service
+            .handle_request(IncomingRequest {
+                from: test_account(1, &enabled),
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn outgoing_rejects_disabled_account_until_reenabled() {
+        let enabled = Arc::new(AtomicBool::new(false));
+        let always_enabled = Arc::new(AtomicBool::new(true));
+        let mut service = AccountStatusService::outgoing(
+            Address::from_str("example.connector").unwrap(),
+            outgoing_service_fn(|_| Ok(fulfill())),
+        );
+
+        let reject = service
+            .send_request(OutgoingRequest {
+                from: test_account(0, &always_enabled),
+                to: test_account(1, &enabled),
+                original_amount: 100,
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+
+        enabled.store(true, Ordering::SeqCst);
+        let result = service
+            .send_request(OutgoingRequest {
+                from: test_account(0, &always_enabled),
+                to: test_account(1, &enabled),
+                original_amount: 100,
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait();
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,141 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+/// # Address Depth Service
+///
+/// Incoming Service that rejects packets whose destination address has more than the
+/// configured number of segments (for example, `g.alice.wallet` has 3). Any valid ILP address
+/// is accepted by `Address` itself, so this is an additional policy for deployments that want
+/// to keep their routing trees shallow. Packets that are too deep are rejected with `F02 Unreachable`.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct AddressDepthService<I, A> {
+    ilp_address: Address,
+    max_segments: usize,
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> AddressDepthService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, max_segments: usize, next: I) -> Self {
+        AddressDepthService {
+            ilp_address,
+            max_segments,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<I, A> IncomingService<A> for AddressDepthService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. Count the segments of the destination address
+    /// 2. If there are no more than the maximum, pass the request to the next service, otherwise reject it
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        let destination = request.prepare.destination();
+        let segments = destination.segment_count();
+        if segments > self.max_segments {
+            debug!(
+                "Rejecting packet from account {} because its destination {} has {} segments (max: {})",
+                request.from.id(),
+                destination,
+                segments,
+                self.max_segments
+            );
+            return Box::new(err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: format!(
+                    "Destination address has {} segments, more than the maximum of {}",
+                    segments, self.max_segments
+                )
+                .as_bytes(),
+                triggered_by: Some(&self.ilp_address),
+                data: &[],
+            }
+            .build()));
+        }
+        Box::new(self.next.handle_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{Fulfill, FulfillBuilder, PrepareBuilder, Reject};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn send_to(destination: &str) -> Result<Fulfill, Reject> {
+        let mut service = AddressDepthService::new(
+            Address::from_str("example.connector").unwrap(),
+            3,
+            incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        service
+            .handle_request(IncomingRequest {
+                from: TestAccount,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str(destination).unwrap(),
+                    amount: 100,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+    }
+
+    #[test]
+    fn forwards_addresses_up_to_max_segments() {
+        assert!(send_to("example.alice").is_ok());
+        assert!(send_to("example.alice.wallet").is_ok());
+    }
+
+    #[test]
+    fn rejects_addresses_with_more_segments() {
+        let reject = send_to("example.alice.wallet.extra").unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+        assert_eq!(
+            reject.message(),
+            &b"Destination address has 4 segments, more than the maximum of 3"[..]
+        );
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use interledger_packet::{Address, ParseError};
+use std::{cmp::min, convert::TryFrom};
+
+/// How many bytes of an invalid address are included in log messages, so that a
+/// malformed packet cannot fill the logs with its payload
+const MAX_LOGGED_ADDRESS_BYTES: usize = 64;
+
+/// Parse an ILP address for a service, logging why it is invalid (along with a
+/// sanitized rendering of the bytes) if it cannot be parsed.
+///
+/// `field` describes where the address came from, for example `"echo source"`.
+pub fn parse_address_or_log(bytes: &[u8], field: &str) -> Result<Address, ParseError> {
+    let result = Address::try_from(bytes);
+    if let Err(ref error) = result {
+        warn!("{}", describe_invalid_address(bytes, field, error));
+    }
+    result
+}
+
+fn describe_invalid_address(bytes: &[u8], field: &str, error: &ParseError) -> String {
+    let logged = &bytes[..min(bytes.len(), MAX_LOGGED_ADDRESS_BYTES)];
+    let rendered = String::from_utf8_lossy(logged).escape_debug().to_string();
+    let truncated = if logged.len() < bytes.len() {
+        "..."
+    } else {
+        ""
+    };
+    format!(
+        "Invalid {} address ({} bytes): {}. Address: \"{}{}\"",
+        field,
+        bytes.len(),
+        reason(error),
+        rendered,
+        truncated
+    )
+}
+
+fn reason(error: &ParseError) -> String {
+    match error {
+        ParseError::InvalidAddress(err) => err.to_string(),
+        ParseError::Utf8(_) => "Address is not valid UTF-8".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_reason_and_sanitized_bytes() {
+        let bytes = b"g.bad address\n";
+        let error = parse_address_or_log(bytes, "destination").unwrap_err();
+        assert_eq!(
+            describe_invalid_address(bytes, "destination", &error),
+            "Invalid destination address (14 bytes): Invalid address format. Address: \"g.bad address\\n\""
+        );
+
+        let bytes = b"g.\xff";
+        let error = parse_address_or_log(bytes, "destination").unwrap_err();
+        assert_eq!(
+            describe_invalid_address(bytes, "destination", &error),
+            "Invalid destination address (3 bytes): Address is not valid UTF-8. Address: \"g.\u{fffd}\""
+        );
+    }
+
+    #[test]
+    fn truncates_long_addresses() {
+        let bytes = vec![b'a'; 2000];
+        let error = parse_address_or_log(&bytes, "destination").unwrap_err();
+        let message = describe_invalid_address(&bytes, "destination", &error);
+        assert!(message.contains("(2000 bytes): Invalid address length"));
+        assert!(message.ends_with(&format!("\"{}...\"", "a".repeat(64))));
+    }
+}
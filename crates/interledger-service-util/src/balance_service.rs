@@ -9,6 +9,8 @@ pub trait BalanceStore: AccountStore {
     fn get_balance(&self, account: Self::Account)
         -> Box<dyn Future<Item = i64, Error = ()> + Send>;
 
+    /// Debit the incoming amount from the `from_account`. This fails, so that the packet is rejected,
+    /// if it would take the account below its minimum balance (which may be negative, as a credit limit).
     fn update_balances_for_prepare(
         &self,
         from_account: Self::Account,
@@ -0,0 +1,149 @@
+use futures::{future::err, Future};
+use interledger_packet::{Address, ErrorCode, Reject, RejectBuilder};
+use interledger_service::*;
+use std::{
+    any::Any,
+    marker::PhantomData,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+/// # Catch Unwind Service
+///
+/// Converts panics in the next service into `T00 Internal Error` rejects, so that a single
+/// packet that triggers a bug does not take down the task handling a peer's connection.
+/// Panics are caught both when the request is passed to the next service and while
+/// its response future is being polled.
+/// Requires an `Account` and _no store_.
+#[derive(Clone)]
+pub struct CatchUnwindService<O, A> {
+    ilp_address: Address,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<O, A> CatchUnwindService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, next: O) -> Self {
+        CatchUnwindService {
+            ilp_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<O, A> OutgoingService<A> for CatchUnwindService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Forwards the request to the next service
+    /// 1. If the next service panics, either immediately or while its future is polled,
+    ///    logs the panic and replies with a `T00 Internal Error` reject
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let next = &mut self.next;
+        let future = match catch_unwind(AssertUnwindSafe(|| next.send_request(request))) {
+            Ok(future) => future,
+            Err(panic) => return Box::new(err(internal_error(&self.ilp_address, panic))),
+        };
+
+        let ilp_address = self.ilp_address.clone();
+        Box::new(
+            AssertUnwindSafe(future)
+                .catch_unwind()
+                .then(move |result| match result {
+                    Ok(result) => result,
+                    Err(panic) => Err(internal_error(&ilp_address, panic)),
+                }),
+        )
+    }
+}
+
+fn internal_error(ilp_address: &Address, panic: Box<dyn Any + Send>) -> Reject {
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown error"
+    };
+    error!("Next service panicked while handling packet: {}", message);
+    RejectBuilder {
+        code: ErrorCode::T00_INTERNAL_ERROR,
+        message: &[],
+        triggered_by: Some(ilp_address),
+        data: &[],
+    }
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::lazy;
+    use interledger_packet::PrepareBuilder;
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn test_request() -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount(1),
+            to: TestAccount(2),
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn converts_panic_into_reject() {
+        let mut service = CatchUnwindService::new(
+            Address::from_str("example.connector").unwrap(),
+            outgoing_service_fn(|_| -> BoxedIlpFuture { panic!("something went wrong") }),
+        );
+        let reject = service.send_request(test_request()).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+    }
+
+    #[test]
+    fn converts_panic_in_future_into_reject() {
+        let mut service = CatchUnwindService::new(
+            Address::from_str("example.connector").unwrap(),
+            outgoing_service_fn(|_| -> BoxedIlpFuture {
+                Box::new(lazy(|| -> Result<_, _> { panic!("something went wrong") }))
+            }),
+        );
+        let reject = service.send_request(test_request()).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+    }
+}
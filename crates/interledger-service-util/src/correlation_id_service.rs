@@ -0,0 +1,158 @@
+use futures::Future;
+use interledger_service::*;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::marker::PhantomData;
+
+fn generate_correlation_id() -> String {
+    let mut bytes = [0; 8];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Unable to generate correlation id");
+    hex::encode(bytes)
+}
+
+/// # Correlation Id Service
+///
+/// Incoming Service that assigns a correlation id to each packet it receives, so that
+/// the packet can be traced through the rest of this connector's services.
+/// The id is logged when the packet arrives and when it is fulfilled or rejected, and it is set as
+/// the request's `correlation_id` so the services after this one can log it too (for example,
+/// the `RejectAuditService`).
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct CorrelationIdService<I, A> {
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> CorrelationIdService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    pub fn new(next: I) -> Self {
+        CorrelationIdService {
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<I, A> IncomingService<A> for CorrelationIdService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. Assign the packet a new correlation id and log it
+    /// 2. Set it as the request's correlation id and pass the request to the next service
+    /// 3. Log the result with the correlation id
+    fn handle_request(&mut self, mut request: IncomingRequest<A>) -> Self::Future {
+        let correlation_id = generate_correlation_id();
+        debug!(
+            "Packet from account {} for destination {} assigned correlation id: {}",
+            request.from.id(),
+            request.prepare.destination(),
+            correlation_id
+        );
+        request.correlation_id = Some(correlation_id.clone());
+        Box::new(self.next.handle_request(request).then(move |result| {
+            match result {
+                Ok(_) => trace!(
+                    "Packet with correlation id {} was fulfilled",
+                    correlation_id
+                ),
+                Err(ref reject) => trace!(
+                    "Packet with correlation id {} was rejected with code: {}",
+                    correlation_id,
+                    reject.code()
+                ),
+            }
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn test_request() -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[1; 32],
+                data: &[],
+            }
+            .build(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn sets_id_on_the_request() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let mut service = CorrelationIdService::new(incoming_service_fn(
+            move |request: IncomingRequest<TestAccount>| {
+                seen_clone.lock().unwrap().push(request.correlation_id);
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            },
+        ));
+        service.handle_request(test_request()).wait().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].as_ref().map(String::len), Some(16));
+    }
+
+    #[test]
+    fn packets_get_different_ids() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let mut service = CorrelationIdService::new(incoming_service_fn(
+            move |request: IncomingRequest<TestAccount>| {
+                seen_clone.lock().unwrap().push(request.correlation_id);
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            },
+        ));
+        // Both packets are in flight at the same time and have the same execution condition
+        let first = service.handle_request(test_request());
+        let second = service.handle_request(test_request());
+        first.join(second).wait().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert!(seen[0].is_some());
+        assert_ne!(seen[0], seen[1]);
+    }
+}
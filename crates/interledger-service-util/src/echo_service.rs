@@ -1,3 +1,4 @@
+use super::parse_address_or_log;
 use byteorder::ReadBytesExt;
 use bytes::{BufMut, BytesMut};
 use core::borrow::Borrow;
@@ -105,13 +106,9 @@ where
 
         // check source address
         let source_address = match reader.read_var_octet_string() {
-            Ok(value) => match Address::try_from(value) {
+            Ok(value) => match parse_address_or_log(value, "echo source") {
                 Ok(value) => value,
-                Err(error) => {
-                    eprintln!(
-                        "Could not parse source address from echo packet: {:?}",
-                        error
-                    );
+                Err(_) => {
                     return Box::new(err(RejectBuilder {
                         code: ErrorCode::F01_INVALID_PACKET,
                         message: b"Could not parse source address from Echo packet",
@@ -269,7 +266,11 @@ mod echo_tests {
 
         // test
         let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
+            .handle_request(IncomingRequest {
+                prepare,
+                from,
+                correlation_id: None,
+            })
             .wait();
         assert!(result.is_ok());
     }
@@ -315,7 +316,11 @@ mod echo_tests {
 
         // test
         let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
+            .handle_request(IncomingRequest {
+                prepare,
+                from,
+                correlation_id: None,
+            })
             .wait();
         assert!(result.is_ok());
     }
@@ -361,7 +366,11 @@ mod echo_tests {
 
         // test
         let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
+            .handle_request(IncomingRequest {
+                prepare,
+                from,
+                correlation_id: None,
+            })
             .wait();
         assert!(result.is_ok());
     }
@@ -402,7 +411,11 @@ mod echo_tests {
 
         // test
         let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
+            .handle_request(IncomingRequest {
+                prepare,
+                from,
+                correlation_id: None,
+            })
             .wait();
         assert!(result.is_err());
     }
@@ -444,7 +457,11 @@ mod echo_tests {
 
         // test
         let result = echo_service
-            .handle_request(IncomingRequest { prepare, from })
+            .handle_request(IncomingRequest {
+                prepare,
+                from,
+                correlation_id: None,
+            })
             .wait();
         assert!(result.is_err());
     }
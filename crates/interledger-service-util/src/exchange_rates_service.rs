@@ -1,5 +1,5 @@
 use futures::{future::err, Future};
-use interledger_ildcp::IldcpAccount;
+use interledger_ildcp::{scale_rate, IldcpAccount};
 use interledger_packet::{Address, ErrorCode, Fulfill, Reject, RejectBuilder};
 use interledger_service::*;
 use std::marker::PhantomData;
@@ -88,15 +88,8 @@ where
                 .build()));
             };
 
-            let scaled_rate = if request.to.asset_scale() >= request.from.asset_scale() {
-                rate * 10f64.powf(f64::from(
-                    request.to.asset_scale() - request.from.asset_scale(),
-                ))
-            } else {
-                rate / 10f64.powf(f64::from(
-                    request.from.asset_scale() - request.to.asset_scale(),
-                ))
-            };
+            let scaled_rate =
+                scale_rate(rate, request.from.asset_scale(), request.to.asset_scale());
 
             let outgoing_amount = (request.prepare.amount() as f64 * scaled_rate) as u64;
             request.prepare.set_amount(outgoing_amount);
@@ -106,3 +99,162 @@ where
         Box::new(self.next.send_request(request))
     }
 }
+
+/// Choose which of several next hops a packet from the `from` account should be forwarded to,
+/// based on their assets. This is intended to be used in a store's implementation of
+/// `RouterStore::select_next_hop`.
+///
+/// A next hop in the same asset as the `from` account is preferred, because no conversion is needed.
+/// Otherwise, the next hop that loses the least value when the amount is converted and rounded
+/// to its asset scale is chosen. Next hops without an exchange rate are skipped.
+/// Falls back to the first next hop if none of them can be used.
+pub fn select_next_hop_by_asset<S, A>(store: &S, from: &A, amount: u64, next_hops: &[A]) -> usize
+where
+    S: ExchangeRateStore,
+    A: IldcpAccount,
+{
+    if let Some(index) = next_hops
+        .iter()
+        .position(|to| to.asset_code() == from.asset_code())
+    {
+        return index;
+    }
+
+    let mut selected: Option<(usize, f64)> = None;
+    for (index, to) in next_hops.iter().enumerate() {
+        let rates = match store.get_exchange_rates(&[from.asset_code(), to.asset_code()]) {
+            Ok(rates) => rates,
+            Err(_) => {
+                trace!(
+                    "Skipping next hop {} because there is no exchange rate from: {} to: {}",
+                    to.id(),
+                    from.asset_code(),
+                    to.asset_code()
+                );
+                continue;
+            }
+        };
+        let rate = rates[1] / rates[0];
+        let scaled_rate = scale_rate(rate, from.asset_scale(), to.asset_scale());
+        if scaled_rate <= 0.0 {
+            continue;
+        }
+
+        let outgoing_amount = (amount as f64 * scaled_rate) as u64;
+        let lost = amount as f64 - outgoing_amount as f64 / scaled_rate;
+        if selected
+            .map(|(_, min_lost)| lost < min_lost)
+            .unwrap_or(true)
+        {
+            selected = Some((index, lost));
+        }
+    }
+
+    selected.map(|(index, _)| index).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, str::FromStr};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        asset_code: String,
+        asset_scale: u8,
+        ilp_address: Address,
+    }
+
+    impl TestAccount {
+        fn new(id: u64, asset_code: &str, asset_scale: u8) -> Self {
+            TestAccount {
+                id,
+                asset_code: asset_code.to_string(),
+                asset_scale,
+                ilp_address: Address::from_str("example.account").unwrap(),
+            }
+        }
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn asset_code(&self) -> &str {
+            &self.asset_code
+        }
+
+        fn asset_scale(&self) -> u8 {
+            self.asset_scale
+        }
+
+        fn client_address(&self) -> &Address {
+            &self.ilp_address
+        }
+    }
+
+    struct TestStore {
+        rates: HashMap<&'static str, f64>,
+    }
+
+    impl ExchangeRateStore for TestStore {
+        fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ()> {
+            asset_codes
+                .iter()
+                .map(|code| self.rates.get(code).cloned().ok_or(()))
+                .collect()
+        }
+    }
+
+    fn test_store() -> TestStore {
+        TestStore {
+            rates: vec![("USD", 1.0), ("EUR", 0.9), ("JPY", 110.0)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn prefers_next_hop_in_same_asset() {
+        let from = TestAccount::new(0, "USD", 2);
+        let next_hops = vec![TestAccount::new(1, "EUR", 6), TestAccount::new(2, "USD", 2)];
+        assert_eq!(
+            select_next_hop_by_asset(&test_store(), &from, 105, &next_hops),
+            1
+        );
+    }
+
+    #[test]
+    fn prefers_next_hop_that_loses_least_to_conversion() {
+        let from = TestAccount::new(0, "USD", 2);
+        // 1.05 USD is 115.5 JPY (rounded down to 115) or 0.945 EUR (rounded down to 0.94)
+        let next_hops = vec![TestAccount::new(1, "EUR", 2), TestAccount::new(2, "JPY", 0)];
+        assert_eq!(
+            select_next_hop_by_asset(&test_store(), &from, 105, &next_hops),
+            1
+        );
+
+        // With a precise enough scale, EUR loses nothing to rounding
+        let next_hops = vec![TestAccount::new(1, "JPY", 0), TestAccount::new(2, "EUR", 6)];
+        assert_eq!(
+            select_next_hop_by_asset(&test_store(), &from, 105, &next_hops),
+            1
+        );
+    }
+
+    #[test]
+    fn skips_next_hops_without_rates() {
+        let from = TestAccount::new(0, "USD", 2);
+        let next_hops = vec![TestAccount::new(1, "XYZ", 9), TestAccount::new(2, "EUR", 2)];
+        assert_eq!(
+            select_next_hop_by_asset(&test_store(), &from, 105, &next_hops),
+            1
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+pub trait FeeAccount: Account {
+    /// Fixed amount deducted from each packet forwarded from this account,
+    /// in the same units as the Prepare packet's amount
+    fn fixed_fee(&self) -> u64 {
+        0
+    }
+
+    /// Fraction of each packet's amount that is deducted when forwarding from this account,
+    /// as a plain ratio rather than basis points or parts per million.
+    /// For example, 0.01 means a 1% fee and 0.0001 means a fee of one basis point.
+    fn proportional_fee(&self) -> f64 {
+        0.0
+    }
+}
+
+/// # Fee Service
+///
+/// Connectors are compensated for forwarding packets by delivering slightly less than they receive.
+/// This service deducts the fixed and proportional fees configured for the `from` account from the
+/// Prepare packet's amount before forwarding it. This is separate from any exchange rate spread.
+/// To charge fees in the `from` account's asset, put it before the `ExchangeRateService`.
+/// The node binary does not use this service, so connectors that want to charge fees need to
+/// add it to their own service chain.
+/// Requires a `FeeAccount` and _no store_.
+#[derive(Clone)]
+pub struct FeeService<O, A> {
+    ilp_address: Address,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<O, A> FeeService<O, A>
+where
+    O: OutgoingService<A>,
+    A: FeeAccount,
+{
+    pub fn new(ilp_address: Address, next: O) -> Self {
+        FeeService {
+            ilp_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<O, A> OutgoingService<A> for FeeService<O, A>
+where
+    O: OutgoingService<A>,
+    A: FeeAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. If the prepare packet's amount is 0, it just forwards
+    /// 1. Calculates the fee as `fixed_fee + amount * proportional_fee`
+    /// 1. Rejects if the fee would consume the whole amount, otherwise reduces the amount by the fee and forwards the request
+    fn send_request(&mut self, mut request: OutgoingRequest<A>) -> Self::Future {
+        let amount = request.prepare.amount();
+        if amount > 0 {
+            let proportional_fee = (amount as f64 * request.from.proportional_fee()) as u64;
+            let fee = request.from.fixed_fee().saturating_add(proportional_fee);
+            if fee >= amount {
+                debug!(
+                    "Rejecting packet from account {} because the amount: {} does not cover the fee: {}",
+                    request.from.id(),
+                    amount,
+                    fee
+                );
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
+                    message: format!(
+                        "Packet amount: {} does not cover the connector fee: {}",
+                        amount, fee
+                    )
+                    .as_bytes(),
+                    triggered_by: Some(&self.ilp_address),
+                    data: &[],
+                }
+                .build()));
+            }
+            request.prepare.set_amount(amount - fee);
+            trace!(
+                "Deducted fee of: {} from packet of amount: {} from account {}",
+                fee,
+                amount,
+                request.from.id()
+            );
+        }
+
+        Box::new(self.next.send_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        fixed_fee: u64,
+        proportional_fee: f64,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl FeeAccount for TestAccount {
+        fn fixed_fee(&self) -> u64 {
+            self.fixed_fee
+        }
+
+        fn proportional_fee(&self) -> f64 {
+            self.proportional_fee
+        }
+    }
+
+    fn send_with_fees(
+        amount: u64,
+        fixed_fee: u64,
+        proportional_fee: f64,
+    ) -> (Result<(), ErrorCode>, Option<u64>) {
+        let forwarded = Arc::new(Mutex::new(None));
+        let forwarded_clone = forwarded.clone();
+        let mut service = FeeService::new(
+            Address::from_str("example.connector").unwrap(),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                *forwarded_clone.lock().unwrap() = Some(request.prepare.amount());
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let account = TestAccount {
+            id: 0,
+            fixed_fee,
+            proportional_fee,
+        };
+        let result = service
+            .send_request(OutgoingRequest {
+                from: account.clone(),
+                to: account,
+                original_amount: amount,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .map(|_| ())
+            .map_err(|reject| reject.code());
+        let forwarded = *forwarded.lock().unwrap();
+        (result, forwarded)
+    }
+
+    #[test]
+    fn fixed_fee() {
+        assert_eq!(send_with_fees(100, 10, 0.0), (Ok(()), Some(90)));
+    }
+
+    #[test]
+    fn proportional_fee() {
+        assert_eq!(send_with_fees(1000, 0, 0.01), (Ok(()), Some(990)));
+    }
+
+    #[test]
+    fn fixed_and_proportional_fee() {
+        assert_eq!(send_with_fees(1000, 5, 0.01), (Ok(()), Some(985)));
+    }
+
+    #[test]
+    fn rejects_if_fee_consumes_amount() {
+        assert_eq!(
+            send_with_fees(10, 10, 0.0),
+            (Err(ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT), None)
+        );
+        assert_eq!(
+            send_with_fees(100, 0, 1.0),
+            (Err(ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT), None)
+        );
+    }
+}
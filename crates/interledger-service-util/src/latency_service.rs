@@ -0,0 +1,275 @@
+use futures::Future;
+use interledger_service::*;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// The upper bounds, in milliseconds, of the buckets of a `LatencyHistogram`.
+/// Round trips longer than the last bound are counted in one more, unbounded, bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 5000];
+
+/// Whether an outgoing packet was fulfilled or rejected
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Outcome {
+    Fulfilled,
+    Rejected,
+}
+
+/// A histogram of round-trip times
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyHistogram {
+    /// The number of round trips in each of the `LATENCY_BUCKETS_MS`, plus the unbounded bucket
+    pub buckets: [u64; 11],
+    pub count: u64,
+    pub total: Duration,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        LatencyHistogram {
+            buckets: [0; 11],
+            count: 0,
+            total: Duration::from_secs(0),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency <= Duration::from_millis(*bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += latency;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count > 0 {
+            // Divide in nanoseconds so that counts above u32::MAX are not truncated
+            let nanos = self.total.as_nanos() / u128::from(self.count);
+            Some(Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+type Histograms<Id> = Arc<RwLock<HashMap<(Id, Outcome), LatencyHistogram>>>;
+
+/// The round-trip times recorded by a `LatencyService`, by account and outcome.
+/// Clones share the same histograms, so one can be kept to read what the service records.
+#[derive(Clone)]
+pub struct Latencies<Id> {
+    histograms: Histograms<Id>,
+}
+
+impl<Id> Latencies<Id>
+where
+    Id: Eq + Hash + Copy,
+{
+    pub fn new() -> Self {
+        Latencies {
+            histograms: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Get the histogram of round-trip times for packets sent to the given account
+    pub fn get(&self, account_id: Id, outcome: Outcome) -> Option<LatencyHistogram> {
+        self.histograms
+            .read()
+            .unwrap()
+            .get(&(account_id, outcome))
+            .cloned()
+    }
+
+    fn record(&self, account_id: Id, outcome: Outcome, latency: Duration) {
+        self.histograms
+            .write()
+            .unwrap()
+            .entry((account_id, outcome))
+            .or_insert_with(LatencyHistogram::new)
+            .record(latency);
+    }
+}
+
+impl<Id> Default for Latencies<Id>
+where
+    Id: Eq + Hash + Copy,
+{
+    fn default() -> Self {
+        Latencies::new()
+    }
+}
+
+/// # Latency Service
+///
+/// Outgoing Service that measures the time between sending each Prepare and getting back
+/// the Fulfill or Reject, and records it in a histogram for the account the packet was sent to.
+/// The histograms can be read from the shared `Latencies`, for example to monitor peers' response times.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct LatencyService<O, A: Account> {
+    latencies: Latencies<A::AccountId>,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<O, A> LatencyService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(latencies: Latencies<A::AccountId>, next: O) -> Self {
+        LatencyService {
+            latencies,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<O, A> OutgoingService<A> for LatencyService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account + 'static,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Pass the request to the next service
+    /// 2. When it is fulfilled or rejected, record how long it took for the account it was sent to
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let account_id = request.to.id();
+        let latencies = self.latencies.clone();
+        let start = Instant::now();
+        Box::new(self.next.send_request(request).then(move |result| {
+            let outcome = if result.is_ok() {
+                Outcome::Fulfilled
+            } else {
+                Outcome::Rejected
+            };
+            let latency = start.elapsed();
+            trace!(
+                "Packet to account {} was {:?} after {:?}",
+                account_id,
+                outcome,
+                latency
+            );
+            latencies.record(account_id, outcome, latency);
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::ok;
+    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use std::{str::FromStr, time::SystemTime};
+    use tokio::{runtime::Runtime, timer::Delay};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn request_to(account_id: u64) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount(0),
+            to: TestAccount(account_id),
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn records_latency_by_account_and_outcome() {
+        let latencies = Latencies::new();
+        // Account 1 fulfills right away and account 2 rejects after 150ms, well clear of the 100ms bound
+        let mut service = LatencyService::new(
+            latencies.clone(),
+            outgoing_service_fn(|request: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                if request.to.id() == 1 {
+                    Box::new(ok(FulfillBuilder {
+                        fulfillment: &[0; 32],
+                        data: &[],
+                    }
+                    .build()))
+                } else {
+                    Box::new(
+                        Delay::new(Instant::now() + Duration::from_millis(150)).then(|_| {
+                            Err(RejectBuilder {
+                                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                                message: &[],
+                                triggered_by: None,
+                                data: &[],
+                            }
+                            .build())
+                        }),
+                    )
+                }
+            }),
+        );
+        let mut runtime = Runtime::new().unwrap();
+        runtime
+            .block_on(service.send_request(request_to(1)))
+            .unwrap();
+        runtime
+            .block_on(service.send_request(request_to(2)))
+            .unwrap_err();
+
+        let fast = latencies.get(1, Outcome::Fulfilled).unwrap();
+        assert_eq!(fast.count, 1);
+        assert!(fast.mean().unwrap() < Duration::from_millis(50));
+        assert!(latencies.get(1, Outcome::Rejected).is_none());
+
+        let slow = latencies.get(2, Outcome::Rejected).unwrap();
+        assert_eq!(slow.count, 1);
+        assert!(slow.mean().unwrap() >= Duration::from_millis(100));
+        assert!(slow.mean().unwrap() < Duration::from_secs(5));
+        // Not in any of the buckets for 100ms or less
+        assert_eq!(slow.buckets[..6].iter().sum::<u64>(), 0);
+    }
+
+    #[test]
+    fn latencies_just_above_a_bound_go_in_the_next_bucket() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(Duration::from_millis(100));
+        histogram.record(Duration::from_micros(100_001));
+        assert_eq!(histogram.buckets[5], 1);
+        assert_eq!(histogram.buckets[6], 1);
+    }
+
+    #[test]
+    fn mean_handles_counts_above_u32_max() {
+        let histogram = LatencyHistogram {
+            buckets: [0; 11],
+            count: 1 << 32,
+            total: Duration::from_millis(3 << 32),
+        };
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(3)));
+        assert_eq!(LatencyHistogram::new().mean(), None);
+    }
+}
@@ -0,0 +1,402 @@
+use futures::{future::err, Future};
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::{Address, ErrorCode, MaxPacketAmountDetails, RejectBuilder};
+use interledger_service::*;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// How long a maximum packet amount learned from an `F08 Amount Too Large` reject is used for,
+/// unless configured otherwise. After this, packets are forwarded again so that a limit that
+/// was raised is noticed.
+pub const DEFAULT_LEARNED_MAX_PACKET_AMOUNT_TTL: Duration = Duration::from_secs(600);
+
+type LearnedAmounts<Id> = Arc<RwLock<HashMap<Id, (u64, Instant)>>>;
+
+/// The maximum packet amounts learned by a `LearnedMaxPacketAmountService`, by next-hop account.
+/// The amounts are in the units of the next-hop account's asset.
+/// Clones share the same amounts, so one can be kept to read what the service learned.
+#[derive(Clone)]
+pub struct LearnedMaxPacketAmounts<Id> {
+    amounts: LearnedAmounts<Id>,
+    ttl: Duration,
+}
+
+impl<Id> LearnedMaxPacketAmounts<Id>
+where
+    Id: Eq + Hash + Copy,
+{
+    pub fn new() -> Self {
+        LearnedMaxPacketAmounts {
+            amounts: Arc::new(RwLock::new(HashMap::new())),
+            ttl: DEFAULT_LEARNED_MAX_PACKET_AMOUNT_TTL,
+        }
+    }
+
+    /// Set how long each learned amount is used for
+    pub fn ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Get the maximum packet amount learned for the given account, if it has not expired
+    pub fn get(&self, account_id: Id) -> Option<u64> {
+        self.amounts
+            .read()
+            .unwrap()
+            .get(&account_id)
+            .filter(|(_, learned_at)| learned_at.elapsed() < self.ttl)
+            .map(|(max_packet_amount, _)| *max_packet_amount)
+    }
+
+    fn learn(&self, account_id: Id, max_packet_amount: u64) {
+        let mut amounts = self.amounts.write().unwrap();
+        let previous = amounts
+            .get(&account_id)
+            .filter(|(_, learned_at)| learned_at.elapsed() < self.ttl)
+            .map(|(amount, _)| *amount);
+        // Keep the lowest limit, in case different parts of the path reject different amounts
+        match previous {
+            Some(previous) if previous <= max_packet_amount => {}
+            _ => {
+                amounts.insert(account_id, (max_packet_amount, Instant::now()));
+            }
+        }
+    }
+}
+
+impl<Id> Default for LearnedMaxPacketAmounts<Id>
+where
+    Id: Eq + Hash + Copy,
+{
+    fn default() -> Self {
+        LearnedMaxPacketAmounts::new()
+    }
+}
+
+/// Convert the maximum amount in `F08` details, which is in the units of the node that rejected
+/// the packet, into the units of the Prepare this node sent
+fn max_amount_in_sent_units(details: &MaxPacketAmountDetails, amount_sent: u64) -> Option<u64> {
+    if details.amount_received() == 0 {
+        return None;
+    }
+    let max_amount = u128::from(details.max_amount()) * u128::from(amount_sent)
+        / u128::from(details.amount_received());
+    Some(max_amount.min(u128::from(u64::MAX)) as u64)
+}
+
+/// # Learned Max Packet Amount Service
+///
+/// Outgoing Service that remembers the maximum packet amount of each next hop from the
+/// `F08 Amount Too Large` rejects it returns, so that later packets which are too large are
+/// rejected here, with the same `F08` details, instead of always being sent and bouncing.
+/// Only rejects triggered by the next hop itself are learned from, because a limit further along
+/// the path may only apply to some destinations.
+/// It should come after the `ExchangeRateService`, so that it sees the amounts sent to the next hop.
+/// It is disabled, and passes every request through, unless turned on with `enabled(true)`.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct LearnedMaxPacketAmountService<O, A: Account> {
+    ilp_address: Address,
+    enabled: bool,
+    learned: LearnedMaxPacketAmounts<A::AccountId>,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<O, A> LearnedMaxPacketAmountService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(
+        ilp_address: Address,
+        learned: LearnedMaxPacketAmounts<A::AccountId>,
+        next: O,
+    ) -> Self {
+        LearnedMaxPacketAmountService {
+            ilp_address,
+            enabled: false,
+            learned,
+            next,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Set whether maximum packet amounts are learned and enforced (disabled by default)
+    pub fn enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+impl<O, A> OutgoingService<A> for LearnedMaxPacketAmountService<O, A>
+where
+    O: OutgoingService<A>,
+    A: IldcpAccount + 'static,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 0. If the service is disabled, forward the request
+    /// 1. If a maximum packet amount was learned for the `to` account and the packet is larger, reject it with `F08`
+    /// 2. Otherwise, forward the request
+    /// 3. If it is rejected with `F08` and valid details by the `to` account itself, learn its maximum amount, in its units
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        if !self.enabled {
+            return Box::new(self.next.send_request(request));
+        }
+        let account_id = request.to.id();
+        let amount = request.prepare.amount();
+        if let Some(max_packet_amount) = self.learned.get(account_id) {
+            if amount > max_packet_amount {
+                debug!(
+                    "Rejecting packet of {} to account {} because it is larger than the maximum packet amount learned for it: {}",
+                    amount, account_id, max_packet_amount
+                );
+                let details = MaxPacketAmountDetails::new(amount, max_packet_amount).to_bytes();
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: Some(&self.ilp_address),
+                    data: &details[..],
+                }
+                .build()));
+            }
+        }
+
+        let next_hop_address = request.to.client_address().clone();
+        let learned = self.learned.clone();
+        Box::new(self.next.send_request(request).map_err(move |reject| {
+            if reject.code() == ErrorCode::F08_AMOUNT_TOO_LARGE
+                && reject.triggered_by().as_ref() == Some(&next_hop_address)
+            {
+                if let Some(max_packet_amount) = MaxPacketAmountDetails::from_bytes(reject.data())
+                    .ok()
+                    .and_then(|details| max_amount_in_sent_units(&details, amount))
+                {
+                    debug!(
+                        "Learned maximum packet amount of {} for account {}",
+                        max_packet_amount, account_id
+                    );
+                    learned.learn(account_id, max_packet_amount);
+                }
+            }
+            reject
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::SystemTime,
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64, Address);
+
+    fn account(id: u64) -> TestAccount {
+        TestAccount(id, Address::from_str("example.next").unwrap())
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &Address {
+            &self.1
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    fn send<O>(service: &mut O, to: u64, amount: u64) -> Result<(), ErrorCode>
+    where
+        O: OutgoingService<TestAccount>,
+    {
+        service
+            .send_request(OutgoingRequest {
+                from: account(0),
+                to: account(to),
+                original_amount: amount,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .map(|_| ())
+            .map_err(|reject| reject.code())
+    }
+
+    /// A next hop that only accepts packets up to 100 units of its asset, which is worth twice ours
+    fn next_hop(sent: Arc<AtomicUsize>) -> impl OutgoingService<TestAccount> + Clone {
+        rejecting_node(sent, "example.next")
+    }
+
+    fn rejecting_node(
+        sent: Arc<AtomicUsize>,
+        triggered_by: &'static str,
+    ) -> impl OutgoingService<TestAccount> + Clone {
+        outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+            sent.fetch_add(1, Ordering::SeqCst);
+            let amount_received = request.prepare.amount() / 2;
+            if amount_received > 100 {
+                Err(RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: Address::from_str(triggered_by).ok().as_ref(),
+                    data: &MaxPacketAmountDetails::new(amount_received, 100).to_bytes()[..],
+                }
+                .build())
+            } else {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }
+        })
+    }
+
+    #[test]
+    fn rejects_packets_over_learned_amount() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let learned = LearnedMaxPacketAmounts::new();
+        let mut service = LearnedMaxPacketAmountService::new(
+            Address::from_str("example.connector").unwrap(),
+            learned.clone(),
+            next_hop(sent.clone()),
+        );
+        service.enabled(true);
+
+        assert_eq!(
+            send(&mut service, 1, 300),
+            Err(ErrorCode::F08_AMOUNT_TOO_LARGE)
+        );
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(learned.get(1), Some(200));
+
+        // Handled here without being sent to the next hop
+        assert_eq!(
+            send(&mut service, 1, 250),
+            Err(ErrorCode::F08_AMOUNT_TOO_LARGE)
+        );
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(send(&mut service, 1, 200), Ok(()));
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+
+        // Other next hops are not affected
+        assert_eq!(
+            send(&mut service, 2, 250),
+            Err(ErrorCode::F08_AMOUNT_TOO_LARGE)
+        );
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn local_reject_includes_learned_amount() {
+        let learned = LearnedMaxPacketAmounts::new();
+        let mut service = LearnedMaxPacketAmountService::new(
+            Address::from_str("example.connector").unwrap(),
+            learned.clone(),
+            next_hop(Arc::new(AtomicUsize::new(0))),
+        );
+        service.enabled(true);
+        send(&mut service, 1, 1000).unwrap_err();
+        let reject = service
+            .send_request(OutgoingRequest {
+                from: account(0),
+                to: account(1),
+                original_amount: 500,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 500,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        let details = MaxPacketAmountDetails::from_bytes(reject.data()).unwrap();
+        assert_eq!(details.amount_received(), 500);
+        assert_eq!(details.max_amount(), 200);
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+    }
+
+    #[test]
+    fn forgets_learned_amount_after_ttl() {
+        let mut learned = LearnedMaxPacketAmounts::new();
+        learned.ttl(Duration::from_millis(0));
+        let mut service = LearnedMaxPacketAmountService::new(
+            Address::from_str("example.connector").unwrap(),
+            learned.clone(),
+            next_hop(Arc::new(AtomicUsize::new(0))),
+        );
+        service.enabled(true);
+        send(&mut service, 1, 300).unwrap_err();
+        assert_eq!(learned.get(1), None);
+    }
+
+    #[test]
+    fn does_not_learn_from_rejects_further_along_the_path() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let learned = LearnedMaxPacketAmounts::new();
+        let mut service = LearnedMaxPacketAmountService::new(
+            Address::from_str("example.connector").unwrap(),
+            learned.clone(),
+            rejecting_node(sent.clone(), "example.next.further"),
+        );
+        service.enabled(true);
+        send(&mut service, 1, 300).unwrap_err();
+        send(&mut service, 1, 300).unwrap_err();
+        assert_eq!(learned.get(1), None);
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn does_not_learn_when_disabled() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let learned = LearnedMaxPacketAmounts::new();
+        let mut service = LearnedMaxPacketAmountService::new(
+            Address::from_str("example.connector").unwrap(),
+            learned.clone(),
+            next_hop(sent.clone()),
+        );
+        send(&mut service, 1, 300).unwrap_err();
+        send(&mut service, 1, 300).unwrap_err();
+        assert_eq!(learned.get(1), None);
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+    }
+}
@@ -5,22 +5,62 @@
 #[macro_use]
 extern crate log;
 
+mod account_status_service;
+mod address_depth_service;
+mod address_logging;
 mod balance_service;
+mod catch_unwind_service;
+mod correlation_id_service;
 mod echo_service;
 mod exchange_rates_service;
 mod expiry_shortener_service;
+mod fee_service;
+mod latency_service;
+mod learned_max_packet_amount_service;
 mod max_packet_amount_service;
+mod ping_service;
+mod prepare_data_limit_service;
 mod rate_limit_service;
+mod reject_audit_service;
+mod rejecter_service;
+mod secrets;
+mod slippage_guard_service;
+mod task_supervisor;
+mod triggered_by_service;
 mod validator_service;
+mod zero_amount_guard_service;
 
+pub use self::account_status_service::{AccountStatusService, EnabledAccount};
+pub use self::address_depth_service::AddressDepthService;
+pub use self::address_logging::parse_address_or_log;
 pub use self::balance_service::{BalanceService, BalanceStore};
+pub use self::catch_unwind_service::CatchUnwindService;
+pub use self::correlation_id_service::CorrelationIdService;
 pub use self::echo_service::EchoService;
-pub use self::exchange_rates_service::{ExchangeRateService, ExchangeRateStore};
+pub use self::exchange_rates_service::{
+    select_next_hop_by_asset, ExchangeRateService, ExchangeRateStore,
+};
 pub use self::expiry_shortener_service::{
     ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
+pub use self::fee_service::{FeeAccount, FeeService};
+pub use self::latency_service::{
+    Latencies, LatencyHistogram, LatencyService, Outcome, LATENCY_BUCKETS_MS,
+};
+pub use self::learned_max_packet_amount_service::{
+    LearnedMaxPacketAmountService, LearnedMaxPacketAmounts, DEFAULT_LEARNED_MAX_PACKET_AMOUNT_TTL,
+};
 pub use self::max_packet_amount_service::{MaxPacketAmountAccount, MaxPacketAmountService};
+pub use self::ping_service::PingService;
+pub use self::prepare_data_limit_service::{MaxPrepareDataLenAccount, PrepareDataLimitService};
 pub use self::rate_limit_service::{
     RateLimitAccount, RateLimitError, RateLimitService, RateLimitStore,
 };
+pub use self::reject_audit_service::{RejectAuditService, RejectAuditStore};
+pub use self::rejecter_service::RejecterService;
+pub use self::secrets::{resolve_secret, SecretError};
+pub use self::slippage_guard_service::SlippageGuardService;
+pub use self::task_supervisor::TaskSupervisor;
+pub use self::triggered_by_service::TriggeredByService;
 pub use self::validator_service::ValidatorService;
+pub use self::zero_amount_guard_service::ZeroAmountGuardService;
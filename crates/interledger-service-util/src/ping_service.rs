@@ -0,0 +1,170 @@
+use futures::future::{err, ok};
+use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
+use interledger_service::*;
+use ring::digest::{digest, SHA256};
+use std::marker::PhantomData;
+
+/// The sub-address of the node that ping packets should be sent to
+const PING_SEGMENT: &str = "ping";
+/// Ping packets use a well-known fulfillment so that any node can fulfill them.
+/// The sender must use the SHA-256 hash of this as the execution condition.
+const PING_FULFILLMENT: [u8; 32] = [0; 32];
+
+/// # Ping Service
+///
+/// Responds to unidirectional ping packets so that peers can check that this node is reachable.
+/// Unlike the `EchoService`, which sends the packet back to the sender, this service fulfills
+/// Prepare packets addressed to `{ilp_address}.ping` directly with an empty payload.
+/// Requires an `Account` and _no store_.
+#[derive(Clone)]
+pub struct PingService<I, A> {
+    ilp_address: Address,
+    ping_address: Address,
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> PingService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, next: I) -> Self {
+        let ping_address = ilp_address
+            .with_suffix(PING_SEGMENT.as_bytes())
+            .expect("Appending the ping segment to the node's address should never fail");
+        PingService {
+            ilp_address,
+            ping_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<I, A> IncomingService<A> for PingService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. If the destination is not this node's ping address, pass the request to the next service
+    /// 1. If the execution condition is not the hash of the ping fulfillment, reject the packet
+    /// 1. Otherwise, fulfill it with an empty data field
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        if request.prepare.destination() != self.ping_address {
+            return Box::new(self.next.handle_request(request));
+        }
+
+        if digest(&SHA256, &PING_FULFILLMENT).as_ref() != request.prepare.execution_condition() {
+            debug!(
+                "Rejecting ping from account {} because it has the wrong execution condition",
+                request.from.id()
+            );
+            return Box::new(err(RejectBuilder {
+                code: ErrorCode::F05_WRONG_CONDITION,
+                message:
+                    b"Ping packets must use the hash of the ping fulfillment as their condition",
+                triggered_by: Some(&self.ilp_address),
+                data: &[],
+            }
+            .build()));
+        }
+
+        trace!("Fulfilling ping from account {}", request.from.id());
+        Box::new(ok(FulfillBuilder {
+            fulfillment: &PING_FULFILLMENT,
+            data: &[],
+        }
+        .build()))
+    }
+}
+
+#[cfg(test)]
+mod ping_tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{Prepare, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Debug, Clone)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn prepare_to(destination: &str) -> Prepare {
+        let mut execution_condition = [0; 32];
+        execution_condition.copy_from_slice(digest(&SHA256, &PING_FULFILLMENT).as_ref());
+        PrepareBuilder {
+            amount: 0,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &execution_condition,
+            destination: Address::from_str(destination).unwrap(),
+            data: &[],
+        }
+        .build()
+    }
+
+    #[test]
+    fn fulfills_ping() {
+        let mut service = PingService::new(
+            Address::from_str("example.node").unwrap(),
+            incoming_service_fn(|_| -> BoxedIlpFuture { panic!("shouldn't get here") }),
+        );
+        let fulfill = service
+            .handle_request(IncomingRequest {
+                from: TestAccount(1),
+                prepare: prepare_to("example.node.ping"),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(fulfill.fulfillment(), &PING_FULFILLMENT);
+        assert!(fulfill.data().is_empty());
+    }
+
+    #[test]
+    fn passes_through_other_packets() {
+        let destinations = Arc::new(Mutex::new(Vec::new()));
+        let destinations_clone = destinations.clone();
+        let mut service = PingService::new(
+            Address::from_str("example.node").unwrap(),
+            incoming_service_fn(move |request| {
+                destinations_clone
+                    .lock()
+                    .unwrap()
+                    .push(request.prepare.destination());
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let result = service
+            .handle_request(IncomingRequest {
+                from: TestAccount(1),
+                prepare: prepare_to("example.node.other"),
+                correlation_id: None,
+            })
+            .wait();
+        assert!(result.is_err());
+        assert_eq!(
+            *destinations.lock().unwrap(),
+            vec![Address::from_str("example.node.other").unwrap()]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+pub trait MaxPrepareDataLenAccount: Account {
+    /// The largest Prepare data, in bytes, the account may send. Unlimited by default.
+    fn max_prepare_data_len(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// # Prepare Data Limit Service
+///
+/// Incoming Service that rejects Prepare packets carrying more data than the sending account is
+/// allowed, so that peers cannot push large STREAM or other application payloads through the node.
+/// Oversized packets are rejected with `F99 Application Error`, because sending the same data again
+/// will not succeed. Accounts without a `max_prepare_data_len` are not limited.
+/// Requires a `MaxPrepareDataLenAccount` and _no store_.
+#[derive(Clone)]
+pub struct PrepareDataLimitService<I, A> {
+    ilp_address: Address,
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> PrepareDataLimitService<I, A>
+where
+    I: IncomingService<A>,
+    A: MaxPrepareDataLenAccount,
+{
+    pub fn new(ilp_address: Address, next: I) -> Self {
+        PrepareDataLimitService {
+            ilp_address,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<I, A> IncomingService<A> for PrepareDataLimitService<I, A>
+where
+    I: IncomingService<A>,
+    A: MaxPrepareDataLenAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. If the Prepare data is longer than the account's limit, reject the request
+    /// 2. Otherwise, pass the request to the next service
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        if let Some(max_len) = request.from.max_prepare_data_len() {
+            let data_len = request.prepare.data().len();
+            if data_len > max_len {
+                debug!(
+                    "Rejecting Prepare from account {} with {} bytes of data, more than its limit of {}",
+                    request.from.id(),
+                    data_len,
+                    max_len
+                );
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::F99_APPLICATION_ERROR,
+                    message: format!(
+                        "Prepare data is {} bytes, the maximum is {}",
+                        data_len, max_len
+                    )
+                    .as_bytes(),
+                    triggered_by: Some(&self.ilp_address),
+                    data: &[],
+                }
+                .build()));
+            }
+        }
+        Box::new(self.next.handle_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{Fulfill, FulfillBuilder, PrepareBuilder, Reject};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        max_prepare_data_len: Option<usize>,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    impl MaxPrepareDataLenAccount for TestAccount {
+        fn max_prepare_data_len(&self) -> Option<usize> {
+            self.max_prepare_data_len
+        }
+    }
+
+    fn send(max_prepare_data_len: Option<usize>, data: &[u8]) -> Result<Fulfill, Reject> {
+        let mut service = PrepareDataLimitService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        service
+            .handle_request(IncomingRequest {
+                from: TestAccount {
+                    max_prepare_data_len,
+                },
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.alice").unwrap(),
+                    amount: 100,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data,
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+    }
+
+    #[test]
+    fn forwards_data_within_limit() {
+        assert!(send(Some(32), &[0; 32]).is_ok());
+        assert!(send(Some(0), &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_data_over_limit() {
+        let reject = send(Some(32), &[0; 33]).unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(
+            reject.message(),
+            b"Prepare data is 33 bytes, the maximum is 32"
+        );
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+    }
+
+    #[test]
+    fn does_not_limit_by_default() {
+        assert!(send(None, &[0; 32767]).is_ok());
+    }
+}
@@ -0,0 +1,266 @@
+use futures::Future;
+use interledger_packet::{Address, Reject};
+use interledger_service::*;
+use std::{marker::PhantomData, str};
+
+pub trait RejectAuditStore {
+    type Account: Account;
+
+    /// Record a Reject that was returned for a packet forwarded from one account to another,
+    /// along with the packet's correlation id, if it was assigned one.
+    fn record_reject(
+        &self,
+        from: &Self::Account,
+        to: &Self::Account,
+        destination: &Address,
+        correlation_id: Option<&str>,
+        reject: &Reject,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+}
+
+/// # Reject Audit Service
+///
+/// When a multi-hop payment fails, the sender only sees the final Reject.
+/// This service logs every Reject passing back through this node, along with the
+/// incoming and outgoing accounts and the packet's destination, and records it
+/// in the store so that operators can see which rejects they produced or forwarded.
+/// Requires a `RejectAuditStore`.
+#[derive(Clone)]
+pub struct RejectAuditService<S, O, A> {
+    store: S,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<S, O, A> RejectAuditService<S, O, A>
+where
+    S: RejectAuditStore<Account = A>,
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(store: S, next: O) -> Self {
+        RejectAuditService {
+            store,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<S, O, A> OutgoingService<A> for RejectAuditService<S, O, A>
+where
+    S: RejectAuditStore<Account = A> + Clone + Send + 'static,
+    O: OutgoingService<A>,
+    A: Account + 'static,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Forward the request
+    /// 2. If it is rejected, log the Reject with the `from` and `to` accounts, the destination,
+    ///    and the packet's correlation id, if a `CorrelationIdService` assigned it one, and record it in the store
+    /// 3. Pass the Reject back unmodified
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let store = self.store.clone();
+        let from = request.from.clone();
+        let to = request.to.clone();
+        let destination = request.prepare.destination();
+        let correlation_id = request.correlation_id.clone();
+        Box::new(self.next.send_request(request).or_else(move |reject| {
+            debug!(
+                "Packet from account {} to account {} for destination {} (correlation id: {}) was rejected with code: {}, message: {}, triggered by: {:?}",
+                from.id(),
+                to.id(),
+                destination,
+                correlation_id.as_deref().unwrap_or("none"),
+                reject.code(),
+                str::from_utf8(reject.message()).unwrap_or_default(),
+                reject.triggered_by(),
+            );
+            store
+                .record_reject(
+                    &from,
+                    &to,
+                    &destination,
+                    correlation_id.as_deref(),
+                    &reject,
+                )
+                .then(move |result| {
+                    if result.is_err() {
+                        error!(
+                            "Error recording reject for packet from account {} to account {}",
+                            from.id(),
+                            to.id()
+                        );
+                    }
+                    Err(reject)
+                })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CorrelationIdService;
+    use futures::future::ok;
+    use interledger_packet::{ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct RecordedReject {
+        from: u64,
+        to: u64,
+        destination: Address,
+        code: ErrorCode,
+        correlation_id: Option<String>,
+    }
+
+    #[derive(Clone)]
+    struct TestStore {
+        rejects: Arc<Mutex<Vec<RecordedReject>>>,
+    }
+
+    impl RejectAuditStore for TestStore {
+        type Account = TestAccount;
+
+        fn record_reject(
+            &self,
+            from: &TestAccount,
+            to: &TestAccount,
+            destination: &Address,
+            correlation_id: Option<&str>,
+            reject: &Reject,
+        ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            self.rejects.lock().unwrap().push(RecordedReject {
+                from: from.id(),
+                to: to.id(),
+                destination: destination.clone(),
+                code: reject.code(),
+                correlation_id: correlation_id.map(String::from),
+            });
+            Box::new(ok(()))
+        }
+    }
+
+    fn test_request() -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount(1),
+            to: TestAccount(2),
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn records_forwarded_reject() {
+        let store = TestStore {
+            rejects: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut service = RejectAuditService::new(
+            store.clone(),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: b"no liquidity",
+                    triggered_by: Some(&Address::from_str("example.connector").unwrap()),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let reject = service.send_request(test_request()).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+        assert_eq!(reject.message(), b"no liquidity");
+        assert_eq!(
+            *store.rejects.lock().unwrap(),
+            vec![RecordedReject {
+                from: 1,
+                to: 2,
+                destination: Address::from_str("example.destination").unwrap(),
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                correlation_id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn does_not_record_fulfills() {
+        let store = TestStore {
+            rejects: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut service = RejectAuditService::new(
+            store.clone(),
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        assert!(service.send_request(test_request()).wait().is_ok());
+        assert!(store.rejects.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_correlation_id_from_incoming_service() {
+        let store = TestStore {
+            rejects: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut audit = RejectAuditService::new(
+            store.clone(),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let mut service = CorrelationIdService::new(incoming_service_fn(
+            move |request: IncomingRequest<TestAccount>| {
+                audit.send_request(request.into_outgoing(TestAccount(2)))
+            },
+        ));
+
+        service
+            .handle_request(IncomingRequest {
+                from: TestAccount(1),
+                prepare: test_request().prepare,
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+
+        let rejects = store.rejects.lock().unwrap();
+        assert_eq!(rejects.len(), 1);
+        let correlation_id = rejects[0].correlation_id.as_ref().unwrap();
+        assert_eq!(correlation_id.len(), 16);
+    }
+}
@@ -0,0 +1,178 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+/// # Rejecter Service
+///
+/// Incoming or Outgoing Service used as the last service in a chain, which rejects every packet it gets.
+/// The default code depends on the direction: incoming packets are rejected with `F06 Unexpected Payment`,
+/// because the node was not expecting to receive them, and outgoing packets are rejected with
+/// `F02 Unreachable`, because there was no way to forward them. Either can be changed with `code`.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct RejecterService<A> {
+    ilp_address: Address,
+    code: ErrorCode,
+    account_type: PhantomData<A>,
+}
+
+impl<A> RejecterService<A>
+where
+    A: Account,
+{
+    pub fn incoming(ilp_address: Address) -> Self {
+        RejecterService {
+            ilp_address,
+            code: ErrorCode::F06_UNEXPECTED_PAYMENT,
+            account_type: PhantomData,
+        }
+    }
+
+    pub fn outgoing(ilp_address: Address) -> Self {
+        RejecterService {
+            ilp_address,
+            code: ErrorCode::F02_UNREACHABLE,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Set the code every packet is rejected with
+    pub fn code(&mut self, code: ErrorCode) -> &mut Self {
+        self.code = code;
+        self
+    }
+}
+
+impl<A> IncomingService<A> for RejecterService<A>
+where
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. Reject the request with the configured code (`F06 Unexpected Payment` by default)
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        debug!(
+            "Rejecting incoming packet from account {} for destination {}",
+            request.from.id(),
+            request.prepare.destination()
+        );
+        Box::new(err(RejectBuilder {
+            code: self.code,
+            message: format!(
+                "Not expecting incoming packets for destination: {}",
+                request.prepare.destination()
+            )
+            .as_bytes(),
+            triggered_by: Some(&self.ilp_address),
+            data: &[],
+        }
+        .build()))
+    }
+}
+
+impl<A> OutgoingService<A> for RejecterService<A>
+where
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Reject the request with the configured code (`F02 Unreachable` by default)
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        debug!(
+            "Rejecting outgoing packet to account {} for destination {}",
+            request.to.id(),
+            request.prepare.destination()
+        );
+        Box::new(err(RejectBuilder {
+            code: self.code,
+            message: format!(
+                "No outgoing route for destination: {}",
+                request.prepare.destination()
+            )
+            .as_bytes(),
+            triggered_by: Some(&self.ilp_address),
+            data: &[],
+        }
+        .build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{Prepare, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn test_prepare() -> Prepare {
+        PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    fn ilp_address() -> Address {
+        Address::from_str("example.connector").unwrap()
+    }
+
+    #[test]
+    fn incoming_and_outgoing_defaults_differ() {
+        let incoming = RejecterService::incoming(ilp_address())
+            .handle_request(IncomingRequest {
+                from: TestAccount,
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        let outgoing = RejecterService::outgoing(ilp_address())
+            .send_request(OutgoingRequest {
+                from: TestAccount,
+                to: TestAccount,
+                original_amount: 100,
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(incoming.code(), ErrorCode::F06_UNEXPECTED_PAYMENT);
+        assert_eq!(outgoing.code(), ErrorCode::F02_UNREACHABLE);
+        assert_eq!(outgoing.triggered_by(), Some(ilp_address()));
+    }
+
+    #[test]
+    fn uses_configured_code() {
+        let mut service = RejecterService::incoming(ilp_address());
+        service.code(ErrorCode::T00_INTERNAL_ERROR);
+        let reject = service
+            .handle_request(IncomingRequest {
+                from: TestAccount,
+                prepare: test_prepare(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{env, fmt, fs};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretError {
+    /// The referenced environment variable is not set (or is not valid unicode)
+    MissingEnvVar(String),
+    /// The referenced secret file could not be read
+    UnreadableFile(String),
+    /// The reference uses a source other than `ENV` or `FILE`
+    UnknownSource(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretError::MissingEnvVar(name) => {
+                write!(f, "Environment variable {} is not set", name)
+            }
+            SecretError::UnreadableFile(path) => write!(f, "Unable to read secret file {}", path),
+            SecretError::UnknownSource(source) => write!(f, "Unknown secret source: {}", source),
+        }
+    }
+}
+
+/// Resolve a configured credential that may reference a secret stored outside of the config.
+///
+/// - `${ENV:NAME}` is replaced with the value of the environment variable `NAME`
+/// - `${FILE:/path/to/secret}` is replaced with the contents of the file, without surrounding whitespace
+/// - Any other value is returned as is
+pub fn resolve_secret(value: &str) -> Result<String, SecretError> {
+    if !value.starts_with("${") || !value.ends_with('}') {
+        return Ok(value.to_string());
+    }
+
+    let reference = &value[2..value.len() - 1];
+    let mut parts = reference.splitn(2, ':');
+    let source = parts.next().unwrap_or_default();
+    let location = parts.next().unwrap_or_default();
+    match source {
+        "ENV" => env::var(location).map_err(|_| {
+            error!(
+                "Environment variable {} referenced in config is not set",
+                location
+            );
+            SecretError::MissingEnvVar(location.to_string())
+        }),
+        "FILE" => fs::read_to_string(location)
+            .map(|contents| contents.trim().to_string())
+            .map_err(|err| {
+                error!("Unable to read secret file {}: {:?}", location, err);
+                SecretError::UnreadableFile(location.to_string())
+            }),
+        _ => Err(SecretError::UnknownSource(source.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_env_var() {
+        env::set_var("INTERLEDGER_TEST_PEER_TOKEN", "super secret");
+        assert_eq!(
+            resolve_secret("${ENV:INTERLEDGER_TEST_PEER_TOKEN}"),
+            Ok("super secret".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_env_var() {
+        env::remove_var("INTERLEDGER_TEST_MISSING_TOKEN");
+        assert_eq!(
+            resolve_secret("${ENV:INTERLEDGER_TEST_MISSING_TOKEN}"),
+            Err(SecretError::MissingEnvVar(
+                "INTERLEDGER_TEST_MISSING_TOKEN".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn returns_literal_values() {
+        assert_eq!(resolve_secret("token"), Ok("token".to_string()));
+        assert_eq!(resolve_secret("${ENV"), Ok("${ENV".to_string()));
+    }
+
+    #[test]
+    fn unknown_source() {
+        assert_eq!(
+            resolve_secret("${VAULT:token}"),
+            Err(SecretError::UnknownSource("VAULT".to_string()))
+        );
+    }
+}
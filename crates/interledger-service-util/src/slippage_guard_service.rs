@@ -0,0 +1,144 @@
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+/// # Slippage Guard Service
+///
+/// Outgoing Service that protects senders from bad exchange rates. It compares the amount
+/// of each outgoing packet, after the exchange rate has been applied, with the amount that was
+/// originally sent, and rejects the packet with `F99 Application Error` if the rate it got is
+/// below the configured minimum. This should be placed after the `ExchangeRateService`.
+///
+/// The rate is the outgoing amount divided by the incoming amount, in the accounts' own units
+/// (so it includes any difference between the two accounts' asset scales).
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct SlippageGuardService<S, A> {
+    ilp_address: Address,
+    min_rate: f64,
+    next: S,
+    account_type: PhantomData<A>,
+}
+
+impl<S, A> SlippageGuardService<S, A>
+where
+    S: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, min_rate: f64, next: S) -> Self {
+        SlippageGuardService {
+            ilp_address,
+            min_rate,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<S, A> OutgoingService<A> for SlippageGuardService<S, A>
+where
+    S: OutgoingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Calculate the rate the packet was converted at (zero-amount packets are always allowed)
+    /// 2. If it is at least the minimum rate, pass the request to the next service, otherwise reject it
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        if request.original_amount > 0 {
+            let rate = request.prepare.amount() as f64 / request.original_amount as f64;
+            if rate < self.min_rate {
+                debug!(
+                    "Rejecting packet from account {} to account {} because its exchange rate {} is below the minimum: {}",
+                    request.from.id(),
+                    request.to.id(),
+                    rate,
+                    self.min_rate
+                );
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::F99_APPLICATION_ERROR,
+                    message: format!(
+                        "Exchange rate {} is below the minimum acceptable rate: {}",
+                        rate, self.min_rate
+                    )
+                    .as_bytes(),
+                    triggered_by: Some(&self.ilp_address),
+                    data: &[],
+                }
+                .build()));
+            }
+        }
+        Box::new(self.next.send_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn send_with_rate(original_amount: u64, amount: u64) -> Result<(), ErrorCode> {
+        let mut service = SlippageGuardService::new(
+            Address::from_str("example.connector").unwrap(),
+            0.95,
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        service
+            .send_request(OutgoingRequest {
+                from: TestAccount,
+                to: TestAccount,
+                original_amount,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .map(|_| ())
+            .map_err(|reject| reject.code())
+    }
+
+    #[test]
+    fn forwards_packets_within_slippage() {
+        assert!(send_with_rate(100, 96).is_ok());
+        assert!(send_with_rate(100, 95).is_ok());
+        assert!(send_with_rate(0, 0).is_ok());
+    }
+
+    #[test]
+    fn rejects_excessive_slippage() {
+        assert_eq!(
+            send_with_rate(100, 94),
+            Err(ErrorCode::F99_APPLICATION_ERROR)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use futures::{
+    future::Shared,
+    sync::oneshot::{channel, Receiver, Sender},
+    Future,
+};
+use std::sync::{Arc, Mutex};
+use tokio_executor::spawn;
+
+/// Tracks background tasks, such as pollers and retriers, and cancels them all on shutdown
+/// so that the process can exit promptly.
+///
+/// Clones share the same set of tasks. Tasks are also cancelled when the last
+/// clone of the supervisor is dropped.
+#[derive(Clone)]
+pub struct TaskSupervisor {
+    trigger: Arc<Mutex<Option<Sender<()>>>>,
+    shutdown_signal: Shared<Receiver<()>>,
+}
+
+impl TaskSupervisor {
+    pub fn new() -> Self {
+        let (trigger, shutdown_signal) = channel();
+        TaskSupervisor {
+            trigger: Arc::new(Mutex::new(Some(trigger))),
+            shutdown_signal: shutdown_signal.shared(),
+        }
+    }
+
+    /// Spawn the future on the default executor. It will be dropped when `shutdown` is called.
+    /// If the supervisor has already been shut down, the future is dropped immediately.
+    pub fn spawn_supervised<F>(&self, future: F)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let shutdown_signal = self.shutdown_signal.clone().then(|_| {
+            debug!("Cancelling supervised task because of shutdown");
+            Ok(())
+        });
+        spawn(future.select(shutdown_signal).then(|_| Ok(())));
+    }
+
+    /// Cancel all supervised tasks.
+    pub fn shutdown(&self) {
+        if let Some(trigger) = self.trigger.lock().unwrap().take() {
+            debug!("Shutting down supervised tasks");
+            let _ = trigger.send(());
+        }
+    }
+}
+
+impl Default for TaskSupervisor {
+    fn default() -> Self {
+        TaskSupervisor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{empty, lazy};
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+    use tokio::runtime::Runtime;
+
+    /// Records when the task holding it is dropped
+    struct DropGuard(Arc<AtomicBool>);
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn cancels_tasks_on_shutdown() {
+        let mut runtime = Runtime::new().unwrap();
+        let supervisor = TaskSupervisor::new();
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let guard = DropGuard(dropped.clone());
+        let supervisor_clone = supervisor.clone();
+        runtime
+            .block_on(lazy(move || {
+                // This task never finishes on its own
+                supervisor_clone.spawn_supervised(empty().map(move |_: ()| drop(guard)));
+                Ok::<(), ()>(())
+            }))
+            .unwrap();
+        sleep(Duration::from_millis(50));
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        supervisor.shutdown();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !dropped.load(Ordering::SeqCst) && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,169 @@
+use futures::Future;
+use interledger_packet::{Address, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+/// # Triggered By Service
+///
+/// Outgoing Service that sets the `triggered_by` address of Rejects returned for forwarded packets.
+/// Rejects that come back from the next node already say where they were triggered, so by default
+/// they are passed back unmodified and only the Rejects without a `triggered_by` address, which were
+/// generated by this node (for example, by the BTP or HTTP client when the peer could not be reached),
+/// are given this node's address. With `preserve_triggered_by(false)`, every Reject is attributed to this node.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct TriggeredByService<O, A> {
+    ilp_address: Address,
+    preserve_triggered_by: bool,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<O, A> TriggeredByService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, next: O) -> Self {
+        TriggeredByService {
+            ilp_address,
+            preserve_triggered_by: true,
+            next,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Set whether Rejects from downstream keep their original `triggered_by` address (the default)
+    pub fn preserve_triggered_by(&mut self, preserve_triggered_by: bool) -> &mut Self {
+        self.preserve_triggered_by = preserve_triggered_by;
+        self
+    }
+}
+
+impl<O, A> OutgoingService<A> for TriggeredByService<O, A>
+where
+    O: OutgoingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On send request:
+    /// 1. Forward the request
+    /// 2. If it is rejected without a `triggered_by` address, or the original address should not be preserved,
+    ///    rebuild the Reject with this node's address, keeping its code, message and data
+    /// 3. Otherwise, pass the Reject back unmodified
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let ilp_address = self.ilp_address.clone();
+        let preserve_triggered_by = self.preserve_triggered_by;
+        Box::new(self.next.send_request(request).map_err(move |reject| {
+            if preserve_triggered_by && reject.triggered_by().is_some() {
+                return reject;
+            }
+            trace!(
+                "Setting triggered_by of reject with code {} to: {} (was: {:?})",
+                reject.code(),
+                ilp_address,
+                reject.triggered_by()
+            );
+            RejectBuilder {
+                code: reject.code(),
+                message: reject.message(),
+                triggered_by: Some(&ilp_address),
+                data: reject.data(),
+            }
+            .build()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::{ErrorCode, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn rejected_by(
+        triggered_by: Option<&'static str>,
+    ) -> impl OutgoingService<TestAccount> + Clone {
+        outgoing_service_fn(move |_| {
+            Err(RejectBuilder {
+                code: ErrorCode::T02_PEER_BUSY,
+                message: b"busy",
+                triggered_by: triggered_by
+                    .map(|address| Address::from_str(address).unwrap())
+                    .as_ref(),
+                data: b"details",
+            }
+            .build())
+        })
+    }
+
+    fn send_through<O>(service: &mut TriggeredByService<O, TestAccount>) -> Option<Address>
+    where
+        O: OutgoingService<TestAccount>,
+    {
+        let reject = service
+            .send_request(OutgoingRequest {
+                from: TestAccount,
+                to: TestAccount,
+                original_amount: 100,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T02_PEER_BUSY);
+        assert_eq!(reject.message(), b"busy");
+        assert_eq!(reject.data(), b"details");
+        reject.triggered_by()
+    }
+
+    fn ilp_address() -> Address {
+        Address::from_str("example.connector").unwrap()
+    }
+
+    #[test]
+    fn keeps_downstream_triggered_by() {
+        let mut service =
+            TriggeredByService::new(ilp_address(), rejected_by(Some("example.downstream")));
+        assert_eq!(
+            send_through(&mut service),
+            Some(Address::from_str("example.downstream").unwrap())
+        );
+    }
+
+    #[test]
+    fn sets_own_address_when_missing() {
+        let mut service = TriggeredByService::new(ilp_address(), rejected_by(None));
+        assert_eq!(send_through(&mut service), Some(ilp_address()));
+    }
+
+    #[test]
+    fn overwrites_when_not_preserving() {
+        let mut service =
+            TriggeredByService::new(ilp_address(), rejected_by(Some("example.downstream")));
+        service.preserve_triggered_by(false);
+        assert_eq!(send_through(&mut service), Some(ilp_address()));
+    }
+}
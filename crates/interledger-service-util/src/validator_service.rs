@@ -2,7 +2,10 @@ use futures::{future::err, Future};
 use hex;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
-use ring::digest::{digest, SHA256};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    digest::{digest, SHA256},
+};
 use std::marker::PhantomData;
 use std::time::{Duration, SystemTime};
 use tokio::prelude::FutureExt;
@@ -127,7 +130,7 @@ where
                     })
                     .and_then(move |fulfill| {
                         let generated_condition = digest(&SHA256, fulfill.fulfillment());
-                        if generated_condition.as_ref() == condition {
+                        if verify_slices_are_equal(generated_condition.as_ref(), &condition[..]).is_ok() {
                             Ok(fulfill)
                         } else {
                             error!("Fulfillment did not match condition. Fulfillment: {}, hash: {}, actual condition: {}", hex::encode(fulfill.fulfillment()), hex::encode(generated_condition), hex::encode(condition));
@@ -210,6 +213,7 @@ mod incoming {
                     data: b"test data",
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
 
@@ -243,6 +247,7 @@ mod incoming {
                     data: b"test data",
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
 
@@ -293,6 +298,7 @@ mod outgoing {
                     data: b"test data",
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
 
@@ -328,6 +334,7 @@ mod outgoing {
                     data: b"test data",
                 }
                 .build(),
+                correlation_id: None,
             })
             .wait();
 
@@ -0,0 +1,146 @@
+use bytes::Bytes;
+use futures::future::err;
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::marker::PhantomData;
+
+/// # Zero Amount Guard Service
+///
+/// Incoming Service that rejects Prepare packets with an amount of zero, which are usually a bug
+/// or a probe, before they reach the balance checks. Peer protocol packets (to `peer.` destinations,
+/// such as ILDCP and route updates) are always zero-amount, so they are let through, as are packets
+/// for any other prefixes configured with `allowed_prefixes`. Other packets are rejected with `F99 Application Error`.
+///
+/// Note that STREAM senders use zero-amount packets to probe the exchange rate, so this should only
+/// be used on paths that do not need to carry them.
+/// Requires _no store_.
+#[derive(Clone)]
+pub struct ZeroAmountGuardService<I, A> {
+    ilp_address: Address,
+    allowed_prefixes: Vec<Bytes>,
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+impl<I, A> ZeroAmountGuardService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    pub fn new(ilp_address: Address, next: I) -> Self {
+        ZeroAmountGuardService {
+            ilp_address,
+            allowed_prefixes: vec![Bytes::from_static(b"peer.")],
+            next,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Set the destination prefixes zero-amount packets are allowed to (only `peer.` by default)
+    pub fn allowed_prefixes(&mut self, prefixes: impl IntoIterator<Item = Bytes>) -> &mut Self {
+        self.allowed_prefixes = prefixes.into_iter().collect();
+        self
+    }
+}
+
+impl<I, A> IncomingService<A> for ZeroAmountGuardService<I, A>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    /// On receive request:
+    /// 1. If the amount is zero and the destination does not start with an allowed prefix, reject the request
+    /// 2. Otherwise, pass the request to the next service
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        if request.prepare.amount() == 0 {
+            let destination = request.prepare.destination();
+            let allowed = self
+                .allowed_prefixes
+                .iter()
+                .any(|prefix| destination.to_bytes().starts_with(&prefix[..]));
+            if !allowed {
+                debug!(
+                    "Rejecting zero-amount packet from account {} for destination: {}",
+                    request.from.id(),
+                    destination
+                );
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::F99_APPLICATION_ERROR,
+                    message: b"Zero-amount packets are not accepted for this destination",
+                    triggered_by: Some(&self.ilp_address),
+                    data: &[],
+                }
+                .build()));
+            }
+        }
+        Box::new(self.next.handle_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use interledger_packet::{Fulfill, FulfillBuilder, PrepareBuilder, Reject};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn send(destination: &str, amount: u64) -> Result<Fulfill, Reject> {
+        let mut service = ZeroAmountGuardService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        service
+            .handle_request(IncomingRequest {
+                from: TestAccount,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str(destination).unwrap(),
+                    amount,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+    }
+
+    #[test]
+    fn rejects_zero_amount_value_packets() {
+        let reject = send("example.alice", 0).unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.connector").unwrap())
+        );
+        assert!(send("example.alice", 1).is_ok());
+    }
+
+    #[test]
+    fn allows_zero_amount_peer_protocol_packets() {
+        assert!(send("peer.config", 0).is_ok());
+        assert!(send("peer.route.control", 0).is_ok());
+    }
+}
@@ -27,7 +27,7 @@
 //! HttpServerService --> ValidatorService --> StreamReceiverService
 
 use futures::{Future, IntoFuture};
-use interledger_packet::{Fulfill, Prepare, Reject};
+use interledger_packet::{Address, Fulfill, Prepare, Reject};
 use std::{
     cmp::Eq,
     fmt::{Debug, Display},
@@ -36,6 +36,9 @@ use std::{
     str::FromStr,
 };
 
+#[cfg(feature = "test_support")]
+pub mod test_support;
+
 /// The base trait that Account types from other Services extend.
 /// This trait only assumes that the account has an ID that can be compared with others.
 ///
@@ -53,6 +56,8 @@ pub trait Account: Clone + Send + Sized + Debug {
 pub struct IncomingRequest<A: Account> {
     pub from: A,
     pub prepare: Prepare,
+    /// Id used to trace the packet through this node's services in the logs, if one was assigned
+    pub correlation_id: Option<String>,
 }
 
 /// A struct representing an ILP Prepare packet with the incoming and outgoing accounts set.
@@ -62,6 +67,8 @@ pub struct OutgoingRequest<A: Account> {
     pub to: A,
     pub original_amount: u64,
     pub prepare: Prepare,
+    /// Id used to trace the packet through this node's services in the logs, if one was assigned
+    pub correlation_id: Option<String>,
 }
 
 /// Set the `to` Account and turn this into an OutgoingRequest
@@ -75,6 +82,7 @@ where
             original_amount: self.prepare.amount(),
             prepare: self.prepare,
             to,
+            correlation_id: self.correlation_id,
         }
     }
 }
@@ -106,6 +114,16 @@ pub trait AccountStore {
     ) -> Box<dyn Future<Item = Vec<Self::Account>, Error = ()> + Send>;
 }
 
+/// A Store that can look up accounts by their ILP address.
+pub trait AddressStore: AccountStore {
+    /// Load the account whose ILP address is the given address or, failing that,
+    /// the account with the longest address that the given address is a child of.
+    fn get_account_by_address(
+        &self,
+        address: &Address,
+    ) -> Box<dyn Future<Item = Option<Self::Account>, Error = ()> + Send>;
+}
+
 /// Create an IncomingService that calls the given handler for each request.
 pub fn incoming_service_fn<A, B, F>(handler: F) -> ServiceFn<F, A>
 where
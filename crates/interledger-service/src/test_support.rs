@@ -0,0 +1,61 @@
+//! A logger for tests that check what was logged.
+//!
+//! Available to other crates' tests with the `test_support` feature.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::{Mutex, Once};
+
+static LOGGED: Mutex<Vec<(Level, String, String)>> = Mutex::new(Vec::new());
+static INIT_LOGGER: Once = Once::new();
+
+/// Records everything logged by the Interledger.rs crates so that tests can check what was logged.
+/// Tests run in parallel, so they should look for messages that only they log.
+struct TestLogger;
+
+impl Log for TestLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("interledger")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LOGGED.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+            ));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Start recording log messages at every level
+pub fn init_test_logger() {
+    INIT_LOGGER.call_once(|| {
+        log::set_logger(&TestLogger).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// The messages logged at the given level so far
+pub fn logged_messages(level: Level) -> Vec<String> {
+    LOGGED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(logged_level, _, _)| *logged_level == level)
+        .map(|(_, _, message)| message.clone())
+        .collect()
+}
+
+/// The messages logged with the given target so far
+pub fn logged_messages_for_target(target: &str) -> Vec<String> {
+    LOGGED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, logged_target, _)| logged_target == target)
+        .map(|(_, _, message)| message.clone())
+        .collect()
+}
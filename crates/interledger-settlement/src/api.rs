@@ -1,27 +1,54 @@
-use crate::{SettlementAccount, SettlementStore};
+use crate::{
+    fulfillment_matches_condition, normalize_settlement_amount, IdempotentData, RoundingMode,
+    ScaleOverflowPolicy, SettlementAccount, SettlementStore, SettlementWebhook,
+    IDEMPOTENCY_KEY_FIELD,
+};
+use bytes::Bytes;
 use futures::{
-    future::{err, result, Either},
+    future::{err, ok, result, Either},
     Future,
 };
 use hyper::Response;
-use interledger_ildcp::IldcpAccount;
+use interledger_ildcp::{
+    convert_scale, AssetScaleRange, IldcpAccount, DEFAULT_PEER_PROTOCOL_EXPIRY,
+};
 use interledger_packet::PrepareBuilder;
-use interledger_service::{AccountStore, OutgoingRequest, OutgoingService};
-use serde_json::Value;
+use interledger_service::{Account, AccountStore, OutgoingRequest, OutgoingService};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    digest::{digest, SHA256},
+};
+use serde_json::{json, Value};
 use std::{
+    collections::HashSet,
     marker::PhantomData,
     str::{self, FromStr},
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
+use tokio_executor::spawn;
+
+/// The log target of the warnings logged when an incoming settlement could not be credited to the
+/// balance of its account. The settlement engine is expected to retry these, so operators can alert
+/// on them to notice a backlog of settlements that were received but not yet applied.
+pub const UNAPPLIED_SETTLEMENT_LOG_TARGET: &str = "interledger_settlement::unapplied_settlement";
 
-static PEER_PROTOCOL_CONDITION: [u8; 32] = [
-    102, 104, 122, 173, 248, 98, 189, 119, 108, 143, 193, 139, 142, 159, 142, 32, 8, 151, 20, 133,
-    110, 226, 51, 179, 144, 42, 89, 29, 13, 95, 41, 37,
-];
+const BEARER_TOKEN_START: usize = 7;
+/// Longer tokens are rejected without looking up the account
+const MAX_BEARER_TOKEN_LENGTH: usize = 1024;
 
-pub struct SettlementApi<S, T, A> {
+pub struct SettlementApi<S, T, A: Account> {
     outgoing_handler: S,
     store: T,
+    source_account_id: Option<A::AccountId>,
+    rounding_mode: RoundingMode,
+    scale_overflow_policy: ScaleOverflowPolicy,
+    peer_protocol_expiry: Duration,
+    redact_messages: bool,
+    verify_peer_fulfillment: bool,
+    asset_scale_range: AssetScaleRange,
+    webhook: Option<SettlementWebhook>,
+    idempotency_keys_in_progress: Arc<Mutex<HashSet<String>>>,
     account_type: PhantomData<A>,
 }
 
@@ -30,13 +57,320 @@ pub struct SettlementApi<S, T, A> {
 struct SettlementDetails {
     account_id: String,
     amount: u64,
+    /// The asset scale of the amount. If it is omitted, the amount is assumed
+    /// to be in the incoming asset scale configured for the account's settlement engine.
+    scale: Option<u8>,
+}
+
+/// Describe a settlement engine message or reply for the trace logs
+fn describe_message(bytes: &[u8], redact: bool) -> String {
+    if redact {
+        format!("<{} bytes redacted>", bytes.len())
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// A JSON error response for the settlement engine, with a human-readable `error` message
+/// and a stable `code` that callers can match on
+fn json_error(status: u16, code: &str, message: &str) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(json!({ "error": message, "code": code }).to_string())
+        .unwrap()
+}
+
+/// Hash of an incoming settlement, to tell whether a retry with the same idempotency key is for the same settlement
+fn settlement_hash(details: &SettlementDetails) -> [u8; 32] {
+    let mut input = details.account_id.as_bytes().to_vec();
+    input.push(b':');
+    input.extend_from_slice(details.amount.to_string().as_bytes());
+    if let Some(scale) = details.scale {
+        input.push(b':');
+        input.extend_from_slice(scale.to_string().as_bytes());
+    }
+    let mut hash = [0; 32];
+    hash.copy_from_slice(digest(&SHA256, &input[..]).as_ref());
+    hash
+}
+
+/// Get the token from an `Authorization: Bearer <token>` header, if it is not empty or too long
+fn bearer_token(authorization: Option<String>) -> Option<String> {
+    let authorization = authorization?;
+    if authorization.len() > BEARER_TOKEN_START
+        && authorization.len() <= BEARER_TOKEN_START + MAX_BEARER_TOKEN_LENGTH
+        && authorization.starts_with("Bearer ")
+    {
+        Some(authorization[BEARER_TOKEN_START..].to_string())
+    } else {
+        None
+    }
+}
+
+/// Check the token against the account's settlement engine secret in constant time.
+/// Both are hashed first so that the comparison does not reveal the secret's length either.
+fn token_matches<A: SettlementAccount>(account: &A, token: &str) -> bool {
+    match account.settlement_engine_auth_token() {
+        Some(secret) => verify_slices_are_equal(
+            digest(&SHA256, secret.as_bytes()).as_ref(),
+            digest(&SHA256, token.as_bytes()).as_ref(),
+        )
+        .is_ok(),
+        None => false,
+    }
+}
+
+fn unauthorized() -> Response<String> {
+    json_error(
+        401,
+        "unauthorized",
+        "A valid settlement engine secret is required as a bearer token",
+    )
+}
+
+/// Load the account and check that the token is its settlement engine secret.
+/// An unknown account gets the same response as a wrong token, so that the API does not
+/// reveal which accounts exist.
+fn authenticate<T, A>(
+    store: T,
+    account_id: String,
+    token: String,
+) -> impl Future<Item = A, Error = Response<String>>
+where
+    T: AccountStore<Account = A>,
+    A: SettlementAccount,
+{
+    result(
+        A::AccountId::from_str(account_id.as_str()).map_err(move |_err| {
+            error!("Unable to parse account id: {}", account_id);
+            json_error(
+                400,
+                "invalid_account_id",
+                &format!("Invalid account id: {}", account_id),
+            )
+        }),
+    )
+    .and_then(move |account_id| {
+        store.get_accounts(vec![account_id]).map_err(move |_| {
+            error!(
+                "Settlement API called for an unknown account: {}",
+                account_id
+            );
+            unauthorized()
+        })
+    })
+    .and_then(move |mut accounts| {
+        let account = accounts.pop().unwrap();
+        if token_matches(&account, &token) {
+            ok(account)
+        } else {
+            error!(
+                "Settlement API called with an invalid token for account: {}",
+                account.id()
+            );
+            err(unauthorized())
+        }
+    })
+}
+
+/// Convert an incoming settlement to the account's asset scale and credit it to the account's balance.
+/// If it has an idempotency key, the response is saved for the key (with the hash of the settlement)
+/// in the same store operation as the credit.
+#[allow(clippy::too_many_arguments)]
+fn credit_incoming_settlement<T, A>(
+    store: T,
+    account: A,
+    amount: u64,
+    scale: Option<u8>,
+    idempotency_key: Option<(String, [u8; 32])>,
+    rounding_mode: RoundingMode,
+    scale_overflow_policy: ScaleOverflowPolicy,
+    webhook: Option<SettlementWebhook>,
+) -> impl Future<Item = Value, Error = Response<String>>
+where
+    T: SettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    A: SettlementAccount + IldcpAccount + Send + Sync + 'static,
+{
+    let account_id = account.id();
+    let asset_scale = account.asset_scale();
+    let settlement_engine = match account.settlement_engine_details() {
+        Some(settlement_engine) => settlement_engine,
+        None => {
+            error!("Account {} does not have settlement engine details configured. Cannot handle incoming settlement", account_id);
+            return Either::A(err(json_error(
+                500,
+                "no_settlement_engine",
+                &format!("Account {} has no settlement engine configured", account_id),
+            )));
+        }
+    };
+    let incoming_scale = settlement_engine.incoming_asset_scale();
+    let from_scale = match scale {
+        Some(scale) if scale != incoming_scale => {
+            debug!("Incoming settlement for account {} is at scale {} rather than the configured scale {}, converting it from the scale it was sent with", account_id, scale, incoming_scale);
+            scale
+        }
+        _ => incoming_scale,
+    };
+    let received_amount = amount;
+    let amount = match normalize_settlement_amount(
+        amount,
+        from_scale,
+        asset_scale,
+        rounding_mode,
+        scale_overflow_policy,
+    ) {
+        Some(amount) => amount,
+        None => {
+            let reason = if asset_scale > from_scale {
+                "the converted amount does not fit in a u64"
+            } else {
+                "the scales are too far apart"
+            };
+            return Either::A(err(json_error(
+                400,
+                "invalid_amount",
+                &format!(
+                    "Settlement amount {} cannot be converted from asset scale {} to the asset scale {} of account {} because {}",
+                    received_amount, from_scale, asset_scale, account_id, reason
+                ),
+            )));
+        }
+    };
+    // How far the credited amount is from the one received, in the scale it was received in
+    let precision_lost = if asset_scale < from_scale {
+        let credited = convert_scale(amount, asset_scale, from_scale);
+        Some(match credited {
+            Some(credited) if credited > received_amount => credited - received_amount,
+            Some(credited) => received_amount - credited,
+            None => received_amount,
+        })
+    } else {
+        None
+    };
+    if let Some(precision_lost) = precision_lost.filter(|lost| *lost > 0) {
+        debug!("Incoming settlement of {} at scale {} for account {} lost {} to rounding when converted to scale {}", received_amount, from_scale, account_id, precision_lost, asset_scale);
+    }
+    // Echo how the amount was applied so the engine can verify the scale conversion
+    let mut applied = json!({
+        "appliedAmount": amount,
+        "appliedScale": asset_scale,
+    });
+    if let Some(precision_lost) = precision_lost {
+        applied["precisionLost"] = json!(precision_lost);
+    }
+    let idempotent_data = idempotency_key.map(|(idempotency_key, input_hash)| {
+        (
+            idempotency_key,
+            IdempotentData {
+                input_hash,
+                body: Bytes::from(applied.to_string()),
+            },
+        )
+    });
+    Either::B(store.update_balance_for_incoming_settlement(account_id, amount, idempotent_data)
+        .map_err(move |_| {
+            // Logged separately from other errors so operators can alert on settlements that are waiting to be applied
+            warn!(target: UNAPPLIED_SETTLEMENT_LOG_TARGET, "Incoming settlement of {} for account {} was received but not applied to its balance", amount, account_id);
+            let body = json!({
+                "error": "Settlement received but not applied to the account balance",
+                "code": "settlement_not_applied",
+                "accountId": account_id.to_string(),
+                "amount": amount,
+            });
+            Response::builder()
+                .status(500)
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .unwrap()
+        })
+        .map(move |balance| {
+            debug!("Credited incoming settlement of {} to account: {}. Balance is now: {}", amount, account_id, balance);
+            // The webhook is notified in the background so that a slow or unavailable webhook does not hold up the response
+            if let Some(webhook) = webhook {
+                spawn(webhook.notify(account_id.to_string(), amount, asset_scale));
+            }
+            applied
+        }))
+}
+
+/// An idempotency key that a request is being processed for. The key is released when this is dropped.
+struct IdempotencyKeyReservation {
+    keys_in_progress: Arc<Mutex<HashSet<String>>>,
+    idempotency_key: String,
+}
+
+impl IdempotencyKeyReservation {
+    /// Reserve the key, unless another request already holds it
+    fn reserve(
+        keys_in_progress: &Arc<Mutex<HashSet<String>>>,
+        idempotency_key: &str,
+    ) -> Option<Self> {
+        if keys_in_progress
+            .lock()
+            .unwrap()
+            .insert(idempotency_key.to_string())
+        {
+            Some(IdempotencyKeyReservation {
+                keys_in_progress: keys_in_progress.clone(),
+                idempotency_key: idempotency_key.to_string(),
+            })
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Response)]
-#[web(status = "200")]
-struct Success;
+impl Drop for IdempotencyKeyReservation {
+    fn drop(&mut self) {
+        self.keys_in_progress
+            .lock()
+            .unwrap()
+            .remove(&self.idempotency_key);
+    }
+}
 
-// TODO add authentication
+/// Credit an incoming settlement like `credit_incoming_settlement`, unless one with the same idempotency key
+/// was already credited, in which case the saved response is returned instead
+fn credit_idempotently<T, A>(
+    store: T,
+    account: A,
+    details: SettlementDetails,
+    idempotency_key: String,
+    rounding_mode: RoundingMode,
+    scale_overflow_policy: ScaleOverflowPolicy,
+    webhook: Option<SettlementWebhook>,
+) -> impl Future<Item = Value, Error = Response<String>>
+where
+    T: SettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    A: SettlementAccount + IldcpAccount + Send + Sync + 'static,
+{
+    let key = idempotency_key.clone();
+    let store_clone = store.clone();
+    let input_hash = settlement_hash(&details);
+    store.load_idempotent_data(idempotency_key.clone())
+        .map_err(move |_| {
+            error!("Error loading the saved response for idempotency key: {}", key);
+            json_error(500, "idempotency_store_error", "Unable to load the saved response for the idempotency key")
+        })
+        .and_then(move |data| {
+            if let Some(data) = data {
+                if data.input_hash != input_hash {
+                    error!("Idempotency key {} was reused for a different settlement", idempotency_key);
+                    return Either::A(err(json_error(409, "idempotency_key_reused", "The idempotency key was already used for a different settlement")));
+                }
+                debug!("Already credited settlement with idempotency key {}, responding with the saved response", idempotency_key);
+                return Either::A(result(serde_json::from_slice(&data.body[..]).map_err(|_| {
+                    error!("Saved response for idempotency key {} is not valid JSON", idempotency_key);
+                    json_error(500, "idempotency_store_error", "The saved response for the idempotency key is invalid")
+                })));
+            }
+            // The response is saved together with the credit, so failed settlements are not saved
+            // and retrying them tries to credit them again
+            Either::B(credit_incoming_settlement(store_clone, account, details.amount, details.scale, Some((idempotency_key, input_hash)), rounding_mode, scale_overflow_policy, webhook))
+        })
+}
 
 impl_web! {
     impl<S, T, A> SettlementApi<S, T, A>
@@ -49,113 +383,1276 @@ impl_web! {
             SettlementApi {
                 outgoing_handler,
                 store,
+                source_account_id: None,
+                rounding_mode: RoundingMode::Floor,
+                scale_overflow_policy: ScaleOverflowPolicy::Reject,
+                peer_protocol_expiry: DEFAULT_PEER_PROTOCOL_EXPIRY,
+                redact_messages: false,
+                verify_peer_fulfillment: true,
+                asset_scale_range: AssetScaleRange::default(),
+                webhook: None,
+                idempotency_keys_in_progress: Arc::new(Mutex::new(HashSet::new())),
                 account_type: PhantomData,
             }
         }
 
+        /// Set the account used as the `from` account of outgoing settlement engine messages.
+        /// By default the message is attributed to the account it is being sent to.
+        pub fn source_account(&mut self, account_id: A::AccountId) -> &mut Self {
+            self.source_account_id = Some(account_id);
+            self
+        }
+
+        /// Set how incoming settlement amounts are rounded when scaling down. Defaults to rounding down.
+        pub fn rounding_mode(&mut self, rounding_mode: RoundingMode) -> &mut Self {
+            self.rounding_mode = rounding_mode;
+            self
+        }
+
+        /// Set what to do with incoming settlements that cannot be converted to the account asset scale. Defaults to rejecting them.
+        pub fn scale_overflow_policy(&mut self, policy: ScaleOverflowPolicy) -> &mut Self {
+            self.scale_overflow_policy = policy;
+            self
+        }
+
+        /// Set how long messages sent to peers' settlement engines are valid for, unless the account's settlement engine sets its own `message_timeout`. Defaults to `DEFAULT_PEER_PROTOCOL_EXPIRY`.
+        pub fn peer_protocol_expiry(&mut self, expiry: Duration) -> &mut Self {
+            self.peer_protocol_expiry = expiry;
+            self
+        }
+
+        /// Only log the lengths of settlement engine messages and replies, even at trace level, because they may contain sensitive data.
+        pub fn redact_messages(&mut self, redact: bool) -> &mut Self {
+            self.redact_messages = redact;
+            self
+        }
+
+        /// Set whether replies from the settlement engines of peers are only accepted if their fulfillment matches the condition of the message. Defaults to true.
+        pub fn verify_peer_fulfillment(&mut self, verify: bool) -> &mut Self {
+            self.verify_peer_fulfillment = verify;
+            self
+        }
+
+        /// Set the asset scales that incoming settlement amounts may be sent with. Defaults to `AssetScaleRange::default()`.
+        pub fn asset_scale_range(&mut self, range: AssetScaleRange) -> &mut Self {
+            self.asset_scale_range = range;
+            self
+        }
+
+        /// Notify the given webhook of every incoming settlement once it has been credited
+        pub fn settlement_webhook(&mut self, webhook: SettlementWebhook) -> &mut Self {
+            self.webhook = Some(webhook);
+            self
+        }
+
         #[post("/settlements/receiveMoney")]
-        fn receive_settlement(&self, body: SettlementDetails) -> impl Future<Item = Success, Error = Response<()>> {
-            let amount = body.amount;
+        fn receive_settlement(&self, body: SettlementDetails, authorization: Option<String>, idempotency_key: Option<String>) -> impl Future<Item = Value, Error = Response<String>> {
+            let token = match bearer_token(authorization) {
+                Some(token) => token,
+                None => {
+                    error!("Settlement API called without a bearer token");
+                    return Either::A(err(unauthorized()));
+                }
+            };
+            if let Some(scale) = body.scale {
+                if let Err(scale_err) = self.asset_scale_range.check(scale) {
+                    error!("Rejecting incoming settlement for account {}: {}", body.account_id, scale_err);
+                    return Either::A(err(json_error(400, "invalid_scale", &scale_err.to_string())));
+                }
+            }
+
             let store = self.store.clone();
-            let store_clone = store.clone();
-            let account_id = body.account_id;
-            result(A::AccountId::from_str(account_id.as_str())
-                .map_err(move |_err| {
-                    error!("Unable to parse account id: {}", account_id);
-                    Response::builder().status(400).body(()).unwrap()
-                }))
-                .and_then(move |account_id| store.get_accounts(vec![account_id]).map_err(move |_| {
-                    error!("Error getting account: {}", account_id);
-                    Response::builder().status(404).body(()).unwrap()
+            let rounding_mode = self.rounding_mode;
+            let scale_overflow_policy = self.scale_overflow_policy;
+            let webhook = self.webhook.clone();
+            let keys_in_progress = self.idempotency_keys_in_progress.clone();
+            Either::B(authenticate(store.clone(), body.account_id.clone(), token)
+                .and_then(move |account| {
+                    // Only one request per key is processed at a time, so that concurrent retries
+                    // cannot both miss the saved response and credit the settlement twice.
+                    // The key is only taken once the engine is authenticated, so other callers cannot hold it.
+                    let reservation = match idempotency_key {
+                        Some(ref idempotency_key) => match IdempotencyKeyReservation::reserve(&keys_in_progress, idempotency_key) {
+                            Some(reservation) => Some(reservation),
+                            None => {
+                                debug!("Settlement with idempotency key {} is already being processed", idempotency_key);
+                                return Either::A(err(json_error(409, "idempotency_key_in_progress", "A request with this idempotency key is already being processed")));
+                            }
+                        },
+                        None => None,
+                    };
+                    let credit = match idempotency_key {
+                        Some(idempotency_key) => Either::A(credit_idempotently(store, account, body, idempotency_key, rounding_mode, scale_overflow_policy, webhook)),
+                        None => Either::B(credit_incoming_settlement(store, account, body.amount, body.scale, None, rounding_mode, scale_overflow_policy, webhook)),
+                    };
+                    // The reservation is moved into the future, so the key is released when it completes
+                    // or if it is dropped before then (for example because the client disconnected)
+                    Either::B(credit.then(move |response| {
+                        drop(reservation);
+                        result(response)
+                    }))
                 }))
-                .and_then(move |mut accounts| {
-                    let account = accounts.pop().unwrap();
+        }
+
+        #[get("/accounts/:account_id/settlement/info")]
+        fn get_settlement_info(&self, account_id: String, authorization: Option<String>) -> impl Future<Item = Value, Error = Response<String>> {
+            let token = match bearer_token(authorization) {
+                Some(token) => token,
+                None => {
+                    error!("Settlement API called without a bearer token");
+                    return Either::A(err(unauthorized()));
+                }
+            };
+            Either::B(authenticate(self.store.clone(), account_id, token)
+                .and_then(|account| {
                     if let Some(settlement_engine) = account.settlement_engine_details() {
-                        Ok((account, settlement_engine))
+                        ok(json!({
+                            "assetCode": account.asset_code(),
+                            "assetScale": account.asset_scale(),
+                            "ilpAddress": settlement_engine.ilp_address.to_string(),
+                        }))
                     } else {
-                        error!("Account {} does not have settlement engine details configured. Cannot handle incoming settlement", account.id());
-                        Err(Response::builder().status(500).body(()).unwrap())
+                        error!("Account {} does not have settlement engine details configured", account.id());
+                        err(json_error(404, "no_settlement_engine", &format!("Account {} has no settlement engine configured", account.id())))
                     }
-                })
-                .and_then(move |(account, settlement_engine)| {
-                    let account_id = account.id();
-
-                    let amount = if account.asset_scale() >= settlement_engine.asset_scale {
-                        amount
-                            * 10u64.pow(u32::from(
-                                account.asset_scale() - settlement_engine.asset_scale,
-                            ))
-                    } else {
-                        amount
-                            / 10u64.pow(u32::from(
-                                settlement_engine.asset_scale - account.asset_scale(),
-                            ))
-                    };
+                }))
+        }
 
-                    store_clone.update_balance_for_incoming_settlement(account_id, amount)
-                        .map_err(move |_| {
-                            error!("Error updating balance of account: {} for incoming settlement of amount: {}", account_id, amount);
-                            Response::builder().status(500).body(()).unwrap()
-                        })
-                })
-                .and_then(|_| Ok(Success))
+        #[get("/accounts/:account_id/settlement/balance")]
+        fn get_settlement_balance(&self, account_id: String, authorization: Option<String>) -> impl Future<Item = Value, Error = Response<String>> {
+            let token = match bearer_token(authorization) {
+                Some(token) => token,
+                None => {
+                    error!("Settlement API called without a bearer token");
+                    return Either::A(err(unauthorized()));
+                }
+            };
+            let store = self.store.clone();
+            Either::B(authenticate(store.clone(), account_id, token)
+                .and_then(move |account| {
+                    let account_id = account.id();
+                    store.load_balance(account_id)
+                        .map_err(move |_| json_error(500, "balance_unavailable", &format!("Could not load the balance of account {}", account_id)))
+                        .map(move |balance| json!({
+                            "amount": balance.to_string(),
+                            "scale": account.asset_scale(),
+                            "assetCode": account.asset_code(),
+                        }))
+                }))
         }
 
         #[post("/settlements/sendMessage")]
-        fn send_outgoing_message(&self, body: Value)-> impl Future<Item = Value, Error = Response<()>> {
+        fn send_outgoing_message(&self, body: Value, authorization: Option<String>, idempotency_key: Option<String>)-> impl Future<Item = Value, Error = Response<String>> {
+            let token = match bearer_token(authorization) {
+                Some(token) => token,
+                None => {
+                    error!("Settlement API called without a bearer token");
+                    return Either::B(err(unauthorized()));
+                }
+            };
             if let Value::Object(json) = &body {
                 if let Some(account_id) = json.get("accountId").and_then(|a| a.as_str()) {
                     if let Ok(account_id) = A::AccountId::from_str(account_id) {
+                        // Forward the `Idempotency-Key` header to the peer's connector so that
+                        // it only delivers a retried message to its settlement engine once
+                        let mut message = json.clone();
+                        if let Some(idempotency_key) = idempotency_key {
+                            message.insert(IDEMPOTENCY_KEY_FIELD.to_string(), Value::String(idempotency_key));
+                        }
+                        let data = Value::Object(message).to_string();
+                        let redact_messages = self.redact_messages;
+                        let verify_peer_fulfillment = self.verify_peer_fulfillment;
+                        debug!("Sending message of {} bytes to the settlement engine of account {}", data.len(), account_id);
+                        trace!("Message to the settlement engine of account {}: {}", account_id, describe_message(data.as_bytes(), redact_messages));
                         let mut outgoing_handler = self.outgoing_handler.clone();
-                        return Either::A(self.store.get_accounts(vec![account_id])
-                            .map_err(move |_| {
-                                error!("Account {} not found", account_id);
-                                Response::builder().status(404).body(()).unwrap()
-                            })
-                            .and_then(|accounts| {
-                                let account = &accounts[0];
-                                if let Some(settlement_engine) = account.settlement_engine_details() {
-                                    Ok((account.clone(), settlement_engine))
-                                } else {
-                                    error!("Account {} has no settlement engine details configured, cannot send a settlement engine message to that account", accounts[0].id());
-                                    Err(Response::builder().status(404).body(()).unwrap())
+                        let peer_protocol_expiry = self.peer_protocol_expiry;
+                        let source_account_id = self.source_account_id;
+                        let store = self.store.clone();
+                        return Either::A(authenticate(self.store.clone(), account_id.to_string(), token)
+                            .and_then(move |account| {
+                                let settlement_engine = match account.settlement_engine_details() {
+                                    Some(settlement_engine) => settlement_engine,
+                                    None => {
+                                        error!("Account {} has no settlement engine details configured, cannot send a settlement engine message to that account", account.id());
+                                        return Either::A(err(json_error(404, "no_settlement_engine", &format!("Account {} has no settlement engine configured", account.id()))));
+                                    }
+                                };
+                                match source_account_id {
+                                    Some(source_account_id) => Either::B(Either::A(store.get_accounts(vec![source_account_id])
+                                        .map_err(move |_| {
+                                            error!("Source account {} for settlement engine messages not found", source_account_id);
+                                            json_error(500, "source_account_not_found", &format!("Source account {} not found", source_account_id))
+                                        })
+                                        .map(move |mut accounts| (accounts.pop().unwrap(), account, settlement_engine)))),
+                                    None => Either::B(Either::B(ok((account.clone(), account, settlement_engine)))),
                                 }
                             })
-                            .and_then(move |(account, settlement_engine)| {
+                            .and_then(move |(from, account, settlement_engine)| {
                                 // Send the message to the peer's settlement engine.
-                                // Note that we use dummy values for the `from` and `original_amount`
+                                // Note that we use a dummy value for the `original_amount`
                                 // because this `OutgoingRequest` will bypass the router and thus will not
-                                // use either of these values. Including dummy values in the rare case where
-                                // we do not need them seems easier than using
-                                // `Option`s all over the place.
+                                // use it. Unless a source account is configured, the `from` account
+                                // is also set to the account the message is being sent to.
+                                let execution_condition = settlement_engine.peer_protocol_condition();
+                                let expiry = settlement_engine.message_timeout.unwrap_or(peer_protocol_expiry);
                                 outgoing_handler.send_request(OutgoingRequest {
-                                    from: account.clone(),
+                                    from,
                                     to: account.clone(),
                                     original_amount: 0,
                                     prepare: PrepareBuilder {
                                         destination: settlement_engine.ilp_address,
                                         amount: 0,
-                                        expires_at: SystemTime::now() + Duration::from_secs(30),
-                                        data: body.to_string().as_bytes().as_ref(),
-                                        execution_condition: &PEER_PROTOCOL_CONDITION,
-                                    }.build()
+                                        expires_at: SystemTime::now() + expiry,
+                                        data: data.as_bytes(),
+                                        execution_condition: &execution_condition,
+                                    }.build(),
+                                    correlation_id: None,
                                 })
                                 .map_err(|reject| {
-                                    error!("Error sending message to peer settlement engine. Packet rejected with code: {}, message: {}", reject.code(), str::from_utf8(reject.message()).unwrap_or_default());
+                                    let message = str::from_utf8(reject.message()).unwrap_or_default();
+                                    error!("Error sending message to peer settlement engine. Packet rejected with code: {}, message: {}", reject.code(), message);
                                     // TODO should we respond with different HTTP error codes based on the ILP error codes?
-                                    Response::builder().status(502).body(()).unwrap()
+                                    json_error(502, "peer_rejected", &format!("Message to the peer's settlement engine was rejected with code: {}, message: {}", reject.code(), message))
                                 })
+                                .map(move |fulfill| (fulfill, execution_condition))
                             })
-                            .and_then(|fulfill| {
-                                serde_json::from_slice(fulfill.data()).map_err(|err| {
-                                    error!("Error parsing response from peer settlement engine as JSON: {:?}", err);
-                                    Response::builder().status(502).body(()).unwrap()
-                                })
+                            .and_then(move |(fulfill, execution_condition)| {
+                                if verify_peer_fulfillment && !fulfillment_matches_condition(fulfill.fulfillment(), &execution_condition) {
+                                    error!("Fulfillment of the reply from the settlement engine of account {} does not match the condition of the message", account_id);
+                                    return err(json_error(502, "invalid_peer_fulfillment", "The fulfillment of the reply from the peer's settlement engine does not match the condition of the message"));
+                                }
+                                debug!("Received reply of {} bytes from the settlement engine of account {}", fulfill.data().len(), account_id);
+                                trace!("Reply from the settlement engine of account {}: {}", account_id, describe_message(fulfill.data(), redact_messages));
+                                let response: Value = match serde_json::from_slice(fulfill.data()) {
+                                    Ok(response) => response,
+                                    Err(parse_err) => {
+                                        error!("Error parsing response from peer settlement engine as JSON: {:?}", parse_err);
+                                        return err(json_error(502, "invalid_peer_response", "The reply from the peer's settlement engine is not valid JSON"));
+                                    }
+                                };
+                                // The local settlement engine expects the peer's response to be an object
+                                if response.is_object() {
+                                    ok(response)
+                                } else {
+                                    error!("Expected the response from peer settlement engine to be a JSON object, got: {}", response);
+                                    err(json_error(502, "invalid_peer_response", "The reply from the peer's settlement engine is not a JSON object"))
+                                }
                             }));
                     }
                 }
             }
-            Either::B(err(Response::builder().status(400).body(()).unwrap()))
+            Either::B(err(json_error(400, "invalid_request", "The message must be a JSON object with a valid accountId")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::PEER_PROTOCOL_CONDITION;
+    use interledger_packet::ErrorCode;
+    use log::Level;
+    use ring::digest::{digest, SHA256};
+    use std::time::Instant;
+    use tokio::runtime::Runtime;
+
+    // Settlement Tests
+
+    #[test]
+    fn receive_settlement_converts_scale() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        api.receive_settlement(
+            SettlementDetails {
+                account_id: "0".to_string(),
+                amount: 100,
+                scale: None,
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 100_000)]
+        );
+    }
+
+    #[test]
+    fn receive_settlement_uses_incoming_scale() {
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_incoming_asset_scale = Some(3);
+        let store = TestStore::new(vec![account]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        api.receive_settlement(
+            SettlementDetails {
+                account_id: "0".to_string(),
+                amount: 100,
+                scale: None,
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 100_000_000)]
+        );
+    }
+
+    #[test]
+    fn receive_settlement_uses_scale_of_the_amount() {
+        // Assuming the configured scale of 6 would credit 1000x too much
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6), TestAccount::new(1, 6, 9)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let applied = api
+            .receive_settlement(
+                SettlementDetails {
+                    account_id: "0".to_string(),
+                    amount: 100,
+                    scale: Some(9),
+                },
+                auth(),
+                None,
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(applied, json!({"appliedAmount": 100, "appliedScale": 9}));
+
+        // Assuming the configured scale of 9 would credit 1000x too little
+        api.receive_settlement(
+            SettlementDetails {
+                account_id: "1".to_string(),
+                amount: 100,
+                scale: Some(6),
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 100), (1, 100)]
+        );
+    }
+
+    #[test]
+    fn receive_settlement_checks_scale_range() {
+        let store = TestStore::new(vec![TestAccount::new(0, 18, 18)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        for scale in &[0, 18] {
+            let mut details = settlement("0", 1);
+            details.scale = Some(*scale);
+            api.receive_settlement(details, auth(), None)
+                .wait()
+                .unwrap();
         }
+        let mut details = settlement("0", 1);
+        details.scale = Some(30);
+        let rejected = api
+            .receive_settlement(details, auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(rejected.status(), 400);
+        let body: Value = serde_json::from_str(rejected.body()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "error": "Asset scale 30 is outside the accepted range of 0 to 18",
+                "code": "invalid_scale",
+            })
+        );
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 1_000_000_000_000_000_000), (0, 1)]
+        );
+    }
+
+    #[test]
+    fn receive_settlement_idempotency_covers_scale() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let key = Some("scaled".to_string());
+        api.receive_settlement(settlement("0", 100), auth(), key.clone())
+            .wait()
+            .unwrap();
+        let mut rescaled = settlement("0", 100);
+        rescaled.scale = Some(9);
+        let reused = api
+            .receive_settlement(rescaled, auth(), key)
+            .wait()
+            .unwrap_err();
+        assert_eq!(reused.status(), 409);
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 100_000)]
+        );
+    }
+
+    #[test]
+    fn receive_settlement_rejects_extreme_scale_difference() {
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_incoming_asset_scale = Some(40);
+        let store = TestStore::new(vec![account]);
+        let mut api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let rejected = api
+            .receive_settlement(
+                SettlementDetails {
+                    account_id: "0".to_string(),
+                    amount: 100,
+                    scale: None,
+                },
+                auth(),
+                None,
+            )
+            .wait()
+            .unwrap_err();
+        assert_eq!(rejected.status(), 400);
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+
+        api.scale_overflow_policy(ScaleOverflowPolicy::Clamp);
+        api.receive_settlement(
+            SettlementDetails {
+                account_id: "0".to_string(),
+                amount: 100,
+                scale: None,
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(*store.incoming_settlements.lock().unwrap(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn receive_settlement_reports_unapplied_settlements() {
+        init_test_logger();
+        let succeeding = TestStore::new(vec![TestAccount::new(22, 9, 9)]);
+        SettlementApi::new(
+            succeeding,
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        )
+        .receive_settlement(
+            SettlementDetails {
+                account_id: "22".to_string(),
+                amount: 100,
+                scale: None,
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+
+        let mut failing = TestStore::new(vec![TestAccount::new(21, 9, 9)]);
+        failing.fail_balance_updates = true;
+        let response = SettlementApi::new(
+            failing,
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        )
+        .receive_settlement(
+            SettlementDetails {
+                account_id: "21".to_string(),
+                amount: 100,
+                scale: None,
+            },
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap_err();
+        assert_eq!(response.status(), 500);
+        let body: Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(body["accountId"], "21");
+        assert_eq!(body["amount"], 100);
+
+        let events = logged_messages_for_target(UNAPPLIED_SETTLEMENT_LOG_TARGET);
+        assert!(events
+            .iter()
+            .any(|event| event.contains("for account 21 was received but not applied")));
+        assert!(!events.iter().any(|event| event.contains("account 22")));
+    }
+
+    #[test]
+    fn receive_settlement_echoes_applied_amount() {
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_incoming_asset_scale = Some(3);
+        let store = TestStore::new(vec![account]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let applied = api
+            .receive_settlement(
+                SettlementDetails {
+                    account_id: "0".to_string(),
+                    amount: 100,
+                    scale: None,
+                },
+                auth(),
+                None,
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(
+            applied,
+            json!({"appliedAmount": 100_000_000, "appliedScale": 9})
+        );
+    }
+
+    #[test]
+    fn receive_settlement_notifies_webhook_in_background() {
+        let mut runtime = Runtime::new().unwrap();
+        // The webhook takes longer to respond than the settlement may take
+        let (webhook_url, received) =
+            mock_webhook(&mut runtime, vec![200], Duration::from_secs(10));
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let mut api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        api.settlement_webhook(SettlementWebhook::new(webhook_url, b"webhook secret"));
+
+        let started = Instant::now();
+        runtime
+            .block_on(api.receive_settlement(settlement("0", 100), auth(), None))
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            *store.incoming_settlements.lock().unwrap(),
+            vec![(0, 100_000)]
+        );
+
+        wait_for_webhook(&received, 1, Duration::from_secs(5));
+        let body: Value = serde_json::from_str(&received.lock().unwrap()[0].0).unwrap();
+        assert_eq!(
+            body,
+            json!({"accountId": "0", "amount": "100000", "assetScale": 9})
+        );
+    }
+
+    #[test]
+    fn failed_settlements_do_not_notify_webhook() {
+        let mut runtime = Runtime::new().unwrap();
+        let (webhook_url, received) =
+            mock_webhook(&mut runtime, vec![200], Duration::from_millis(0));
+        let mut store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        store.fail_balance_updates = true;
+        let mut api = SettlementApi::new(
+            store,
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        api.settlement_webhook(SettlementWebhook::new(webhook_url, b"webhook secret"));
+        assert!(runtime
+            .block_on(api.receive_settlement(settlement("0", 100), auth(), None))
+            .is_err());
+        wait_for_webhook(&received, 1, Duration::from_millis(200));
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn receive_settlement_rejects_amounts_that_overflow() {
+        let mut account = TestAccount::new(0, 255, 6);
+        account.settlement_engine_incoming_asset_scale = Some(254);
+        let store = TestStore::new(vec![account]);
+        let mut api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let rejected = api
+            .receive_settlement(settlement("0", u64::MAX), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(rejected.status(), 400);
+        let body: Value = serde_json::from_str(rejected.body()).unwrap();
+        assert_eq!(
+            body["error"],
+            format!("Settlement amount {} cannot be converted from asset scale 254 to the asset scale 255 of account 0 because the converted amount does not fit in a u64", u64::MAX)
+        );
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+
+        // The overflow policy only applies when scaling down
+        for policy in &[ScaleOverflowPolicy::Clamp, ScaleOverflowPolicy::Floor] {
+            api.scale_overflow_policy(*policy);
+            let rejected = api
+                .receive_settlement(settlement("0", u64::MAX), auth(), None)
+                .wait()
+                .unwrap_err();
+            assert_eq!(rejected.status(), 400);
+        }
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+
+        let applied = api
+            .receive_settlement(settlement("0", u64::MAX / 10), auth(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            applied,
+            json!({"appliedAmount": u64::MAX / 10 * 10, "appliedScale": 255})
+        );
+    }
+
+    #[test]
+    fn receive_settlement_reports_precision_lost() {
+        let mut account = TestAccount::new(0, 6, 6);
+        account.settlement_engine_incoming_asset_scale = Some(9);
+        let store = TestStore::new(vec![account]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let applied = api
+            .receive_settlement(settlement("0", 1_234_567), auth(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            applied,
+            json!({"appliedAmount": 1234, "appliedScale": 6, "precisionLost": 567})
+        );
+
+        let mut account = TestAccount::new(1, 0, 6);
+        account.settlement_engine_incoming_asset_scale = Some(255);
+        let store = TestStore::new(vec![account]);
+        let mut api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let rejected = api
+            .receive_settlement(settlement("1", u64::MAX), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(rejected.status(), 400);
+        let body: Value = serde_json::from_str(rejected.body()).unwrap();
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .ends_with("because the scales are too far apart"));
+
+        api.scale_overflow_policy(ScaleOverflowPolicy::Floor);
+        let applied = api
+            .receive_settlement(settlement("1", u64::MAX), auth(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            applied,
+            json!({"appliedAmount": 0, "appliedScale": 0, "precisionLost": u64::MAX})
+        );
+    }
+
+    #[test]
+    fn receive_settlement_invalid_account_id() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let response = api
+            .receive_settlement(
+                SettlementDetails {
+                    account_id: "a".to_string(),
+                    amount: 100,
+                    scale: None,
+                },
+                auth(),
+                None,
+            )
+            .wait();
+        assert_eq!(response.err().unwrap().status(), 400);
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+    }
+
+    fn settlement(account_id: &str, amount: u64) -> SettlementDetails {
+        SettlementDetails {
+            account_id: account_id.to_string(),
+            amount,
+            scale: None,
+        }
+    }
+
+    #[test]
+    fn receive_settlement_with_idempotency_key_credits_once() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 9)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let key = Some("abc123".to_string());
+        let first = api
+            .receive_settlement(settlement("0", 100), auth(), key.clone())
+            .wait()
+            .unwrap();
+        let retry = api
+            .receive_settlement(settlement("0", 100), auth(), key.clone())
+            .wait()
+            .unwrap();
+        assert_eq!(first, retry);
+        assert_eq!(*store.incoming_settlements.lock().unwrap(), vec![(0, 100)]);
+        assert_eq!(
+            store.idempotent_data.lock().unwrap()["abc123"].body,
+            Bytes::from(first.to_string())
+        );
+
+        let reused = api
+            .receive_settlement(settlement("0", 200), auth(), key)
+            .wait()
+            .unwrap_err();
+        assert_eq!(reused.status(), 409);
+        assert_eq!(*store.incoming_settlements.lock().unwrap(), vec![(0, 100)]);
+    }
+
+    #[test]
+    fn receive_settlement_with_idempotency_key_in_progress() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 9)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let key = Some("abc123".to_string());
+        // As if another request with the key were being processed
+        api.idempotency_keys_in_progress
+            .lock()
+            .unwrap()
+            .insert("abc123".to_string());
+        let concurrent = api.receive_settlement(settlement("0", 100), auth(), key.clone());
+        assert_eq!(concurrent.wait().unwrap_err().status(), 409);
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+
+        api.idempotency_keys_in_progress.lock().unwrap().clear();
+        api.receive_settlement(settlement("0", 100), auth(), key.clone())
+            .wait()
+            .unwrap();
+        api.receive_settlement(settlement("0", 100), auth(), key)
+            .wait()
+            .unwrap();
+        assert_eq!(*store.incoming_settlements.lock().unwrap(), vec![(0, 100)]);
+        assert!(api.idempotency_keys_in_progress.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropping_a_reservation_releases_the_idempotency_key() {
+        let keys_in_progress = Arc::new(Mutex::new(HashSet::new()));
+        let reservation = IdempotencyKeyReservation::reserve(&keys_in_progress, "abc123").unwrap();
+        assert!(IdempotencyKeyReservation::reserve(&keys_in_progress, "abc123").is_none());
+        assert!(IdempotencyKeyReservation::reserve(&keys_in_progress, "def456").is_some());
+
+        // As if the request's future were dropped before it completed
+        drop(reservation);
+        assert!(keys_in_progress.lock().unwrap().is_empty());
+        assert!(IdempotencyKeyReservation::reserve(&keys_in_progress, "abc123").is_some());
+    }
+
+    #[test]
+    fn unauthenticated_requests_do_not_hold_idempotency_keys() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 9)]);
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let key = Some("abc123".to_string());
+        let unauthenticated = api.receive_settlement(
+            settlement("0", 100),
+            Some("Bearer wrong".to_string()),
+            key.clone(),
+        );
+        api.receive_settlement(settlement("0", 100), auth(), key)
+            .wait()
+            .unwrap();
+        assert_eq!(unauthenticated.wait().unwrap_err().status(), 401);
+        assert_eq!(*store.incoming_settlements.lock().unwrap(), vec![(0, 100)]);
+    }
+
+    #[test]
+    fn failed_settlements_are_not_saved() {
+        let mut store = TestStore::new(vec![TestAccount::new(0, 9, 9)]);
+        store.fail_balance_updates = true;
+        let api = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let response = api
+            .receive_settlement(settlement("0", 100), auth(), Some("abc123".to_string()))
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 500);
+        assert!(store.idempotent_data.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_requests_without_the_settlement_engine_secret() {
+        // There are no accounts, so these would also be rejected if they reached the store
+        let store = TestStore::new(Vec::new());
+        let api = SettlementApi::new(store.clone(), MockOutgoingService::fulfill(b"{}"));
+        let missing = api
+            .receive_settlement(settlement("0", 100), None, None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(missing.status(), 401);
+        let not_bearer = api
+            .get_settlement_info("0".to_string(), Some(TEST_AUTH_TOKEN.to_string()))
+            .wait()
+            .unwrap_err();
+        assert_eq!(not_bearer.status(), 401);
+        let missing = api
+            .send_outgoing_message(json!({"accountId": "0"}), None, None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(missing.status(), 401);
+    }
+
+    #[test]
+    fn rejects_requests_with_the_wrong_secret() {
+        let mut without_secret = TestAccount::new(1, 9, 9);
+        without_secret.settlement_engine_auth_token = None;
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 9), without_secret]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let api = SettlementApi::new(store.clone(), outgoing.clone());
+        let wrong = Some("Bearer wrong_secret".to_string());
+        let response = api
+            .receive_settlement(settlement("0", 100), wrong.clone(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        let response = api
+            .get_settlement_info("0".to_string(), wrong.clone())
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        let response = api
+            .send_outgoing_message(json!({"accountId": "0"}), wrong, None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        let response = api
+            .receive_settlement(settlement("1", 100), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+        assert!(outgoing.sent_requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_accounts_get_the_same_response_as_wrong_secrets() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 9)]);
+        let api = SettlementApi::new(store.clone(), MockOutgoingService::fulfill(b"{}"));
+        let unknown_account = api
+            .receive_settlement(settlement("1", 100), auth(), None)
+            .wait()
+            .unwrap_err();
+        let wrong_secret = api
+            .receive_settlement(
+                settlement("0", 100),
+                Some("Bearer wrong_secret".to_string()),
+                None,
+            )
+            .wait()
+            .unwrap_err();
+        assert_eq!(unknown_account.status(), 401);
+        assert_eq!(unknown_account.status(), wrong_secret.status());
+        assert_eq!(unknown_account.body(), wrong_secret.body());
+
+        let unknown_account = api
+            .send_outgoing_message(json!({"accountId": "1"}), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(unknown_account.status(), 401);
+        assert_eq!(unknown_account.body(), wrong_secret.body());
+    }
+
+    #[test]
+    fn rejects_overlong_tokens() {
+        let mut account = TestAccount::new(0, 9, 9);
+        let secret = "a".repeat(MAX_BEARER_TOKEN_LENGTH + 1);
+        account.settlement_engine_auth_token = Some(secret.clone());
+        let store = TestStore::new(vec![account]);
+        let api = SettlementApi::new(store.clone(), MockOutgoingService::fulfill(b"{}"));
+        let response = api
+            .receive_settlement(
+                settlement("0", 100),
+                Some(format!("Bearer {}", secret)),
+                None,
+            )
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        assert!(store.incoming_settlements.lock().unwrap().is_empty());
+    }
+
+    // Settlement Info Tests
+
+    #[test]
+    fn settlement_info_for_configured_account() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{}"));
+        let info = api
+            .get_settlement_info("0".to_string(), auth())
+            .wait()
+            .unwrap();
+        assert_eq!(
+            info,
+            json!({"assetCode": "XYZ", "assetScale": 9, "ilpAddress": "peer.settle.xyz"})
+        );
+    }
+
+    #[test]
+    fn settlement_info_for_unknown_account() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{}"));
+        let response = api.get_settlement_info("1".to_string(), auth()).wait();
+        assert_eq!(response.err().unwrap().status(), 401);
+    }
+
+    // Settlement Balance Tests
+
+    #[test]
+    fn settlement_balance_for_configured_account() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        store.balances.lock().unwrap().insert(0, -250);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{}"));
+        let balance = api
+            .get_settlement_balance("0".to_string(), auth())
+            .wait()
+            .unwrap();
+        assert_eq!(
+            balance,
+            json!({"amount": "-250", "scale": 9, "assetCode": "XYZ"})
+        );
+    }
+
+    #[test]
+    fn settlement_balance_for_unknown_account() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{}"));
+        let response = api
+            .get_settlement_balance("1".to_string(), auth())
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+        let response = api
+            .get_settlement_balance("0".to_string(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 401);
+    }
+
+    // Message Tests
+
+    #[test]
+    fn send_message_fulfilled() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let outgoing = MockOutgoingService::fulfill(b"{\"status\":\"ok\"}");
+        let api = SettlementApi::new(store, outgoing.clone());
+        let response = api
+            .send_outgoing_message(json!({"accountId": "0", "type": "paychan"}), auth(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(response, json!({"status": "ok"}));
+        let sent: Value =
+            serde_json::from_slice(outgoing.sent_requests.lock().unwrap()[0].prepare.data())
+                .unwrap();
+        assert_eq!(sent, json!({"accountId": "0", "type": "paychan"}));
+    }
+
+    fn condition_for(fulfillment: &[u8; 32]) -> [u8; 32] {
+        let mut condition = [0; 32];
+        condition.copy_from_slice(digest(&SHA256, fulfillment).as_ref());
+        condition
+    }
+
+    #[test]
+    fn send_message_uses_peer_protocol_condition() {
+        let mut account = TestAccount::new(1, 9, 6);
+        account.settlement_engine_peer_protocol_fulfillment = Some([7; 32]);
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6), account]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        SettlementApi::new(store.clone(), outgoing.clone())
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap();
+        let custom_outgoing = MockOutgoingService::fulfill_with(&[7; 32], b"{}");
+        SettlementApi::new(store, custom_outgoing.clone())
+            .send_outgoing_message(json!({"accountId": "1"}), auth(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            outgoing.sent_requests.lock().unwrap()[0]
+                .prepare
+                .execution_condition(),
+            &PEER_PROTOCOL_CONDITION[..]
+        );
+        assert_eq!(
+            custom_outgoing.sent_requests.lock().unwrap()[0]
+                .prepare
+                .execution_condition(),
+            &condition_for(&[7; 32])[..]
+        );
+    }
+
+    #[test]
+    fn send_message_verifies_fulfillment() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let correct = SettlementApi::new(
+            store.clone(),
+            MockOutgoingService::fulfill_with(&[0; 32], b"{}"),
+        );
+        assert!(correct
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .is_ok());
+
+        let mut incorrect =
+            SettlementApi::new(store, MockOutgoingService::fulfill_with(&[1; 32], b"{}"));
+        let response = incorrect
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(response.status(), 502);
+
+        // Unless verification is turned off
+        incorrect.verify_peer_fulfillment(false);
+        assert!(incorrect
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .is_ok());
+    }
+
+    #[test]
+    fn send_message_forwards_idempotency_key() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let api = SettlementApi::new(store, outgoing.clone());
+        // The engine retries the same message with the same key
+        for _ in 0..2 {
+            api.send_outgoing_message(
+                json!({"accountId": "0", "type": "paychan"}),
+                auth(),
+                Some("abc123".to_string()),
+            )
+            .wait()
+            .unwrap();
+        }
+        let sent_requests = outgoing.sent_requests.lock().unwrap();
+        assert_eq!(sent_requests.len(), 2);
+        for request in sent_requests.iter() {
+            let sent: Value = serde_json::from_slice(request.prepare.data()).unwrap();
+            assert_eq!(
+                sent,
+                json!({"accountId": "0", "type": "paychan", "idempotencyKey": "abc123"})
+            );
+        }
+    }
+
+    #[test]
+    fn logs_message_exchange_at_trace_level() {
+        init_test_logger();
+        let store = TestStore::new(vec![TestAccount::new(11, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{\"claim\":\"xyz\"}"));
+        api.send_outgoing_message(
+            json!({"accountId": "11", "type": "trace-test"}),
+            auth(),
+            None,
+        )
+        .wait()
+        .unwrap();
+        let trace = logged_messages(Level::Trace);
+        assert!(trace.contains(
+            &r#"Message to the settlement engine of account 11: {"accountId":"11","type":"trace-test"}"#
+                .to_string()
+        ));
+        assert!(trace.contains(
+            &r#"Reply from the settlement engine of account 11: {"claim":"xyz"}"#.to_string()
+        ));
+        assert!(logged_messages(Level::Debug).contains(
+            &"Received reply of 15 bytes from the settlement engine of account 11".to_string()
+        ));
+    }
+
+    #[test]
+    fn redacts_logged_messages() {
+        init_test_logger();
+        let store = TestStore::new(vec![TestAccount::new(12, 9, 6)]);
+        let mut api =
+            SettlementApi::new(store, MockOutgoingService::fulfill(b"{\"claim\":\"xyz\"}"));
+        api.redact_messages(true);
+        api.send_outgoing_message(json!({"accountId": "12"}), auth(), None)
+            .wait()
+            .unwrap();
+        let trace = logged_messages(Level::Trace);
+        assert!(trace.contains(
+            &"Message to the settlement engine of account 12: <18 bytes redacted>".to_string()
+        ));
+        assert!(trace.contains(
+            &"Reply from the settlement engine of account 12: <15 bytes redacted>".to_string()
+        ));
+    }
+
+    #[test]
+    fn send_message_response_must_be_object() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"42"));
+        let response = api
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait();
+        assert_eq!(response.err().unwrap().status(), 502);
+    }
+
+    #[test]
+    fn send_message_invalid_json_response() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(store, MockOutgoingService::fulfill(b"{\"status\""));
+        let response = api
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait();
+        assert_eq!(response.err().unwrap().status(), 502);
+    }
+
+    #[test]
+    fn send_message_rejected() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(
+            store,
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let response = api
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait();
+        assert_eq!(response.err().unwrap().status(), 502);
+    }
+
+    #[test]
+    fn send_message_without_account_id() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let api = SettlementApi::new(store, outgoing.clone());
+        let response = api
+            .send_outgoing_message(json!({"type": "paychan"}), auth(), None)
+            .wait();
+        assert_eq!(response.err().unwrap().status(), 400);
+        assert!(outgoing.sent_requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_responses_explain_the_error() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let api = SettlementApi::new(
+            store,
+            MockOutgoingService::reject(ErrorCode::F02_UNREACHABLE),
+        );
+        let body = |response: Response<String>| -> Value {
+            serde_json::from_str(response.body()).unwrap()
+        };
+
+        let missing = api
+            .receive_settlement(settlement("5", 100), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(missing.status(), 401);
+        assert_eq!(body(missing)["code"], "unauthorized");
+
+        let rejected = api
+            .send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(rejected.status(), 502);
+        assert_eq!(body(rejected)["code"], "peer_rejected");
+
+        let unauthenticated = api
+            .send_outgoing_message(json!({"accountId": "0"}), None, None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(unauthenticated.status(), 401);
+        assert_eq!(body(unauthenticated)["code"], "unauthorized");
+    }
+
+    #[test]
+    fn send_message_from_configured_source_account() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6), TestAccount::new(1, 9, 6)]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let mut api = SettlementApi::new(store, outgoing.clone());
+        api.source_account(1);
+        api.send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap();
+        let requests = outgoing.sent_requests.lock().unwrap();
+        assert_eq!(requests[0].from.id(), 1);
+        assert_eq!(requests[0].to.id(), 0);
+    }
+
+    #[test]
+    fn send_message_uses_configured_expiry() {
+        let store = TestStore::new(vec![TestAccount::new(0, 9, 6)]);
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let mut api = SettlementApi::new(store, outgoing.clone());
+        api.peer_protocol_expiry(Duration::from_secs(5));
+        api.send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap();
+        let expires_in = outgoing.sent_requests.lock().unwrap()[0]
+            .prepare
+            .expires_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(expires_in <= Duration::from_secs(5));
+        assert!(expires_in > Duration::from_secs(4));
+    }
+
+    #[test]
+    fn send_message_uses_settlement_engine_timeout() {
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_message_timeout = Some(Duration::from_secs(2));
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let api = SettlementApi::new(TestStore::new(vec![account]), outgoing.clone());
+        api.send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap();
+        let expires_in = outgoing.sent_requests.lock().unwrap()[0]
+            .prepare
+            .expires_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(expires_in <= Duration::from_secs(2));
+        assert!(expires_in > Duration::from_secs(1));
+    }
+
+    #[test]
+    fn send_message_expires_after_30_seconds_by_default() {
+        let outgoing = MockOutgoingService::fulfill(b"{}");
+        let api = SettlementApi::new(
+            TestStore::new(vec![TestAccount::new(0, 9, 6)]),
+            outgoing.clone(),
+        );
+        api.send_outgoing_message(json!({"accountId": "0"}), auth(), None)
+            .wait()
+            .unwrap();
+        let expires_in = outgoing.sent_requests.lock().unwrap()[0]
+            .prepare
+            .expires_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(expires_in <= Duration::from_secs(30));
+        assert!(expires_in > Duration::from_secs(29));
     }
 }
@@ -0,0 +1,221 @@
+use crate::{PendingSettlementStore, SettlementAccount, SettlementClient};
+use futures::{
+    future::{join_all, loop_fn, Either, Loop},
+    Future,
+};
+use interledger_ildcp::IldcpAccount;
+use interledger_service::Account;
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+type Batch<A> = HashMap<<A as Account>::AccountId, (A, u64)>;
+
+/// # Settlement Batcher
+///
+/// Collects the settlements a store triggers when fulfilled packets take accounts over their
+/// settle threshold and sends each account's total to its settlement engine once per batch
+/// window, rather than once per packet. This cuts down the requests to the engine when an
+/// account with a low settle threshold fulfills many small packets.
+///
+/// The store must already have reserved each amount before it is added, so the same balance is
+/// not settled twice. Like other settlements, the total is only debited from the account's balance
+/// once the engine accepts it and is queued for the `SettlementRetrier` if the engine cannot be reached.
+/// Batched amounts are only held in memory until the end of the window, so the future returned by
+/// `run_until` sends whatever is left in the batch before it resolves.
+#[derive(Clone)]
+pub struct SettlementBatcher<A: Account> {
+    settlement_client: SettlementClient,
+    batch_window: Duration,
+    batch: Arc<Mutex<Batch<A>>>,
+}
+
+impl<A> SettlementBatcher<A>
+where
+    A: SettlementAccount + IldcpAccount + Send + Sync + 'static,
+{
+    pub fn new(settlement_client: SettlementClient) -> Self {
+        SettlementBatcher {
+            settlement_client,
+            batch_window: DEFAULT_BATCH_WINDOW,
+            batch: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set how long settlements are collected before they are sent. Defaults to 500ms.
+    pub fn batch_window(&mut self, batch_window: Duration) -> &mut Self {
+        self.batch_window = batch_window;
+        self
+    }
+
+    /// Add an amount the store has reserved for a settlement to the account's next batched settlement.
+    pub fn add(&self, account: A, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        trace!(
+            "Batching settlement of {} for account {}",
+            amount,
+            account.id()
+        );
+        self.batch
+            .lock()
+            .unwrap()
+            .entry(account.id())
+            .or_insert_with(|| (account, 0))
+            .1 += amount;
+    }
+
+    /// Settle everything collected so far, sending one settlement per account.
+    pub fn flush<S>(&self, store: &S) -> impl Future<Item = (), Error = ()>
+    where
+        S: PendingSettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    {
+        let batch = mem::take(&mut *self.batch.lock().unwrap());
+        let settlements: Vec<_> = batch
+            .into_iter()
+            .map(|(account_id, (account, amount))| {
+                let store = store.clone();
+                let store_clone = store.clone();
+                trace!(
+                    "Sending batched settlement of {} for account {}",
+                    amount,
+                    account_id
+                );
+                self.settlement_client
+                    .send_settlement(account, amount)
+                    .then(move |result| match result {
+                        Ok(_) => Either::A(store.confirm_settlement(account_id, amount)),
+                        Err(_) => Either::B(store_clone.queue_settlement(account_id, amount)),
+                    })
+                    .then(move |result| {
+                        if result.is_err() {
+                            error!(
+                                "Error settling batched amount of {} for account {}",
+                                amount, account_id
+                            );
+                        }
+                        Ok(())
+                    })
+            })
+            .collect();
+        join_all(settlements).map(|_| ())
+    }
+
+    /// Settle the batch at the end of every window until the `shutdown` future resolves,
+    /// then settle whatever is left. The returned future should be spawned on the same
+    /// executor as the node.
+    pub fn run_until<S, F>(self, store: S, shutdown: F) -> impl Future<Item = (), Error = ()>
+    where
+        S: PendingSettlementStore<Account = A> + Clone + Send + Sync + 'static,
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let batch_window = self.batch_window;
+        loop_fn(shutdown, move |shutdown| {
+            let batcher = self.clone();
+            let store = store.clone();
+            Delay::new(Instant::now() + batch_window)
+                .select2(shutdown)
+                .then(move |result| match result {
+                    Ok(Either::A((_, shutdown))) => Either::A(
+                        batcher
+                            .flush(&store)
+                            .then(move |_| Ok(Loop::Continue(shutdown))),
+                    ),
+                    result => {
+                        if let Err(Either::A((err, _))) = result {
+                            error!("Timer error in settlement batcher: {:?}", err);
+                        }
+                        debug!("Settling batched amounts before shutting down");
+                        Either::B(batcher.flush(&store).then(|_| Ok(Loop::Break(()))))
+                    }
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use futures::{future::ok, sync::oneshot};
+    use serde_json::json;
+    use std::thread::sleep;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn settles_amounts_in_window_together() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = engine_url;
+        let store = TestStore::new(vec![account.clone()]);
+        store.balances.lock().unwrap().insert(0, 600);
+        store.pending_settlements.lock().unwrap().insert(0, 600);
+        let mut batcher = SettlementBatcher::new(SettlementClient::new());
+        batcher.batch_window(Duration::from_millis(50));
+        let (stop, stopped) = oneshot::channel::<()>();
+        runtime.spawn(
+            batcher
+                .clone()
+                .run_until(store.clone(), stopped.then(|_| Ok(()))),
+        );
+
+        for amount in &[100, 200, 300] {
+            batcher.add(account.clone(), *amount);
+        }
+        sleep(Duration::from_millis(500));
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({"accountId": "0", "amount": "600"})]
+        );
+        assert_eq!(*store.confirmed_settlements.lock().unwrap(), vec![(0, 600)]);
+        assert_eq!(store.balances.lock().unwrap()[&0], 0);
+        assert_eq!(store.pending_settlements.lock().unwrap()[&0], 0);
+        stop.send(()).unwrap();
+    }
+
+    #[test]
+    fn does_not_send_empty_batches() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = engine_url;
+        let batcher = SettlementBatcher::new(SettlementClient::new());
+
+        batcher.add(account, 0);
+        runtime
+            .block_on(batcher.flush(&TestStore::new(Vec::new())))
+            .unwrap();
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn settles_batch_on_shutdown() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = engine_url;
+        let store = TestStore::new(vec![account.clone()]);
+        store.balances.lock().unwrap().insert(0, 100);
+        store.pending_settlements.lock().unwrap().insert(0, 100);
+        let mut batcher = SettlementBatcher::new(SettlementClient::new());
+        batcher.batch_window(Duration::from_secs(3600));
+
+        batcher.add(account, 100);
+        runtime
+            .block_on(batcher.run_until(store.clone(), ok(())))
+            .unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({"accountId": "0", "amount": "100"})]
+        );
+        assert_eq!(*store.confirmed_settlements.lock().unwrap(), vec![(0, 100)]);
+    }
+}
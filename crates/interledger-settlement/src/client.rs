@@ -1,6 +1,6 @@
-use super::SettlementAccount;
+use super::{normalize_amount, PendingSettlementStore, RoundingMode, SettlementAccount};
 use futures::{
-    future::{err, Either},
+    future::{err, ok, Either},
     Future,
 };
 use interledger_ildcp::IldcpAccount;
@@ -17,32 +17,43 @@ struct SendSettlement {
 #[derive(Clone)]
 pub struct SettlementClient {
     http_client: Client,
+    rounding_mode: RoundingMode,
 }
 
 impl SettlementClient {
     pub fn new() -> Self {
         SettlementClient {
             http_client: Client::new(),
+            rounding_mode: RoundingMode::Floor,
         }
     }
 
+    /// Set how amounts are rounded when the settlement engine uses a smaller asset scale
+    /// than the account. Defaults to `RoundingMode::Floor`.
+    pub fn rounding_mode(&mut self, rounding_mode: RoundingMode) -> &mut Self {
+        self.rounding_mode = rounding_mode;
+        self
+    }
+
     pub fn send_settlement<A: SettlementAccount + IldcpAccount>(
         &self,
         account: A,
         amount: u64,
     ) -> impl Future<Item = (), Error = ()> {
         if let Some(settlement_engine) = account.settlement_engine_details() {
-            let mut settlement_engine_url = settlement_engine.url;
-            let amount = if settlement_engine.asset_scale >= account.asset_scale() {
-                amount
-                    * 10u64.pow(u32::from(
-                        settlement_engine.asset_scale - account.asset_scale(),
-                    ))
-            } else {
-                amount
-                    / 10u64.pow(u32::from(
-                        account.asset_scale() - settlement_engine.asset_scale,
-                    ))
+            let mut settlement_engine_url = settlement_engine.url.clone();
+            let outgoing_scale = settlement_engine.outgoing_asset_scale();
+            let amount = match normalize_amount(
+                amount,
+                account.asset_scale(),
+                outgoing_scale,
+                self.rounding_mode,
+            ) {
+                Some(amount) => amount,
+                None => {
+                    error!("Cannot send settlement of {} for account {} because the amount overflows when converted from scale {} to the settlement engine's scale {}", amount, account.id(), account.asset_scale(), outgoing_scale);
+                    return Either::B(err(()));
+                }
             };
 
             settlement_engine_url
@@ -77,6 +88,55 @@ impl SettlementClient {
         error!("Cannot send settlement for account {} because it does not have the settlement_engine_url and scale configured", account.id());
         Either::B(err(()))
     }
+
+    /// Settle the account's whole outstanding balance now, rather than waiting for it to cross
+    /// the settle threshold. Resolves to the amount settled, which is 0 if there was nothing to settle.
+    ///
+    /// Like automatic settlements, the amount is only debited once the settlement engine accepts it.
+    /// If the engine cannot be reached, the settlement is queued for the `SettlementRetrier`.
+    pub fn settle_outstanding_balance<S, A>(
+        &self,
+        store: S,
+        account: A,
+    ) -> impl Future<Item = u64, Error = ()>
+    where
+        S: PendingSettlementStore<Account = A> + Clone + Send + Sync + 'static,
+        A: SettlementAccount + IldcpAccount + Send + Sync + 'static,
+    {
+        let client = self.clone();
+        let account_id = account.id();
+        if account.settlement_engine_details().is_none() {
+            error!("Cannot settle the balance of account {} because it does not have a settlement engine configured", account_id);
+            return Either::A(err(()));
+        }
+        Either::B(
+            store
+                .reserve_outstanding_balance(account_id)
+                .and_then(move |amount| {
+                    if amount == 0 {
+                        debug!(
+                            "Account {} has no outstanding balance to settle",
+                            account_id
+                        );
+                        return Either::A(ok(0));
+                    }
+                    Either::B(client.send_settlement(account, amount).then(move |result| {
+                        match result {
+                            Ok(_) => Either::A(
+                                store
+                                    .confirm_settlement(account_id, amount)
+                                    .map(move |_| amount),
+                            ),
+                            Err(_) => Either::B(
+                                store
+                                    .queue_settlement(account_id, amount)
+                                    .and_then(|_| Err(())),
+                            ),
+                        }
+                    }))
+                }),
+        )
+    }
 }
 
 impl Default for SettlementClient {
@@ -84,3 +144,68 @@ impl Default for SettlementClient {
         SettlementClient::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use serde_json::json;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn send_settlement_uses_outgoing_scale() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_url = engine_url;
+        account.settlement_engine_outgoing_asset_scale = Some(3);
+        runtime
+            .block_on(SettlementClient::new().send_settlement(account, 1_000_000))
+            .unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({"accountId": "0", "amount": "1"})]
+        );
+    }
+
+    #[test]
+    fn settles_outstanding_balance() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = engine_url;
+        let store = TestStore::new(vec![account.clone()]);
+        store.balances.lock().unwrap().insert(0, 500);
+        let settled = runtime
+            .block_on(SettlementClient::new().settle_outstanding_balance(store.clone(), account))
+            .unwrap();
+        assert_eq!(settled, 500);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({"accountId": "0", "amount": "500"})]
+        );
+        assert_eq!(*store.confirmed_settlements.lock().unwrap(), vec![(0, 500)]);
+        assert_eq!(store.balances.lock().unwrap()[&0], 0);
+        assert_eq!(store.pending_settlements.lock().unwrap()[&0], 0);
+    }
+
+    #[test]
+    fn nothing_to_settle() {
+        let mut runtime = Runtime::new().unwrap();
+        let (engine_url, received) = mock_engine(&mut runtime);
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = engine_url;
+        let store = TestStore::new(vec![account.clone()]);
+        store.balances.lock().unwrap().insert(0, -100);
+        let settled = runtime
+            .block_on(SettlementClient::new().settle_outstanding_balance(store.clone(), account))
+            .unwrap();
+        assert_eq!(settled, 0);
+        assert!(received.lock().unwrap().is_empty());
+        assert!(store.confirmed_settlements.lock().unwrap().is_empty());
+        assert_eq!(store.balances.lock().unwrap()[&0], -100);
+    }
+}
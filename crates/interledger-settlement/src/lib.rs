@@ -5,18 +5,168 @@ extern crate log;
 #[macro_use]
 extern crate tower_web;
 
+use bytes::Bytes;
 use futures::Future;
+use interledger_ildcp::convert_scale;
 use interledger_packet::Address;
-use interledger_service::Account;
+use interledger_service::{Account, AccountStore};
+use ring::digest::{digest, SHA256};
+use std::time::Duration;
 use url::Url;
 
 mod api;
+mod batcher;
 mod client;
 mod message_service;
+mod retrier;
+#[cfg(test)]
+mod test_helpers;
+mod webhook;
 
-pub use api::SettlementApi;
+pub use api::{SettlementApi, UNAPPLIED_SETTLEMENT_LOG_TARGET};
+pub use batcher::SettlementBatcher;
 pub use client::SettlementClient;
-pub use message_service::SettlementMessageService;
+pub use message_service::{SettlementMessageService, IDEMPOTENCY_KEY_FIELD};
+pub use retrier::SettlementRetrier;
+pub use webhook::{SettlementWebhook, WEBHOOK_SIGNATURE_HEADER};
+
+/// How to round an amount when converting it to a smaller asset scale loses precision.
+///
+/// Whatever is rounded off is not settled, so the mode decides which side absorbs the loss.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundingMode {
+    /// Round down. The side receiving the converted amount is credited or paid up to
+    /// one unit of the smaller scale less than the original amount.
+    Floor,
+    /// Round up. The side receiving the converted amount is credited or paid up to
+    /// one unit of the smaller scale more than the original amount.
+    Ceil,
+    /// Round to the nearest unit, with halves rounded up. The loss on any one amount is at most
+    /// half a unit and tends to even out between the two sides over many settlements.
+    Round,
+}
+
+/// Convert an amount from one asset scale to another with `convert_scale`, using the given
+/// rounding mode if the amount cannot be represented exactly in the new scale.
+/// Returns `None` if the converted amount does not fit in a `u64`.
+///
+/// Both scales are `u8`s, like `IldcpAccount::asset_scale` and the scales in `SettlementEngineDetails`,
+/// so the account's and the settlement engine's scales can be passed in as they are.
+pub fn normalize_amount(
+    amount: u64,
+    from_scale: u8,
+    to_scale: u8,
+    rounding: RoundingMode,
+) -> Option<u64> {
+    let quotient = convert_scale(amount, from_scale, to_scale)?;
+    if to_scale >= from_scale || rounding == RoundingMode::Floor {
+        return Some(quotient);
+    }
+    // Scaling the rounded down amount back up cannot overflow, because it is at most the original amount
+    let remainder = amount - convert_scale(quotient, to_scale, from_scale).unwrap_or(0);
+    let rounds_up = match rounding {
+        RoundingMode::Ceil => remainder > 0,
+        // If the divisor does not fit in a u64, no amount is half of it
+        RoundingMode::Round => convert_scale(1, to_scale, from_scale)
+            .map(|divisor| remainder >= divisor - divisor / 2)
+            .unwrap_or(false),
+        RoundingMode::Floor => false,
+    };
+    Some(if rounds_up { quotient + 1 } else { quotient })
+}
+
+/// The largest difference between two asset scales that amounts can be converted across.
+/// The factor for a larger difference does not fit in a `u64`, so every amount would overflow
+/// when scaling up and be rounded to zero when scaling down.
+pub const MAX_SCALE_DIFFERENCE: u8 = 19;
+
+/// What to do with an incoming settlement that cannot be scaled down to the account's asset scale,
+/// because the scales differ by more than `MAX_SCALE_DIFFERENCE`.
+///
+/// Settlements that cannot be scaled up, because the converted amount would overflow,
+/// are always rejected whatever the policy, because any amount credited for them would
+/// be one the settlement engine never sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleOverflowPolicy {
+    /// Refuse the settlement, so that it is not credited at all
+    Reject,
+    /// Credit the nearest amount that can be represented, which is 1 for a non-zero amount
+    Clamp,
+    /// Log a warning and credit the amount rounded down, which is zero
+    Floor,
+}
+
+/// Convert an incoming settlement amount to the account's asset scale like `normalize_amount`,
+/// using the given policy if the scales are too far apart to scale the amount down.
+/// Returns `None` if the amount cannot be scaled up or if the policy is to reject the settlement.
+pub fn normalize_settlement_amount(
+    amount: u64,
+    from_scale: u8,
+    to_scale: u8,
+    rounding: RoundingMode,
+    policy: ScaleOverflowPolicy,
+) -> Option<u64> {
+    let scaling_up = to_scale >= from_scale;
+    let scale_difference = if scaling_up {
+        to_scale - from_scale
+    } else {
+        from_scale - to_scale
+    };
+    let converted = if scale_difference > MAX_SCALE_DIFFERENCE {
+        None
+    } else {
+        normalize_amount(amount, from_scale, to_scale, rounding)
+    };
+    if converted.is_some() {
+        return converted;
+    }
+    if scaling_up {
+        error!(
+            "Rejecting settlement of {} because it overflows when converted from scale {} to scale {}",
+            amount, from_scale, to_scale
+        );
+        return None;
+    }
+
+    match policy {
+        ScaleOverflowPolicy::Reject => {
+            error!(
+                "Rejecting settlement of {} because it cannot be converted from scale {} to scale {}",
+                amount, from_scale, to_scale
+            );
+            None
+        }
+        ScaleOverflowPolicy::Clamp => {
+            let clamped = amount.min(1);
+            warn!(
+                "Settlement of {} cannot be converted from scale {} to scale {}, clamping it to: {}",
+                amount, from_scale, to_scale, clamped
+            );
+            Some(clamped)
+        }
+        ScaleOverflowPolicy::Floor => {
+            warn!(
+                "Settlement of {} cannot be converted from scale {} to scale {}, crediting: 0",
+                amount, from_scale, to_scale
+            );
+            Some(0)
+        }
+    }
+}
+
+/// The execution condition of peer protocol messages, such as the ones sent to settlement engines
+pub const PEER_PROTOCOL_CONDITION: [u8; 32] = [
+    102, 104, 122, 173, 248, 98, 189, 119, 108, 143, 193, 139, 142, 159, 142, 32, 8, 151, 20, 133,
+    110, 226, 51, 179, 144, 42, 89, 29, 13, 95, 41, 37,
+];
+
+/// The fulfillment of `PEER_PROTOCOL_CONDITION`
+pub const PEER_PROTOCOL_FULFILLMENT: [u8; 32] = [0; 32];
+
+/// Check that a fulfillment is the SHA-256 preimage of the given execution condition
+pub fn fulfillment_matches_condition(fulfillment: &[u8], condition: &[u8]) -> bool {
+    digest(&SHA256, fulfillment).as_ref() == condition
+}
 
 pub struct SettlementEngineDetails {
     /// Base URL of the settlement engine
@@ -27,23 +177,299 @@ pub struct SettlementEngineDetails {
     /// The SettlementClient translates the amounts used for each account internally within
     /// Interledger.rs into the correct scale used by the settlement engine.
     pub asset_scale: u8,
+    /// Asset scale of the amounts the settlement engine reports as received, if it differs from `asset_scale`.
+    pub incoming_asset_scale: Option<u8>,
+    /// Asset scale of the amounts the settlement engine is asked to send, if it differs from `asset_scale`.
+    pub outgoing_asset_scale: Option<u8>,
     /// The ILP address of the settlement engine. For example, `peer.settle.xrp-paychan`.
     /// Note that both peers' settlement engines are expected to use the same address.
     pub ilp_address: Address,
+    /// The fulfillment of the execution condition to use for messages between the peers' settlement
+    /// engines, if the peers have agreed on one other than the standard `PEER_PROTOCOL_FULFILLMENT`.
+    /// The condition is derived from it, so that messages from the peer can be fulfilled too.
+    pub peer_protocol_fulfillment: Option<[u8; 32]>,
+    /// How long messages to the peer's settlement engine are valid for, if this engine needs
+    /// a different timeout than the `SettlementApi`'s `peer_protocol_expiry`. For example,
+    /// engines that wait for on-chain confirmations before replying may need much longer.
+    pub message_timeout: Option<Duration>,
+}
+
+impl SettlementEngineDetails {
+    /// The asset scale used for incoming settlements
+    pub fn incoming_asset_scale(&self) -> u8 {
+        self.incoming_asset_scale.unwrap_or(self.asset_scale)
+    }
+
+    /// The asset scale used for outgoing settlements
+    pub fn outgoing_asset_scale(&self) -> u8 {
+        self.outgoing_asset_scale.unwrap_or(self.asset_scale)
+    }
+
+    /// The fulfillment used for messages from the peer's settlement engine
+    pub fn peer_protocol_fulfillment(&self) -> [u8; 32] {
+        self.peer_protocol_fulfillment
+            .unwrap_or(PEER_PROTOCOL_FULFILLMENT)
+    }
+
+    /// The execution condition used for messages to the peer's settlement engine
+    pub fn peer_protocol_condition(&self) -> [u8; 32] {
+        match self.peer_protocol_fulfillment {
+            Some(fulfillment) => {
+                let mut condition = [0; 32];
+                condition.copy_from_slice(digest(&SHA256, &fulfillment).as_ref());
+                condition
+            }
+            None => PEER_PROTOCOL_CONDITION,
+        }
+    }
 }
 
 pub trait SettlementAccount: Account {
     fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
         None
     }
+
+    /// The secret the settlement engine must send as a bearer token when it calls the
+    /// `SettlementApi` about this account. Without one, those calls are rejected.
+    fn settlement_engine_auth_token(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// The saved response to a request that carried an idempotency key
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotentData {
+    /// Hash of the request the response was for, used to detect a key being reused for a different request
+    pub input_hash: [u8; 32],
+    /// The JSON body of the response
+    pub body: Bytes,
 }
 
 pub trait SettlementStore {
     type Account: SettlementAccount;
 
+    /// Load the response saved for the given idempotency key, if there is one
+    fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send>;
+
+    /// Credit an incoming settlement to the account and return the account's resulting balance
+    /// (including any prepaid amount). The update and the read must happen atomically, so the
+    /// returned balance reflects this settlement even if other updates happen at the same time.
+    ///
+    /// If the settlement was sent with an idempotency key, the response to it is saved for that key
+    /// in the same atomic operation, so that retries of the settlement can be answered without
+    /// crediting it again. Either both the credit and the response are saved or neither is.
     fn update_balance_for_incoming_settlement(
         &self,
         account_id: <Self::Account as Account>::AccountId,
         amount: u64,
+        idempotent_data: Option<(String, IdempotentData)>,
+    ) -> Box<dyn Future<Item = i64, Error = ()> + Send>;
+
+    /// Load the account's balance (including any prepaid amount), in the account's asset scale
+    fn load_balance(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+    ) -> Box<dyn Future<Item = i64, Error = ()> + Send>;
+}
+
+type QueuedSettlements<T> = Vec<(T, u64)>;
+
+/// A store that durably records outgoing settlements that could not be sent to the
+/// settlement engine so that they can be retried later.
+///
+/// The amount of a queued settlement should stay reserved, but it should only be
+/// debited from the account's balance once the settlement engine has accepted it.
+pub trait PendingSettlementStore: AccountStore {
+    /// Add a settlement that failed to be sent to the queue.
+    fn queue_settlement(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Load the settlements currently waiting to be retried.
+    fn get_queued_settlements(
+        &self,
+    ) -> Box<
+        dyn Future<Item = QueuedSettlements<<Self::Account as Account>::AccountId>, Error = ()>
+            + Send,
+    >;
+
+    /// Remove a settlement from the queue and debit the account's balance,
+    /// after the settlement engine has accepted it.
+    fn confirm_queued_settlement(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+
+    /// Reserve the account's whole outstanding balance, less anything already reserved for
+    /// settlements in progress, and return the amount reserved. This is 0 if there is nothing to settle.
+    fn reserve_outstanding_balance(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+    ) -> Box<dyn Future<Item = u64, Error = ()> + Send>;
+
+    /// Release the reservation for a settlement and debit the account's balance,
+    /// after the settlement engine has accepted it.
+    fn confirm_settlement(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        amount: u64,
     ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::test_helpers::TestAccount;
+    use super::*;
+    use interledger_ildcp::IldcpAccount;
+
+    #[test]
+    fn derives_peer_protocol_condition_from_fulfillment() {
+        let mut account = TestAccount::new(0, 9, 6);
+        let engine = account.settlement_engine_details().unwrap();
+        assert_eq!(engine.peer_protocol_condition(), PEER_PROTOCOL_CONDITION);
+        assert!(fulfillment_matches_condition(
+            &engine.peer_protocol_fulfillment(),
+            &PEER_PROTOCOL_CONDITION
+        ));
+
+        account.settlement_engine_peer_protocol_fulfillment = Some([7; 32]);
+        let engine = account.settlement_engine_details().unwrap();
+        assert_eq!(engine.peer_protocol_fulfillment(), [7; 32]);
+        assert!(fulfillment_matches_condition(
+            &[7; 32],
+            &engine.peer_protocol_condition()
+        ));
+    }
+
+    #[test]
+    fn converts_between_engine_and_account_scales() {
+        let mut account = TestAccount::new(0, 9, 6);
+        account.settlement_engine_incoming_asset_scale = Some(3);
+        let engine = account.settlement_engine_details().unwrap();
+        assert_eq!(
+            normalize_amount(
+                1_234_567,
+                account.asset_scale(),
+                engine.asset_scale,
+                RoundingMode::Floor
+            ),
+            Some(1234)
+        );
+        assert_eq!(
+            normalize_amount(
+                5,
+                engine.incoming_asset_scale(),
+                account.asset_scale(),
+                RoundingMode::Floor
+            ),
+            Some(5_000_000)
+        );
+    }
+
+    #[test]
+    fn converts_settlements_within_max_scale_difference() {
+        for policy in &[
+            ScaleOverflowPolicy::Reject,
+            ScaleOverflowPolicy::Clamp,
+            ScaleOverflowPolicy::Floor,
+        ] {
+            assert_eq!(
+                normalize_settlement_amount(1, 0, 19, RoundingMode::Floor, *policy),
+                Some(10_000_000_000_000_000_000)
+            );
+            assert_eq!(
+                normalize_settlement_amount(5, 19, 0, RoundingMode::Floor, *policy),
+                Some(0)
+            );
+        }
+    }
+
+    #[test]
+    fn reject_policy_refuses_extreme_scale_differences() {
+        let policy = ScaleOverflowPolicy::Reject;
+        assert!(normalize_settlement_amount(1, 0, 30, RoundingMode::Floor, policy).is_none());
+        assert!(normalize_settlement_amount(1000, 30, 0, RoundingMode::Floor, policy).is_none());
+        assert!(normalize_settlement_amount(20, 0, 18, RoundingMode::Floor, policy).is_none());
+    }
+
+    #[test]
+    fn always_rejects_settlements_that_overflow_when_scaled_up() {
+        for policy in &[
+            ScaleOverflowPolicy::Reject,
+            ScaleOverflowPolicy::Clamp,
+            ScaleOverflowPolicy::Floor,
+        ] {
+            assert!(normalize_settlement_amount(1, 0, 30, RoundingMode::Floor, *policy).is_none());
+            assert!(normalize_settlement_amount(20, 0, 18, RoundingMode::Floor, *policy).is_none());
+        }
+    }
+
+    #[test]
+    fn clamp_policy_saturates_extreme_scale_differences() {
+        let policy = ScaleOverflowPolicy::Clamp;
+        assert_eq!(
+            normalize_settlement_amount(1000, 30, 0, RoundingMode::Floor, policy),
+            Some(1)
+        );
+        assert_eq!(
+            normalize_settlement_amount(0, 30, 0, RoundingMode::Floor, policy),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn floor_policy_rounds_down_extreme_scale_differences() {
+        let policy = ScaleOverflowPolicy::Floor;
+        assert_eq!(
+            normalize_settlement_amount(1000, 30, 0, RoundingMode::Ceil, policy),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn scales_up_exactly() {
+        for mode in &[RoundingMode::Floor, RoundingMode::Ceil, RoundingMode::Round] {
+            assert_eq!(normalize_amount(123, 2, 5, *mode), Some(123_000));
+        }
+    }
+
+    #[test]
+    fn returns_none_instead_of_overflowing() {
+        assert_eq!(normalize_amount(u64::MAX, 0, 1, RoundingMode::Floor), None);
+        assert_eq!(normalize_amount(1, 0, 25, RoundingMode::Ceil), None);
+        assert_eq!(normalize_amount(1, 25, 0, RoundingMode::Ceil), Some(1));
+        assert_eq!(normalize_amount(1, 25, 0, RoundingMode::Round), Some(0));
+    }
+
+    #[test]
+    fn floor_rounds_down() {
+        assert_eq!(normalize_amount(1299, 3, 1, RoundingMode::Floor), Some(12));
+        assert_eq!(normalize_amount(1250, 3, 1, RoundingMode::Floor), Some(12));
+        assert_eq!(normalize_amount(1201, 3, 1, RoundingMode::Floor), Some(12));
+        assert_eq!(normalize_amount(1200, 3, 1, RoundingMode::Floor), Some(12));
+    }
+
+    #[test]
+    fn ceil_rounds_up() {
+        assert_eq!(normalize_amount(1299, 3, 1, RoundingMode::Ceil), Some(13));
+        assert_eq!(normalize_amount(1250, 3, 1, RoundingMode::Ceil), Some(13));
+        assert_eq!(normalize_amount(1201, 3, 1, RoundingMode::Ceil), Some(13));
+        assert_eq!(normalize_amount(1200, 3, 1, RoundingMode::Ceil), Some(12));
+    }
+
+    #[test]
+    fn round_rounds_half_up() {
+        assert_eq!(normalize_amount(1299, 3, 1, RoundingMode::Round), Some(13));
+        assert_eq!(normalize_amount(1250, 3, 1, RoundingMode::Round), Some(13));
+        assert_eq!(normalize_amount(1249, 3, 1, RoundingMode::Round), Some(12));
+        assert_eq!(normalize_amount(1200, 3, 1, RoundingMode::Round), Some(12));
+        assert_eq!(normalize_amount(15, 1, 0, RoundingMode::Round), Some(2));
+        assert_eq!(normalize_amount(14, 1, 0, RoundingMode::Round), Some(1));
+    }
+}
@@ -1,21 +1,107 @@
 use super::SettlementAccount;
+use bytes::Bytes;
 use futures::{
-    future::{err, Either},
+    future::{err, loop_fn, ok, Either, Loop},
     Future, Stream,
 };
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_service::{BoxedIlpFuture, IncomingRequest, IncomingService};
-use reqwest::r#async::Client;
-use serde_json::{self, Value};
-use std::marker::PhantomData;
+use reqwest::{r#async::Client, StatusCode};
+use serde_json::{self, Map, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+use url::Url;
+
+/// How many undelivered messages to keep before rejecting new ones
+const MAX_BUFFERED_MESSAGES: usize = 1000;
+/// How many responses to messages with idempotency keys to remember before forgetting the oldest
+const MAX_PROCESSED_MESSAGES: usize = 1000;
+
+/// The field of a settlement message that carries the sending engine's idempotency key across ILP.
+/// The receiving connector removes it from the message and passes it to its settlement engine
+/// in the `Idempotency-Key` header instead.
+pub const IDEMPOTENCY_KEY_FIELD: &str = "idempotencyKey";
+
+type BufferedMessages = Arc<Mutex<VecDeque<(Url, Map<String, Value>, Option<String>)>>>;
+
+/// What happened to a message with an idempotency key
+#[derive(Clone)]
+enum MessageStatus {
+    /// The message is being sent to the engine
+    InProgress,
+    /// The peer was sent this response, either from the engine or, if the message was buffered, an empty one
+    Responded(Bytes),
+}
+
+/// The messages that had idempotency keys, keyed by the account the message came from
+/// and the key. Keys are recorded as soon as the message is accepted, so that a retried
+/// message is answered with the original response instead of being processed again.
+#[derive(Default)]
+struct ProcessedMessages {
+    statuses: HashMap<(String, String), MessageStatus>,
+    order: VecDeque<(String, String)>,
+}
+
+impl ProcessedMessages {
+    /// Record that the message is being processed, unless it already was.
+    /// Returns the status of the earlier message otherwise.
+    fn start(&mut self, key: (String, String)) -> Option<MessageStatus> {
+        if let Some(status) = self.statuses.get(&key) {
+            return Some(status.clone());
+        }
+        self.statuses.insert(key.clone(), MessageStatus::InProgress);
+        self.order.push_back(key);
+        if self.order.len() > MAX_PROCESSED_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.statuses.remove(&oldest);
+            }
+        }
+        None
+    }
+
+    fn respond(&mut self, key: &(String, String), response: Bytes) {
+        if let Some(status) = self.statuses.get_mut(key) {
+            *status = MessageStatus::Responded(response);
+        }
+    }
 
-const PEER_FULFILLMENT: [u8; 32] = [0; 32];
+    /// Forget a message that was not accepted, so that the peer can send it again
+    fn forget(&mut self, key: &(String, String)) {
+        if self.statuses.remove(key).is_some() {
+            self.order.retain(|other| other != key);
+        }
+    }
+}
+
+enum DeliveryError {
+    /// The engine could not be reached or had a server error, so the message should be retried
+    Unavailable(String),
+    /// The engine rejected the message, so retrying it would not help
+    Rejected(StatusCode),
+}
 
+/// # Settlement Message Service
+///
+/// Forwards messages from peers' settlement engines to the settlement engine configured for the account.
+/// If the local settlement engine is unavailable, the message is buffered so that it can be delivered
+/// later with `replay_buffered_messages` or `replay_periodically`, and the peer gets a Fulfill with no data
+/// so that it does not send the message again. Once `MAX_BUFFERED_MESSAGES` are waiting, further
+/// messages are rejected instead.
+///
+/// Messages that carry an idempotency key (see `IDEMPOTENCY_KEY_FIELD`) are only delivered to the
+/// engine once; if the peer retries one, it gets the engine's original response.
 #[derive(Clone)]
 pub struct SettlementMessageService<I, A> {
     ilp_address: Address,
     next: I,
     http_client: Client,
+    buffered_messages: BufferedMessages,
+    processed_messages: Arc<Mutex<ProcessedMessages>>,
     account_type: PhantomData<A>,
 }
 
@@ -29,9 +115,134 @@ where
             next,
             ilp_address,
             http_client: Client::new(),
+            buffered_messages: Arc::new(Mutex::new(VecDeque::new())),
+            processed_messages: Arc::new(Mutex::new(ProcessedMessages::default())),
             account_type: PhantomData,
         }
     }
+
+    /// Try delivering the buffered messages to their settlement engines again, in the order they were received.
+    /// Resolves to the number of messages that are still undelivered.
+    pub fn replay_buffered_messages(&self) -> impl Future<Item = usize, Error = ()> {
+        replay_messages(self.http_client.clone(), self.buffered_messages.clone())
+    }
+
+    /// Replay the buffered messages every `interval`, forever.
+    pub fn replay_periodically(&self, interval: Duration) -> impl Future<Item = (), Error = ()> {
+        let http_client = self.http_client.clone();
+        let buffered_messages = self.buffered_messages.clone();
+        loop_fn((), move |_| {
+            let http_client = http_client.clone();
+            let buffered_messages = buffered_messages.clone();
+            Delay::new(Instant::now() + interval)
+                .map_err(|err| error!("Timer error in settlement message replay: {:?}", err))
+                .and_then(move |_| replay_messages(http_client, buffered_messages))
+                .and_then(|remaining| {
+                    if remaining > 0 {
+                        trace!("{} settlement messages are still undelivered", remaining);
+                    }
+                    Ok(Loop::Continue(()))
+                })
+        })
+    }
+}
+
+fn send_to_engine(
+    http_client: &Client,
+    url: Url,
+    message: &Map<String, Value>,
+    idempotency_key: Option<&str>,
+) -> impl Future<Item = Bytes, Error = DeliveryError> {
+    let mut request = http_client.post(url).json(message);
+    if let Some(idempotency_key) = idempotency_key {
+        request = request.header("Idempotency-Key", idempotency_key);
+    }
+    request
+        .send()
+        .map_err(|error| DeliveryError::Unavailable(format!("{:?}", error)))
+        .and_then(|response| {
+            let status = response.status();
+            if status.is_success() {
+                Either::A(
+                    response
+                        .into_body()
+                        .concat2()
+                        .map(|body| Bytes::from(body.as_ref()))
+                        .map_err(|error| DeliveryError::Unavailable(format!("{:?}", error))),
+                )
+            } else if status.is_server_error() {
+                Either::B(err(DeliveryError::Unavailable(format!(
+                    "HTTP error code: {}",
+                    status
+                ))))
+            } else {
+                Either::B(err(DeliveryError::Rejected(status)))
+            }
+        })
+}
+
+/// Buffer a new message, unless the buffer is full. Returns whether the message was buffered.
+fn buffer_message(
+    buffered_messages: &BufferedMessages,
+    url: Url,
+    message: Map<String, Value>,
+    idempotency_key: Option<String>,
+) -> bool {
+    let mut buffered_messages = buffered_messages.lock().unwrap();
+    if buffered_messages.len() >= MAX_BUFFERED_MESSAGES {
+        return false;
+    }
+    buffered_messages.push_back((url, message, idempotency_key));
+    true
+}
+
+fn replay_messages(
+    http_client: Client,
+    buffered_messages: BufferedMessages,
+) -> impl Future<Item = usize, Error = ()> {
+    // Only go through the messages buffered when the replay started so that
+    // messages that fail again are not retried until the next replay
+    let to_replay = buffered_messages.lock().unwrap().len();
+    loop_fn(to_replay, move |to_replay| {
+        let next = if to_replay > 0 {
+            buffered_messages.lock().unwrap().pop_front()
+        } else {
+            None
+        };
+        let (url, message, idempotency_key) = match next {
+            Some(next) => next,
+            None => return Either::B(ok(Loop::Break(buffered_messages.lock().unwrap().len()))),
+        };
+        let buffered_messages = buffered_messages.clone();
+        Either::A(
+            send_to_engine(
+                &http_client,
+                url.clone(),
+                &message,
+                idempotency_key.as_deref(),
+            )
+            .then(move |result| {
+                match result {
+                    Ok(_) => trace!("Delivered buffered settlement message to {}", url),
+                    Err(DeliveryError::Unavailable(error)) => {
+                        debug!("Settlement engine is still unavailable: {}", error);
+                        // The peer was already told the message was received, so it is kept
+                        // even if new messages have filled up the buffer in the meantime
+                        buffered_messages.lock().unwrap().push_back((
+                            url,
+                            message,
+                            idempotency_key,
+                        ));
+                    }
+                    Err(DeliveryError::Rejected(status)) => error!(
+                        "Settlement engine rejected buffered message with HTTP error code: {}",
+                        status
+                    ),
+                }
+                Ok(Loop::Continue(to_replay - 1))
+            }),
+        )
+    })
 }
 
 impl<I, A> IncomingService<A> for SettlementMessageService<I, A>
@@ -48,64 +259,105 @@ where
         if let Some(settlement_engine_details) = request.from.settlement_engine_details() {
             if request.prepare.destination() == settlement_engine_details.ilp_address {
                 let ilp_address_clone = self.ilp_address.clone();
+                // Fulfill with the preimage of the condition agreed with the peer
+                let fulfillment = settlement_engine_details.peer_protocol_fulfillment();
                 let mut settlement_engine_url = settlement_engine_details.url;
 
                 match serde_json::from_slice(request.prepare.data()) {
                     Ok(Value::Object(mut message)) => {
-                        message.insert(
-                            "accountId".to_string(),
-                            Value::String(request.from.id().to_string()),
-                        );
+                        let account_id = request.from.id().to_string();
+                        let idempotency_key = match message.remove(IDEMPOTENCY_KEY_FIELD) {
+                            Some(Value::String(key)) => Some((account_id.clone(), key)),
+                            _ => None,
+                        };
+                        if let Some(ref key) = idempotency_key {
+                            let status = self.processed_messages.lock().unwrap().start(key.clone());
+                            match status {
+                                Some(MessageStatus::Responded(response)) => {
+                                    debug!("Already processed settlement message with idempotency key {} from account {}, responding with the previous response", key.1, key.0);
+                                    return Box::new(ok(FulfillBuilder {
+                                        fulfillment: &fulfillment,
+                                        data: response.as_ref(),
+                                    }
+                                    .build()));
+                                }
+                                Some(MessageStatus::InProgress) => {
+                                    debug!("Settlement message with idempotency key {} from account {} is already being processed", key.1, key.0);
+                                    return Box::new(err(RejectBuilder {
+                                        code: ErrorCode::T00_INTERNAL_ERROR,
+                                        message: b"Message with this idempotency key is already being processed",
+                                        data: &[],
+                                        triggered_by: Some(&ilp_address),
+                                    }
+                                    .build()));
+                                }
+                                None => {}
+                            }
+                        }
+                        message.insert("accountId".to_string(), Value::String(account_id));
                         // TODO add auth
                         settlement_engine_url
                             .path_segments_mut()
                             .expect("Invalid settlement engine URL")
-                            .push("receiveMessage"); // Maybe set the idempotency flag here in the headers
-                        return Box::new(self.http_client.post(settlement_engine_url)
-                        .json(&message)
-                        .send()
-                        .map_err(move |error| {
-                            error!("Error sending message to settlement engine: {:?}", error);
-                            RejectBuilder {
-                                code: ErrorCode::T00_INTERNAL_ERROR,
-                                message: b"Error sending message to settlement engine",
-                                data: &[],
-                                triggered_by: Some(&ilp_address_clone),
-                            }.build()
-                        })
-                        .and_then(move |response| {
-                            let status = response.status();
-                            if status.is_success() {
-                                Either::A(response.into_body().concat2().map_err(move |err| {
-                                    error!("Error concatenating settlement engine response body: {:?}", err);
-                                    RejectBuilder {
-                                    code: ErrorCode::T00_INTERNAL_ERROR,
-                                    message: b"Error getting settlement engine response",
-                                    data: &[],
-                                    triggered_by: Some(&ilp_address),
-                                }.build()
-                                })
-                                .and_then(|body| {
-                                    Ok(FulfillBuilder {
-                                        fulfillment: &PEER_FULFILLMENT,
-                                        data: body.as_ref(),
-                                    }.build())
-                                }))
-                            } else {
-                                error!("Settlement engine rejected message with HTTP error code: {}", response.status());
-                                let code = if status.is_client_error() {
-                                    ErrorCode::F00_BAD_REQUEST
-                                } else {
-                                    ErrorCode::T00_INTERNAL_ERROR
-                                };
-                                Either::B(err(RejectBuilder {
-                                    code,
-                                    message: format!("Settlement engine rejected request with error code: {}", response.status()).as_str().as_ref(),
-                                    data: &[],
-                                    triggered_by: Some(&ilp_address),
-                                }.build()))
-                            }
-                        }));
+                            .push("receiveMessage");
+                        let buffered_messages = self.buffered_messages.clone();
+                        let processed_messages = self.processed_messages.clone();
+                        let key_to_remember = idempotency_key.clone();
+                        let idempotency_key = idempotency_key.map(|(_, key)| key);
+                        return Box::new(
+                            send_to_engine(&self.http_client, settlement_engine_url.clone(), &message, idempotency_key.as_deref())
+                                .then(move |result| {
+                                    let result = match result {
+                                        Ok(body) => {
+                                            if let Some(ref key) = key_to_remember {
+                                                processed_messages.lock().unwrap().respond(key, body.clone());
+                                            }
+                                            Ok(FulfillBuilder {
+                                                fulfillment: &fulfillment,
+                                                data: body.as_ref(),
+                                            }
+                                            .build())
+                                        }
+                                        Err(DeliveryError::Unavailable(error)) => {
+                                            if buffer_message(&buffered_messages, settlement_engine_url, message, idempotency_key) {
+                                                warn!("Error sending message to settlement engine, buffered it to retry later: {}", error);
+                                                if let Some(ref key) = key_to_remember {
+                                                    processed_messages.lock().unwrap().respond(key, Bytes::new());
+                                                }
+                                                Ok(FulfillBuilder {
+                                                    fulfillment: &fulfillment,
+                                                    data: &[],
+                                                }
+                                                .build())
+                                            } else {
+                                                error!("Error sending message to settlement engine and the message buffer is full: {}", error);
+                                                Err(RejectBuilder {
+                                                    code: ErrorCode::T00_INTERNAL_ERROR,
+                                                    message: b"Error sending message to settlement engine",
+                                                    data: &[],
+                                                    triggered_by: Some(&ilp_address_clone),
+                                                }
+                                                .build())
+                                            }
+                                        }
+                                        Err(DeliveryError::Rejected(status)) => {
+                                            error!("Settlement engine rejected message with HTTP error code: {}", status);
+                                            Err(RejectBuilder {
+                                                code: ErrorCode::F00_BAD_REQUEST,
+                                                message: format!("Settlement engine rejected request with error code: {}", status).as_str().as_ref(),
+                                                data: &[],
+                                                triggered_by: Some(&ilp_address),
+                                            }
+                                            .build())
+                                        }
+                                    };
+                                    // Messages that were not accepted can be sent again
+                                    if let (Err(_), Some(key)) = (&result, key_to_remember) {
+                                        processed_messages.lock().unwrap().forget(&key);
+                                    }
+                                    result
+                                }),
+                        );
                     }
                     Err(error) => {
                         error!(
@@ -148,3 +400,336 @@ where
         Box::new(self.next.handle_request(request))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use hyper::{service::service_fn, Body, Response, Server};
+    use interledger_packet::PrepareBuilder;
+    use interledger_service::incoming_service_fn;
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicBool, Ordering},
+        time::SystemTime,
+    };
+    use tokio::runtime::Runtime;
+
+    type ReceivedMessages = Arc<Mutex<Vec<(Option<String>, Value)>>>;
+
+    /// Start a settlement engine that responds with 503 while `down` is set
+    /// and records the messages it receives (and their idempotency keys) otherwise
+    fn start_engine(
+        runtime: &mut Runtime,
+        down: Arc<AtomicBool>,
+        received: ReceivedMessages,
+    ) -> String {
+        let engine = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(move || {
+            let down = down.clone();
+            let received = received.clone();
+            service_fn(move |req: hyper::Request<Body>| {
+                let down = down.clone();
+                let received = received.clone();
+                let idempotency_key = req
+                    .headers()
+                    .get("Idempotency-Key")
+                    .map(|key| key.to_str().unwrap().to_string());
+                req.into_body().concat2().map(move |body| {
+                    if down.load(Ordering::SeqCst) {
+                        Response::builder().status(503).body(Body::empty()).unwrap()
+                    } else {
+                        received
+                            .lock()
+                            .unwrap()
+                            .push((idempotency_key, serde_json::from_slice(&body).unwrap()));
+                        Response::new(Body::from("{\"status\":\"ok\"}"))
+                    }
+                })
+            })
+        });
+        let engine_url = format!("http://{}", engine.local_addr());
+        runtime.spawn(engine.map_err(|err| panic!("Settlement engine error: {:?}", err)));
+        engine_url
+    }
+
+    #[test]
+    fn replays_message_after_engine_comes_back() {
+        let mut runtime = Runtime::new().unwrap();
+        let down = Arc::new(AtomicBool::new(true));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let engine_url = start_engine(&mut runtime, down.clone(), received.clone());
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(&engine_url).unwrap();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+
+        // The peer is told the message was received, so it does not send it again
+        let fulfill = runtime
+            .block_on(
+                service.handle_request(IncomingRequest {
+                    from: account,
+                    prepare: PrepareBuilder {
+                        destination: Address::from_str("peer.settle.xyz").unwrap(),
+                        amount: 0,
+                        expires_at: SystemTime::now() + Duration::from_secs(30),
+                        execution_condition: &[0; 32],
+                        data: b"{\"type\":\"paychan\"}",
+                    }
+                    .build(),
+                    correlation_id: None,
+                }),
+            )
+            .unwrap();
+        assert!(fulfill.data().is_empty());
+
+        // Still down, so the message stays buffered
+        let remaining = runtime
+            .block_on(service.replay_buffered_messages())
+            .unwrap();
+        assert_eq!(remaining, 1);
+        assert!(received.lock().unwrap().is_empty());
+
+        down.store(false, Ordering::SeqCst);
+        let remaining = runtime
+            .block_on(service.replay_buffered_messages())
+            .unwrap();
+        assert_eq!(remaining, 0);
+
+        // Replaying again does not deliver the message twice
+        let remaining = runtime
+            .block_on(service.replay_buffered_messages())
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(
+                None,
+                serde_json::json!({"type": "paychan", "accountId": "0"})
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_messages_when_buffer_is_full() {
+        let mut runtime = Runtime::new().unwrap();
+        let engine_url = start_engine(
+            &mut runtime,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(&engine_url).unwrap();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+        for i in 0..MAX_BUFFERED_MESSAGES {
+            let mut message = Map::new();
+            message.insert("id".to_string(), Value::from(i));
+            service.buffered_messages.lock().unwrap().push_back((
+                Url::parse(&engine_url).unwrap(),
+                message,
+                None,
+            ));
+        }
+
+        let reject = runtime
+            .block_on(
+                service.handle_request(IncomingRequest {
+                    from: account,
+                    prepare: PrepareBuilder {
+                        destination: Address::from_str("peer.settle.xyz").unwrap(),
+                        amount: 0,
+                        expires_at: SystemTime::now() + Duration::from_secs(30),
+                        execution_condition: &[0; 32],
+                        data: b"{\"type\":\"paychan\"}",
+                    }
+                    .build(),
+                    correlation_id: None,
+                }),
+            )
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+        // None of the messages that were already accepted were dropped
+        let buffered_messages = service.buffered_messages.lock().unwrap();
+        assert_eq!(buffered_messages.len(), MAX_BUFFERED_MESSAGES);
+        assert_eq!(buffered_messages[0].1["id"], 0);
+    }
+
+    #[test]
+    fn fulfills_with_agreed_peer_protocol_fulfillment() {
+        let mut runtime = Runtime::new().unwrap();
+        let engine_url = start_engine(
+            &mut runtime,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(&engine_url).unwrap();
+        account.settlement_engine_peer_protocol_fulfillment = Some([7; 32]);
+        let condition = account
+            .settlement_engine_details()
+            .unwrap()
+            .peer_protocol_condition();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+
+        let fulfill = runtime
+            .block_on(
+                service.handle_request(IncomingRequest {
+                    from: account,
+                    prepare: PrepareBuilder {
+                        destination: Address::from_str("peer.settle.xyz").unwrap(),
+                        amount: 0,
+                        expires_at: SystemTime::now() + Duration::from_secs(30),
+                        execution_condition: &condition,
+                        data: b"{\"type\":\"paychan\"}",
+                    }
+                    .build(),
+                    correlation_id: None,
+                }),
+            )
+            .unwrap();
+        assert!(crate::fulfillment_matches_condition(
+            fulfill.fulfillment(),
+            &condition
+        ));
+    }
+
+    #[test]
+    fn delivers_message_with_idempotency_key_once() {
+        let mut runtime = Runtime::new().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let engine_url = start_engine(
+            &mut runtime,
+            Arc::new(AtomicBool::new(false)),
+            received.clone(),
+        );
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(&engine_url).unwrap();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+
+        // The peer retries the same message
+        for _ in 0..2 {
+            let fulfill = runtime
+                .block_on(
+                    service.handle_request(IncomingRequest {
+                        from: account.clone(),
+                        prepare: PrepareBuilder {
+                            destination: Address::from_str("peer.settle.xyz").unwrap(),
+                            amount: 0,
+                            expires_at: SystemTime::now() + Duration::from_secs(30),
+                            execution_condition: &[0; 32],
+                            data: b"{\"type\":\"paychan\",\"idempotencyKey\":\"abc123\"}",
+                        }
+                        .build(),
+                        correlation_id: None,
+                    }),
+                )
+                .unwrap();
+            assert_eq!(fulfill.data(), b"{\"status\":\"ok\"}");
+        }
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(
+                Some("abc123".to_string()),
+                serde_json::json!({"type": "paychan", "accountId": "0"})
+            )]
+        );
+    }
+
+    #[test]
+    fn delivers_buffered_message_with_idempotency_key_once() {
+        let mut runtime = Runtime::new().unwrap();
+        let down = Arc::new(AtomicBool::new(true));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let engine_url = start_engine(&mut runtime, down.clone(), received.clone());
+
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(&engine_url).unwrap();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+
+        // The peer retries the message after it was buffered
+        for _ in 0..2 {
+            let fulfill = runtime
+                .block_on(
+                    service.handle_request(IncomingRequest {
+                        from: account.clone(),
+                        prepare: PrepareBuilder {
+                            destination: Address::from_str("peer.settle.xyz").unwrap(),
+                            amount: 0,
+                            expires_at: SystemTime::now() + Duration::from_secs(30),
+                            execution_condition: &[0; 32],
+                            data: b"{\"type\":\"paychan\",\"idempotencyKey\":\"abc123\"}",
+                        }
+                        .build(),
+                        correlation_id: None,
+                    }),
+                )
+                .unwrap();
+            assert!(fulfill.data().is_empty());
+        }
+        assert_eq!(service.buffered_messages.lock().unwrap().len(), 1);
+
+        down.store(false, Ordering::SeqCst);
+        let remaining = runtime
+            .block_on(service.replay_buffered_messages())
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(
+                Some("abc123".to_string()),
+                serde_json::json!({"type": "paychan", "accountId": "0"})
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_retry_while_message_is_in_progress() {
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse("http://localhost:1").unwrap();
+        let mut service = SettlementMessageService::new(
+            Address::from_str("example.connector").unwrap(),
+            incoming_service_fn(|_| -> Result<_, _> { panic!("shouldn't get here") }),
+        );
+        // As if the first message were still being sent to the engine
+        service
+            .processed_messages
+            .lock()
+            .unwrap()
+            .start(("0".to_string(), "abc123".to_string()));
+
+        let reject = service
+            .handle_request(IncomingRequest {
+                from: account,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("peer.settle.xyz").unwrap(),
+                    amount: 0,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: b"{\"type\":\"paychan\",\"idempotencyKey\":\"abc123\"}",
+                }
+                .build(),
+                correlation_id: None,
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+        assert!(service.buffered_messages.lock().unwrap().is_empty());
+    }
+}
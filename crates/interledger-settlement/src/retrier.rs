@@ -0,0 +1,207 @@
+use crate::{PendingSettlementStore, SettlementAccount, SettlementClient};
+use futures::{
+    future::{join_all, loop_fn, ok, Either, Loop},
+    Future,
+};
+use interledger_ildcp::IldcpAccount;
+use std::{
+    cmp::min,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+const DEFAULT_MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// # Settlement Retrier
+///
+/// Drains the queue of outgoing settlements that could not be sent to the settlement engine,
+/// for example because the engine was down. Each pass retries every queued settlement and
+/// debits the account's balance for the ones the engine accepts. While settlements remain
+/// queued, the time between passes doubles, up to the maximum retry interval.
+#[derive(Clone)]
+pub struct SettlementRetrier<S> {
+    store: S,
+    settlement_client: SettlementClient,
+    min_retry_interval: Duration,
+    max_retry_interval: Duration,
+}
+
+impl<S, A> SettlementRetrier<S>
+where
+    S: PendingSettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    A: SettlementAccount + IldcpAccount + Send + Sync + 'static,
+{
+    pub fn new(store: S, settlement_client: SettlementClient) -> Self {
+        SettlementRetrier {
+            store,
+            settlement_client,
+            min_retry_interval: DEFAULT_MIN_RETRY_INTERVAL,
+            max_retry_interval: DEFAULT_MAX_RETRY_INTERVAL,
+        }
+    }
+
+    /// Set the minimum and maximum time to wait between passes over the queue.
+    pub fn retry_interval(&mut self, min_interval: Duration, max_interval: Duration) -> &mut Self {
+        self.min_retry_interval = min_interval;
+        self.max_retry_interval = max_interval;
+        self
+    }
+
+    /// Try to send each queued settlement once.
+    /// Resolves to the number of settlements that are still queued afterwards.
+    pub fn retry_queued_settlements(&self) -> impl Future<Item = usize, Error = ()> {
+        let store = self.store.clone();
+        let settlement_client = self.settlement_client.clone();
+        self.store
+            .get_queued_settlements()
+            .and_then(move |queued| {
+                if queued.is_empty() {
+                    return Either::A(ok(0));
+                }
+
+                // Load each account separately so that a missing account
+                // does not stop the other queued settlements from being sent
+                let retries: Vec<_> = queued
+                    .into_iter()
+                    .map(|(account_id, amount)| {
+                        let store_clone = store.clone();
+                        let settlement_client = settlement_client.clone();
+                        store
+                            .get_accounts(vec![account_id])
+                            .and_then(|mut accounts| accounts.pop().ok_or(()))
+                            .map_err(move |_| {
+                                error!(
+                                    "Error loading account {} for queued settlement",
+                                    account_id
+                                )
+                            })
+                            .and_then(move |account| {
+                                settlement_client.send_settlement(account, amount)
+                            })
+                            .and_then(move |_| {
+                                store_clone.confirm_queued_settlement(account_id, amount)
+                            })
+                            .then(move |result| {
+                                if result.is_ok() {
+                                    debug!("Sent queued settlement of {} for account {}", amount, account_id);
+                                } else {
+                                    debug!("Queued settlement of {} for account {} could not be sent, will retry later", amount, account_id);
+                                }
+                                Ok(result.is_ok())
+                            })
+                    })
+                    .collect();
+                Either::B(
+                    join_all(retries)
+                        .map(|results| results.iter().filter(|sent| !**sent).count()),
+                )
+            })
+    }
+
+    /// Retry queued settlements forever, backing off while they cannot be sent.
+    /// The returned future should be spawned on the same executor as the node.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        let min_interval = self.min_retry_interval;
+        let max_interval = self.max_retry_interval;
+        loop_fn(min_interval, move |interval| {
+            let retrier = self.clone();
+            Delay::new(Instant::now() + interval)
+                .map_err(|err| error!("Timer error in settlement retrier: {:?}", err))
+                .and_then(move |_| {
+                    retrier.retry_queued_settlements().then(move |result| {
+                        let next_interval = match result {
+                            Ok(0) => min_interval,
+                            Ok(remaining) => {
+                                trace!("{} settlements are still queued", remaining);
+                                min(interval * 2, max_interval)
+                            }
+                            Err(_) => min(interval * 2, max_interval),
+                        };
+                        Ok(Loop::Continue(next_interval))
+                    })
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use hyper::{service::service_fn_ok, Body, Response, Server};
+    use std::net::TcpListener;
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    fn account_with_engine(url: &str) -> TestAccount {
+        let mut account = TestAccount::new(0, 9, 9);
+        account.settlement_engine_url = Url::parse(url).unwrap();
+        account
+    }
+
+    #[test]
+    fn retries_queued_settlement() {
+        let mut runtime = Runtime::new().unwrap();
+        let engine = Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(|| service_fn_ok(|_| Response::new(Body::empty())));
+        let engine_url = format!("http://{}", engine.local_addr());
+        runtime.spawn(engine.map_err(|err| panic!("Settlement engine error: {:?}", err)));
+
+        let store = TestStore::new(vec![account_with_engine(&engine_url)]);
+        store.queued_settlements.lock().unwrap().push((0, 100));
+        let retrier = SettlementRetrier::new(store.clone(), SettlementClient::new());
+
+        let remaining = runtime
+            .block_on(retrier.retry_queued_settlements())
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert!(store.queued_settlements.lock().unwrap().is_empty());
+        assert_eq!(*store.confirmed_settlements.lock().unwrap(), vec![(0, 100)]);
+    }
+
+    #[test]
+    fn settlement_stays_queued_while_engine_is_down() {
+        let mut runtime = Runtime::new().unwrap();
+        // Find a port that nothing is listening on
+        let engine_url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+
+        let store = TestStore::new(vec![account_with_engine(&engine_url)]);
+        store.queued_settlements.lock().unwrap().push((0, 100));
+        let retrier = SettlementRetrier::new(store.clone(), SettlementClient::new());
+
+        let remaining = runtime
+            .block_on(retrier.retry_queued_settlements())
+            .unwrap();
+        assert_eq!(remaining, 1);
+        assert_eq!(*store.queued_settlements.lock().unwrap(), vec![(0, 100)]);
+        assert!(store.confirmed_settlements.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_account_does_not_stop_other_retries() {
+        let mut runtime = Runtime::new().unwrap();
+        let engine = Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(|| service_fn_ok(|_| Response::new(Body::empty())));
+        let engine_url = format!("http://{}", engine.local_addr());
+        runtime.spawn(engine.map_err(|err| panic!("Settlement engine error: {:?}", err)));
+
+        let store = TestStore::new(vec![account_with_engine(&engine_url)]);
+        store
+            .queued_settlements
+            .lock()
+            .unwrap()
+            .extend(vec![(5, 50), (0, 100)]);
+        let retrier = SettlementRetrier::new(store.clone(), SettlementClient::new());
+
+        let remaining = runtime
+            .block_on(retrier.retry_queued_settlements())
+            .unwrap();
+        assert_eq!(remaining, 1);
+        assert_eq!(*store.queued_settlements.lock().unwrap(), vec![(5, 50)]);
+        assert_eq!(*store.confirmed_settlements.lock().unwrap(), vec![(0, 100)]);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{
+    IdempotentData, PendingSettlementStore, SettlementAccount, SettlementEngineDetails,
+    SettlementStore, WEBHOOK_SIGNATURE_HEADER,
+};
+use futures::{
+    future::{err, ok},
+    Future, IntoFuture, Stream,
+};
+use hyper::{service::service_fn, Body, Response, Server};
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::{Address, ErrorCode, Fulfill, FulfillBuilder, Reject, RejectBuilder};
+pub use interledger_service::test_support::{
+    init_test_logger, logged_messages, logged_messages_for_target,
+};
+use interledger_service::{
+    Account, AccountStore, BoxedIlpFuture, OutgoingRequest, OutgoingService,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+use tokio_timer::Delay;
+use url::Url;
+
+/// The settlement engine secret of `TestAccount`s
+pub const TEST_AUTH_TOKEN: &str = "settlement_engine_secret";
+
+/// An `Authorization` header with the settlement engine secret of `TestAccount`s
+pub fn auth() -> Option<String> {
+    Some(format!("Bearer {}", TEST_AUTH_TOKEN))
+}
+
+#[derive(Debug, Clone)]
+pub struct TestAccount {
+    pub id: u64,
+    pub asset_scale: u8,
+    pub ilp_address: Address,
+    pub settlement_engine_asset_scale: u8,
+    pub settlement_engine_incoming_asset_scale: Option<u8>,
+    pub settlement_engine_outgoing_asset_scale: Option<u8>,
+    pub settlement_engine_peer_protocol_fulfillment: Option<[u8; 32]>,
+    pub settlement_engine_message_timeout: Option<Duration>,
+    pub settlement_engine_url: Url,
+    pub settlement_engine_auth_token: Option<String>,
+}
+
+impl TestAccount {
+    pub fn new(id: u64, asset_scale: u8, settlement_engine_asset_scale: u8) -> Self {
+        TestAccount {
+            id,
+            asset_scale,
+            ilp_address: Address::from_str("example.alice").unwrap(),
+            settlement_engine_asset_scale,
+            settlement_engine_incoming_asset_scale: None,
+            settlement_engine_outgoing_asset_scale: None,
+            settlement_engine_peer_protocol_fulfillment: None,
+            settlement_engine_message_timeout: None,
+            settlement_engine_url: Url::parse("http://localhost:3000").unwrap(),
+            settlement_engine_auth_token: Some(TEST_AUTH_TOKEN.to_string()),
+        }
+    }
+}
+
+impl Account for TestAccount {
+    type AccountId = u64;
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl IldcpAccount for TestAccount {
+    fn asset_code(&self) -> &str {
+        "XYZ"
+    }
+
+    fn asset_scale(&self) -> u8 {
+        self.asset_scale
+    }
+
+    fn client_address(&self) -> &Address {
+        &self.ilp_address
+    }
+}
+
+impl SettlementAccount for TestAccount {
+    fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
+        Some(SettlementEngineDetails {
+            url: self.settlement_engine_url.clone(),
+            asset_scale: self.settlement_engine_asset_scale,
+            incoming_asset_scale: self.settlement_engine_incoming_asset_scale,
+            outgoing_asset_scale: self.settlement_engine_outgoing_asset_scale,
+            ilp_address: Address::from_str("peer.settle.xyz").unwrap(),
+            peer_protocol_fulfillment: self.settlement_engine_peer_protocol_fulfillment,
+            message_timeout: self.settlement_engine_message_timeout,
+        })
+    }
+
+    fn settlement_engine_auth_token(&self) -> Option<&str> {
+        self.settlement_engine_auth_token.as_deref()
+    }
+}
+
+#[derive(Clone)]
+pub struct TestStore {
+    pub accounts: Vec<TestAccount>,
+    pub incoming_settlements: Arc<Mutex<Vec<(u64, u64)>>>,
+    pub queued_settlements: Arc<Mutex<Vec<(u64, u64)>>>,
+    pub confirmed_settlements: Arc<Mutex<Vec<(u64, u64)>>>,
+    pub idempotent_data: Arc<Mutex<HashMap<String, IdempotentData>>>,
+    pub balances: Arc<Mutex<HashMap<u64, i64>>>,
+    pub pending_settlements: Arc<Mutex<HashMap<u64, u64>>>,
+    pub fail_balance_updates: bool,
+}
+
+impl TestStore {
+    pub fn new(accounts: Vec<TestAccount>) -> Self {
+        TestStore {
+            accounts,
+            incoming_settlements: Arc::new(Mutex::new(Vec::new())),
+            queued_settlements: Arc::new(Mutex::new(Vec::new())),
+            confirmed_settlements: Arc::new(Mutex::new(Vec::new())),
+            idempotent_data: Arc::new(Mutex::new(HashMap::new())),
+            balances: Arc::new(Mutex::new(HashMap::new())),
+            pending_settlements: Arc::new(Mutex::new(HashMap::new())),
+            fail_balance_updates: false,
+        }
+    }
+}
+
+impl AccountStore for TestStore {
+    type Account = TestAccount;
+
+    fn get_accounts(
+        &self,
+        account_ids: Vec<u64>,
+    ) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+        let accounts: Vec<TestAccount> = account_ids
+            .iter()
+            .filter_map(|id| self.accounts.iter().find(|account| account.id == *id))
+            .cloned()
+            .collect();
+        if accounts.len() == account_ids.len() {
+            Box::new(ok(accounts))
+        } else {
+            Box::new(err(()))
+        }
+    }
+}
+
+impl SettlementStore for TestStore {
+    type Account = TestAccount;
+
+    fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send> {
+        Box::new(ok(self
+            .idempotent_data
+            .lock()
+            .unwrap()
+            .get(&idempotency_key)
+            .cloned()))
+    }
+
+    fn update_balance_for_incoming_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+        idempotent_data: Option<(String, IdempotentData)>,
+    ) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
+        if self.fail_balance_updates {
+            return Box::new(err(()));
+        }
+        if let Some((idempotency_key, data)) = idempotent_data {
+            self.idempotent_data
+                .lock()
+                .unwrap()
+                .insert(idempotency_key, data);
+        }
+        let mut incoming_settlements = self.incoming_settlements.lock().unwrap();
+        incoming_settlements.push((account_id, amount));
+        let balance = incoming_settlements
+            .iter()
+            .filter(|(id, _)| *id == account_id)
+            .map(|(_, amount)| *amount as i64)
+            .sum();
+        Box::new(ok(balance))
+    }
+
+    fn load_balance(&self, account_id: u64) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
+        Box::new(ok(*self
+            .balances
+            .lock()
+            .unwrap()
+            .get(&account_id)
+            .unwrap_or(&0)))
+    }
+}
+
+impl PendingSettlementStore for TestStore {
+    fn queue_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        self.queued_settlements
+            .lock()
+            .unwrap()
+            .push((account_id, amount));
+        Box::new(ok(()))
+    }
+
+    fn get_queued_settlements(&self) -> Box<dyn Future<Item = Vec<(u64, u64)>, Error = ()> + Send> {
+        Box::new(ok(self.queued_settlements.lock().unwrap().clone()))
+    }
+
+    fn confirm_queued_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        self.queued_settlements
+            .lock()
+            .unwrap()
+            .retain(|queued| *queued != (account_id, amount));
+        self.confirmed_settlements
+            .lock()
+            .unwrap()
+            .push((account_id, amount));
+        Box::new(ok(()))
+    }
+
+    fn reserve_outstanding_balance(
+        &self,
+        account_id: u64,
+    ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
+        let balance = *self.balances.lock().unwrap().get(&account_id).unwrap_or(&0);
+        let mut pending_settlements = self.pending_settlements.lock().unwrap();
+        let pending = pending_settlements.entry(account_id).or_insert(0);
+        let amount = (balance - *pending as i64).max(0) as u64;
+        *pending += amount;
+        Box::new(ok(amount))
+    }
+
+    fn confirm_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        *self
+            .pending_settlements
+            .lock()
+            .unwrap()
+            .entry(account_id)
+            .or_insert(0) -= amount;
+        *self.balances.lock().unwrap().entry(account_id).or_insert(0) -= amount as i64;
+        self.confirmed_settlements
+            .lock()
+            .unwrap()
+            .push((account_id, amount));
+        Box::new(ok(()))
+    }
+}
+
+pub type Received = Arc<Mutex<Vec<Value>>>;
+
+/// Start a settlement engine that accepts every request and records its JSON body
+pub fn mock_engine(runtime: &mut Runtime) -> (Url, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    let engine = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(move || {
+        let received = received_clone.clone();
+        service_fn(move |req: hyper::Request<Body>| {
+            let received = received.clone();
+            req.into_body().concat2().map(move |body| {
+                received
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::from_slice(&body).unwrap());
+                Response::new(Body::empty())
+            })
+        })
+    });
+    let engine_url = Url::parse(&format!("http://{}", engine.local_addr())).unwrap();
+    runtime.spawn(engine.map_err(|err| panic!("Settlement engine error: {:?}", err)));
+    (engine_url, received)
+}
+
+pub type WebhookRequests = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+/// Start a webhook that records the body and signature of each request it receives. It responds
+/// after the given delay, with each of the given status codes in turn and then with the last one.
+pub fn mock_webhook(
+    runtime: &mut Runtime,
+    status_codes: Vec<u16>,
+    delay: Duration,
+) -> (Url, WebhookRequests) {
+    let received: WebhookRequests = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    let webhook = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(move || {
+        let received = received_clone.clone();
+        let status_codes = status_codes.clone();
+        service_fn(move |req: hyper::Request<Body>| {
+            let received = received.clone();
+            let status_codes = status_codes.clone();
+            let signature = req
+                .headers()
+                .get(WEBHOOK_SIGNATURE_HEADER)
+                .and_then(|signature| signature.to_str().ok())
+                .map(String::from);
+            req.into_body()
+                .concat2()
+                .map(move |body| {
+                    let mut received = received.lock().unwrap();
+                    received.push((String::from_utf8(body.to_vec()).unwrap(), signature));
+                    let status = status_codes
+                        .get(received.len() - 1)
+                        .or_else(|| status_codes.last())
+                        .cloned()
+                        .unwrap_or(200);
+                    Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap()
+                })
+                .and_then(move |response| {
+                    Delay::new(Instant::now() + delay)
+                        .then(move |_| Ok::<_, hyper::Error>(response))
+                })
+        })
+    });
+    let webhook_url = Url::parse(&format!("http://{}", webhook.local_addr())).unwrap();
+    runtime.spawn(webhook.map_err(|err| panic!("Webhook error: {:?}", err)));
+    (webhook_url, received)
+}
+
+/// Wait until the webhook has received the given number of requests, or the timeout has passed
+pub fn wait_for_webhook(received: &WebhookRequests, count: usize, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while received.lock().unwrap().len() < count && Instant::now() < deadline {
+        sleep(Duration::from_millis(10));
+    }
+}
+
+/// An OutgoingService that responds to every request with the same scripted
+/// Fulfill or Reject and records the requests it was sent.
+#[derive(Clone)]
+pub struct MockOutgoingService {
+    response: Result<Fulfill, Reject>,
+    pub sent_requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>,
+}
+
+impl MockOutgoingService {
+    /// Fulfill every request with the given data
+    pub fn fulfill(data: &[u8]) -> Self {
+        MockOutgoingService::fulfill_with(&[0; 32], data)
+    }
+
+    /// Fulfill every request with the given fulfillment and data
+    pub fn fulfill_with(fulfillment: &[u8; 32], data: &[u8]) -> Self {
+        MockOutgoingService {
+            response: Ok(FulfillBuilder { fulfillment, data }.build()),
+            sent_requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Reject every request with the given error code
+    pub fn reject(code: ErrorCode) -> Self {
+        MockOutgoingService {
+            response: Err(RejectBuilder {
+                code,
+                message: b"mock reject",
+                triggered_by: None,
+                data: &[],
+            }
+            .build()),
+            sent_requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl OutgoingService<TestAccount> for MockOutgoingService {
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<TestAccount>) -> Self::Future {
+        self.sent_requests.lock().unwrap().push(request);
+        Box::new(self.response.clone().into_future())
+    }
+}
@@ -0,0 +1,201 @@
+use futures::{
+    future::{err, loop_fn, ok, Either, Loop},
+    Future,
+};
+use reqwest::r#async::Client;
+use ring::{digest, hmac};
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+use url::Url;
+
+/// The header containing the hex-encoded HMAC-SHA256 of the webhook body, keyed with the webhook secret
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Settlement-Signature";
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SettlementNotification {
+    account_id: String,
+    amount: String,
+    asset_scale: u8,
+}
+
+/// # Settlement Webhook
+///
+/// Notifies an external system, such as an accounting system, of incoming settlements by POSTing
+/// `{"accountId", "amount", "assetScale"}` to its URL once a settlement has been credited. The amount
+/// is the one credited to the account, in the account's asset scale.
+///
+/// Each request is signed with the webhook secret so the receiver can check that it came from this node.
+/// Requests that fail are retried, with the time between attempts doubling, until the maximum number
+/// of attempts is reached.
+#[derive(Clone)]
+pub struct SettlementWebhook {
+    url: Url,
+    signing_key: Arc<hmac::SigningKey>,
+    http_client: Client,
+    max_attempts: u32,
+    retry_interval: Duration,
+}
+
+impl SettlementWebhook {
+    pub fn new(url: Url, secret: &[u8]) -> Self {
+        SettlementWebhook {
+            url,
+            signing_key: Arc::new(hmac::SigningKey::new(&digest::SHA256, secret)),
+            http_client: Client::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        }
+    }
+
+    /// Set how many times a notification is sent before giving up, and how long to wait before
+    /// the first retry. Defaults to 5 attempts, starting 1 second apart.
+    pub fn retries(&mut self, max_attempts: u32, retry_interval: Duration) -> &mut Self {
+        self.max_attempts = max_attempts;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Hex-encoded signature of a webhook body
+    pub fn sign(&self, body: &[u8]) -> String {
+        hex::encode(hmac::sign(&self.signing_key, body).as_ref())
+    }
+
+    /// Send the notification for a settlement that was credited to an account,
+    /// retrying until it is accepted or the maximum number of attempts is reached.
+    pub fn notify(
+        &self,
+        account_id: String,
+        amount: u64,
+        asset_scale: u8,
+    ) -> impl Future<Item = (), Error = ()> {
+        let body = serde_json::to_string(&SettlementNotification {
+            account_id: account_id.clone(),
+            amount: amount.to_string(),
+            asset_scale,
+        })
+        .expect("Settlement notifications can always be serialized");
+        let signature = self.sign(body.as_bytes());
+        let webhook = self.clone();
+        loop_fn(
+            (1, self.retry_interval),
+            move |(attempt, retry_interval)| {
+                let max_attempts = webhook.max_attempts;
+                let account_id = account_id.clone();
+                webhook
+                    .post(body.clone(), signature.clone())
+                    .then(move |result| match result {
+                        Ok(_) => {
+                            debug!(
+                                "Notified settlement webhook of settlement of {} for account {}",
+                                amount, account_id
+                            );
+                            Either::A(ok(Loop::Break(())))
+                        }
+                        Err(_) if attempt >= max_attempts => {
+                            error!(
+                                "Giving up notifying settlement webhook of settlement of {} for account {} after {} attempts",
+                                amount, account_id, attempt
+                            );
+                            Either::A(err(()))
+                        }
+                        Err(_) => Either::B(
+                            Delay::new(Instant::now() + retry_interval)
+                                .map_err(|err| {
+                                    error!("Timer error in settlement webhook: {:?}", err)
+                                })
+                                .map(move |_| Loop::Continue((attempt + 1, retry_interval * 2))),
+                        ),
+                    })
+            },
+        )
+    }
+
+    fn post(&self, body: String, signature: String) -> impl Future<Item = (), Error = ()> {
+        let url = self.url.clone();
+        self.http_client
+            .post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .header(WEBHOOK_SIGNATURE_HEADER, signature)
+            .body(body)
+            .send()
+            .map_err(|err| warn!("Error sending settlement webhook: {:?}", err))
+            .and_then(move |response| {
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    warn!(
+                        "Settlement webhook {} responded with HTTP code: {}",
+                        url,
+                        response.status()
+                    );
+                    Err(())
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use serde_json::{json, Value};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn signs_notifications() {
+        let mut runtime = Runtime::new().unwrap();
+        let (webhook_url, received) =
+            mock_webhook(&mut runtime, vec![200], Duration::from_millis(0));
+        let webhook = SettlementWebhook::new(webhook_url, b"webhook secret");
+        runtime
+            .block_on(webhook.notify("3".to_string(), 1000, 6))
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        let (body, signature) = &received[0];
+        assert_eq!(
+            serde_json::from_str::<Value>(body).unwrap(),
+            json!({"accountId": "3", "amount": "1000", "assetScale": 6})
+        );
+        assert_eq!(signature.as_ref(), Some(&webhook.sign(body.as_bytes())));
+        assert_ne!(
+            webhook.sign(body.as_bytes()),
+            SettlementWebhook::new(Url::parse("http://localhost").unwrap(), b"other secret")
+                .sign(body.as_bytes())
+        );
+    }
+
+    #[test]
+    fn retries_failed_notifications() {
+        let mut runtime = Runtime::new().unwrap();
+        let (webhook_url, received) =
+            mock_webhook(&mut runtime, vec![500, 503, 200], Duration::from_millis(0));
+        let mut webhook = SettlementWebhook::new(webhook_url, b"webhook secret");
+        webhook.retries(3, Duration::from_millis(10));
+        runtime
+            .block_on(webhook.notify("3".to_string(), 1000, 6))
+            .unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut runtime = Runtime::new().unwrap();
+        let (webhook_url, received) =
+            mock_webhook(&mut runtime, vec![500], Duration::from_millis(0));
+        let mut webhook = SettlementWebhook::new(webhook_url, b"webhook secret");
+        webhook.retries(2, Duration::from_millis(10));
+        assert!(runtime
+            .block_on(webhook.notify("3".to_string(), 1000, 6))
+            .is_err());
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+}
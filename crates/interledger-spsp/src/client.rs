@@ -1,25 +1,76 @@
-use super::{Error, SpspResponse};
-use futures::{future::result, Future};
-use interledger_packet::Address;
+use super::display::{estimate_display_amount, PaymentResult, RateSource};
+use super::{Error, SpspResponse, StreamError};
+use futures::{
+    future::{err, lazy, loop_fn, ok, Either, Loop},
+    sync::oneshot,
+    Future,
+};
+use interledger_ildcp::get_ildcp_info;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::send_money;
-use reqwest::r#async::Client;
-use std::convert::TryFrom;
+use interledger_stream::{send_money_with_options, CancelHandle, DeliveryTarget, PaymentOptions};
+use reqwest::{header::LINK, r#async::Client, StatusCode, Url};
+use std::{
+    collections::{HashMap, VecDeque},
+    str,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
+/// Query the SPSP receiver for the given Payment Pointer or URL.
+///
+/// The URLs from `spsp_url_variations` are tried in order until one does not respond with 404 Not Found.
+/// If a 404 response includes a `Link` header, the linked URL is tried next.
 pub fn query(server: &str) -> impl Future<Item = SpspResponse, Error = Error> {
-    let server = payment_pointer_to_url(server);
-    trace!("Querying receiver: {}", server);
-
     let client = Client::new();
-    client
-        .get(&server)
-        .header("Accept", "application/spsp4+json")
-        .send()
-        .map_err(|err| Error::HttpError(format!("Error querying SPSP receiver: {:?}", err)))
-        .and_then(|mut res| {
-            res.json::<SpspResponse>()
-                .map_err(|err| Error::InvalidResponseError(format!("{:?}", err)))
-        })
+    let urls: VecDeque<String> = spsp_url_variations(server).into_iter().collect();
+    loop_fn(
+        (urls, Vec::new()),
+        move |(mut urls, mut tried): (VecDeque<String>, Vec<String>)| {
+            let url = match urls.pop_front() {
+                Some(url) => url,
+                None => return Either::B(err(Error::ReceiverNotFoundError(tried))),
+            };
+            trace!("Querying receiver: {}", url);
+            tried.push(url.clone());
+
+            Either::A(
+                client
+                    .get(&url)
+                    .header("Accept", "application/spsp4+json")
+                    .send()
+                    .map_err(|err| {
+                        Error::HttpError(format!("Error querying SPSP receiver: {:?}", err))
+                    })
+                    .and_then(move |mut res| {
+                        if res.status() == StatusCode::NOT_FOUND {
+                            let link = res
+                                .headers()
+                                .get(LINK)
+                                .and_then(|link| link.to_str().ok())
+                                .and_then(|link| parse_link_header(&url, link))
+                                .filter(|link| !tried.contains(link));
+                            if let Some(link) = link {
+                                debug!("SPSP receiver at {} linked to {}", url, link);
+                                urls.push_front(link);
+                            } else {
+                                debug!("No SPSP receiver found at {}", url);
+                            }
+                            Either::A(ok(Loop::Continue((urls, tried))))
+                        } else {
+                            Either::B(
+                                res.json::<SpspResponse>()
+                                    .map_err(|err| {
+                                        Error::InvalidResponseError(format!("{:?}", err))
+                                    })
+                                    .map(Loop::Break),
+                            )
+                        }
+                    }),
+            )
+        },
+    )
 }
 
 /// Query the details of the given Payment Pointer and send a payment using the STREAM protocol.
@@ -31,36 +82,415 @@ pub fn pay<S, A>(
     receiver: &str,
     source_amount: u64,
 ) -> impl Future<Item = u64, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    pay_with_target(
+        service,
+        from_account,
+        receiver,
+        DeliveryTarget::Send(source_amount),
+    )
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol,
+/// either sending a fixed source amount or sending until a fixed amount is delivered.
+///
+/// This returns the amount delivered, as reported by the receiver and in the receiver's asset's units.
+pub fn pay_with_target<S, A>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    target: DeliveryTarget,
+) -> impl Future<Item = u64, Error = Error>
 where
     S: IncomingService<A> + Clone,
     A: Account,
 {
     query(receiver).and_then(move |spsp| {
-        let shared_secret = spsp.shared_secret;
-        let dest = spsp.destination_account;
-        result(Address::try_from(dest).map_err(move |err| {
-            error!("Error parsing address");
-            Error::InvalidResponseError(err.to_string())
-        }))
-        .and_then(move |addr| {
-            debug!("Sending SPSP payment to address: {}", addr);
-
-            send_money(service, &from_account, addr, &shared_secret, source_amount)
-                .map(move |(amount_delivered, _plugin)| {
-                    debug!(
-                        "Sent SPSP payment of {} and delivered {} of the receiver's units",
-                        source_amount, amount_delivered
-                    );
-                    amount_delivered
-                })
-                .map_err(move |err| {
-                    error!("Error sending payment: {:?}", err);
-                    Error::SendMoneyError(source_amount)
-                })
+        send_to_receiver(
+            service,
+            from_account,
+            spsp,
+            target,
+            PaymentOptions::default(),
+        )
+    })
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol,
+/// like `pay_with_target`, with at most `max_packets_in_flight` STREAM packets in flight at a time.
+pub fn pay_with_concurrency<S, A>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    target: DeliveryTarget,
+    max_packets_in_flight: usize,
+) -> impl Future<Item = u64, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    query(receiver).and_then(move |spsp| {
+        send_to_receiver(
+            service,
+            from_account,
+            spsp,
+            target,
+            PaymentOptions {
+                max_packets_in_flight: Some(max_packets_in_flight),
+                ..PaymentOptions::default()
+            },
+        )
+    })
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol,
+/// like `pay_with_target`, with the given `PaymentOptions`.
+///
+/// If the payment does not finish within the options' timeout, it fails with `Error::Timeout`.
+pub fn pay_with_options<S, A>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    target: DeliveryTarget,
+    options: PaymentOptions,
+) -> impl Future<Item = u64, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    query(receiver)
+        .and_then(move |spsp| send_to_receiver(service, from_account, spsp, target, options))
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol,
+/// like `pay_with_target`, and also estimate what was delivered in the given display currency.
+///
+/// The estimate is only available if the receiver advertised its asset in the SPSP response
+/// and the `rate_source` has a rate between the receiver's asset and the display currency.
+/// The result also includes the sender's own asset code and scale, which are looked up over
+/// ILDCP, so that the amount sent can be displayed too. If the ILDCP request fails, the payment
+/// is still sent and the result uses an empty asset code and a scale of 0 (raw amounts).
+pub fn pay_with_display<S, A, R>(
+    service: S,
+    from_account: A,
+    receiver: &str,
+    target: DeliveryTarget,
+    display_asset_code: String,
+    rate_source: R,
+) -> impl Future<Item = PaymentResult, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+    R: RateSource,
+{
+    let receiver = receiver.to_string();
+    get_ildcp_info(&mut service.clone(), from_account.clone())
+        .then(|result| {
+            let source_asset = match result {
+                Ok(info) => (
+                    str::from_utf8(info.asset_code())
+                        .unwrap_or_default()
+                        .to_string(),
+                    info.asset_scale(),
+                ),
+                Err(_) => {
+                    warn!("Unable to get ILDCP info, the amount sent will not be scaled");
+                    (String::new(), 0)
+                }
+            };
+            Ok(source_asset)
+        })
+        .and_then(move |source_asset| query(&receiver).map(move |spsp| (source_asset, spsp)))
+        .and_then(move |((source_asset_code, source_asset_scale), spsp)| {
+            let receiver_asset = match (spsp.asset_code.clone(), spsp.asset_scale) {
+                (Some(asset_code), Some(asset_scale)) => Some((asset_code, asset_scale)),
+                _ => None,
+            };
+            send_to_receiver(
+                service,
+                from_account,
+                spsp,
+                target,
+                PaymentOptions::default(),
+            )
+            .map(move |delivered_amount| {
+                let estimated_display_amount =
+                    receiver_asset.and_then(|(asset_code, asset_scale)| {
+                        estimate_display_amount(
+                            delivered_amount,
+                            &asset_code,
+                            asset_scale,
+                            &display_asset_code,
+                            &rate_source,
+                        )
+                    });
+                PaymentResult {
+                    source_asset_code,
+                    source_asset_scale,
+                    delivered_amount,
+                    estimated_display_amount,
+                }
+            })
         })
+}
+
+fn send_to_receiver<S, A>(
+    service: S,
+    from_account: A,
+    spsp: SpspResponse,
+    target: DeliveryTarget,
+    options: PaymentOptions,
+) -> impl Future<Item = u64, Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    let source_amount = match target {
+        DeliveryTarget::Send(source_amount) => source_amount,
+        DeliveryTarget::Deliver {
+            max_source_amount, ..
+        } => max_source_amount,
+    };
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!("Sending SPSP payment to address: {}", addr);
+
+    send_money_with_options(
+        service,
+        &from_account,
+        addr,
+        &shared_secret,
+        target,
+        options,
+    )
+    .map(move |(amount_delivered, _plugin)| {
+        debug!(
+            "Sent SPSP payment ({:?}) and delivered {} of the receiver's units",
+            target, amount_delivered
+        );
+        amount_delivered
+    })
+    .map_err(move |err| match err {
+        StreamError::Timeout { elapsed, delivered } => {
+            error!(
+                "Payment deadline exceeded after {:?}, having delivered: {}",
+                elapsed, delivered
+            );
+            Error::Timeout { elapsed, delivered }
+        }
+        err => {
+            error!("Error sending payment: {:?}", err);
+            Error::SendMoneyError(source_amount)
+        }
     })
 }
 
+/// The number of SPSP queries an `SpspClient` has outstanding at once, unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_QUERIES: usize = 10;
+
+/// Limits how many SPSP queries are outstanding at once. Queries over the limit
+/// wait, in the order they were started, for an earlier one to finish.
+#[derive(Clone)]
+struct QueryLimiter {
+    max_in_flight: usize,
+    state: Arc<Mutex<(usize, VecDeque<oneshot::Sender<QueryPermit>>)>>,
+}
+
+/// Allows one query to be outstanding. The slot is given back to the limiter when the permit
+/// is dropped, including when the future holding it is dropped before the query finishes.
+struct QueryPermit {
+    limiter: Option<QueryLimiter>,
+}
+
+impl Drop for QueryPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release();
+        }
+    }
+}
+
+impl QueryLimiter {
+    fn new(max_in_flight: usize) -> Self {
+        QueryLimiter {
+            max_in_flight: max_in_flight.max(1),
+            state: Arc::new(Mutex::new((0, VecDeque::new()))),
+        }
+    }
+
+    /// Resolves once the query may be sent. The slot is only taken when the returned future is polled.
+    fn acquire(&self) -> impl Future<Item = QueryPermit, Error = Error> {
+        let limiter = self.clone();
+        lazy(move || {
+            let mut state = limiter.state.lock().unwrap();
+            if state.0 < limiter.max_in_flight {
+                state.0 += 1;
+                Either::A(ok(QueryPermit {
+                    limiter: Some(limiter.clone()),
+                }))
+            } else {
+                trace!("Waiting for an SPSP query to finish before sending another");
+                let (sender, receiver) = oneshot::channel();
+                state.1.push_back(sender);
+                Either::B(receiver.map_err(|_| {
+                    Error::HttpError("SPSP query was dropped while waiting to be sent".to_string())
+                }))
+            }
+        })
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        // Hand the slot directly to the next waiting query, if there is one still waiting
+        while let Some(waiting) = state.1.pop_front() {
+            match waiting.send(QueryPermit {
+                limiter: Some(self.clone()),
+            }) {
+                Ok(()) => return,
+                // That query is no longer waiting, so the permit must not release the slot again
+                Err(mut permit) => {
+                    permit.limiter.take();
+                }
+            }
+        }
+        state.0 -= 1;
+    }
+}
+
+/// A payment started by an `SpspClient`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActivePayment {
+    pub id: u64,
+    pub receiver: String,
+    pub target: DeliveryTarget,
+}
+
+type ActivePayments = Arc<Mutex<HashMap<u64, (ActivePayment, CancelHandle)>>>;
+
+/// Sends SPSP payments and keeps track of the ones in progress,
+/// so that they can be listed and cancelled.
+///
+/// At most `DEFAULT_MAX_CONCURRENT_QUERIES` SPSP queries are sent at once (see `max_concurrent_queries`),
+/// so that starting many payments does not open a connection to every receiver at the same time.
+/// This does not limit how many payments are sending STREAM packets.
+#[derive(Clone)]
+pub struct SpspClient {
+    payments: ActivePayments,
+    next_id: Arc<AtomicU64>,
+    query_limiter: QueryLimiter,
+}
+
+impl Default for SpspClient {
+    fn default() -> Self {
+        SpspClient {
+            payments: ActivePayments::default(),
+            next_id: Arc::new(AtomicU64::new(0)),
+            query_limiter: QueryLimiter::new(DEFAULT_MAX_CONCURRENT_QUERIES),
+        }
+    }
+}
+
+impl SpspClient {
+    pub fn new() -> Self {
+        SpspClient::default()
+    }
+
+    /// Set the maximum number of SPSP queries that may be outstanding at once (at least 1).
+    /// This should be set before any payments are started.
+    pub fn max_concurrent_queries(&mut self, max_concurrent_queries: usize) -> &mut Self {
+        self.query_limiter = QueryLimiter::new(max_concurrent_queries);
+        self
+    }
+
+    /// Query the SPSP receiver like `query`, waiting first if too many queries are already outstanding
+    pub fn query(&self, receiver: &str) -> impl Future<Item = SpspResponse, Error = Error> {
+        let receiver = receiver.to_string();
+        self.query_limiter.acquire().and_then(move |permit| {
+            query(&receiver).then(move |result| {
+                drop(permit);
+                result
+            })
+        })
+    }
+
+    /// Start a payment like `pay_with_target`, returning its id along with the payment itself.
+    /// The payment is listed as active until the returned future finishes.
+    pub fn pay<S, A>(
+        &self,
+        service: S,
+        from_account: A,
+        receiver: &str,
+        target: DeliveryTarget,
+    ) -> (u64, impl Future<Item = u64, Error = Error>)
+    where
+        S: IncomingService<A> + Clone,
+        A: Account,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let cancel_handle = CancelHandle::new();
+        self.payments.lock().unwrap().insert(
+            id,
+            (
+                ActivePayment {
+                    id,
+                    receiver: receiver.to_string(),
+                    target,
+                },
+                cancel_handle.clone(),
+            ),
+        );
+        let payments = self.payments.clone();
+        let payment = self
+            .query(receiver)
+            .and_then(move |spsp| {
+                send_to_receiver(
+                    service,
+                    from_account,
+                    spsp,
+                    target,
+                    PaymentOptions {
+                        cancel_handle,
+                        ..PaymentOptions::default()
+                    },
+                )
+            })
+            .then(move |result| {
+                payments.lock().unwrap().remove(&id);
+                result
+            });
+        (id, payment)
+    }
+
+    /// The payments that are currently in progress
+    pub fn active_payments(&self) -> Vec<ActivePayment> {
+        let mut payments: Vec<ActivePayment> = self
+            .payments
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(payment, _)| payment.clone())
+            .collect();
+        payments.sort_by_key(|payment| payment.id);
+        payments
+    }
+
+    /// Stop sending packets for the given payment. The payment then resolves to
+    /// the amount delivered before it was cancelled, which is final.
+    /// Returns false if there is no such payment in progress.
+    pub fn cancel(&self, id: u64) -> bool {
+        if let Some((_, cancel_handle)) = self.payments.lock().unwrap().get(&id) {
+            debug!("Cancelling SPSP payment {}", id);
+            cancel_handle.cancel();
+            true
+        } else {
+            false
+        }
+    }
+}
+
 fn payment_pointer_to_url(payment_pointer: &str) -> String {
     let mut url: String = if payment_pointer.starts_with('$') {
         let mut url = "https://".to_string();
@@ -84,6 +514,36 @@ fn payment_pointer_to_url(payment_pointer: &str) -> String {
     url
 }
 
+/// Get the URLs that the SPSP receiver for a Payment Pointer or URL might be served at, in the order they should be tried.
+///
+/// The first is the standard URL for the Payment Pointer. The second is the same URL with or without
+/// a trailing slash, because some static file hosts only serve a path like `/.well-known/pay` as a directory.
+pub fn spsp_url_variations(payment_pointer: &str) -> Vec<String> {
+    let url = payment_pointer_to_url(payment_pointer);
+    let alternate = if url.ends_with('/') {
+        url.trim_end_matches('/').to_string()
+    } else {
+        format!("{}/", url)
+    };
+    if alternate.matches('/').count() > 2 {
+        vec![url, alternate]
+    } else {
+        vec![url]
+    }
+}
+
+/// Get the target of a `Link` header such as `</pay/alice>; rel="alternate"`,
+/// resolved relative to the URL that was requested.
+fn parse_link_header(base: &str, link: &str) -> Option<String> {
+    let start = link.find('<')?;
+    let end = link[start..].find('>')? + start;
+    let target = &link[start + 1..end];
+    Url::parse(base)
+        .and_then(|base| base.join(target))
+        .map(|url| url.to_string())
+        .ok()
+}
+
 #[cfg(test)]
 mod payment_pointer {
     use super::*;
@@ -96,4 +556,471 @@ mod payment_pointer {
             "https://subdomain.domain.example/.well-known/pay"
         );
     }
+
+    #[test]
+    fn url_variations() {
+        assert_eq!(
+            spsp_url_variations("$example.com"),
+            vec![
+                "https://example.com/.well-known/pay",
+                "https://example.com/.well-known/pay/"
+            ]
+        );
+        assert_eq!(
+            spsp_url_variations("https://example.com/alice/"),
+            vec!["https://example.com/alice/", "https://example.com/alice"]
+        );
+    }
+
+    #[test]
+    fn parses_link_header() {
+        assert_eq!(
+            parse_link_header(
+                "https://example.com/.well-known/pay",
+                "</pay/alice>; rel=\"alternate\""
+            ),
+            Some("https://example.com/pay/alice".to_string())
+        );
+        assert_eq!(
+            parse_link_header("https://example.com/", "<https://other.example/spsp>"),
+            Some("https://other.example/spsp".to_string())
+        );
+        assert_eq!(parse_link_header("https://example.com/", "invalid"), None);
+    }
+}
+
+#[cfg(test)]
+mod querying {
+    use super::*;
+    use futures::future::join_all;
+    use hyper::{
+        service::{service_fn, service_fn_ok},
+        Body, Response, Server,
+    };
+    use std::{
+        sync::atomic::AtomicUsize,
+        time::{Duration, Instant},
+    };
+    use tokio::{runtime::Runtime, timer::Delay};
+
+    static SPSP_RESPONSE: &str = r#"{"destination_account":"example.receiver","shared_secret":"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="}"#;
+
+    /// Serve the SPSP response only at the given path, optionally linking to it from 404 responses
+    fn serve_spsp_at(
+        runtime: &mut Runtime,
+        path: &'static str,
+        link: Option<&'static str>,
+    ) -> String {
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(move || {
+            service_fn_ok(move |req| {
+                if req.uri().path() == path {
+                    Response::new(Body::from(SPSP_RESPONSE))
+                } else {
+                    let mut res = Response::builder();
+                    res.status(404);
+                    if let Some(link) = link {
+                        res.header("Link", link);
+                    }
+                    res.body(Body::empty()).unwrap()
+                }
+            })
+        });
+        let url = format!("http://{}", server.local_addr());
+        runtime.spawn(server.map_err(|err| panic!("SPSP server error: {:?}", err)));
+        url
+    }
+
+    #[test]
+    fn queries_well_known_path() {
+        let mut runtime = Runtime::new().unwrap();
+        let server = serve_spsp_at(&mut runtime, "/.well-known/pay", None);
+        let response = runtime.block_on(query(&server)).unwrap();
+        assert_eq!(response.destination_account.to_string(), "example.receiver");
+    }
+
+    #[test]
+    fn follows_link_header_after_not_found() {
+        let mut runtime = Runtime::new().unwrap();
+        let server = serve_spsp_at(
+            &mut runtime,
+            "/custom/spsp",
+            Some("</custom/spsp>; rel=\"alternate\""),
+        );
+        let response = runtime.block_on(query(&server)).unwrap();
+        assert_eq!(response.destination_account.to_string(), "example.receiver");
+    }
+
+    #[test]
+    fn falls_back_to_trailing_slash() {
+        let mut runtime = Runtime::new().unwrap();
+        let server = serve_spsp_at(&mut runtime, "/.well-known/pay/", None);
+        let response = runtime.block_on(query(&server)).unwrap();
+        assert_eq!(response.destination_account.to_string(), "example.receiver");
+    }
+
+    #[test]
+    fn errors_if_no_url_resolves() {
+        let mut runtime = Runtime::new().unwrap();
+        let server = serve_spsp_at(&mut runtime, "/nowhere", None);
+        match runtime.block_on(query(&server)) {
+            Err(Error::ReceiverNotFoundError(tried)) => assert_eq!(
+                tried,
+                vec![
+                    format!("{}/.well-known/pay", server),
+                    format!("{}/.well-known/pay/", server)
+                ]
+            ),
+            other => panic!("Expected receiver not found error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limits_concurrent_queries() {
+        let mut runtime = Runtime::new().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let (in_flight_clone, max_in_flight_clone) = (in_flight.clone(), max_in_flight.clone());
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(move || {
+            let (in_flight, max_in_flight) = (in_flight_clone.clone(), max_in_flight_clone.clone());
+            service_fn(move |_| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(now, Ordering::SeqCst);
+                let in_flight = in_flight.clone();
+                Delay::new(Instant::now() + Duration::from_millis(20)).then(move |_| {
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, hyper::Error>(Response::new(Body::from(SPSP_RESPONSE)))
+                })
+            })
+        });
+        let url = format!("http://{}/.well-known/pay", server.local_addr());
+        runtime.spawn(server.map_err(|err| panic!("SPSP server error: {:?}", err)));
+
+        let mut client = SpspClient::new();
+        client.max_concurrent_queries(3);
+        let queries: Vec<_> = (0..20).map(|_| client.query(&url)).collect();
+        let responses = runtime.block_on(join_all(queries)).unwrap();
+        assert_eq!(responses.len(), 20);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn releases_queries_that_are_dropped() {
+        let mut runtime = Runtime::new().unwrap();
+        // This receiver never responds in time
+        let slow_server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(|| {
+            service_fn(|_| {
+                Delay::new(Instant::now() + Duration::from_secs(60))
+                    .then(|_| Ok::<_, hyper::Error>(Response::new(Body::from(SPSP_RESPONSE))))
+            })
+        });
+        let slow_url = format!("http://{}/.well-known/pay", slow_server.local_addr());
+        runtime.spawn(slow_server.map_err(|err| panic!("SPSP server error: {:?}", err)));
+        let server = serve_spsp_at(&mut runtime, "/.well-known/pay", None);
+
+        let mut client = SpspClient::new();
+        client.max_concurrent_queries(1);
+        // Queries that are never polled do not hold a slot
+        let _not_started = client.query(&slow_url);
+
+        // Give up on a query while it is outstanding
+        let outstanding = client.query(&slow_url);
+        match runtime
+            .block_on(outstanding.select2(Delay::new(Instant::now() + Duration::from_millis(50))))
+        {
+            Ok(Either::B((_, outstanding))) => drop(outstanding),
+            _ => panic!("Query to the slow receiver should not have finished"),
+        }
+
+        let response = runtime
+            .block_on(
+                client
+                    .query(&server)
+                    .select2(Delay::new(Instant::now() + Duration::from_secs(5))),
+            )
+            .map_err(|_| ())
+            .unwrap();
+        match response {
+            Either::A((response, _)) => {
+                assert_eq!(response.destination_account.to_string(), "example.receiver")
+            }
+            Either::B(_) => panic!("Query waited for a slot that was never released"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod paying {
+    use super::*;
+    use futures::sync::oneshot;
+    use hyper::{service::service_fn_ok, Body, Response, Server};
+    use interledger_ildcp::{IldcpAccount, IldcpService};
+    use interledger_packet::{Address, ErrorCode, Fulfill, Reject, RejectBuilder};
+    use interledger_service::{incoming_service_fn, BoxedIlpFuture, IncomingRequest};
+    use std::{
+        str::FromStr,
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+    use tokio::{runtime::Runtime, timer::Delay};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        ilp_address: Address,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &Address {
+            &self.ilp_address
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    fn test_account() -> TestAccount {
+        TestAccount {
+            ilp_address: Address::from_str("example.sender").unwrap(),
+        }
+    }
+
+    /// Serve an SPSP response for a receiver that does not advertise its asset
+    fn serve_spsp(runtime: &mut Runtime) -> String {
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(|| {
+            service_fn_ok(|_| Response::new(Body::from(r#"{"destination_account":"example.receiver","shared_secret":"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="}"#)))
+        });
+        let receiver = format!("http://{}", server.local_addr());
+        runtime.spawn(server.map_err(|err| panic!("SPSP server error: {:?}", err)));
+        receiver
+    }
+
+    struct NoRates;
+
+    impl RateSource for NoRates {
+        fn get_rate(&self, _from: &str, _to: &str) -> Option<f64> {
+            None
+        }
+    }
+
+    #[test]
+    fn result_includes_source_asset() {
+        let mut runtime = Runtime::new().unwrap();
+        let receiver = serve_spsp(&mut runtime);
+        // Nothing is sent, so only the connection close is rejected (with a temporary error)
+        let service = IldcpService::new(incoming_service_fn(|_| {
+            Err(RejectBuilder {
+                code: ErrorCode::T00_INTERNAL_ERROR,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build())
+        }));
+        let result = runtime
+            .block_on(pay_with_display(
+                service,
+                test_account(),
+                &receiver,
+                DeliveryTarget::Send(0),
+                "USD".to_string(),
+                NoRates,
+            ))
+            .unwrap();
+        assert_eq!(
+            result,
+            PaymentResult {
+                source_asset_code: "XYZ".to_string(),
+                source_asset_scale: 9,
+                delivered_amount: 0,
+                estimated_display_amount: None,
+            }
+        );
+    }
+
+    #[test]
+    fn result_uses_raw_amounts_if_ildcp_fails() {
+        let mut runtime = Runtime::new().unwrap();
+        let receiver = serve_spsp(&mut runtime);
+        // Only the first ILDCP request (the one for the display details) is rejected
+        let mut ildcp_service = IldcpService::new(incoming_service_fn(|_| {
+            Err(RejectBuilder {
+                code: ErrorCode::T00_INTERNAL_ERROR,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build())
+        }));
+        let requests = Arc::new(Mutex::new(0));
+        let service = incoming_service_fn(
+            move |request: IncomingRequest<TestAccount>| -> BoxedIlpFuture {
+                let mut requests = requests.lock().unwrap();
+                *requests += 1;
+                if *requests == 1 {
+                    Box::new(err(RejectBuilder {
+                        code: ErrorCode::T00_INTERNAL_ERROR,
+                        message: &[],
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build()))
+                } else {
+                    Box::new(ildcp_service.handle_request(request))
+                }
+            },
+        );
+        let result = runtime
+            .block_on(pay_with_display(
+                service,
+                test_account(),
+                &receiver,
+                DeliveryTarget::Send(0),
+                "USD".to_string(),
+                NoRates,
+            ))
+            .unwrap();
+        assert_eq!(
+            result,
+            PaymentResult {
+                source_asset_code: String::new(),
+                source_asset_scale: 0,
+                delivered_amount: 0,
+                estimated_display_amount: None,
+            }
+        );
+    }
+
+    #[test]
+    fn cancelled_payment_stops_sending() {
+        let mut runtime = Runtime::new().unwrap();
+        let receiver = serve_spsp(&mut runtime);
+
+        // Every packet is rejected with a temporary error after a short delay, so the payment keeps retrying
+        let packets = Arc::new(Mutex::new(0));
+        let packets_clone = packets.clone();
+        let service = IldcpService::new(incoming_service_fn(
+            move |request: IncomingRequest<TestAccount>| -> BoxedIlpFuture {
+                if request.prepare.amount() > 0 {
+                    *packets_clone.lock().unwrap() += 1;
+                }
+                Box::new(
+                    Delay::new(Instant::now() + Duration::from_millis(10)).then(|_| {
+                        Err(RejectBuilder {
+                            code: ErrorCode::T00_INTERNAL_ERROR,
+                            message: b"Try again",
+                            triggered_by: None,
+                            data: &[],
+                        }
+                        .build())
+                    }),
+                )
+            },
+        ));
+
+        let client = SpspClient::new();
+        let (id, payment) = client.pay(
+            service,
+            test_account(),
+            &receiver,
+            DeliveryTarget::Send(1000),
+        );
+        let (sender, result) = oneshot::channel();
+        runtime.spawn(payment.then(move |result| {
+            sender.send(result).unwrap();
+            Ok(())
+        }));
+
+        sleep(Duration::from_millis(200));
+        assert_eq!(
+            client.active_payments(),
+            vec![ActivePayment {
+                id,
+                receiver: receiver.clone(),
+                target: DeliveryTarget::Send(1000),
+            }]
+        );
+        assert!(*packets.lock().unwrap() > 0);
+
+        assert!(client.cancel(id));
+        let delivered = result.wait().unwrap().unwrap();
+        assert_eq!(delivered, 0);
+        assert!(client.active_payments().is_empty());
+        assert!(!client.cancel(id));
+
+        let sent = *packets.lock().unwrap();
+        sleep(Duration::from_millis(100));
+        assert_eq!(*packets.lock().unwrap(), sent);
+    }
+
+    fn reject_with(code: ErrorCode) -> Result<Fulfill, Reject> {
+        Err(RejectBuilder {
+            code,
+            message: b"Timed out",
+            triggered_by: None,
+            data: &[],
+        }
+        .build())
+    }
+
+    #[test]
+    fn local_deadline_is_a_timeout() {
+        let mut runtime = Runtime::new().unwrap();
+        let receiver = serve_spsp(&mut runtime);
+        // Packets are never fulfilled or rejected, so only the payment's own deadline stops it
+        let service = IldcpService::new(incoming_service_fn(|_| {
+            futures::future::empty::<Fulfill, Reject>()
+        }));
+        let result = runtime.block_on(pay_with_options(
+            service,
+            test_account(),
+            &receiver,
+            DeliveryTarget::Send(1000),
+            PaymentOptions {
+                timeout: Some(Duration::from_millis(200)),
+                ..PaymentOptions::default()
+            },
+        ));
+        match result {
+            Err(Error::Timeout { elapsed, delivered }) => {
+                assert!(elapsed >= Duration::from_millis(200));
+                assert_eq!(delivered, 0);
+            }
+            other => panic!("Expected a timeout, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn peer_r00_is_not_a_timeout() {
+        let mut runtime = Runtime::new().unwrap();
+        let receiver = serve_spsp(&mut runtime);
+        let service = IldcpService::new(incoming_service_fn(|_| {
+            reject_with(ErrorCode::R00_TRANSFER_TIMED_OUT)
+        }));
+        let result = runtime.block_on(pay_with_options(
+            service,
+            test_account(),
+            &receiver,
+            DeliveryTarget::Send(1000),
+            PaymentOptions {
+                timeout: Some(Duration::from_secs(30)),
+                ..PaymentOptions::default()
+            },
+        ));
+        match result {
+            Err(Error::SendMoneyError(1000)) => {}
+            other => panic!("Expected a send money error, got: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,171 @@
+/// A source of exchange rates, used to estimate what a payment is worth in another currency.
+pub trait RateSource {
+    /// The number of whole units of `to_asset_code` that one whole unit of `from_asset_code` is worth, if known.
+    fn get_rate(&self, from_asset_code: &str, to_asset_code: &str) -> Option<f64>;
+}
+
+/// An amount converted into another currency using a `RateSource`.
+/// This is only an estimate for display purposes and is not what the receiver was paid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EstimatedAmount {
+    pub asset_code: String,
+    /// The amount in whole units of the asset
+    pub amount: f64,
+}
+
+/// The result of an SPSP payment sent with `pay_with_display`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentResult {
+    /// The sender's asset code, as reported by its upstream node over ILDCP (empty if ILDCP failed)
+    pub source_asset_code: String,
+    /// The sender's asset scale, as reported by its upstream node over ILDCP (0 if ILDCP failed)
+    pub source_asset_scale: u8,
+    /// The amount delivered, as reported by the receiver and in the receiver's asset's units
+    pub delivered_amount: u64,
+    /// The delivered amount converted into the display currency, if it could be estimated
+    pub estimated_display_amount: Option<EstimatedAmount>,
+}
+
+/// Format an amount given in an asset's smallest units as whole units, for example
+/// `format_amount(150, 2, "USD")` returns `"1.50 USD"`.
+///
+/// Trailing zeros are trimmed from the fractional part, but at least two decimal places
+/// are kept (or fewer, if the scale is smaller). The conversion is done on the digits
+/// so large scales do not lose precision.
+pub fn format_amount(value: u64, scale: u8, asset_code: &str) -> String {
+    let scale = usize::from(scale);
+    let mut digits = value.to_string();
+    if scale > 0 {
+        if digits.len() <= scale {
+            digits = format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits);
+        }
+        let integer_len = digits.len() - scale;
+        let min_fraction_len = scale.min(2);
+        let fraction = digits[integer_len..].trim_end_matches('0');
+        let fraction = if fraction.len() < min_fraction_len {
+            &digits[integer_len..integer_len + min_fraction_len]
+        } else {
+            fraction
+        };
+        digits = if fraction.is_empty() {
+            digits[..integer_len].to_string()
+        } else {
+            format!("{}.{}", &digits[..integer_len], fraction)
+        };
+    }
+    if asset_code.is_empty() {
+        digits
+    } else {
+        format!("{} {}", digits, asset_code)
+    }
+}
+
+pub(crate) fn estimate_display_amount<R: RateSource>(
+    amount: u64,
+    asset_code: &str,
+    asset_scale: u8,
+    display_asset_code: &str,
+    rate_source: &R,
+) -> Option<EstimatedAmount> {
+    let rate = if asset_code == display_asset_code {
+        1.0
+    } else {
+        rate_source.get_rate(asset_code, display_asset_code)?
+    };
+    let whole_units = amount as f64 / 10f64.powi(i32::from(asset_scale));
+    Some(EstimatedAmount {
+        asset_code: display_asset_code.to_string(),
+        amount: whole_units * rate,
+    })
+}
+
+#[cfg(test)]
+mod estimating {
+    use super::*;
+
+    struct StubRates;
+
+    impl RateSource for StubRates {
+        fn get_rate(&self, from_asset_code: &str, to_asset_code: &str) -> Option<f64> {
+            match (from_asset_code, to_asset_code) {
+                ("XRP", "USD") => Some(0.5),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn converts_with_rate_and_scale() {
+        assert_eq!(
+            estimate_display_amount(3_000_000, "XRP", 6, "USD", &StubRates),
+            Some(EstimatedAmount {
+                asset_code: "USD".to_string(),
+                amount: 1.5,
+            })
+        );
+    }
+
+    #[test]
+    fn same_asset_does_not_need_a_rate() {
+        assert_eq!(
+            estimate_display_amount(250, "USD", 2, "USD", &StubRates),
+            Some(EstimatedAmount {
+                asset_code: "USD".to_string(),
+                amount: 2.5,
+            })
+        );
+    }
+
+    #[test]
+    fn no_estimate_without_rate() {
+        assert_eq!(
+            estimate_display_amount(100, "EUR", 2, "USD", &StubRates),
+            None
+        );
+    }
+}
+
+#[cfg(test)]
+mod formatting {
+    use super::*;
+
+    #[test]
+    fn integer_scale() {
+        assert_eq!(format_amount(0, 0, "XRP"), "0 XRP");
+        assert_eq!(format_amount(1500, 0, "XRP"), "1500 XRP");
+    }
+
+    #[test]
+    fn keeps_two_decimal_places() {
+        assert_eq!(format_amount(150, 2, "USD"), "1.50 USD");
+        assert_eq!(format_amount(100, 2, "USD"), "1.00 USD");
+        assert_eq!(format_amount(1_000_000_000, 9, "XYZ"), "1.00 XYZ");
+        assert_eq!(format_amount(0, 6, "XRP"), "0.00 XRP");
+    }
+
+    #[test]
+    fn trims_trailing_zeros() {
+        assert_eq!(format_amount(1_230_000, 6, "XRP"), "1.23 XRP");
+        assert_eq!(format_amount(1_234_500, 6, "XRP"), "1.2345 XRP");
+        assert_eq!(format_amount(5, 1, "ABC"), "0.5 ABC");
+        assert_eq!(format_amount(10, 1, "ABC"), "1.0 ABC");
+    }
+
+    #[test]
+    fn small_values_and_large_scales() {
+        assert_eq!(format_amount(1, 9, "XYZ"), "0.000000001 XYZ");
+        assert_eq!(
+            format_amount(u64::MAX, 18, "ETH"),
+            "18.446744073709551615 ETH"
+        );
+        assert_eq!(
+            format_amount(1, 25, "ABC"),
+            "0.0000000000000000000000001 ABC"
+        );
+    }
+
+    #[test]
+    fn no_asset_code() {
+        assert_eq!(format_amount(150, 2, ""), "1.50");
+    }
+}
@@ -14,11 +14,18 @@ extern crate failure;
 
 use interledger_packet::Address;
 use interledger_stream::Error as StreamError;
+use std::time::Duration;
 
 mod client;
+mod display;
 mod server;
 
-pub use client::{pay, query};
+pub use client::{
+    pay, pay_with_concurrency, pay_with_display, pay_with_options, pay_with_target, query,
+    spsp_url_variations, ActivePayment, SpspClient, DEFAULT_MAX_CONCURRENT_QUERIES,
+};
+pub use display::{format_amount, EstimatedAmount, PaymentResult, RateSource};
+pub use interledger_stream::{DeliveryTarget, PaymentOptions};
 pub use server::SpspResponder;
 
 #[derive(Fail, Debug)]
@@ -35,6 +42,14 @@ pub enum Error {
     ListenError(String),
     #[fail(display = "Invalid Payment Pointer: {}", _0)]
     InvalidPaymentPointerError(String),
+    #[fail(display = "No SPSP receiver found at any of: {:?}", _0)]
+    ReceiverNotFoundError(Vec<String>),
+    /// The payment's own deadline passed. A peer timing out a packet with `R00` is a `SendMoneyError` instead.
+    #[fail(
+        display = "Payment deadline exceeded locally after {:?}, having delivered: {}",
+        elapsed, delivered
+    )]
+    Timeout { elapsed: Duration, delivered: u64 },
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -42,6 +57,11 @@ pub struct SpspResponse {
     destination_account: Address,
     #[serde(with = "serde_base64")]
     shared_secret: Vec<u8>,
+    /// The receiver's asset, which receivers may include so that senders can display amounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asset_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asset_scale: Option<u8>,
 }
 
 // From https://github.com/serde-rs/json/issues/360#issuecomment-330095360
@@ -13,6 +13,7 @@ use std::{fmt, str};
 pub struct SpspResponder {
     ilp_address: Address,
     connection_generator: ConnectionGenerator,
+    asset_details: Option<(String, u8)>,
 }
 
 impl SpspResponder {
@@ -21,9 +22,22 @@ impl SpspResponder {
         SpspResponder {
             ilp_address,
             connection_generator,
+            asset_details: None,
         }
     }
 
+    /// Include the receiver's asset code and scale in SPSP responses, so senders can show what they delivered.
+    pub fn asset_details(&mut self, asset_code: String, asset_scale: u8) -> &mut Self {
+        self.asset_details = Some((asset_code, asset_scale));
+        self
+    }
+
+    /// The generator used to derive the STREAM details in responses, which can be used to
+    /// pre-compute or verify the shared secrets for connection tokens, or to rotate the server secret
+    pub fn connection_generator(&self) -> &ConnectionGenerator {
+        &self.connection_generator
+    }
+
     pub fn generate_http_response(&self) -> Response<Body> {
         let (destination_account, shared_secret) = self
             .connection_generator
@@ -35,6 +49,8 @@ impl SpspResponder {
         let response = SpspResponse {
             destination_account,
             shared_secret: shared_secret.to_vec(),
+            asset_code: self.asset_details.as_ref().map(|(code, _)| code.clone()),
+            asset_scale: self.asset_details.as_ref().map(|(_, scale)| *scale),
         };
 
         Response::builder()
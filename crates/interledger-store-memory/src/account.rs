@@ -1,10 +1,13 @@
+use super::AccountId;
 use bytes::Bytes;
 use interledger_btp::BtpAccount;
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
-use interledger_service_util::MaxPacketAmountAccount;
+use interledger_service_util::{
+    resolve_secret, EnabledAccount, MaxPacketAmountAccount, SecretError,
+};
 use std::{fmt, str, sync::Arc};
 use url::Url;
 
@@ -18,7 +21,7 @@ impl AccountBuilder {
         let details = AccountDetails {
             ilp_address,
             max_packet_amount: u64::max_value(),
-            id: 0,
+            id: AccountId::default(),
             additional_routes: Vec::new(),
             asset_code: String::new(),
             asset_scale: 0,
@@ -28,6 +31,7 @@ impl AccountBuilder {
             btp_uri: None,
             btp_incoming_token: None,
             btp_outgoing_token: None,
+            enabled: true,
         };
         AccountBuilder { details }
     }
@@ -37,7 +41,7 @@ impl AccountBuilder {
     }
 
     pub fn id(mut self, id: u64) -> Self {
-        self.details.id = id;
+        self.details.id = AccountId::from(id);
         self
     }
 
@@ -95,11 +99,31 @@ impl AccountBuilder {
         self.details.max_packet_amount = amount;
         self
     }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.details.enabled = enabled;
+        self
+    }
+
+    /// Replace BTP and HTTP tokens that reference an environment variable or secret file
+    /// (for example `${ENV:PEER_TOKEN}`) with the secret's value.
+    pub fn resolve_secrets(mut self) -> Result<Self, SecretError> {
+        let details = &mut self.details;
+        details.http_incoming_token = resolve_optional_secret(details.http_incoming_token.take())?;
+        details.http_outgoing_token = resolve_optional_secret(details.http_outgoing_token.take())?;
+        details.btp_outgoing_token = resolve_optional_secret(details.btp_outgoing_token.take())?;
+        details.btp_incoming_token = resolve_optional_secret(details.btp_incoming_token.take())?;
+        Ok(self)
+    }
+}
+
+fn resolve_optional_secret(value: Option<String>) -> Result<Option<String>, SecretError> {
+    value.map(|value| resolve_secret(&value)).transpose()
 }
 
 #[derive(Clone)]
 pub(crate) struct AccountDetails {
-    pub(crate) id: u64,
+    pub(crate) id: AccountId,
     pub(crate) ilp_address: Address,
     pub(crate) additional_routes: Vec<Bytes>,
     pub(crate) asset_code: String,
@@ -111,6 +135,7 @@ pub(crate) struct AccountDetails {
     pub(crate) btp_outgoing_token: Option<String>,
     pub(crate) btp_incoming_token: Option<String>,
     pub(crate) max_packet_amount: u64,
+    pub(crate) enabled: bool,
 }
 
 impl AccountDetails {
@@ -139,7 +164,7 @@ impl fmt::Debug for Account {
 }
 
 impl AccountTrait for Account {
-    type AccountId = u64;
+    type AccountId = AccountId;
 
     fn id(&self) -> Self::AccountId {
         self.inner.id
@@ -166,6 +191,12 @@ impl MaxPacketAmountAccount for Account {
     }
 }
 
+impl EnabledAccount for Account {
+    fn enabled(&self) -> bool {
+        self.inner.enabled
+    }
+}
+
 impl HttpAccount for Account {
     fn get_http_url(&self) -> Option<&Url> {
         self.inner.http_endpoint.as_ref()
@@ -199,12 +230,13 @@ mod tests {
     #[test]
     fn uses_default_values() {
         let account = AccountBuilder::new(Address::from_str("example.address").unwrap()).build();
-        assert_eq!(account.id(), 0);
+        assert_eq!(account.id(), AccountId::new(0));
         assert_eq!(account.asset_code(), "");
         assert_eq!(account.asset_scale(), 0);
         assert_eq!(account.get_btp_uri(), None);
         assert_eq!(account.get_http_auth_token(), None);
         assert_eq!(account.max_packet_amount(), u64::max_value());
+        assert!(account.enabled());
         assert_eq!(
             *account.client_address(),
             Address::from_str("example.address").unwrap()
@@ -226,8 +258,9 @@ mod tests {
             .http_outgoing_token("sodgiuoixfugoiudf".to_string())
             .btp_incoming_token("asdflkjsaldkfjoi".to_string())
             .max_packet_amount(7777)
+            .enabled(false)
             .build();
-        assert_eq!(account.id(), 1);
+        assert_eq!(account.id(), AccountId::new(1));
         assert_eq!(account.asset_code(), "XYZ");
         assert_eq!(account.asset_scale(), 9);
         assert_eq!(
@@ -237,6 +270,35 @@ mod tests {
         assert_eq!(account.get_btp_token(), Some(&b"token"[..]));
         assert_eq!(account.get_http_auth_token(), Some("sodgiuoixfugoiudf"));
         assert_eq!(account.max_packet_amount(), 7777);
+        assert!(!account.enabled());
         assert_eq!(account.client_address(), &b"example.address"[..]);
     }
+
+    #[test]
+    fn resolves_tokens_from_env() {
+        std::env::set_var("INTERLEDGER_TEST_BTP_TOKEN", "btp secret");
+        std::env::set_var("INTERLEDGER_TEST_HTTP_TOKEN", "http secret");
+        let account = AccountBuilder::new(Address::from_str("example.address").unwrap())
+            .btp_outgoing_token("${ENV:INTERLEDGER_TEST_BTP_TOKEN}".to_string())
+            .http_outgoing_token("${ENV:INTERLEDGER_TEST_HTTP_TOKEN}".to_string())
+            .resolve_secrets()
+            .unwrap()
+            .build();
+        assert_eq!(account.get_btp_token(), Some(&b"btp secret"[..]));
+        assert_eq!(account.get_http_auth_token(), Some("http secret"));
+    }
+
+    #[test]
+    fn missing_token_secret() {
+        std::env::remove_var("INTERLEDGER_TEST_MISSING_SECRET");
+        let result = AccountBuilder::new(Address::from_str("example.address").unwrap())
+            .btp_incoming_token("${ENV:INTERLEDGER_TEST_MISSING_SECRET}".to_string())
+            .resolve_secrets();
+        assert_eq!(
+            result.err(),
+            Some(SecretError::MissingEnvVar(
+                "INTERLEDGER_TEST_MISSING_SECRET".to_string()
+            ))
+        );
+    }
 }
@@ -0,0 +1,135 @@
+use std::{error::Error, fmt, str::FromStr};
+
+/// The id of an account in the `InMemoryStore`.
+///
+/// Ids are non-negative integers. They are parsed from their decimal representation,
+/// and anything else (including negative numbers) is rejected with an `AccountIdError`
+/// that says what was wrong with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId(u64);
+
+impl AccountId {
+    pub fn new(id: u64) -> Self {
+        AccountId(id)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for AccountId {
+    fn from(id: u64) -> Self {
+        AccountId(id)
+    }
+}
+
+impl From<AccountId> for u64 {
+    fn from(id: AccountId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for AccountId {
+    type Err = AccountIdError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if src.is_empty() {
+            return Err(AccountIdError::Empty);
+        }
+        let (digits, negative) = match src.strip_prefix('-') {
+            Some(digits) => (digits, true),
+            None => (src, false),
+        };
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(AccountIdError::NotANumber(src.to_string()));
+        }
+        if negative {
+            return Err(AccountIdError::Negative(src.to_string()));
+        }
+        u64::from_str(digits)
+            .map(AccountId)
+            .map_err(|_| AccountIdError::TooLarge(src.to_string()))
+    }
+}
+
+/// Why a string could not be parsed as an `AccountId`
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountIdError {
+    Empty,
+    Negative(String),
+    NotANumber(String),
+    TooLarge(String),
+}
+
+impl fmt::Display for AccountIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountIdError::Empty => write!(f, "Account id is empty"),
+            AccountIdError::Negative(id) => write!(f, "Account id cannot be negative: {}", id),
+            AccountIdError::NotANumber(id) => write!(f, "Account id is not a number: {}", id),
+            AccountIdError::TooLarge(id) => write!(
+                f,
+                "Account id is larger than the maximum of {}: {}",
+                u64::MAX,
+                id
+            ),
+        }
+    }
+}
+
+impl Error for AccountIdError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_valid_ids() {
+        assert_eq!(AccountId::from_str("0"), Ok(AccountId::new(0)));
+        assert_eq!(AccountId::from_str("42"), Ok(AccountId::new(42)));
+        assert_eq!(
+            AccountId::from_str("18446744073709551615"),
+            Ok(AccountId::new(u64::MAX))
+        );
+        assert_eq!(AccountId::new(42).to_string(), "42");
+    }
+
+    #[test]
+    fn rejects_negative_ids() {
+        let err = AccountId::from_str("-1").unwrap_err();
+        assert_eq!(err, AccountIdError::Negative("-1".to_string()));
+        assert_eq!(err.to_string(), "Account id cannot be negative: -1");
+    }
+
+    #[test]
+    fn rejects_non_numeric_ids() {
+        assert_eq!(
+            AccountId::from_str("abc"),
+            Err(AccountIdError::NotANumber("abc".to_string()))
+        );
+        assert_eq!(
+            AccountId::from_str("+1"),
+            Err(AccountIdError::NotANumber("+1".to_string()))
+        );
+        assert_eq!(
+            AccountId::from_str("-"),
+            Err(AccountIdError::NotANumber("-".to_string()))
+        );
+        assert_eq!(
+            AccountId::from_str(" 1"),
+            Err(AccountIdError::NotANumber(" 1".to_string()))
+        );
+        assert_eq!(AccountId::from_str(""), Err(AccountIdError::Empty));
+        assert_eq!(
+            AccountId::from_str("18446744073709551616"),
+            Err(AccountIdError::TooLarge("18446744073709551616".to_string()))
+        );
+    }
+}
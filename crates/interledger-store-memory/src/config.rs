@@ -0,0 +1,270 @@
+use super::{AccountBuilder, InMemoryStore};
+use interledger_packet::Address;
+use interledger_service_util::SecretError;
+use serde::Deserialize;
+use std::{collections::HashSet, fmt, fs, path::Path, str::FromStr};
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The config file could not be read
+    UnreadableFile(String),
+    /// The config is not a JSON array of account definitions
+    InvalidJson(String),
+    /// A field of the account at the given index in the array is not valid
+    InvalidField {
+        index: usize,
+        field: &'static str,
+        reason: String,
+    },
+    /// A token of the account at the given index references a secret that could not be resolved
+    Secret { index: usize, error: SecretError },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnreadableFile(path) => write!(f, "Unable to read config file {}", path),
+            ConfigError::InvalidJson(reason) => write!(f, "Invalid accounts config: {}", reason),
+            ConfigError::InvalidField {
+                index,
+                field,
+                reason,
+            } => write!(f, "Invalid {} for account {}: {}", field, index, reason),
+            ConfigError::Secret { index, error } => {
+                write!(
+                    f,
+                    "Unable to resolve token for account {}: {}",
+                    index, error
+                )
+            }
+        }
+    }
+}
+
+/// The definition of an account in a JSON accounts config
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountConfig {
+    id: Option<u64>,
+    ilp_address: String,
+    #[serde(default)]
+    additional_routes: Vec<String>,
+    asset_code: String,
+    asset_scale: u8,
+    http_endpoint: Option<String>,
+    http_incoming_token: Option<String>,
+    http_outgoing_token: Option<String>,
+    btp_uri: Option<String>,
+    btp_incoming_token: Option<String>,
+    btp_outgoing_token: Option<String>,
+    max_packet_amount: Option<u64>,
+    enabled: Option<bool>,
+}
+
+fn invalid(index: usize, field: &'static str, reason: impl ToString) -> ConfigError {
+    ConfigError::InvalidField {
+        index,
+        field,
+        reason: reason.to_string(),
+    }
+}
+
+fn parse_url(
+    index: usize,
+    field: &'static str,
+    url: &str,
+    schemes: &[&str],
+) -> Result<Url, ConfigError> {
+    let url = Url::parse(url).map_err(|err| invalid(index, field, err))?;
+    if schemes.contains(&url.scheme()) {
+        Ok(url)
+    } else {
+        Err(invalid(
+            index,
+            field,
+            format!("scheme must be one of: {}", schemes.join(", ")),
+        ))
+    }
+}
+
+impl AccountConfig {
+    fn into_builder(self, index: usize) -> Result<AccountBuilder, ConfigError> {
+        let ilp_address = Address::from_str(&self.ilp_address)
+            .map_err(|err| invalid(index, "ilp_address", err))?;
+        for route in self.additional_routes.iter() {
+            Address::from_str(route).map_err(|err| invalid(index, "additional_routes", err))?;
+        }
+        if self.asset_code.is_empty() {
+            return Err(invalid(index, "asset_code", "must not be empty"));
+        }
+
+        let routes: Vec<&[u8]> = self
+            .additional_routes
+            .iter()
+            .map(|route| route.as_bytes())
+            .collect();
+        let mut builder = AccountBuilder::new(ilp_address)
+            .id(self.id.unwrap_or(index as u64))
+            .additional_routes(&routes)
+            .asset_code(self.asset_code)
+            .asset_scale(self.asset_scale);
+        if let Some(ref url) = self.http_endpoint {
+            builder =
+                builder.http_endpoint(parse_url(index, "http_endpoint", url, &["http", "https"])?);
+        }
+        if let Some(ref url) = self.btp_uri {
+            builder = builder.btp_uri(parse_url(index, "btp_uri", url, &["btp+ws", "btp+wss"])?);
+        }
+        if let Some(token) = self.http_incoming_token {
+            builder = builder.http_incoming_token(token);
+        }
+        if let Some(token) = self.http_outgoing_token {
+            builder = builder.http_outgoing_token(token);
+        }
+        if let Some(token) = self.btp_incoming_token {
+            builder = builder.btp_incoming_token(token);
+        }
+        if let Some(token) = self.btp_outgoing_token {
+            builder = builder.btp_outgoing_token(token);
+        }
+        if let Some(max_packet_amount) = self.max_packet_amount {
+            builder = builder.max_packet_amount(max_packet_amount);
+        }
+        if let Some(enabled) = self.enabled {
+            builder = builder.enabled(enabled);
+        }
+        builder
+            .resolve_secrets()
+            .map_err(|error| ConfigError::Secret { index, error })
+    }
+}
+
+impl InMemoryStore {
+    /// Create a store from a JSON array of account definitions, for example:
+    ///
+    /// ```json
+    /// [{
+    ///   "ilp_address": "example.alice",
+    ///   "asset_code": "XYZ",
+    ///   "asset_scale": 9,
+    ///   "http_endpoint": "http://localhost:7770/ilp",
+    ///   "http_outgoing_token": "${ENV:ALICE_TOKEN}"
+    /// }]
+    /// ```
+    ///
+    /// Each account takes the same fields as the `AccountBuilder`. The `id` defaults to the
+    /// account's index in the array, and tokens may reference secrets as in `resolve_secrets`.
+    /// Unknown fields, invalid addresses and URLs, and duplicate ids are rejected.
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let configs: Vec<AccountConfig> =
+            serde_json::from_str(json).map_err(|err| ConfigError::InvalidJson(err.to_string()))?;
+        let mut ids = HashSet::new();
+        let mut builders = Vec::with_capacity(configs.len());
+        for (index, config) in configs.into_iter().enumerate() {
+            let id = config.id.unwrap_or(index as u64);
+            if !ids.insert(id) {
+                return Err(invalid(index, "id", format!("duplicate id {}", id)));
+            }
+            builders.push(config.into_builder(index)?);
+        }
+        Ok(InMemoryStore::new(builders))
+    }
+
+    /// Create a store from a file containing a JSON array of account definitions. See `from_json`.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|_| ConfigError::UnreadableFile(path.display().to_string()))?;
+        InMemoryStore::from_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountId;
+    use futures::Future;
+    use interledger_btp::BtpAccount;
+    use interledger_http::HttpAccount;
+    use interledger_ildcp::IldcpAccount;
+    use interledger_service::AccountStore;
+    use interledger_service_util::MaxPacketAmountAccount;
+
+    #[test]
+    fn loads_accounts_from_json() {
+        let store = InMemoryStore::from_json(
+            r#"[
+                {
+                    "ilp_address": "example.alice",
+                    "asset_code": "XYZ",
+                    "asset_scale": 9,
+                    "http_endpoint": "http://localhost:7770/ilp",
+                    "http_outgoing_token": "alice_token",
+                    "max_packet_amount": 1000
+                },
+                {
+                    "id": 5,
+                    "ilp_address": "example.bob",
+                    "additional_routes": ["example.carol"],
+                    "asset_code": "ABC",
+                    "asset_scale": 6,
+                    "btp_uri": "btp+ws://localhost:7768",
+                    "btp_incoming_token": "bob_token"
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        let accounts = store
+            .get_accounts(vec![AccountId::new(0), AccountId::new(5)])
+            .wait()
+            .unwrap();
+        assert_eq!(accounts[0].client_address(), &b"example.alice"[..]);
+        assert_eq!(accounts[0].asset_code(), "XYZ");
+        assert_eq!(accounts[0].asset_scale(), 9);
+        assert_eq!(accounts[0].get_http_auth_token(), Some("alice_token"));
+        assert_eq!(accounts[0].max_packet_amount(), 1000);
+        assert_eq!(accounts[1].asset_scale(), 6);
+        assert_eq!(
+            accounts[1].get_btp_uri(),
+            Some(&Url::parse("btp+ws://localhost:7768").unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let result = InMemoryStore::from_json(
+            r#"[
+                { "ilp_address": "example.alice", "asset_code": "XYZ", "asset_scale": 9 },
+                {
+                    "ilp_address": "example.bob",
+                    "asset_code": "XYZ",
+                    "asset_scale": 9,
+                    "http_endpoint": "ftp://localhost/ilp"
+                }
+            ]"#,
+        );
+        match result {
+            Err(ConfigError::InvalidField { index, field, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(field, "http_endpoint");
+            }
+            _ => panic!("Expected an invalid field error"),
+        }
+
+        match InMemoryStore::from_json(
+            r#"[{ "ilp_address": "not an address", "asset_code": "XYZ", "asset_scale": 9 }]"#,
+        ) {
+            Err(ConfigError::InvalidField { field, .. }) => assert_eq!(field, "ilp_address"),
+            _ => panic!("Expected an invalid field error"),
+        }
+
+        match InMemoryStore::from_json(
+            r#"[{ "ilp_address": "example.alice", "asset_code": "XYZ", "asset_scale": 9, "colour": "blue" }]"#,
+        ) {
+            Err(ConfigError::InvalidJson(_)) => {}
+            _ => panic!("Expected unknown fields to be rejected"),
+        }
+    }
+}
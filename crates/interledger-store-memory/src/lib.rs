@@ -5,7 +5,11 @@
 //! relevant account details when the store is instantiated.
 
 mod account;
+mod account_id;
+mod config;
 mod store;
 
 pub use self::account::{Account, AccountBuilder};
+pub use self::account_id::{AccountId, AccountIdError};
+pub use self::config::ConfigError;
 pub use self::store::InMemoryStore;
@@ -1,4 +1,4 @@
-use super::{Account, AccountBuilder};
+use super::{Account, AccountBuilder, AccountId};
 use bytes::Bytes;
 use futures::{
     future::{err, ok},
@@ -7,9 +7,9 @@ use futures::{
 use hashbrown::HashMap;
 use interledger_btp::{BtpOpenSignupAccount, BtpOpenSignupStore, BtpStore};
 use interledger_http::HttpStore;
-use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
 use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, AccountStore};
+use interledger_service::{Account as AccountTrait, AccountStore, AddressStore};
 use parking_lot::{Mutex, RwLock};
 use std::{
     cmp::max,
@@ -23,10 +23,12 @@ use std::{
 /// relevant account details when the store is instantiated.
 #[derive(Clone)]
 pub struct InMemoryStore {
-    accounts: Arc<RwLock<HashMap<u64, Account>>>,
-    routing_table: Arc<RwLock<HashMap<Bytes, u64>>>,
-    btp_auth: Arc<RwLock<HashMap<String, u64>>>,
-    http_auth: Arc<RwLock<HashMap<String, u64>>>,
+    accounts: Arc<RwLock<HashMap<AccountId, Account>>>,
+    routing_table: Arc<RwLock<Arc<HashMap<Bytes, AccountId>>>>,
+    equal_cost_routes: Arc<RwLock<Arc<EqualCostRoutes>>>,
+    addresses: Arc<RwLock<HashMap<Bytes, AccountId>>>,
+    btp_auth: Arc<RwLock<HashMap<String, AccountId>>>,
+    http_auth: Arc<RwLock<HashMap<String, AccountId>>>,
     next_account_id: Arc<Mutex<u64>>,
 }
 
@@ -43,21 +45,18 @@ impl InMemoryStore {
         let mut next_account_id: u64 = 0;
 
         let accounts = HashMap::from_iter(accounts.into_iter().map(|account| {
-            next_account_id = max(account.id(), next_account_id);
+            next_account_id = max(account.id().as_u64(), next_account_id);
             (account.id(), account)
         }));
         next_account_id += 1;
 
-        let routing_table: HashMap<Bytes, u64> =
-            HashMap::from_iter(accounts.iter().flat_map(|(account_id, account)| {
-                once((account.inner.ilp_address.to_bytes(), *account_id)).chain(
-                    account
-                        .inner
-                        .additional_routes
-                        .iter()
-                        .map(move |route| (route.clone(), *account_id)),
-                )
-            }));
+        let (routing_table, equal_cost_routes) = build_routes(&accounts);
+
+        let addresses = HashMap::from_iter(
+            accounts
+                .iter()
+                .map(|(account_id, account)| (account.inner.ilp_address.to_bytes(), *account_id)),
+        );
 
         let btp_auth = HashMap::from_iter(accounts.iter().filter_map(|(account_id, account)| {
             if let Some(ref token) = account.inner.btp_incoming_token {
@@ -77,7 +76,9 @@ impl InMemoryStore {
 
         InMemoryStore {
             accounts: Arc::new(RwLock::new(accounts)),
-            routing_table: Arc::new(RwLock::new(routing_table)),
+            routing_table: Arc::new(RwLock::new(Arc::new(routing_table))),
+            equal_cost_routes: Arc::new(RwLock::new(Arc::new(equal_cost_routes))),
+            addresses: Arc::new(RwLock::new(addresses)),
             btp_auth: Arc::new(RwLock::new(btp_auth)),
             http_auth: Arc::new(RwLock::new(http_auth)),
             next_account_id: Arc::new(Mutex::new(next_account_id)),
@@ -85,15 +86,16 @@ impl InMemoryStore {
     }
 
     pub fn add_account(&self, account: Account) {
-        self.accounts.write().insert(account.id(), account.clone());
-        self.routing_table
+        {
+            let mut accounts = self.accounts.write();
+            accounts.insert(account.id(), account.clone());
+            let (routing_table, equal_cost_routes) = build_routes(&accounts);
+            *self.routing_table.write() = Arc::new(routing_table);
+            *self.equal_cost_routes.write() = Arc::new(equal_cost_routes);
+        }
+        self.addresses
             .write()
             .insert(account.inner.ilp_address.to_bytes(), account.id());
-        for route in &account.inner.additional_routes {
-            self.routing_table
-                .write()
-                .insert(route.clone(), account.id());
-        }
         if let Some(ref btp_auth) = account.inner.btp_incoming_token {
             self.btp_auth.write().insert(btp_auth.clone(), account.id());
         }
@@ -103,16 +105,45 @@ impl InMemoryStore {
                 .insert(http_auth.clone(), account.id());
         }
         let mut next_account_id = self.next_account_id.lock();
-        *next_account_id = max(*next_account_id, account.inner.id);
+        *next_account_id = max(*next_account_id, account.inner.id.as_u64());
     }
 }
 
+type EqualCostRoutes = HashMap<Bytes, Vec<AccountId>>;
+type Routes = (HashMap<Bytes, AccountId>, EqualCostRoutes);
+
+/// Build the routing table from each account's address and additional routes.
+/// If several accounts have a route for the same prefix, they are equally good, so the one with
+/// the lowest ID goes in the routing table and the others are returned as equal-cost routes.
+fn build_routes(accounts: &HashMap<AccountId, Account>) -> Routes {
+    let mut next_hops: HashMap<Bytes, Vec<AccountId>> = HashMap::new();
+    for (account_id, account) in accounts.iter() {
+        let prefixes = once(account.inner.ilp_address.to_bytes())
+            .chain(account.inner.additional_routes.iter().cloned());
+        for prefix in prefixes {
+            next_hops.entry(prefix).or_default().push(*account_id);
+        }
+    }
+
+    let mut routing_table = HashMap::with_capacity(next_hops.len());
+    let mut equal_cost_routes = HashMap::new();
+    for (prefix, mut account_ids) in next_hops {
+        account_ids.sort();
+        account_ids.dedup();
+        routing_table.insert(prefix.clone(), account_ids[0]);
+        if account_ids.len() > 1 {
+            equal_cost_routes.insert(prefix, account_ids.split_off(1));
+        }
+    }
+    (routing_table, equal_cost_routes)
+}
+
 impl AccountStore for InMemoryStore {
     type Account = Account;
 
     fn get_accounts(
         &self,
-        accounts_ids: Vec<u64>,
+        accounts_ids: Vec<AccountId>,
     ) -> Box<dyn Future<Item = Vec<Account>, Error = ()> + Send> {
         let accounts: Vec<Account> = accounts_ids
             .iter()
@@ -126,6 +157,31 @@ impl AccountStore for InMemoryStore {
     }
 }
 
+impl AddressStore for InMemoryStore {
+    fn get_account_by_address(
+        &self,
+        address: &Address,
+    ) -> Box<dyn Future<Item = Option<Account>, Error = ()> + Send> {
+        let address: &[u8] = address.as_ref();
+        let addresses = self.addresses.read();
+        // Try the full address first and then each parent prefix, longest first
+        let account_id = once(address.len())
+            .chain(
+                address
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .filter(|(_, byte)| **byte == b'.')
+                    .map(|(index, _)| index),
+            )
+            .filter_map(|len| addresses.get(&address[..len]))
+            .next();
+        Box::new(ok(account_id.and_then(|account_id| {
+            self.accounts.read().get(account_id).cloned()
+        })))
+    }
+}
+
 impl HttpStore for InMemoryStore {
     type Account = Account;
 
@@ -143,9 +199,13 @@ impl HttpStore for InMemoryStore {
 }
 
 impl RouterStore for InMemoryStore {
-    fn routing_table(&self) -> HashMap<Bytes, u64> {
+    fn routing_table(&self) -> Arc<HashMap<Bytes, AccountId>> {
         self.routing_table.read().clone()
     }
+
+    fn equal_cost_routes(&self) -> Arc<HashMap<Bytes, Vec<AccountId>>> {
+        self.equal_cost_routes.read().clone()
+    }
 }
 
 impl BtpStore for InMemoryStore {
@@ -185,22 +245,16 @@ impl BtpOpenSignupStore for InMemoryStore {
         let account_id = {
             let next_id: u64 = *self.next_account_id.lock();
             *self.next_account_id.lock() += 1;
-            next_id
+            AccountId::from(next_id)
         };
         let account = AccountBuilder::new(account.ilp_address.clone())
-            .id(account_id)
+            .id(account_id.as_u64())
             .btp_incoming_token(account.auth_token.to_string())
             .asset_code(account.asset_code.to_string())
             .asset_scale(account.asset_scale)
             .build();
 
-        (*self.accounts.write()).insert(account_id, account.clone());
-        let ilp_address = account.client_address().clone();
-        (*self.routing_table.write()).insert(ilp_address.to_bytes(), account_id);
-        (*self.btp_auth.write()).insert(
-            account.inner.btp_incoming_token.clone().unwrap(),
-            account_id,
-        );
+        self.add_account(account.clone());
 
         Box::new(ok(account))
     }
@@ -210,7 +264,6 @@ impl BtpOpenSignupStore for InMemoryStore {
 mod tests {
     use super::*;
 
-    use interledger_packet::Address;
     use std::str::FromStr;
     #[test]
     fn get_accounts() {
@@ -219,11 +272,67 @@ mod tests {
             AccountBuilder::new(Address::from_str("example.one").unwrap()).id(1),
             AccountBuilder::new(Address::from_str("example.four").unwrap()).id(4),
         ]);
-        let accounts = store.get_accounts(vec![0, 4]).wait().unwrap();
-        assert_eq!(accounts[0].id(), 0);
-        assert_eq!(accounts[1].id(), 4);
+        let accounts = store
+            .get_accounts(vec![AccountId::new(0), AccountId::new(4)])
+            .wait()
+            .unwrap();
+        assert_eq!(accounts[0].id(), AccountId::new(0));
+        assert_eq!(accounts[1].id(), AccountId::new(4));
+
+        assert!(store
+            .get_accounts(vec![AccountId::new(0), AccountId::new(5)])
+            .wait()
+            .is_err());
+    }
 
-        assert!(store.get_accounts(vec![0, 5]).wait().is_err());
+    #[test]
+    fn query_by_exact_address() {
+        let store = InMemoryStore::new(vec![
+            AccountBuilder::new(Address::from_str("example.alice").unwrap()).id(0),
+            AccountBuilder::new(Address::from_str("example.bob").unwrap()).id(1),
+        ]);
+        let account = store
+            .get_account_by_address(&Address::from_str("example.bob").unwrap())
+            .wait()
+            .unwrap();
+        assert_eq!(account.unwrap().id(), AccountId::new(1));
+    }
+
+    #[test]
+    fn query_by_address_prefix() {
+        let store = InMemoryStore::new(vec![
+            AccountBuilder::new(Address::from_str("example.alice").unwrap()).id(0),
+            AccountBuilder::new(Address::from_str("example.alice.child").unwrap()).id(1),
+        ]);
+        let account = store
+            .get_account_by_address(&Address::from_str("example.alice.child.stream").unwrap())
+            .wait()
+            .unwrap();
+        assert_eq!(account.unwrap().id(), AccountId::new(1));
+        let account = store
+            .get_account_by_address(&Address::from_str("example.alice.other").unwrap())
+            .wait()
+            .unwrap();
+        assert_eq!(account.unwrap().id(), AccountId::new(0));
+    }
+
+    #[test]
+    fn query_by_unknown_address() {
+        let store = InMemoryStore::new(vec![AccountBuilder::new(
+            Address::from_str("example.alice").unwrap(),
+        )
+        .id(0)]);
+        // Prefixes only match on segment boundaries
+        assert!(store
+            .get_account_by_address(&Address::from_str("example.alicebob").unwrap())
+            .wait()
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_account_by_address(&Address::from_str("example.bob").unwrap())
+            .wait()
+            .unwrap()
+            .is_none());
     }
 
     #[test]
@@ -268,13 +377,38 @@ mod tests {
         ]);
 
         assert_eq!(
-            store.routing_table(),
+            *store.routing_table(),
             HashMap::from_iter(vec![
-                (Bytes::from("example.one"), 1),
-                (Bytes::from("example.two"), 2),
-                (Bytes::from("example.three"), 1)
+                (Bytes::from("example.one"), AccountId::new(1)),
+                (Bytes::from("example.two"), AccountId::new(2)),
+                (Bytes::from("example.three"), AccountId::new(1))
             ])
         );
+        assert!(store.equal_cost_routes().is_empty());
+    }
+
+    #[test]
+    fn shared_routes_are_equal_cost() {
+        let store = InMemoryStore::new(vec![
+            AccountBuilder::new(Address::from_str("example.one").unwrap())
+                .id(1)
+                .additional_routes(&[b"example.three"]),
+            AccountBuilder::new(Address::from_str("example.two").unwrap())
+                .id(2)
+                .additional_routes(&[b"example.three"]),
+        ]);
+
+        assert_eq!(
+            store.routing_table().get(&Bytes::from("example.three")),
+            Some(&AccountId::new(1))
+        );
+        assert_eq!(
+            *store.equal_cost_routes(),
+            HashMap::from_iter(vec![(
+                Bytes::from("example.three"),
+                vec![AccountId::new(2)]
+            )])
+        );
     }
 
     #[test]
@@ -290,6 +424,6 @@ mod tests {
             })
             .wait()
             .unwrap();
-        assert_eq!(account.id(), 1);
+        assert_eq!(account.id(), AccountId::new(1));
     }
 }
@@ -8,7 +8,8 @@ use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_service_util::{
-    MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+    EnabledAccount, MaxPacketAmountAccount, MaxPrepareDataLenAccount, RateLimitAccount,
+    RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::{SettlementAccount, SettlementEngineDetails};
 use redis::{from_redis_value, ErrorKind, FromRedisValue, RedisError, ToRedisArgs, Value};
@@ -18,10 +19,11 @@ use std::{
     collections::HashMap,
     convert::TryFrom,
     str::{self, FromStr},
+    time::Duration,
 };
 use url::Url;
 
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 28;
 
 #[derive(Clone, Debug, Serialize)]
 pub struct Account {
@@ -50,11 +52,20 @@ pub struct Account {
     pub(crate) round_trip_time: u64,
     pub(crate) packets_per_minute_limit: Option<u32>,
     pub(crate) amount_per_minute_limit: Option<u64>,
+    pub(crate) max_prepare_data_len: Option<usize>,
     #[serde(serialize_with = "optional_url_to_string")]
     pub(crate) settlement_engine_url: Option<Url>,
     pub(crate) settlement_engine_asset_scale: Option<u8>,
+    pub(crate) settlement_engine_incoming_asset_scale: Option<u8>,
+    pub(crate) settlement_engine_outgoing_asset_scale: Option<u8>,
     #[serde(serialize_with = "optional_address_to_string")]
     pub(crate) settlement_engine_ilp_address: Option<Address>,
+    #[serde(skip_serializing)]
+    pub(crate) settlement_engine_auth_token: Option<Bytes>,
+    #[serde(skip_serializing)]
+    pub(crate) settlement_engine_peer_protocol_fulfillment: Option<[u8; 32]>,
+    pub(crate) settlement_engine_message_timeout: Option<u64>,
+    pub(crate) enabled: bool,
 }
 
 fn optional_address_to_string<S>(
@@ -138,6 +149,20 @@ impl Account {
             } else {
                 None
             };
+        let settlement_engine_peer_protocol_fulfillment =
+            if let Some(ref fulfillment) = details.settlement_engine_peer_protocol_fulfillment {
+                let decoded = hex::decode(fulfillment)
+                    .map_err(|err| error!("Invalid peer protocol fulfillment: {:?}", err))?;
+                if decoded.len() != 32 {
+                    error!("Invalid peer protocol fulfillment (must be 32 hex-encoded bytes)");
+                    return Err(());
+                }
+                let mut bytes = [0; 32];
+                bytes.copy_from_slice(&decoded);
+                Some(bytes)
+            } else {
+                None
+            };
         Ok(Account {
             id,
             ilp_address: Address::try_from(details.ilp_address.as_ref()).map_err(|err| {
@@ -159,9 +184,16 @@ impl Account {
             round_trip_time: details.round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME),
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
+            max_prepare_data_len: details.max_prepare_data_len,
             settlement_engine_url,
             settlement_engine_asset_scale: details.settlement_engine_asset_scale,
+            settlement_engine_incoming_asset_scale: details.settlement_engine_incoming_asset_scale,
+            settlement_engine_outgoing_asset_scale: details.settlement_engine_outgoing_asset_scale,
             settlement_engine_ilp_address: details.settlement_engine_ilp_address,
+            settlement_engine_auth_token: details.settlement_engine_auth_token.map(Bytes::from),
+            settlement_engine_peer_protocol_fulfillment,
+            settlement_engine_message_timeout: details.settlement_engine_message_timeout,
+            enabled: details.enabled.unwrap_or(true),
         })
     }
 
@@ -175,6 +207,9 @@ impl Account {
         if let Some(ref token) = self.http_outgoing_token {
             self.http_outgoing_token = Some(encrypt_token(encryption_key, token));
         }
+        if let Some(ref token) = self.settlement_engine_auth_token {
+            self.settlement_engine_auth_token = Some(encrypt_token(encryption_key, token));
+        }
         AccountWithEncryptedTokens { account: self }
     }
 }
@@ -191,6 +226,9 @@ impl AccountWithEncryptedTokens {
         if let Some(ref encrypted) = self.account.http_outgoing_token {
             self.account.http_outgoing_token = decrypt_token(decryption_key, encrypted);
         }
+        if let Some(ref encrypted) = self.account.settlement_engine_auth_token {
+            self.account.settlement_engine_auth_token = decrypt_token(decryption_key, encrypted);
+        }
 
         self.account
     }
@@ -222,6 +260,8 @@ impl ToRedisArgs for AccountWithEncryptedTokens {
             .write_redis_args(&mut rv);
         "round_trip_time".write_redis_args(&mut rv);
         account.round_trip_time.write_redis_args(&mut rv);
+        "enabled".write_redis_args(&mut rv);
+        account.enabled.write_redis_args(&mut rv);
 
         // Write optional fields
         if let Some(http_endpoint) = account.http_endpoint.as_ref() {
@@ -264,6 +304,10 @@ impl ToRedisArgs for AccountWithEncryptedTokens {
             "amount_per_minute_limit".write_redis_args(&mut rv);
             limit.write_redis_args(&mut rv);
         }
+        if let Some(max_len) = account.max_prepare_data_len {
+            "max_prepare_data_len".write_redis_args(&mut rv);
+            max_len.write_redis_args(&mut rv);
+        }
         if let Some(min_balance) = account.min_balance {
             "min_balance".write_redis_args(&mut rv);
             min_balance.write_redis_args(&mut rv);
@@ -276,10 +320,32 @@ impl ToRedisArgs for AccountWithEncryptedTokens {
             "settlement_engine_asset_scale".write_redis_args(&mut rv);
             settlement_engine_asset_scale.write_redis_args(&mut rv);
         }
+        if let Some(scale) = account.settlement_engine_incoming_asset_scale {
+            "settlement_engine_incoming_asset_scale".write_redis_args(&mut rv);
+            scale.write_redis_args(&mut rv);
+        }
+        if let Some(scale) = account.settlement_engine_outgoing_asset_scale {
+            "settlement_engine_outgoing_asset_scale".write_redis_args(&mut rv);
+            scale.write_redis_args(&mut rv);
+        }
         if let Some(ref settlement_engine_ilp_address) = account.settlement_engine_ilp_address {
             "settlement_engine_ilp_address".write_redis_args(&mut rv);
             rv.push(settlement_engine_ilp_address.to_bytes().to_vec());
         }
+        if let Some(ref settlement_engine_auth_token) = account.settlement_engine_auth_token {
+            "settlement_engine_auth_token".write_redis_args(&mut rv);
+            settlement_engine_auth_token
+                .as_ref()
+                .write_redis_args(&mut rv);
+        }
+        if let Some(ref fulfillment) = account.settlement_engine_peer_protocol_fulfillment {
+            "settlement_engine_peer_protocol_fulfillment".write_redis_args(&mut rv);
+            rv.push(fulfillment.to_vec());
+        }
+        if let Some(timeout) = account.settlement_engine_message_timeout {
+            "settlement_engine_message_timeout".write_redis_args(&mut rv);
+            timeout.write_redis_args(&mut rv);
+        }
 
         debug_assert!(rv.len() <= ACCOUNT_DETAILS_FIELDS * 2);
         debug_assert!((rv.len() % 2) == 0);
@@ -311,6 +377,21 @@ impl FromRedisValue for AccountWithEncryptedTokens {
             } else {
                 None
             };
+        let settlement_engine_peer_protocol_fulfillment =
+            match get_bytes_option("settlement_engine_peer_protocol_fulfillment", &hash)? {
+                Some(ref fulfillment) if fulfillment.len() == 32 => {
+                    let mut bytes = [0; 32];
+                    bytes.copy_from_slice(fulfillment);
+                    Some(bytes)
+                }
+                Some(_) => {
+                    return Err(RedisError::from((
+                        ErrorKind::TypeError,
+                        "Invalid peer protocol fulfillment",
+                    )))
+                }
+                None => None,
+            };
         Ok(AccountWithEncryptedTokens {
             account: Account {
                 id: get_value("id", &hash)?,
@@ -331,12 +412,32 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 round_trip_time,
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
+                max_prepare_data_len: get_value_option("max_prepare_data_len", &hash)?,
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
                 settlement_engine_asset_scale: get_value_option(
                     "settlement_engine_asset_scale",
                     &hash,
                 )?,
+                settlement_engine_incoming_asset_scale: get_value_option(
+                    "settlement_engine_incoming_asset_scale",
+                    &hash,
+                )?,
+                settlement_engine_outgoing_asset_scale: get_value_option(
+                    "settlement_engine_outgoing_asset_scale",
+                    &hash,
+                )?,
                 settlement_engine_ilp_address,
+                settlement_engine_auth_token: get_bytes_option(
+                    "settlement_engine_auth_token",
+                    &hash,
+                )?,
+                settlement_engine_peer_protocol_fulfillment,
+                settlement_engine_message_timeout: get_value_option(
+                    "settlement_engine_message_timeout",
+                    &hash,
+                )?,
+                // Accounts stored before this field was added are enabled
+                enabled: !hash.contains_key("enabled") || get_bool("enabled", &hash),
             },
         })
     }
@@ -421,6 +522,10 @@ impl IldcpAccount for Account {
     fn asset_scale(&self) -> u8 {
         self.asset_scale
     }
+
+    fn is_child(&self) -> bool {
+        self.routing_relation == RoutingRelation::Child
+    }
 }
 
 impl HttpAccount for Account {
@@ -485,6 +590,18 @@ impl RateLimitAccount for Account {
     }
 }
 
+impl MaxPrepareDataLenAccount for Account {
+    fn max_prepare_data_len(&self) -> Option<usize> {
+        self.max_prepare_data_len
+    }
+}
+
+impl EnabledAccount for Account {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
 impl SettlementAccount for Account {
     fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
         match (
@@ -495,11 +612,23 @@ impl SettlementAccount for Account {
             (Some(url), Some(asset_scale), Some(ilp_address)) => Some(SettlementEngineDetails {
                 url: url.clone(),
                 asset_scale,
+                incoming_asset_scale: self.settlement_engine_incoming_asset_scale,
+                outgoing_asset_scale: self.settlement_engine_outgoing_asset_scale,
                 ilp_address: ilp_address.clone(),
+                peer_protocol_fulfillment: self.settlement_engine_peer_protocol_fulfillment,
+                message_timeout: self
+                    .settlement_engine_message_timeout
+                    .map(Duration::from_millis),
             }),
             _ => None,
         }
     }
+
+    fn settlement_engine_auth_token(&self) -> Option<&str> {
+        self.settlement_engine_auth_token
+            .as_ref()
+            .and_then(|token| str::from_utf8(token).ok())
+    }
 }
 
 #[cfg(test)]
@@ -526,9 +655,16 @@ mod redis_account {
             round_trip_time: Some(600),
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
+            max_prepare_data_len: None,
             settlement_engine_asset_scale: None,
+            settlement_engine_incoming_asset_scale: None,
+            settlement_engine_outgoing_asset_scale: None,
             settlement_engine_url: None,
             settlement_engine_ilp_address: None,
+            settlement_engine_auth_token: None,
+            settlement_engine_peer_protocol_fulfillment: None,
+            settlement_engine_message_timeout: None,
+            enabled: None,
         };
     }
 
@@ -542,5 +678,64 @@ mod redis_account {
         );
         assert_eq!(account.get_btp_token().unwrap(), b"btp_token");
         assert_eq!(account.routing_relation(), RoutingRelation::Peer);
+        assert!(account.enabled());
+    }
+
+    fn to_redis_and_back(account: Account) -> Account {
+        let args = AccountWithEncryptedTokens { account }.to_redis_args();
+        let value = Value::Bulk(args.into_iter().map(Value::Data).collect());
+        AccountWithEncryptedTokens::from_redis_value(&value)
+            .unwrap()
+            .account
+    }
+
+    #[test]
+    fn stores_peer_protocol_fulfillment() {
+        let mut details = ACCOUNT_DETAILS.clone();
+        details.settlement_engine_url = Some("http://localhost:3000".to_string());
+        details.settlement_engine_asset_scale = Some(9);
+        details.settlement_engine_ilp_address = Some(Address::from_str("peer.settle.xyz").unwrap());
+        details.settlement_engine_peer_protocol_fulfillment = Some(hex::encode([7; 32]));
+        let account = to_redis_and_back(Account::try_from(10, details).unwrap());
+        assert_eq!(
+            account
+                .settlement_engine_details()
+                .unwrap()
+                .peer_protocol_fulfillment(),
+            [7; 32]
+        );
+
+        let mut details = ACCOUNT_DETAILS.clone();
+        details.settlement_engine_peer_protocol_fulfillment = Some("abcd".to_string());
+        assert!(Account::try_from(10, details).is_err());
+    }
+
+    #[test]
+    fn stores_settlement_engine_asset_scales() {
+        let mut details = ACCOUNT_DETAILS.clone();
+        details.settlement_engine_url = Some("http://localhost:3000".to_string());
+        details.settlement_engine_asset_scale = Some(9);
+        details.settlement_engine_incoming_asset_scale = Some(6);
+        details.settlement_engine_outgoing_asset_scale = Some(3);
+        details.settlement_engine_ilp_address = Some(Address::from_str("peer.settle.xyz").unwrap());
+        let account = to_redis_and_back(Account::try_from(10, details).unwrap());
+        let engine = account.settlement_engine_details().unwrap();
+        assert_eq!(engine.asset_scale, 9);
+        assert_eq!(engine.incoming_asset_scale(), 6);
+        assert_eq!(engine.outgoing_asset_scale(), 3);
+    }
+
+    #[test]
+    fn stores_settlement_engine_message_timeout() {
+        let mut details = ACCOUNT_DETAILS.clone();
+        details.settlement_engine_url = Some("http://localhost:3000".to_string());
+        details.settlement_engine_asset_scale = Some(9);
+        details.settlement_engine_ilp_address = Some(Address::from_str("peer.settle.xyz").unwrap());
+        details.settlement_engine_message_timeout = Some(120_000);
+        let account = to_redis_and_back(Account::try_from(10, details).unwrap());
+        assert_eq!(
+            account.settlement_engine_details().unwrap().message_timeout,
+            Some(Duration::from_secs(120))
+        );
     }
 }
@@ -6,17 +6,23 @@ use futures::{
     Future, Stream,
 };
 use hashbrown::{HashMap, HashSet};
-use interledger_api::{AccountDetails, NodeStore};
+use interledger_api::{AccountDetails, AppliedConfig, NodeStore};
 use interledger_btp::BtpStore;
 use interledger_ccp::RouteManagerStore;
 use interledger_http::HttpStore;
-use interledger_router::RouterStore;
+use interledger_router::{RouteEntry, RouterStore};
 use interledger_service::{Account as AccountTrait, AccountStore};
-use interledger_service_util::{BalanceStore, ExchangeRateStore, RateLimitError, RateLimitStore};
-use interledger_settlement::{SettlementAccount, SettlementClient, SettlementStore};
+use interledger_service_util::{
+    select_next_hop_by_asset, BalanceStore, ExchangeRateStore, RateLimitError, RateLimitStore,
+};
+use interledger_settlement::{
+    IdempotentData, PendingSettlementStore, SettlementAccount, SettlementBatcher, SettlementClient,
+    SettlementStore,
+};
 use parking_lot::RwLock;
 use redis::{
-    self, cmd, r#async::SharedConnection, Client, ConnectionInfo, PipelineCommands, Value,
+    self, cmd, r#async::SharedConnection, Client, ConnectionInfo, FromRedisValue, PipelineCommands,
+    Value,
 };
 use ring::{aead, hmac};
 use std::{
@@ -46,10 +52,11 @@ local from_id = ARGV[1]
 local from_account = 'accounts:' .. ARGV[1]
 local from_amount = tonumber(ARGV[2])
 local min_balance, balance, prepaid_amount = unpack(redis.call('HMGET', from_account, 'min_balance', 'balance', 'prepaid_amount'))
-balance = tonumber(balance)
-prepaid_amount = tonumber(balance)
+balance = tonumber(balance) or 0
+prepaid_amount = tonumber(prepaid_amount) or 0
 
--- Check that the prepare wouldn't go under the account's minimum balance
+-- Check that the prepare wouldn't go under the account's minimum balance.
+-- A negative minimum balance is the credit limit the account can run down to
 if min_balance then
     min_balance = tonumber(min_balance)
     if balance + prepaid_amount - from_amount < min_balance then
@@ -75,17 +82,18 @@ local to_account = 'accounts:' .. ARGV[1]
 local to_amount = tonumber(ARGV[2])
 
 local balance = redis.call('HINCRBY', to_account, 'balance', to_amount)
-local prepaid_amount, settle_threshold, settle_to = unpack(redis.call('HMGET', to_account, 'prepaid_amount', 'settle_threshold', 'settle_to'))
+local prepaid_amount, settle_threshold, settle_to, pending_settlement = unpack(redis.call('HMGET', to_account, 'prepaid_amount', 'settle_threshold', 'settle_to', 'pending_settlement'))
+pending_settlement = tonumber(pending_settlement) or 0
 
 -- Check if we should send a settlement for this account
 local settle_amount = 0
-if settle_threshold and settle_to and balance > tonumber(settle_threshold) then
-    settle_amount = balance - tonumber(settle_to)
+if settle_threshold and settle_to and balance - pending_settlement > tonumber(settle_threshold) then
+    settle_amount = balance - pending_settlement - tonumber(settle_to)
 
-    -- Update the balance _before_ sending the settlement so that we don't accidentally send
-    -- multiple settlements for the same balance. If the settlement fails we'll roll back
-    -- the balance change by re-adding the amount back to the balance
-    balance = settle_to
+    -- Reserve the amount _before_ sending the settlement so that we don't accidentally send
+    -- multiple settlements for the same balance. The balance itself is only reduced once
+    -- the settlement engine has accepted the settlement
+    redis.call('HINCRBY', to_account, 'pending_settlement', settle_amount)
 end
 
 return {balance + prepaid_amount, settle_amount}";
@@ -96,11 +104,33 @@ local from_amount = tonumber(ARGV[2])
 local prepaid_amount = redis.call('HGET', from_account, 'prepaid_amount')
 local balance = redis.call('HINCRBY', from_account, 'balance', from_amount)
 return balance + prepaid_amount";
-static REFUND_SETTLEMENT: &str = "
+static CONFIRM_SETTLEMENT: &str = "
+local account = 'accounts:' .. ARGV[1]
+local settle_amount = tonumber(ARGV[2])
+
+redis.call('HINCRBY', account, 'pending_settlement', 0 - settle_amount)
+local balance = redis.call('HINCRBY', account, 'balance', 0 - settle_amount)
+return balance";
+static RESERVE_OUTSTANDING_BALANCE: &str = "
+local account = 'accounts:' .. ARGV[1]
+local balance, pending_settlement = unpack(redis.call('HMGET', account, 'balance', 'pending_settlement'))
+local settle_amount = (tonumber(balance) or 0) - (tonumber(pending_settlement) or 0)
+
+if settle_amount <= 0 then
+    return 0
+end
+redis.call('HINCRBY', account, 'pending_settlement', settle_amount)
+return settle_amount";
+static CONFIRM_QUEUED_SETTLEMENT: &str = "
 local account = 'accounts:' .. ARGV[1]
 local settle_amount = tonumber(ARGV[2])
 
-local balance = redis.call('HINCRBY', account, 'balance', settle_amount)
+local queued = redis.call('HINCRBY', KEYS[1], ARGV[1], 0 - settle_amount)
+if queued <= 0 then
+    redis.call('HDEL', KEYS[1], ARGV[1])
+end
+redis.call('HINCRBY', account, 'pending_settlement', 0 - settle_amount)
+local balance = redis.call('HINCRBY', account, 'balance', 0 - settle_amount)
 return balance";
 static PROCESS_INCOMING_SETTLEMENT: &str = "
 local account = 'accounts:' .. ARGV[1]
@@ -119,22 +149,72 @@ else
     redis.call('HSET', account, 'balance', 0)
 end
 
+-- Save the response for the settlement's idempotency key, if it has one
+if KEYS[1] then
+    redis.call('HMSET', KEYS[1], 'input_hash', ARGV[3], 'body', ARGV[4])
+    redis.call('EXPIRE', KEYS[1], ARGV[5])
+end
+
 return balance + prepaid_amount";
 
+static SET_ACCOUNT_ENABLED: &str = "
+local account = 'accounts:' .. ARGV[1]
+if redis.call('EXISTS', account) == 0 then
+    error('Account ' .. ARGV[1] .. ' does not exist')
+end
+redis.call('HSET', account, 'enabled', ARGV[2])
+return redis.call('HGETALL', account)";
+static DELETE_ACCOUNT: &str = "
+local id = ARGV[1]
+local account = 'accounts:' .. id
+if redis.call('EXISTS', account) == 0 then
+    error('Account ' .. id .. ' does not exist')
+end
+redis.call('DEL', account)
+redis.call('SREM', 'send_routes_to', id)
+redis.call('SREM', 'receive_routes_from', id)
+redis.call('SREM', 'btp_outgoing', id)
+redis.call('HDEL', KEYS[1], id)
+
+-- Remove the account's incoming auth tokens and the routes through it
+for _, key in ipairs({'btp_auth', 'http_auth', KEYS[2], KEYS[3]}) do
+    local entries = redis.call('HGETALL', key)
+    for i = 1, #entries, 2 do
+        if entries[i + 1] == id then
+            redis.call('HDEL', key, entries[i])
+        end
+    end
+end
+return 1";
+
 static ROUTES_KEY: &str = "routes:current";
 static RATES_KEY: &str = "rates:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
+static EQUAL_COST_ROUTES_KEY: &str = "routes:equal_cost";
+static ROUTE_COSTS_KEY: &str = "routes:costs";
 static NEXT_ACCOUNT_ID_KEY: &str = "next_account_id";
+static QUEUED_SETTLEMENTS_KEY: &str = "settlements:queued";
+static APPLIED_CONFIG_ROUTES_KEY: &str = "config:static_routes";
+static APPLIED_CONFIG_ACCOUNTS_KEY: &str = "config:accounts";
+/// How long responses to requests with idempotency keys are kept for (24 hours)
+const IDEMPOTENT_DATA_TTL: usize = 86400;
 
 fn account_details_key(account_id: u64) -> String {
     format!("accounts:{}", account_id)
 }
 
+type IdempotentDataFields = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+fn idempotent_data_key(idempotency_key: &str) -> String {
+    format!("idempotency-key:{}", idempotency_key)
+}
+
 pub struct RedisStoreBuilder {
     redis_uri: ConnectionInfo,
     secret: [u8; 32],
     poll_interval: u64,
     settlement_client: Option<SettlementClient>,
+    settlement_batcher: Option<SettlementBatcher<Account>>,
 }
 
 impl RedisStoreBuilder {
@@ -144,6 +224,7 @@ impl RedisStoreBuilder {
             secret,
             poll_interval: DEFAULT_POLL_INTERVAL,
             settlement_client: None,
+            settlement_batcher: None,
         }
     }
 
@@ -157,6 +238,16 @@ impl RedisStoreBuilder {
         self
     }
 
+    /// Hand the settlements triggered by fulfilled packets to the given batcher instead of
+    /// sending each one to the settlement engine right away. The batcher must be run with this store.
+    pub fn settlement_batcher(
+        &mut self,
+        settlement_batcher: SettlementBatcher<Account>,
+    ) -> &mut Self {
+        self.settlement_batcher = Some(settlement_batcher);
+        self
+    }
+
     pub fn connect(&self) -> impl Future<Item = RedisStore, Error = ()> {
         let (hmac_key, encryption_key, decryption_key) = generate_keys(&self.secret[..]);
         let poll_interval = self.poll_interval;
@@ -164,6 +255,7 @@ impl RedisStoreBuilder {
             .settlement_client
             .clone()
             .unwrap_or_else(SettlementClient::new);
+        let settlement_batcher = self.settlement_batcher.clone();
 
         result(Client::open(self.redis_uri.clone()))
             .map_err(|err| error!("Error creating Redis client: {:?}", err))
@@ -177,11 +269,12 @@ impl RedisStoreBuilder {
                 let store = RedisStore {
                     connection: Arc::new(connection),
                     exchange_rates: Arc::new(RwLock::new(HashMap::new())),
-                    routes: Arc::new(RwLock::new(HashMap::new())),
+                    routes: Arc::new(RwLock::new(Routes::default())),
                     hmac_key: Arc::new(hmac_key),
                     encryption_key: Arc::new(encryption_key),
                     decryption_key: Arc::new(decryption_key),
                     settlement_client,
+                    settlement_batcher,
                 };
 
                 // Start polling for rate updates
@@ -241,11 +334,12 @@ impl RedisStoreBuilder {
 pub struct RedisStore {
     connection: Arc<SharedConnection>,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
-    routes: Arc<RwLock<HashMap<Bytes, u64>>>,
+    routes: Arc<RwLock<Routes>>,
     hmac_key: Arc<hmac::SigningKey>, // redisstore stores a key, this must be protected
     encryption_key: Arc<aead::SealingKey>,
     decryption_key: Arc<aead::OpeningKey>,
     settlement_client: SettlementClient,
+    settlement_batcher: Option<SettlementBatcher<Account>>,
 }
 
 impl RedisStore {
@@ -366,39 +460,6 @@ impl RedisStore {
                 }),
         )
     }
-
-    fn refund_settlement(
-        &self,
-        account_id: u64,
-        settle_amount: u64,
-    ) -> impl Future<Item = (), Error = ()> {
-        trace!(
-            "Refunding settlement for account: {} of amount: {}",
-            account_id,
-            settle_amount
-        );
-        cmd("EVAL")
-            .arg(REFUND_SETTLEMENT)
-            .arg(0)
-            .arg(account_id)
-            .arg(settle_amount)
-            .query_async(self.connection.as_ref().clone())
-            .map_err(move |err| {
-                error!(
-                    "Error refunding settlement for account: {} of amount: {}: {:?}",
-                    account_id, settle_amount, err
-                )
-            })
-            .and_then(move |(_connection, balance): (_, i64)| {
-                trace!(
-                    "Refunded settlement for account: {} of amount: {}. Balance is now: {}",
-                    account_id,
-                    settle_amount,
-                    balance
-                );
-                Ok(())
-            })
-    }
 }
 
 impl AccountStore for RedisStore {
@@ -444,23 +505,7 @@ impl BalanceStore for RedisStore {
     /// Returns the balance **from the account holder's perspective**, meaning the sum of
     /// the Payable Balance and Pending Outgoing minus the Receivable Balance and the Pending Incoming.
     fn get_balance(&self, account: Account) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
-        Box::new(
-            cmd("HMGET")
-                .arg(account_details_key(account.id))
-                .arg(&["balance", "prepaid_amount"])
-                .query_async(self.connection.as_ref().clone())
-                .map_err(move |err| {
-                    error!(
-                        "Error getting balance for account: {} {:?}",
-                        account.id, err
-                    )
-                })
-                .and_then(|(_connection, values): (_, Vec<i64>)| {
-                    let balance = values[0];
-                    let prepaid_amount = values[1];
-                    Ok(balance + prepaid_amount)
-                }),
-        )
+        self.load_balance(account.id)
     }
 
     fn update_balances_for_prepare(
@@ -507,6 +552,7 @@ impl BalanceStore for RedisStore {
         outgoing_amount: u64,
     ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
         let settlement_client = self.settlement_client.clone();
+        let settlement_batcher = self.settlement_batcher.clone();
         let store = self.clone();
         if outgoing_amount > 0 {
             let from_account_id = from_account.id;
@@ -535,15 +581,20 @@ impl BalanceStore for RedisStore {
                                     balance,
                                 );
 
-                                // Note that if this program crashes after changing the balance (in the PROCESS_FULFILL script)
-                                // and the send_settlement fails but the program isn't alive to hear that, the balance will be incorrect.
-                                // No other instance will know that it was trying to send an outgoing settlement. We could
-                                // make this more robust by saving something to the DB about the outgoing settlement when we change the balance
-                                // but then we would also need to prevent a situation where every connector instance is polling the
-                                // settlement engine for the status of each outgoing settlement and putting unnecessary load on the settlement engine.
+                                // Note that if this program crashes after reserving the amount (in the PROCESS_FULFILL script)
+                                // but before the settlement is sent, the amount will stay reserved and will not be retried.
+                                // If sending the settlement fails, it is queued in the DB so that the SettlementRetrier can send it later.
+                                if let Some(settlement_batcher) = settlement_batcher {
+                                    settlement_batcher.add(to_account, amount_to_settle);
+                                    return Ok(());
+                                }
+                                let store_clone = store.clone();
                                 spawn(settlement_client
                                     .send_settlement(to_account, amount_to_settle)
-                                    .or_else(move |_| store.refund_settlement(to_account_id, amount_to_settle)));
+                                    .then(move |result| match result {
+                                        Ok(_) => Either::A(store.confirm_settlement(to_account_id, amount_to_settle)),
+                                        Err(_) => Either::B(store_clone.queue_settlement(to_account_id, amount_to_settle)),
+                                    }));
                             } else {
                                 trace!(
                                     "Processed fulfill for outgoing amount {}. Account {} has balance: {}",
@@ -728,8 +779,20 @@ impl HttpStore for RedisStore {
 }
 
 impl RouterStore for RedisStore {
-    fn routing_table(&self) -> HashMap<Bytes, u64> {
-        self.routes.read().clone()
+    fn routing_table(&self) -> Arc<HashMap<Bytes, u64>> {
+        self.routes.read().table.clone()
+    }
+
+    fn equal_cost_routes(&self) -> Arc<HashMap<Bytes, Vec<u64>>> {
+        self.routes.read().equal_cost.clone()
+    }
+
+    fn route_cost(&self, prefix: &[u8]) -> Option<u32> {
+        self.routes.read().costs.get(prefix).cloned()
+    }
+
+    fn select_next_hop(&self, from: &Account, amount: u64, next_hops: &[Account]) -> usize {
+        select_next_hop_by_asset(self, from, amount, next_hops)
     }
 }
 
@@ -760,16 +823,21 @@ impl NodeStore for RedisStore {
                                     pipe.hgetall(account_details_key(i));
                                 }
                                 return Either::A(pipe.query_async(connection).and_then(
-                                    move |(_, accounts): (
-                                        _,
-                                        Vec<Option<AccountWithEncryptedTokens>>,
-                                    )| {
-                                        let accounts: Vec<Account> = accounts
+                                    move |(_, accounts): (_, Vec<Value>)| {
+                                        // Deleted accounts leave gaps in the account ids
+                                        let accounts: Vec<AccountWithEncryptedTokens> = accounts
+                                            .iter()
+                                            .filter(|account| match account {
+                                                Value::Bulk(fields) => !fields.is_empty(),
+                                                Value::Nil => false,
+                                                _ => true,
+                                            })
+                                            .map(AccountWithEncryptedTokens::from_redis_value)
+                                            .collect::<Result<_, _>>()?;
+                                        Ok(accounts
                                             .into_iter()
-                                            .filter_map(|a| a)
                                             .map(|account| account.decrypt_tokens(&decryption_key))
-                                            .collect();
-                                        Ok(accounts)
+                                            .collect())
                                     },
                                 ));
                             }
@@ -876,6 +944,186 @@ impl NodeStore for RedisStore {
             })
         )
     }
+
+    fn delete_static_route(&self, prefix: String) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        let routing_table = self.routes.clone();
+        Box::new(
+            cmd("HDEL")
+                .arg(STATIC_ROUTES_KEY)
+                .arg(prefix)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| error!("Error deleting static route: {:?}", err))
+                .and_then(move |(connection, _): (SharedConnection, Value)| {
+                    update_routes(connection, routing_table)
+                }),
+        )
+    }
+
+    /// Imported routes (and their costs) replace the ones learned from peers, so they will be
+    /// updated by the next route broadcasts. Static routes still take precedence over them.
+    fn import_routes(
+        &self,
+        routes: Vec<RouteEntry<u64>>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        let costs: Vec<(String, u32)> = routes
+            .iter()
+            .map(|route| (route.prefix.clone(), route.cost))
+            .collect();
+        let routes: Vec<(String, u64)> = routes
+            .into_iter()
+            .map(|route| (route.prefix, route.account_id))
+            .collect();
+
+        // There are no accounts to check if all of the routes are being removed
+        let connection = self.connection.as_ref().clone();
+        let connection = if routes.is_empty() {
+            Either::A(ok(connection))
+        } else {
+            let accounts: HashSet<u64> =
+                HashSet::from_iter(routes.iter().map(|(_prefix, account_id)| *account_id));
+            let mut pipe = redis::pipe();
+            for account_id in accounts {
+                pipe.exists(account_details_key(account_id));
+            }
+            Either::B(
+                pipe.query_async(connection)
+                    .map_err(|err| {
+                        error!(
+                            "Error checking if accounts exist while importing routes: {:?}",
+                            err
+                        )
+                    })
+                    .and_then(
+                        |(connection, accounts_exist): (SharedConnection, Vec<bool>)| {
+                            if accounts_exist.iter().all(|a| *a) {
+                                Ok(connection)
+                            } else {
+                                error!("Error importing routes because not all of the given accounts exist");
+                                Err(())
+                            }
+                        },
+                    ),
+            )
+        };
+
+        let routing_table = self.routes.clone();
+        Box::new(connection.and_then(move |connection| {
+            let num_routes = routes.len();
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .del(ROUTES_KEY)
+                .ignore()
+                .del(ROUTE_COSTS_KEY)
+                .ignore();
+            if !routes.is_empty() {
+                pipe.hset_multiple(ROUTES_KEY, &routes)
+                    .ignore()
+                    .hset_multiple(ROUTE_COSTS_KEY, &costs)
+                    .ignore();
+            }
+            pipe.query_async(connection)
+                .map_err(|err| error!("Error importing routes: {:?}", err))
+                .and_then(move |(connection, _): (SharedConnection, Value)| {
+                    debug!("Imported {} routes", num_routes);
+                    update_routes(connection, routing_table)
+                })
+        }))
+    }
+
+    fn set_account_enabled(
+        &self,
+        account_id: u64,
+        enabled: bool,
+    ) -> Box<dyn Future<Item = Account, Error = ()> + Send> {
+        let decryption_key = self.decryption_key.clone();
+        Box::new(
+            cmd("EVAL")
+                .arg(SET_ACCOUNT_ENABLED)
+                .arg(0)
+                .arg(account_id)
+                .arg(enabled)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error setting enabled to {} for account {}: {:?}",
+                        enabled, account_id, err
+                    )
+                })
+                .map(
+                    move |(_connection, account): (
+                        SharedConnection,
+                        AccountWithEncryptedTokens,
+                    )| {
+                        debug!("Set enabled to {} for account {}", enabled, account_id);
+                        account.decrypt_tokens(&decryption_key)
+                    },
+                ),
+        )
+    }
+
+    fn delete_account(&self, account_id: u64) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        let routing_table = self.routes.clone();
+        Box::new(
+            cmd("EVAL")
+                .arg(DELETE_ACCOUNT)
+                .arg(3)
+                .arg(QUEUED_SETTLEMENTS_KEY)
+                .arg(ROUTES_KEY)
+                .arg(STATIC_ROUTES_KEY)
+                .arg(account_id)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| error!("Error deleting account {}: {:?}", account_id, err))
+                .and_then(move |(connection, _): (SharedConnection, Value)| {
+                    debug!("Deleted account {}", account_id);
+                    update_routes(connection, routing_table)
+                }),
+        )
+    }
+
+    fn get_applied_config(&self) -> Box<dyn Future<Item = AppliedConfig<u64>, Error = ()> + Send> {
+        let mut pipe = redis::pipe();
+        pipe.hgetall(APPLIED_CONFIG_ROUTES_KEY)
+            .hgetall(APPLIED_CONFIG_ACCOUNTS_KEY);
+        Box::new(
+            pipe.query_async(self.connection.as_ref().clone())
+                .map_err(|err| error!("Error loading the applied config: {:?}", err))
+                .map(
+                    |(_connection, (static_routes, accounts)): (_, (RouteVec, RouteVec))| {
+                        AppliedConfig {
+                            static_routes: static_routes.into_iter().collect(),
+                            accounts: accounts.into_iter().collect(),
+                        }
+                    },
+                ),
+        )
+    }
+
+    fn set_applied_config(
+        &self,
+        config: AppliedConfig<u64>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        let static_routes: RouteVec = config.static_routes.into_iter().collect();
+        let accounts: RouteVec = config.accounts.into_iter().collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(APPLIED_CONFIG_ROUTES_KEY)
+            .ignore()
+            .del(APPLIED_CONFIG_ACCOUNTS_KEY)
+            .ignore();
+        if !static_routes.is_empty() {
+            pipe.hset_multiple(APPLIED_CONFIG_ROUTES_KEY, &static_routes)
+                .ignore();
+        }
+        if !accounts.is_empty() {
+            pipe.hset_multiple(APPLIED_CONFIG_ACCOUNTS_KEY, &accounts)
+                .ignore();
+        }
+        Box::new(
+            pipe.query_async(self.connection.as_ref().clone())
+                .map_err(|err| error!("Error saving the applied config: {:?}", err))
+                .map(|(_connection, _): (SharedConnection, Value)| ()),
+        )
+    }
 }
 
 type RoutingTable<A> = HashMap<Bytes, A>;
@@ -1022,12 +1270,15 @@ impl RouteManagerStore for RedisStore {
             .collect();
         let num_routes = routes.len();
 
-        // Save routes to Redis
+        // Save routes to Redis. The costs of these routes are not known,
+        // so any costs saved with previously imported routes are removed
         let routing_tale = self.routes.clone();
         let mut pipe = redis::pipe();
         pipe.atomic()
             .del(ROUTES_KEY)
             .ignore()
+            .del(ROUTE_COSTS_KEY)
+            .ignore()
             .hset_multiple(ROUTES_KEY, &routes)
             .ignore();
         Box::new(
@@ -1039,6 +1290,42 @@ impl RouteManagerStore for RedisStore {
                 }),
         )
     }
+
+    fn set_equal_cost_routes(
+        &mut self,
+        routes: HashMap<Bytes, Vec<u64>>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        // Each prefix is saved with a comma-separated list of the account IDs
+        let routes: Vec<(String, String)> = routes
+            .into_iter()
+            .filter_map(|(prefix, account_ids)| {
+                let account_ids: Vec<String> =
+                    account_ids.iter().map(|id| id.to_string()).collect();
+                String::from_utf8(prefix.to_vec())
+                    .ok()
+                    .map(|prefix| (prefix, account_ids.join(",")))
+            })
+            .collect();
+        let num_routes = routes.len();
+
+        let routing_table = self.routes.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(EQUAL_COST_ROUTES_KEY).ignore();
+        if !routes.is_empty() {
+            pipe.hset_multiple(EQUAL_COST_ROUTES_KEY, &routes).ignore();
+        }
+        Box::new(
+            pipe.query_async(self.connection.as_ref().clone())
+                .map_err(|err| error!("Error setting equal-cost routes: {:?}", err))
+                .and_then(move |(connection, _): (SharedConnection, Value)| {
+                    trace!(
+                        "Saved equal-cost routes for {} prefixes to Redis",
+                        num_routes
+                    );
+                    update_routes(connection, routing_table)
+                }),
+        )
+    }
 }
 
 impl RateLimitStore for RedisStore {
@@ -1134,23 +1421,87 @@ impl RateLimitStore for RedisStore {
 impl SettlementStore for RedisStore {
     type Account = Account;
 
+    fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Box<dyn Future<Item = Option<IdempotentData>, Error = ()> + Send> {
+        Box::new(
+            cmd("HMGET")
+                .arg(idempotent_data_key(&idempotency_key))
+                .arg(&["input_hash", "body"])
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error loading data for idempotency key {}: {:?}",
+                        idempotency_key, err
+                    )
+                })
+                .and_then(
+                    |(_connection, (input_hash, body)): (_, IdempotentDataFields)| match (
+                        input_hash, body,
+                    ) {
+                        (Some(ref input_hash), Some(body)) if input_hash.len() == 32 => {
+                            let mut hash = [0; 32];
+                            hash.copy_from_slice(&input_hash[..]);
+                            Ok(Some(IdempotentData {
+                                input_hash: hash,
+                                body: Bytes::from(body),
+                            }))
+                        }
+                        _ => Ok(None),
+                    },
+                ),
+        )
+    }
+
     fn update_balance_for_incoming_settlement(
         &self,
         account_id: u64,
         amount: u64,
-    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
-        Box::new(cmd("EVAL")
-            .arg(PROCESS_INCOMING_SETTLEMENT)
-            .arg(0)
-            .arg(account_id)
-            .arg(amount)
+        idempotent_data: Option<(String, IdempotentData)>,
+    ) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
+        let mut command = cmd("EVAL");
+        command.arg(PROCESS_INCOMING_SETTLEMENT);
+        if let Some((ref idempotency_key, _)) = idempotent_data {
+            command.arg(1).arg(idempotent_data_key(idempotency_key));
+        } else {
+            command.arg(0);
+        }
+        command.arg(account_id).arg(amount);
+        if let Some((_, data)) = idempotent_data {
+            command
+                .arg(&data.input_hash[..])
+                .arg(&data.body[..])
+                .arg(IDEMPOTENT_DATA_TTL);
+        }
+        Box::new(command
             .query_async(self.connection.as_ref().clone())
             .map_err(move |err| error!("Error processing incoming settlement from account: {} for amount: {}: {:?}", account_id, amount, err))
             .and_then(move |(_connection, balance): (_, i64)| {
                 trace!("Processed incoming settlement from account: {} for amount: {}. Balance is now: {}", account_id, amount, balance);
-                Ok(())
+                Ok(balance)
             }))
     }
+
+    fn load_balance(&self, account_id: u64) -> Box<dyn Future<Item = i64, Error = ()> + Send> {
+        Box::new(
+            cmd("HMGET")
+                .arg(account_details_key(account_id))
+                .arg(&["balance", "prepaid_amount"])
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error getting balance for account: {} {:?}",
+                        account_id, err
+                    )
+                })
+                .and_then(|(_connection, values): (_, Vec<i64>)| {
+                    let balance = values[0];
+                    let prepaid_amount = values[1];
+                    Ok(balance + prepaid_amount)
+                }),
+        )
+    }
 }
 
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
@@ -1171,25 +1522,73 @@ fn update_rates(
         })
 }
 
-// TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 type RouteVec = Vec<(String, u64)>;
+type EqualCostRouteVec = Vec<(String, String)>;
+type RouteCostVec = Vec<(String, u32)>;
+
+/// The in-memory copy of the routing tables saved in Redis. Each table is replaced, rather than
+/// modified, when it is reloaded, so the Router can use a snapshot of it without copying it.
+#[derive(Default)]
+struct Routes {
+    table: Arc<HashMap<Bytes, u64>>,
+    equal_cost: Arc<HashMap<Bytes, Vec<u64>>>,
+    costs: Arc<HashMap<Bytes, u32>>,
+}
 
+// TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 fn update_routes(
     connection: SharedConnection,
-    routing_table: Arc<RwLock<HashMap<Bytes, u64>>>,
+    routing_table: Arc<RwLock<Routes>>,
 ) -> impl Future<Item = (), Error = ()> {
     let mut pipe = redis::pipe();
-    pipe.hgetall(ROUTES_KEY).hgetall(STATIC_ROUTES_KEY);
+    pipe.hgetall(ROUTES_KEY)
+        .hgetall(STATIC_ROUTES_KEY)
+        .hgetall(EQUAL_COST_ROUTES_KEY)
+        .hgetall(ROUTE_COSTS_KEY);
     pipe.query_async(connection)
         .map_err(|err| error!("Error polling for routing table updates: {:?}", err))
         .and_then(
-            move |(_connection, (routes, static_routes)): (_, (RouteVec, RouteVec))| {
+            move |(_connection, (routes, static_routes, equal_cost_routes, route_costs)): (
+                _,
+                (RouteVec, RouteVec, EqualCostRouteVec, RouteCostVec),
+            )| {
                 trace!(
-                    "Loaded routes from redis. Static routes: {:?}, other routes: {:?}",
+                    "Loaded routes from redis. Static routes: {:?}, other routes: {:?}, equal-cost routes: {:?}",
                     static_routes,
-                    routes
+                    routes,
+                    equal_cost_routes,
+                );
+                // Static routes replace the routes with the same prefix, including their equal-cost routes
+                let static_prefixes: HashSet<String> = static_routes
+                    .iter()
+                    .map(|(prefix, _)| prefix.clone())
+                    .collect();
+                let equal_cost = HashMap::from_iter(
+                    equal_cost_routes
+                        .into_iter()
+                        .filter(|(prefix, _)| !static_prefixes.contains(prefix))
+                        .filter_map(|(prefix, account_ids)| {
+                            let account_ids: Result<Vec<u64>, _> =
+                                account_ids.split(',').map(str::parse).collect();
+                            match account_ids {
+                                Ok(account_ids) => Some((Bytes::from(prefix), account_ids)),
+                                Err(_) => {
+                                    warn!(
+                                        "Ignoring equal-cost routes for prefix: {} with invalid account IDs",
+                                        prefix
+                                    );
+                                    None
+                                }
+                            }
+                        }),
                 );
-                let routes = HashMap::from_iter(
+                let costs = HashMap::from_iter(
+                    route_costs
+                        .into_iter()
+                        .filter(|(prefix, _)| !static_prefixes.contains(prefix))
+                        .map(|(prefix, cost)| (Bytes::from(prefix), cost)),
+                );
+                let table = HashMap::from_iter(
                     routes
                         .into_iter()
                         // Having the static_routes inserted after ensures that they will overwrite
@@ -1197,15 +1596,146 @@ fn update_routes(
                         .chain(static_routes.into_iter())
                         .map(|(prefix, account_id)| (Bytes::from(prefix), account_id)),
                 );
-                trace!("Routing table is now: {:?}", routes);
-                let num_routes = routes.len();
-                *routing_table.write() = routes;
+                trace!("Routing table is now: {:?}", table);
+                let num_routes = table.len();
+                *routing_table.write() = Routes {
+                    table: Arc::new(table),
+                    equal_cost: Arc::new(equal_cost),
+                    costs: Arc::new(costs),
+                };
                 trace!("Updated routing table with {} routes", num_routes);
                 Ok(())
             },
         )
 }
 
+impl PendingSettlementStore for RedisStore {
+    fn queue_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(
+            cmd("HINCRBY")
+                .arg(QUEUED_SETTLEMENTS_KEY)
+                .arg(account_id)
+                .arg(amount)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error queueing settlement for account: {} of amount: {}: {:?}",
+                        account_id, amount, err
+                    )
+                })
+                .and_then(move |(_connection, queued): (_, u64)| {
+                    debug!(
+                        "Queued settlement for account: {} of amount: {}. Total queued for the account is now: {}",
+                        account_id, amount, queued
+                    );
+                    Ok(())
+                }),
+        )
+    }
+
+    fn get_queued_settlements(&self) -> Box<dyn Future<Item = Vec<(u64, u64)>, Error = ()> + Send> {
+        Box::new(
+            cmd("HGETALL")
+                .arg(QUEUED_SETTLEMENTS_KEY)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| error!("Error loading queued settlements: {:?}", err))
+                .and_then(|(_connection, queued): (_, Vec<(u64, u64)>)| Ok(queued)),
+        )
+    }
+
+    fn confirm_queued_settlement(
+        &self,
+        account_id: u64,
+        amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(
+            cmd("EVAL")
+                .arg(CONFIRM_QUEUED_SETTLEMENT)
+                .arg(1)
+                .arg(QUEUED_SETTLEMENTS_KEY)
+                .arg(account_id)
+                .arg(amount)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error confirming queued settlement for account: {} of amount: {}: {:?}",
+                        account_id, amount, err
+                    )
+                })
+                .and_then(move |(_connection, balance): (_, i64)| {
+                    trace!(
+                        "Confirmed queued settlement for account: {} of amount: {}. Balance is now: {}",
+                        account_id,
+                        amount,
+                        balance
+                    );
+                    Ok(())
+                }),
+        )
+    }
+
+    fn reserve_outstanding_balance(
+        &self,
+        account_id: u64,
+    ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
+        Box::new(
+            cmd("EVAL")
+                .arg(RESERVE_OUTSTANDING_BALANCE)
+                .arg(0)
+                .arg(account_id)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error reserving outstanding balance of account: {}: {:?}",
+                        account_id, err
+                    )
+                })
+                .and_then(move |(_connection, amount): (_, u64)| {
+                    trace!(
+                        "Reserved outstanding balance of {} for a settlement of account: {}",
+                        amount,
+                        account_id
+                    );
+                    Ok(amount)
+                }),
+        )
+    }
+
+    fn confirm_settlement(
+        &self,
+        account_id: u64,
+        settle_amount: u64,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(
+            cmd("EVAL")
+                .arg(CONFIRM_SETTLEMENT)
+                .arg(0)
+                .arg(account_id)
+                .arg(settle_amount)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error confirming settlement for account: {} of amount: {}: {:?}",
+                        account_id, settle_amount, err
+                    )
+                })
+                .and_then(move |(_connection, balance): (_, i64)| {
+                    trace!(
+                        "Confirmed settlement for account: {} of amount: {}. Balance is now: {}",
+                        account_id,
+                        settle_amount,
+                        balance
+                    );
+                    Ok(())
+                }),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5,14 +5,14 @@ mod common;
 
 use common::*;
 
-use interledger_api::NodeStore;
+use interledger_api::{AppliedConfig, NodeStore};
 use interledger_btp::BtpAccount;
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccontTrait;
 use interledger_service::AccountStore;
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{BalanceStore, EnabledAccount};
 use std::str::FromStr;
 
 #[test]
@@ -142,3 +142,69 @@ fn errors_for_unknown_accounts() {
     }));
     assert!(result.is_err());
 }
+
+#[test]
+fn deletes_account() {
+    block_on(test_store().and_then(|(store, context)| {
+        let store_clone = store.clone();
+        store.delete_account(0).and_then(move |_| {
+            store_clone.get_all_accounts().and_then(move |accounts| {
+                // Deleting an account leaves a gap in the ids
+                assert_eq!(accounts.len(), 1);
+                assert_eq!(accounts[0].id(), 1);
+                let _ = context;
+                Ok(())
+            })
+        })
+    }))
+    .unwrap();
+}
+
+#[test]
+fn delete_fails_for_unknown_account() {
+    let result = block_on(test_store().and_then(|(store, context)| {
+        store.delete_account(5).then(move |result| {
+            let _ = context;
+            result
+        })
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn saves_applied_config() {
+    block_on(test_store().and_then(|(store, context)| {
+        let store_clone = store.clone();
+        let mut config = AppliedConfig::default();
+        config.static_routes.insert("example.a".to_string(), 1);
+        config.accounts.insert("example.alice".to_string(), 0);
+        store
+            .set_applied_config(config.clone())
+            .and_then(move |_| store_clone.get_applied_config())
+            .and_then(move |applied| {
+                assert_eq!(applied, config);
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap();
+}
+
+#[test]
+fn sets_account_enabled() {
+    block_on(test_store().and_then(|(store, context)| {
+        let store_clone = store.clone();
+        store
+            .set_account_enabled(1, false)
+            .and_then(move |account| {
+                assert!(!account.enabled());
+                store_clone.get_accounts(vec![1])
+            })
+            .and_then(move |accounts| {
+                assert!(!accounts[0].enabled());
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap();
+}
@@ -165,6 +165,58 @@ fn enforces_minimum_balance() {
     .unwrap()
 }
 
+#[test]
+fn allows_negative_balance_down_to_minimum() {
+    // Account 0 has a minimum balance of -1000
+    block_on(test_store().and_then(|(store, context)| {
+        let store_clone_1 = store.clone();
+        let store_clone_2 = store.clone();
+        store
+            .clone()
+            .get_accounts(vec![0, 1])
+            .map_err(|_err| panic!("Unable to get accounts"))
+            .and_then(move |accounts| {
+                let account0 = accounts[0].clone();
+                let account1 = accounts[1].clone();
+                let account0_clone = account0.clone();
+                let account1_clone = account1.clone();
+                // Within the limit
+                store
+                    .update_balances_for_prepare(account0.clone(), 600, account1.clone(), 600)
+                    .and_then(move |_| {
+                        // Exactly at the limit
+                        store_clone_1.update_balances_for_prepare(
+                            account0.clone(),
+                            400,
+                            account1.clone(),
+                            400,
+                        )
+                    })
+                    .and_then(move |_| {
+                        // Beyond the limit
+                        store_clone_2
+                            .clone()
+                            .update_balances_for_prepare(
+                                account0_clone.clone(),
+                                1,
+                                account1_clone,
+                                1,
+                            )
+                            .then(move |result| {
+                                assert!(result.is_err());
+                                store_clone_2.get_balance(account0_clone)
+                            })
+                    })
+                    .and_then(move |balance| {
+                        assert_eq!(balance, -1000);
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+    }))
+    .unwrap()
+}
+
 #[test]
 fn netting_fulfilled_balances() {
     block_on(test_store().and_then(|(store, context)| {
@@ -22,9 +22,16 @@ lazy_static! {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
+        max_prepare_data_len: None,
         settlement_engine_url: None,
         settlement_engine_asset_scale: None,
+        settlement_engine_incoming_asset_scale: None,
+        settlement_engine_outgoing_asset_scale: None,
         settlement_engine_ilp_address: None,
+        settlement_engine_auth_token: None,
+        settlement_engine_peer_protocol_fulfillment: None,
+        settlement_engine_message_timeout: None,
+        enabled: None,
     };
     pub static ref ACCOUNT_DETAILS_1: AccountDetails = AccountDetails {
         ilp_address: Address::from_str("example.bob").unwrap(),
@@ -45,9 +52,16 @@ lazy_static! {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
+        max_prepare_data_len: None,
         settlement_engine_url: None,
         settlement_engine_asset_scale: None,
+        settlement_engine_incoming_asset_scale: None,
+        settlement_engine_outgoing_asset_scale: None,
         settlement_engine_ilp_address: None,
+        settlement_engine_auth_token: None,
+        settlement_engine_peer_protocol_fulfillment: None,
+        settlement_engine_message_timeout: None,
+        enabled: None,
     };
     pub static ref ACCOUNT_DETAILS_2: AccountDetails = AccountDetails {
         ilp_address: Address::from_str("example.charlie").unwrap(),
@@ -68,8 +82,15 @@ lazy_static! {
         round_trip_time: None,
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        max_prepare_data_len: None,
         settlement_engine_url: None,
         settlement_engine_asset_scale: None,
+        settlement_engine_incoming_asset_scale: None,
+        settlement_engine_outgoing_asset_scale: None,
         settlement_engine_ilp_address: None,
+        settlement_engine_auth_token: None,
+        settlement_engine_peer_protocol_fulfillment: None,
+        settlement_engine_message_timeout: None,
+        enabled: None,
     };
 }
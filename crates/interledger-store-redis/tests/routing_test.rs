@@ -8,10 +8,10 @@ use common::*;
 use interledger_api::{AccountDetails, NodeStore};
 use interledger_ccp::RouteManagerStore;
 use interledger_packet::Address;
-use interledger_router::RouterStore;
-use interledger_service::Account as AccountTrait;
+use interledger_router::{RouteEntry, RouterStore};
+use interledger_service::{Account as AccountTrait, AccountStore};
 use std::str::FromStr;
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, iter::FromIterator, time::Duration};
 use tokio_timer::sleep;
 
 #[test]
@@ -55,9 +55,16 @@ fn polls_for_route_updates() {
                             round_trip_time: None,
                             amount_per_minute_limit: None,
                             packets_per_minute_limit: None,
+                            max_prepare_data_len: None,
                             settlement_engine_url: None,
                             settlement_engine_asset_scale: None,
+                            settlement_engine_incoming_asset_scale: None,
+                            settlement_engine_outgoing_asset_scale: None,
                             settlement_engine_ilp_address: None,
+                            settlement_engine_auth_token: None,
+                            settlement_engine_peer_protocol_fulfillment: None,
+                            settlement_engine_message_timeout: None,
+                            enabled: None,
                         })
                     })
                     .and_then(move |_| {
@@ -211,6 +218,45 @@ fn updates_local_routes() {
     .unwrap()
 }
 
+#[test]
+fn saves_equal_cost_routes() {
+    block_on(test_store().and_then(|(store, context)| {
+        let mut store_clone = store.clone();
+        store
+            .clone()
+            .set_static_routes(vec![("example.b".to_string(), 0)])
+            .and_then(move |_| {
+                store_clone.set_equal_cost_routes(hashbrown::HashMap::from_iter(vec![
+                    (Bytes::from("example.a"), vec![1, 2]),
+                    (Bytes::from("example.b"), vec![1]),
+                ]))
+            })
+            .and_then(move |_| {
+                let equal_cost_routes = store.equal_cost_routes();
+                assert_eq!(equal_cost_routes[&b"example.a"[..]], vec![1, 2]);
+                // Static routes replace the routes for their prefix
+                assert!(!equal_cost_routes.contains_key(&b"example.b"[..]));
+                assert_eq!(equal_cost_routes.len(), 1);
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap()
+}
+
+#[test]
+fn selects_next_hop_in_same_asset() {
+    block_on(test_store().and_then(|(store, context)| {
+        store.get_accounts(vec![0, 1]).and_then(move |accounts| {
+            // Account 0 is in XYZ and account 1 is in ABC, so no conversion is needed via account 1
+            assert_eq!(store.select_next_hop(&accounts[1], 100, &accounts), 1);
+            let _ = context;
+            Ok(())
+        })
+    }))
+    .unwrap()
+}
+
 #[test]
 fn adds_static_routes_to_redis() {
     block_on(test_store().and_then(|(store, context)| {
@@ -273,6 +319,68 @@ fn static_routes_override_others() {
     .unwrap()
 }
 
+#[test]
+fn exported_routes_can_be_imported() {
+    block_on(test_store().and_then(|(store, context)| {
+        let store_clone = store.clone();
+        store
+            .clone()
+            .import_routes(vec![
+                RouteEntry {
+                    prefix: "example.a".to_string(),
+                    account_id: 0,
+                    cost: 0,
+                },
+                RouteEntry {
+                    prefix: "example.b".to_string(),
+                    account_id: 1,
+                    cost: 3,
+                },
+            ])
+            .and_then(move |_| {
+                let exported = store.export_routes();
+                assert_eq!(exported.len(), 2);
+                assert_eq!(exported[0].prefix, "example.a");
+                assert_eq!(exported[1].account_id, 1);
+                assert_eq!(exported[1].cost, 3);
+                let table = store.routing_table();
+                let store_clone_2 = store_clone.clone();
+                store
+                    .import_routes(vec![])
+                    .and_then(move |_| {
+                        assert!(store.routing_table().is_empty());
+                        assert_eq!(store.route_cost(b"example.b"), None);
+                        store_clone.import_routes(exported)
+                    })
+                    .and_then(move |_| {
+                        assert_eq!(store_clone_2.routing_table(), table);
+                        assert_eq!(store_clone_2.route_cost(b"example.b"), Some(3));
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+    }))
+    .unwrap()
+}
+
+#[test]
+fn import_fails_for_nonexistent_accounts() {
+    block_on(test_store().and_then(|(store, context)| {
+        store
+            .import_routes(vec![RouteEntry {
+                prefix: "example.a".to_string(),
+                account_id: 99,
+                cost: 0,
+            }])
+            .then(move |result| {
+                assert!(result.is_err());
+                let _ = context;
+                Ok::<(), ()>(())
+            })
+    }))
+    .unwrap()
+}
+
 #[test]
 fn returns_configured_routes_for_route_manager() {
     block_on(test_store().and_then(|(store, context)| {
@@ -3,8 +3,10 @@ extern crate lazy_static;
 
 mod common;
 
+use bytes::Bytes;
 use common::*;
-use interledger_settlement::SettlementStore;
+use futures::future::join_all;
+use interledger_settlement::{IdempotentData, SettlementStore};
 use redis::{cmd, r#async::SharedConnection};
 
 #[test]
@@ -12,7 +14,7 @@ fn credits_prepaid_amount() {
     block_on(test_store().and_then(|(store, context)| {
         context.async_connection().and_then(move |conn| {
             store
-                .update_balance_for_incoming_settlement(0, 100)
+                .update_balance_for_incoming_settlement(0, 100, None)
                 .and_then(move |_| {
                     cmd("HMGET")
                         .arg("accounts:0")
@@ -47,7 +49,7 @@ fn credits_balance_owed() {
                     .map_err(|err| panic!(err))
                     .and_then(move |(conn, _balance): (SharedConnection, i64)| {
                         store
-                            .update_balance_for_incoming_settlement(0, 100)
+                            .update_balance_for_incoming_settlement(0, 100, None)
                             .and_then(move |_| {
                                 cmd("HMGET")
                                     .arg("accounts:0")
@@ -88,7 +90,7 @@ fn clears_balance_owed() {
                     .map_err(|err| panic!(err))
                     .and_then(move |(conn, _balance): (SharedConnection, i64)| {
                         store
-                            .update_balance_for_incoming_settlement(0, 100)
+                            .update_balance_for_incoming_settlement(0, 100, None)
                             .and_then(move |_| {
                                 cmd("HMGET")
                                     .arg("accounts:0")
@@ -129,7 +131,7 @@ fn clears_balance_owed_and_puts_remainder_as_prepaid() {
                     .map_err(|err| panic!(err))
                     .and_then(move |(conn, _balance): (SharedConnection, i64)| {
                         store
-                            .update_balance_for_incoming_settlement(0, 100)
+                            .update_balance_for_incoming_settlement(0, 100, None)
                             .and_then(move |_| {
                                 cmd("HMGET")
                                     .arg("accounts:0")
@@ -154,3 +156,46 @@ fn clears_balance_owed_and_puts_remainder_as_prepaid() {
     }))
     .unwrap()
 }
+
+#[test]
+fn returns_balance_after_concurrent_settlements() {
+    block_on(test_store().and_then(|(store, context)| {
+        let updates: Vec<_> = (0..5)
+            .map(|_| store.update_balance_for_incoming_settlement(0, 100, None))
+            .collect();
+        join_all(updates).and_then(move |mut balances| {
+            // Each update sees its own change and the ones applied before it
+            balances.sort();
+            assert_eq!(balances, vec![100, 200, 300, 400, 500]);
+            let _ = context;
+            Ok(())
+        })
+    }))
+    .unwrap()
+}
+
+#[test]
+fn saves_idempotent_data_with_the_credit() {
+    block_on(test_store().and_then(|(store, context)| {
+        let data = IdempotentData {
+            input_hash: [1; 32],
+            body: Bytes::from(r#"{"appliedAmount":100}"#),
+        };
+        store
+            .update_balance_for_incoming_settlement(
+                0,
+                100,
+                Some(("abc123".to_string(), data.clone())),
+            )
+            .and_then(move |balance| {
+                assert_eq!(balance, 100);
+                store.load_idempotent_data("abc123".to_string())
+            })
+            .and_then(move |saved| {
+                assert_eq!(saved, Some(data));
+                let _ = context;
+                Ok(())
+            })
+    }))
+    .unwrap()
+}
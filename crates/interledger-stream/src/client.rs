@@ -3,7 +3,7 @@ use super::crypto::*;
 use super::error::Error;
 use super::packet::*;
 use bytes::Bytes;
-use futures::{Async, Future, Poll};
+use futures::{future::Either, Async, Future, Poll};
 use interledger_ildcp::get_ildcp_info;
 use interledger_packet::{
     Address, ErrorClass, ErrorCode as IlpErrorCode, Fulfill, PacketType as IlpPacketType,
@@ -14,8 +14,46 @@ use std::{
     cell::Cell,
     cmp::min,
     str,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
+use tokio_timer::{Delay, Timeout};
+
+/// When a STREAM payment is complete.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryTarget {
+    /// Send exactly this amount, in the sender's units.
+    Send(u64),
+    /// Send until the receiver reports that this amount, in the receiver's units, has been delivered.
+    /// No more than `max_source_amount` will be sent, and the payment fails if the target is not
+    /// reached with it. Packets that are already in flight when the target is reached may deliver
+    /// slightly more than the target.
+    Deliver { amount: u64, max_source_amount: u64 },
+}
+
+/// Stops a payment that is in progress.
+///
+/// Once cancelled, the payment does not send any more packets. It finishes when the packets
+/// already in flight are fulfilled or rejected and resolves to the amount delivered until then.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
 /// Send a given amount of money using the STREAM transport protocol.
 ///
@@ -31,27 +69,173 @@ where
     S: IncomingService<A> + Clone,
     A: Account,
 {
+    send_money_with_target(
+        service,
+        from_account,
+        destination_account,
+        shared_secret,
+        DeliveryTarget::Send(source_amount),
+    )
+}
+
+/// Send money using the STREAM transport protocol until the given `DeliveryTarget` is met.
+///
+/// This returns the amount delivered, as reported by the receiver and in the receiver's asset's units.
+pub fn send_money_with_target<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    target: DeliveryTarget,
+) -> impl Future<Item = (u64, S), Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    send_money_with_concurrency(
+        service,
+        from_account,
+        destination_account,
+        shared_secret,
+        target,
+        None,
+    )
+}
+
+/// Send money using the STREAM transport protocol, like `send_money_with_target`, with at most
+/// `max_packets_in_flight` packets in flight at a time.
+///
+/// The sender always keeps as many packets in flight as the congestion controller allows, so
+/// pipelining packets helps on paths with a high round-trip time. `None` means the number of
+/// packets is only limited by the congestion controller's amount in flight.
+pub fn send_money_with_concurrency<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    target: DeliveryTarget,
+    max_packets_in_flight: Option<usize>,
+) -> impl Future<Item = (u64, S), Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    send_money_cancellable(
+        service,
+        from_account,
+        destination_account,
+        shared_secret,
+        target,
+        max_packets_in_flight,
+        CancelHandle::new(),
+    )
+}
+
+/// Send money using the STREAM transport protocol, like `send_money_with_concurrency`,
+/// until the target is met or the payment is stopped with the given `CancelHandle`.
+///
+/// A cancelled payment resolves to the amount delivered before it was cancelled,
+/// even if that is short of the `DeliveryTarget`.
+pub fn send_money_cancellable<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    target: DeliveryTarget,
+    max_packets_in_flight: Option<usize>,
+    cancel_handle: CancelHandle,
+) -> impl Future<Item = (u64, S), Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    send_money_with_options(
+        service,
+        from_account,
+        destination_account,
+        shared_secret,
+        target,
+        PaymentOptions {
+            max_packets_in_flight,
+            cancel_handle,
+            timeout: None,
+        },
+    )
+}
+
+/// How a STREAM payment is sent, apart from its `DeliveryTarget`
+#[derive(Clone, Debug, Default)]
+pub struct PaymentOptions {
+    /// The most packets to have in flight at a time, as in `send_money_with_concurrency`
+    pub max_packets_in_flight: Option<usize>,
+    /// Stops the payment, as in `send_money_cancellable`
+    pub cancel_handle: CancelHandle,
+    /// How long the payment may take, including getting the sender's ILDCP info, before it fails
+    /// with `Error::Timeout`. Packets still in flight at the deadline are not waited for.
+    pub timeout: Option<Duration>,
+}
+
+/// Send money using the STREAM transport protocol until the given `DeliveryTarget` is met,
+/// with the given `PaymentOptions`.
+///
+/// A timeout needs to be run on a Tokio runtime, because it uses the Tokio timer.
+pub fn send_money_with_options<S, A>(
+    service: S,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    target: DeliveryTarget,
+    options: PaymentOptions,
+) -> impl Future<Item = (u64, S), Error = Error>
+where
+    S: IncomingService<A> + Clone,
+    A: Account,
+{
+    let (max_source_amount, delivery_target) = match target {
+        DeliveryTarget::Send(source_amount) => (source_amount, None),
+        DeliveryTarget::Deliver {
+            amount,
+            max_source_amount,
+        } => (max_source_amount, Some(amount)),
+    };
+    let started_at = Instant::now();
+    let deadline = options.timeout.map(|timeout| started_at + timeout);
     let shared_secret = Bytes::from(shared_secret);
     let from_account = from_account.clone();
     // TODO can/should we avoid cloning the account?
-    get_ildcp_info(&mut service.clone(), from_account.clone())
-        .map_err(|_err| Error::ConnectionError("Unable to get ILDCP info: {:?}".to_string()))
-        .and_then(move |account_details| SendMoneyFuture {
-            state: SendMoneyFutureState::SendMoney,
-            next: Some(service),
-            from_account,
-            source_account: account_details.client_address(),
-            destination_account,
-            shared_secret,
-            source_amount,
-            congestion_controller: CongestionController::default(),
-            pending_requests: Cell::new(Vec::new()),
-            delivered_amount: 0,
-            should_send_source_account: true,
-            sequence: 1,
-            rejected_packets: 0,
-            error: None,
-        })
+    let ildcp_info = get_ildcp_info(&mut service.clone(), from_account.clone())
+        .map_err(|_err| Error::ConnectionError("Unable to get ILDCP info: {:?}".to_string()));
+    let ildcp_info = match deadline {
+        Some(deadline) => Either::A(Timeout::new_at(ildcp_info, deadline).map_err(move |err| {
+            err.into_inner().unwrap_or_else(|| Error::Timeout {
+                elapsed: started_at.elapsed(),
+                delivered: 0,
+            })
+        })),
+        None => Either::B(ildcp_info),
+    };
+    ildcp_info.and_then(move |account_details| SendMoneyFuture {
+        state: SendMoneyFutureState::SendMoney,
+        next: Some(service),
+        from_account,
+        source_account: account_details.client_address(),
+        destination_account,
+        shared_secret,
+        max_source_amount,
+        delivery_target,
+        congestion_controller: CongestionController::default(),
+        max_packets_in_flight: options.max_packets_in_flight,
+        cancel_handle: options.cancel_handle,
+        started_at,
+        deadline: deadline.map(Delay::new),
+        pending_requests: Cell::new(Vec::new()),
+        fulfilled_source_amount: 0,
+        delivered_amount: 0,
+        should_send_source_account: true,
+        sequence: 1,
+        rejected_packets: 0,
+        error: None,
+    })
 }
 
 struct SendMoneyFuture<S: IncomingService<A>, A: Account> {
@@ -61,9 +245,15 @@ struct SendMoneyFuture<S: IncomingService<A>, A: Account> {
     source_account: Address,
     destination_account: Address,
     shared_secret: Bytes,
-    source_amount: u64,
+    max_source_amount: u64,
+    delivery_target: Option<u64>,
     congestion_controller: CongestionController,
+    max_packets_in_flight: Option<usize>,
+    cancel_handle: CancelHandle,
+    started_at: Instant,
+    deadline: Option<Delay>,
     pending_requests: Cell<Vec<PendingRequest>>,
+    fulfilled_source_amount: u64,
     delivered_amount: u64,
     should_send_source_account: bool,
     sequence: u64,
@@ -94,15 +284,26 @@ where
         // Fire off requests until the congestion controller tells us to stop or we've sent the total amount
         let mut sent_packets = false;
         loop {
+            if self.target_reached() || self.cancel_handle.is_cancelled() {
+                break;
+            }
+            if let Some(max_packets_in_flight) = self.max_packets_in_flight {
+                if self.pending_requests.get_mut().len() >= max_packets_in_flight {
+                    break;
+                }
+            }
+
             // Determine the amount to send
-            let amount = min(
-                self.source_amount,
+            let mut amount = min(
+                self.source_amount_left(),
                 self.congestion_controller.get_max_amount(),
             );
+            if let Some(amount_for_target) = self.source_amount_for_target() {
+                amount = min(amount, amount_for_target);
+            }
             if amount == 0 {
                 break;
             }
-            self.source_amount -= amount;
 
             // Load up the STREAM packet
             let sequence = self.next_sequence();
@@ -147,6 +348,7 @@ where
                 let send_request = next.handle_request(IncomingRequest {
                     from: self.from_account.clone(),
                     prepare,
+                    correlation_id: None,
                 });
                 self.pending_requests.get_mut().push(PendingRequest {
                     sequence,
@@ -161,6 +363,55 @@ where
         Ok(sent_packets)
     }
 
+    /// The amount that can still be sent without the total of the fulfilled and
+    /// in-flight packets exceeding the maximum source amount.
+    /// This is derived from the packets themselves rather than kept as a running
+    /// total, so retries and rejections cannot make the sender go over the limit.
+    fn source_amount_left(&mut self) -> u64 {
+        let in_flight = self.amount_in_flight();
+        self.max_source_amount
+            .saturating_sub(self.fulfilled_source_amount)
+            .saturating_sub(in_flight)
+    }
+
+    fn amount_in_flight(&mut self) -> u64 {
+        self.pending_requests
+            .get_mut()
+            .iter()
+            .map(|request| request.amount)
+            .sum()
+    }
+
+    fn target_reached(&self) -> bool {
+        match self.delivery_target {
+            Some(target) => self.delivered_amount >= target,
+            None => false,
+        }
+    }
+
+    /// Estimate how much more needs to be sent to reach the delivery target,
+    /// based on the exchange rate of the packets fulfilled so far
+    fn source_amount_for_target(&mut self) -> Option<u64> {
+        let target = self.delivery_target?;
+        if self.delivered_amount == 0 || self.fulfilled_source_amount == 0 {
+            return None;
+        }
+        let left_to_deliver = u128::from(target.saturating_sub(self.delivered_amount));
+        let delivered = u128::from(self.delivered_amount);
+        let scaled = left_to_deliver * u128::from(self.fulfilled_source_amount);
+        let mut needed = scaled / delivered;
+        if scaled % delivered != 0 {
+            needed += 1;
+        }
+        let in_flight = self.amount_in_flight();
+        // This is capped by the amount left to send, so it always fits in a u64
+        let left = self
+            .max_source_amount
+            .saturating_sub(self.fulfilled_source_amount);
+        let needed = min(needed, u128::from(left)) as u64;
+        Some(needed.saturating_sub(in_flight))
+    }
+
     fn try_send_connection_close(&mut self) -> Result<(), Error> {
         let sequence = self.next_sequence();
         let stream_packet = StreamPacketBuilder {
@@ -190,6 +441,7 @@ where
             let send_request = next.handle_request(IncomingRequest {
                 from: self.from_account.clone(),
                 prepare,
+                correlation_id: None,
             });
             self.pending_requests.get_mut().push(PendingRequest {
                 sequence,
@@ -234,35 +486,58 @@ where
         // TODO should we check the fulfillment and expiry or can we assume the plugin does that?
         self.congestion_controller.fulfill(amount);
         self.should_send_source_account = false;
+        self.fulfilled_source_amount += amount;
 
-        if let Ok(packet) = StreamPacket::from_encrypted(&self.shared_secret, fulfill.into_data()) {
-            if packet.ilp_packet_type() == IlpPacketType::Fulfill {
-                // TODO check that the sequence matches our outgoing packet
-                self.delivered_amount += packet.prepare_amount();
+        // The source amount was spent either way, so it is only counted once above;
+        // the delivered amount is only updated if the receiver's response is valid
+        match self.parse_fulfill_data(sequence, fulfill) {
+            Ok(packet) => self.delivered_amount += packet.prepare_amount(),
+            Err(error) => {
+                warn!("{}", error);
+                self.error = Some(error);
             }
-        } else {
-            warn!(
-                "Unable to parse STREAM packet from fulfill data for sequence {}",
-                sequence
-            );
         }
 
         debug!(
-            "Prepare {} with amount {} was fulfilled ({} left to send)",
-            sequence, amount, self.source_amount
+            "Prepare {} with amount {} was fulfilled ({} of {} sent)",
+            sequence, amount, self.fulfilled_source_amount, self.max_source_amount
         );
     }
 
+    /// Check that the Fulfill's data decrypts to the receiver's STREAM Fulfill for the given sequence
+    fn parse_fulfill_data(&self, sequence: u64, fulfill: Fulfill) -> Result<StreamPacket, Error> {
+        let packet = StreamPacket::from_encrypted(&self.shared_secret, fulfill.into_data())
+            .map_err(|_| {
+                Error::ProtocolError(format!(
+                    "Unable to parse STREAM packet from fulfill data for sequence {}",
+                    sequence
+                ))
+            })?;
+        if packet.ilp_packet_type() != IlpPacketType::Fulfill {
+            return Err(Error::ProtocolError(format!(
+                "Fulfill for sequence {} contained a STREAM packet of type: {:?}",
+                sequence,
+                packet.ilp_packet_type()
+            )));
+        }
+        if packet.sequence() != sequence {
+            return Err(Error::ProtocolError(format!(
+                "Fulfill for sequence {} contained a STREAM packet for sequence {}",
+                sequence,
+                packet.sequence()
+            )));
+        }
+        Ok(packet)
+    }
+
     fn handle_reject(&mut self, sequence: u64, amount: u64, reject: Reject) {
-        self.source_amount += amount;
         self.congestion_controller.reject(amount, &reject);
         self.rejected_packets += 1;
         debug!(
-            "Prepare {} with amount {} was rejected with code: {} ({} left to send)",
+            "Prepare {} with amount {} was rejected with code: {}",
             sequence,
             amount,
             reject.code(),
-            self.source_amount
         );
 
         match (reject.code().class(), reject.code()) {
@@ -273,6 +548,13 @@ where
             (_, IlpErrorCode::F99_APPLICATION_ERROR) => {
                 // TODO handle STREAM errors
             }
+            (_, IlpErrorCode::R00_TRANSFER_TIMED_OUT) => {
+                self.error = Some(Error::SendMoneyError(format!(
+                    "Packet timed out on the path to the receiver: {} {}",
+                    reject.code(),
+                    str::from_utf8(reject.message()).unwrap_or_default(),
+                )));
+            }
             _ => {
                 self.error = Some(Error::SendMoneyError(format!(
                     "Packet was rejected with error: {} {}",
@@ -299,11 +581,34 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut deadline) = self.deadline {
+            let expired = deadline.poll().map_err(|err| {
+                Error::SendMoneyError(format!("Error polling payment deadline: {:?}", err))
+            })?;
+            if expired.is_ready() {
+                let elapsed = self.started_at.elapsed();
+                warn!(
+                    "Payment deadline exceeded after {:?}. Delivered: {} ({} packets still in flight)",
+                    elapsed,
+                    self.delivered_amount,
+                    self.pending_requests.get_mut().len()
+                );
+                return Err(Error::Timeout {
+                    elapsed,
+                    delivered: self.delivered_amount,
+                });
+            }
+        }
+
         // TODO maybe don't have loops here and in try_send_money
         loop {
             self.poll_pending_requests()?;
 
-            if self.source_amount == 0 && self.pending_requests.get_mut().is_empty() {
+            if (self.fulfilled_source_amount >= self.max_source_amount
+                || self.target_reached()
+                || self.cancel_handle.is_cancelled())
+                && self.pending_requests.get_mut().is_empty()
+            {
                 if self.state == SendMoneyFutureState::SendMoney {
                     self.state = SendMoneyFutureState::Closing;
                     self.try_send_connection_close()?;
@@ -312,6 +617,14 @@ where
                     debug!(
                         "Send money future finished. Delivered: {} ({} packets fulfilled, {} packets rejected)", self.delivered_amount, self.sequence - 1, self.rejected_packets,
                     );
+                    if let Some(target) = self.delivery_target {
+                        if self.delivered_amount < target && !self.cancel_handle.is_cancelled() {
+                            return Err(Error::SendMoneyError(format!(
+                                "Only delivered {} of the target amount {}",
+                                self.delivered_amount, target
+                            )));
+                        }
+                    }
                     return Ok(Async::Ready((
                         self.delivered_amount,
                         self.next.take().unwrap(),
@@ -329,7 +642,7 @@ mod send_money_tests {
     use super::*;
     use crate::test_helpers::{TestAccount, EXAMPLE_CONNECTOR};
     use interledger_ildcp::IldcpService;
-    use interledger_packet::{ErrorCode as IlpErrorCode, RejectBuilder};
+    use interledger_packet::{ErrorCode as IlpErrorCode, FulfillBuilder, RejectBuilder};
     use interledger_service::incoming_service_fn;
     use parking_lot::Mutex;
     use std::str::FromStr;
@@ -365,4 +678,100 @@ mod send_money_tests {
         assert!(result.is_err());
         assert_eq!(requests.lock().len(), 1);
     }
+
+    #[test]
+    fn stops_at_fulfill_with_invalid_data() {
+        let account = TestAccount {
+            id: 0,
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.destination").unwrap(),
+        };
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let result = send_money(
+            IldcpService::new(incoming_service_fn(move |request| {
+                requests_clone.lock().push(request);
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"this is not a stream packet",
+                }
+                .build())
+            })),
+            &account,
+            Address::from_str("example.destination").unwrap(),
+            &[0; 32][..],
+            100,
+        )
+        .wait();
+        match result {
+            Err(Error::ProtocolError(_)) => {}
+            other => panic!("Expected a protocol error, got: {:?}", other.map(|r| r.0)),
+        }
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    fn test_account() -> TestAccount {
+        TestAccount {
+            id: 0,
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.destination").unwrap(),
+        }
+    }
+
+    #[test]
+    fn times_out_locally() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(send_money_with_options(
+            IldcpService::new(incoming_service_fn(|_request| {
+                futures::future::empty::<Fulfill, Reject>()
+            })),
+            &test_account(),
+            Address::from_str("example.destination").unwrap(),
+            &[0; 32][..],
+            DeliveryTarget::Send(100),
+            PaymentOptions {
+                timeout: Some(Duration::from_millis(50)),
+                ..PaymentOptions::default()
+            },
+        ));
+        match result {
+            Err(Error::Timeout { elapsed, delivered }) => {
+                assert!(elapsed >= Duration::from_millis(50));
+                assert_eq!(delivered, 0);
+            }
+            other => panic!("Expected a timeout, got: {:?}", other.map(|r| r.0)),
+        }
+    }
+
+    #[test]
+    fn peer_timeout_is_not_a_local_timeout() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(send_money_with_options(
+            IldcpService::new(incoming_service_fn(|_request| {
+                Err(RejectBuilder {
+                    code: IlpErrorCode::R00_TRANSFER_TIMED_OUT,
+                    message: b"too slow",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            })),
+            &test_account(),
+            Address::from_str("example.destination").unwrap(),
+            &[0; 32][..],
+            DeliveryTarget::Send(100),
+            PaymentOptions {
+                timeout: Some(Duration::from_secs(30)),
+                ..PaymentOptions::default()
+            },
+        ));
+        match result {
+            Err(Error::SendMoneyError(message)) => {
+                assert!(message.contains("timed out on the path"))
+            }
+            other => panic!("Expected a send money error, got: {:?}", other.map(|r| r.0)),
+        }
+    }
 }
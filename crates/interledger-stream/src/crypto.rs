@@ -99,6 +99,14 @@ fn encrypt_with_nonce(
 }
 
 pub fn decrypt(shared_secret: &[u8], mut ciphertext: BytesMut) -> Result<BytesMut, ()> {
+    if ciphertext.len() < NONCE_LENGTH + AUTH_TAG_LENGTH {
+        debug!(
+            "Ciphertext is too short to contain a nonce and auth tag: {} bytes",
+            ciphertext.len()
+        );
+        return Err(());
+    }
+
     let key = hmac_sha256(shared_secret, &ENCRYPTION_KEY_STRING);
     let key = aead::OpeningKey::new(&aead::AES_256_GCM, &key)
         .expect("Failed to create a new opening key for decrypting data!");
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 #[derive(Fail, Debug)]
 pub enum Error {
     #[fail(display = "Error connecting: {}", _0)]
@@ -6,4 +8,13 @@ pub enum Error {
     PollError(String),
     #[fail(display = "Error polling: {}", _0)]
     SendMoneyError(String),
+    #[fail(display = "STREAM protocol error: {}", _0)]
+    ProtocolError(String),
+    /// The payment did not finish within the timeout set by the sender.
+    /// This is enforced locally, unlike an `R00 Transfer Timed Out` reject from a peer.
+    #[fail(
+        display = "Payment deadline exceeded locally after {:?}, having delivered: {}",
+        elapsed, delivered
+    )]
+    Timeout { elapsed: Duration, delivered: u64 },
 }
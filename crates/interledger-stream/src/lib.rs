@@ -17,10 +17,15 @@ mod crypto;
 mod error;
 mod packet;
 mod server;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
 
-pub use client::send_money;
+pub use client::{
+    send_money, send_money_cancellable, send_money_with_concurrency, send_money_with_options,
+    send_money_with_target, CancelHandle, DeliveryTarget, PaymentOptions,
+};
 pub use error::Error;
-pub use server::{ConnectionGenerator, StreamReceiverService};
+pub use server::{ConnectionGenerator, StreamReceiverService, CONNECTION_TOKEN_LENGTH};
 
 #[cfg(test)]
 pub mod test_helpers {
@@ -33,6 +38,7 @@ pub mod test_helpers {
     use interledger_service::{Account, AccountStore};
     use std::iter::FromIterator;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     lazy_static! {
         pub static ref EXAMPLE_CONNECTOR: Address = Address::from_str("example.connector").unwrap();
@@ -86,8 +92,11 @@ pub mod test_helpers {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> HashMap<Bytes, u64> {
-            HashMap::from_iter(vec![(self.route.0.clone(), self.route.1.id())].into_iter())
+        fn routing_table(&self) -> Arc<HashMap<Bytes, u64>> {
+            Arc::new(HashMap::from_iter(vec![(
+                self.route.0.clone(),
+                self.route.1.id(),
+            )]))
         }
     }
 }
@@ -97,13 +106,18 @@ mod send_money_to_receiver {
     use super::test_helpers::*;
     use super::*;
     use bytes::Bytes;
+    use futures::future::err;
     use futures::Future;
     use interledger_ildcp::IldcpService;
     use interledger_packet::Address;
-    use interledger_packet::{ErrorCode, RejectBuilder};
+    use interledger_packet::{ErrorCode, MaxPacketAmountDetails, RejectBuilder};
     use interledger_router::Router;
-    use interledger_service::outgoing_service_fn;
+    use interledger_service::{
+        incoming_service_fn, outgoing_service_fn, BoxedIlpFuture, IncomingRequest, IncomingService,
+    };
+    use parking_lot::Mutex;
     use std::str::FromStr;
+    use std::sync::Arc;
     use tokio::runtime::Runtime;
 
     #[test]
@@ -159,4 +173,222 @@ mod send_money_to_receiver {
         let runtime = Runtime::new().unwrap();
         runtime.block_on_all(run).unwrap();
     }
+
+    fn sender_account() -> TestAccount {
+        TestAccount {
+            id: 0,
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.sender").unwrap(),
+        }
+    }
+
+    /// A STREAM receiver with the address and shared secret to send to it
+    fn test_receiver() -> (impl IncomingService<TestAccount> + Clone, Address, [u8; 32]) {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let store = TestStore {
+            route: (
+                receiver_address.to_bytes(),
+                TestAccount {
+                    id: 0,
+                    ilp_address: receiver_address.clone(),
+                    asset_code: "XYZ".to_string(),
+                    asset_scale: 9,
+                },
+            ),
+        };
+        let server = StreamReceiverService::new(
+            server_secret.clone(),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let (destination_account, shared_secret) =
+            ConnectionGenerator::new(server_secret).generate_address_and_secret(&receiver_address);
+        (
+            IldcpService::new(Router::new(store, server)),
+            destination_account,
+            shared_secret,
+        )
+    }
+
+    /// Deliver half of each packet's amount to the receiver, as if there were an exchange rate of 0.5
+    fn halve_amounts<I>(mut next: I) -> impl IncomingService<TestAccount> + Clone
+    where
+        I: IncomingService<TestAccount> + Clone,
+    {
+        incoming_service_fn(move |mut request: IncomingRequest<TestAccount>| {
+            let amount = request.prepare.amount() / 2;
+            request.prepare.set_amount(amount);
+            next.handle_request(request)
+        })
+    }
+
+    #[test]
+    fn send_target_limits_source_amount() {
+        let (receiver, destination_account, shared_secret) = test_receiver();
+        let (delivered_amount, _) = send_money_with_target(
+            halve_amounts(receiver),
+            &sender_account(),
+            destination_account,
+            &shared_secret[..],
+            DeliveryTarget::Send(1000),
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(delivered_amount, 500);
+    }
+
+    #[test]
+    fn deliver_target_stops_when_delivered() {
+        let (receiver, destination_account, shared_secret) = test_receiver();
+        let (delivered_amount, _) = send_money_with_target(
+            halve_amounts(receiver),
+            &sender_account(),
+            destination_account,
+            &shared_secret[..],
+            DeliveryTarget::Deliver {
+                amount: 1600,
+                max_source_amount: 10_000,
+            },
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(delivered_amount, 1600);
+    }
+
+    #[test]
+    fn deliver_target_fails_if_source_amount_runs_out() {
+        let (receiver, destination_account, shared_secret) = test_receiver();
+        let result = send_money_with_target(
+            halve_amounts(receiver),
+            &sender_account(),
+            destination_account,
+            &shared_secret[..],
+            DeliveryTarget::Deliver {
+                amount: 1600,
+                max_source_amount: 2000,
+            },
+        )
+        .wait();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn never_sends_more_than_source_amount() {
+        let (receiver, destination_account, shared_secret) = test_receiver();
+        // After the exchange rate is applied, packets of more than 33 units are rejected with F08.
+        // The max packet amount the sender works out from the F08 details is rounded, so it
+        // is not always exactly right, and every third packet is rejected with a temporary error
+        let mut next = halve_amounts(receiver);
+        let mut packets = 0;
+        let connector = incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            packets += 1;
+            let received = request.prepare.amount() / 2;
+            let response: BoxedIlpFuture = if received > 33 {
+                Box::new(err(RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: b"Amount too large",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &MaxPacketAmountDetails::new(received, 33).to_bytes()[..],
+                }
+                .build()))
+            } else if packets % 3 == 0 {
+                Box::new(err(RejectBuilder {
+                    code: ErrorCode::T00_INTERNAL_ERROR,
+                    message: b"Try again",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build()))
+            } else {
+                Box::new(next.handle_request(request))
+            };
+            response
+        });
+        let fulfilled = Arc::new(Mutex::new(0));
+        let fulfilled_clone = fulfilled.clone();
+        let mut connector_clone = connector.clone();
+        let sender = incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            let amount = request.prepare.amount();
+            let fulfilled = fulfilled_clone.clone();
+            connector_clone.handle_request(request).map(move |fulfill| {
+                *fulfilled.lock() += amount;
+                fulfill
+            })
+        });
+
+        let (delivered_amount, _) = send_money_with_target(
+            sender,
+            &sender_account(),
+            destination_account,
+            &shared_secret[..],
+            DeliveryTarget::Send(1001),
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(*fulfilled.lock(), 1001);
+        assert!(delivered_amount <= 500);
+    }
+
+    /// Send 1000 units through a connector with a max packet amount of 100 and return
+    /// the largest number of packets that were in flight at the same time
+    fn max_packets_in_flight_with_window(max_packets_in_flight: Option<usize>) -> usize {
+        let (mut receiver, destination_account, shared_secret) = test_receiver();
+        let in_flight = Arc::new(Mutex::new((0, 0)));
+        let in_flight_clone = in_flight.clone();
+        let sender = incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            let amount = request.prepare.amount();
+            let response: BoxedIlpFuture = if amount > 100 {
+                Box::new(err(RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: b"Amount too large",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &MaxPacketAmountDetails::new(amount, 100).to_bytes()[..],
+                }
+                .build()))
+            } else {
+                let mut counts = in_flight_clone.lock();
+                counts.0 += 1;
+                counts.1 = counts.1.max(counts.0);
+                let in_flight = in_flight_clone.clone();
+                Box::new(receiver.handle_request(request).then(move |result| {
+                    in_flight.lock().0 -= 1;
+                    result
+                }))
+            };
+            response
+        });
+
+        let (delivered_amount, _) = send_money_with_concurrency(
+            sender,
+            &sender_account(),
+            destination_account,
+            &shared_secret[..],
+            DeliveryTarget::Send(1000),
+            max_packets_in_flight,
+        )
+        .wait()
+        .unwrap();
+        assert_eq!(delivered_amount, 1000);
+        let max_in_flight = in_flight.lock().1;
+        max_in_flight
+    }
+
+    #[test]
+    fn pipelines_packets_within_window() {
+        assert!(max_packets_in_flight_with_window(Some(20)) > 1);
+    }
+
+    #[test]
+    fn limits_packets_in_flight_to_window() {
+        assert_eq!(max_packets_in_flight_with_window(Some(2)), 2);
+    }
 }
@@ -113,28 +113,15 @@ pub struct StreamPacket {
 
 impl StreamPacket {
     pub fn from_encrypted(shared_secret: &[u8], ciphertext: BytesMut) -> Result<Self, ParseError> {
-        // TODO handle decryption failure
         let decrypted = decrypt(shared_secret, ciphertext)
             .map_err(|_err| ParseError::InvalidPacket(String::from("Unable to decrypt packet")))?;
         StreamPacket::from_bytes_unencrypted(decrypted)
     }
 
-    fn from_bytes_unencrypted(buffer_unencrypted: BytesMut) -> Result<Self, ParseError> {
+    pub(crate) fn from_bytes_unencrypted(buffer_unencrypted: BytesMut) -> Result<Self, ParseError> {
         // TODO don't copy the whole packet again
         let mut reader = &buffer_unencrypted[..];
-        let version = reader.read_u8()?;
-        if version != STREAM_VERSION {
-            return Err(ParseError::InvalidPacket(format!(
-                "Unsupported STREAM version: {}",
-                version
-            )));
-        }
-        let ilp_packet_type = IlpPacketType::try_from(reader.read_u8()?)?;
-        let sequence = reader.read_var_uint()?;
-        let prepare_amount = reader.read_var_uint()?;
-
-        // TODO save num_frames?
-        let num_frames = reader.read_var_uint()?;
+        let (ilp_packet_type, sequence, prepare_amount, num_frames) = read_header(&mut reader)?;
         let frames_offset = buffer_unencrypted.len() - reader.len();
 
         // Try reading through all the frames to make sure they can be parsed correctly
@@ -158,6 +145,13 @@ impl StreamPacket {
         }
     }
 
+    /// Read the number of frames a decrypted STREAM packet says it has, without parsing the frames
+    pub(crate) fn num_frames_unencrypted(buffer_unencrypted: &[u8]) -> Result<u64, ParseError> {
+        let mut reader = buffer_unencrypted;
+        let (_, _, _, num_frames) = read_header(&mut reader)?;
+        Ok(num_frames)
+    }
+
     pub fn into_encrypted(self, shared_secret: &[u8]) -> BytesMut {
         encrypt(shared_secret, self.buffer_unencrypted)
     }
@@ -194,6 +188,24 @@ impl fmt::Debug for StreamPacket {
     }
 }
 
+/// Read the fields of a decrypted STREAM packet that come before its frames: the ILP packet type,
+/// the sequence, the prepare amount and the number of frames
+fn read_header(reader: &mut &[u8]) -> Result<(IlpPacketType, u64, u64, u64), ParseError> {
+    let version = reader.read_u8()?;
+    if version != STREAM_VERSION {
+        return Err(ParseError::InvalidPacket(format!(
+            "Unsupported STREAM version: {}",
+            version
+        )));
+    }
+    let ilp_packet_type = IlpPacketType::try_from(reader.read_u8()?)?;
+    let sequence = reader.read_var_uint()?;
+    let prepare_amount = reader.read_var_uint()?;
+    // TODO save num_frames?
+    let num_frames = reader.read_var_uint()?;
+    Ok((ilp_packet_type, sequence, prepare_amount, num_frames))
+}
+
 pub struct FrameIterator<'a> {
     buffer: &'a [u8],
 }
@@ -811,6 +823,13 @@ mod serialization {
         );
     }
 
+    #[test]
+    fn it_rejects_frames_with_invalid_lengths() {
+        let mut buffer = BytesMut::from(&[1, 12, 1, 1, 1, 99, 1, 1][..]);
+        buffer.extend_from_slice(&[0xff; 16]);
+        assert!(StreamPacket::from_bytes_unencrypted(buffer).is_err());
+    }
+
     #[test]
     fn it_iterates_through_the_frames() {
         let mut iter = PACKET.frames();
@@ -2,7 +2,7 @@ use super::crypto::*;
 use super::packet::*;
 use base64;
 use bytes::Bytes;
-use futures::future::result;
+use futures::future::{err, result};
 use hex;
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::{
@@ -10,31 +10,79 @@ use interledger_packet::{
     RejectBuilder,
 };
 use interledger_service::{Account, BoxedIlpFuture, OutgoingRequest, OutgoingService};
+use parking_lot::RwLock;
+use ring::constant_time::verify_slices_are_equal;
 use std::convert::TryFrom;
 use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_secret_generator";
+const DEFAULT_MAX_FRAMES_PER_PACKET: usize = 1000;
+
+/// The length of the random connection token included in each generated `destination_account`
+pub const CONNECTION_TOKEN_LENGTH: usize = 18;
 
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
 ///
 /// This can be reused across multiple STREAM connections so that a single receiver can
 /// accept incoming packets for multiple connections.
+///
+/// The values are derived as follows, where `token` is a random `CONNECTION_TOKEN_LENGTH`-byte
+/// connection token and `base64url` is unpadded URL-safe base64:
+///
+/// 1. `secret_generator = HMAC-SHA256(server_secret, "ilp_stream_secret_generator")`
+/// 2. `shared_secret = HMAC-SHA256(secret_generator, token)`
+/// 3. `auth_tag = HMAC-SHA256(shared_secret, base_address + "." + base64url(token))[..14]`
+/// 4. `destination_account = base_address + "." + base64url(token + auth_tag)`
+///
+/// The receiver re-derives the `shared_secret` from the token in the last segment of the
+/// destination, and checks the auth tag to make sure the address was not modified.
+///
+/// The server secret can be replaced with `rotate_server_secret`. Connections generated with the
+/// previous secret are still accepted for a grace period, so that payments already in flight
+/// are not cut off. Clones of a generator share its secrets, so rotating one rotates them all.
 #[derive(Clone)]
 pub struct ConnectionGenerator {
-    secret_generator: Bytes,
+    secret_generators: Arc<RwLock<SecretGenerators>>,
+}
+
+struct SecretGenerators {
+    current: Bytes,
+    /// The generator for the previous server secret and when it stops being accepted
+    previous: Option<(Bytes, Instant)>,
+}
+
+fn secret_generator(server_secret: &[u8]) -> Bytes {
+    assert_eq!(server_secret.len(), 32, "Server secret must be 32 bytes");
+    Bytes::from(&hmac_sha256(server_secret, STREAM_SERVER_SECRET_GENERATOR)[..])
 }
 
 impl ConnectionGenerator {
     pub fn new(server_secret: Bytes) -> Self {
-        assert_eq!(server_secret.len(), 32, "Server secret must be 32 bytes");
         ConnectionGenerator {
-            secret_generator: Bytes::from(
-                &hmac_sha256(&server_secret[..], STREAM_SERVER_SECRET_GENERATOR)[..],
-            ),
+            secret_generators: Arc::new(RwLock::new(SecretGenerators {
+                current: secret_generator(&server_secret[..]),
+                previous: None,
+            })),
         }
     }
 
+    /// Generate new connections with `server_secret` from now on, while still accepting
+    /// connections generated with the current secret for the `grace_period`.
+    pub fn rotate_server_secret(&self, server_secret: Bytes, grace_period: Duration) {
+        let new_generator = secret_generator(&server_secret[..]);
+        let mut secret_generators = self.secret_generators.write();
+        let previous = mem::replace(&mut secret_generators.current, new_generator);
+        secret_generators.previous = Some((previous, Instant::now() + grace_period));
+        debug!(
+            "Rotated STREAM server secret, previous secret accepted for {:?}",
+            grace_period
+        );
+    }
+
     /// Generate the STREAM parameters for the given ILP address and the configured server secret.
     ///
     /// The `destination_account` is generated such that the `shared_secret` can be re-derived
@@ -42,15 +90,22 @@ impl ConnectionGenerator {
     /// in any way, the server will not be able to re-derive the secret and the packet will be rejected.
     // TODO make sure this is an ILP address
     pub fn generate_address_and_secret(&self, base_address: &Address) -> (Address, [u8; 32]) {
-        let random_bytes = generate_token();
+        self.generate_address_and_secret_with_token(base_address, &generate_token())
+    }
+
+    /// Generate the STREAM parameters for the given ILP address from a chosen connection token
+    /// instead of a random one. The same token and server secret always give the same values.
+    pub fn generate_address_and_secret_with_token(
+        &self,
+        base_address: &Address,
+        token: &[u8; CONNECTION_TOKEN_LENGTH],
+    ) -> (Address, [u8; 32]) {
         // base_address + "." + 32-bytes encoded as base64url
-        let shared_secret = hmac_sha256(&self.secret_generator[..], &random_bytes[..]);
+        let shared_secret = self.derive_secret(&token[..]);
         // Note that the unwrap here is safe because we know the base_address
         // is valid and adding base64-url characters will always be valid
         let destination_account = base_address
-            .with_suffix(
-                &base64::encode_config(&random_bytes[..], base64::URL_SAFE_NO_PAD).as_ref(),
-            )
+            .with_suffix(&base64::encode_config(&token[..], base64::URL_SAFE_NO_PAD).as_ref())
             .unwrap();
 
         let auth_tag = &hmac_sha256(&shared_secret[..], destination_account.as_ref())[..14];
@@ -64,25 +119,54 @@ impl ConnectionGenerator {
         (destination_account, shared_secret)
     }
 
+    /// Derive the `shared_secret` for a connection token with the configured server secret
+    pub fn derive_secret(&self, token: &[u8]) -> [u8; 32] {
+        hmac_sha256(&self.secret_generators.read().current[..], token)
+    }
+
     /// Rederive the `shared_secret` from a `destination_account`. This will return an
     /// error if the address has been modified in any way or if the packet was not generated
-    /// with the same server secret.
+    /// with the same server secret, or with the previous one during its grace period.
     pub fn rederive_secret(&self, destination_account: &Address) -> Result<[u8; 32], ()> {
         let local_part = destination_account.segments().rev().next().unwrap();
         let local_part =
             base64::decode_config(local_part, base64::URL_SAFE_NO_PAD).map_err(|_| ())?;
         if local_part.len() == 32 {
-            let (random_bytes, auth_tag) = local_part.split_at(18);
-            let shared_secret = hmac_sha256(&self.secret_generator[..], &random_bytes[..]);
+            let (random_bytes, auth_tag) = local_part.split_at(CONNECTION_TOKEN_LENGTH);
             let dest: &[u8] = destination_account.as_ref();
-            let derived_auth_tag = &hmac_sha256(&shared_secret[..], &dest[..dest.len() - 19])[..14];
-            if derived_auth_tag == auth_tag {
-                return Ok(shared_secret);
-            } else {
-                warn!("Got packet where auth tag doesn't match. Expected: {}, actual: {}, destination_account: {:?}",
-                base64::encode_config(derived_auth_tag, base64::URL_SAFE_NO_PAD),
-                base64::encode_config(auth_tag, base64::URL_SAFE_NO_PAD),
-                destination_account)
+            let dest = &dest[..dest.len() - 19];
+            let secret_generators = self.secret_generators.read();
+            let check_auth_tag = |secret_generator: &Bytes| {
+                let shared_secret = hmac_sha256(&secret_generator[..], random_bytes);
+                let derived_auth_tag = &hmac_sha256(&shared_secret[..], dest)[..14];
+                if verify_slices_are_equal(derived_auth_tag, auth_tag).is_ok() {
+                    Ok(shared_secret)
+                } else {
+                    Err(base64::encode_config(
+                        derived_auth_tag,
+                        base64::URL_SAFE_NO_PAD,
+                    ))
+                }
+            };
+            match check_auth_tag(&secret_generators.current) {
+                Ok(shared_secret) => return Ok(shared_secret),
+                Err(expected) => {
+                    if let Some((previous, expires_at)) = &secret_generators.previous {
+                        if Instant::now() < *expires_at {
+                            if let Ok(shared_secret) = check_auth_tag(previous) {
+                                trace!(
+                                    "Accepted connection generated with the previous server secret: {:?}",
+                                    destination_account
+                                );
+                                return Ok(shared_secret);
+                            }
+                        }
+                    }
+                    warn!("Got packet where auth tag doesn't match. Expected: {}, actual: {}, destination_account: {:?}",
+                    expected,
+                    base64::encode_config(auth_tag, base64::URL_SAFE_NO_PAD),
+                    destination_account)
+                }
             }
         }
         Err(())
@@ -99,6 +183,8 @@ impl ConnectionGenerator {
 pub struct StreamReceiverService<O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: O,
+    allowed_sources: Option<Vec<Address>>,
+    max_frames_per_packet: usize,
     account_type: PhantomData<A>,
 }
 
@@ -112,9 +198,36 @@ where
         StreamReceiverService {
             connection_generator,
             next,
+            allowed_sources: None,
+            max_frames_per_packet: DEFAULT_MAX_FRAMES_PER_PACKET,
             account_type: PhantomData,
         }
     }
+
+    /// Only accept STREAM packets from accounts whose ILP address is, or is beneath, one of the given prefixes.
+    /// Prefixes match whole segments, so `example.sender` allows `example.sender.alice` but not `example.senderx`.
+    /// Packets from other accounts are rejected with `F02 Unreachable`.
+    /// By default, packets from all accounts are accepted.
+    pub fn allowed_sources(&mut self, prefixes: impl IntoIterator<Item = Address>) -> &mut Self {
+        self.allowed_sources = Some(prefixes.into_iter().collect());
+        self
+    }
+
+    /// The generator used to check incoming packets, which can be used to rotate the server secret
+    pub fn connection_generator(&self) -> &ConnectionGenerator {
+        &self.connection_generator
+    }
+
+    /// Set the maximum number of frames the receiver will handle in a single STREAM packet.
+    /// The receiver does not keep any state between packets, so this is a per-packet limit
+    /// rather than a per-connection one. The number of frames a packet says it has is
+    /// checked before the frames are parsed, so the limit also bounds the parsing work.
+    /// Packets with more frames are rejected with `T04 Insufficient Liquidity`.
+    /// Defaults to 1000.
+    pub fn max_frames_per_packet(&mut self, max_frames: usize) -> &mut Self {
+        self.max_frames_per_packet = max_frames;
+        self
+    }
 }
 
 // TODO should this be an OutgoingService instead so the balance logic is applied before this is called?
@@ -140,8 +253,30 @@ where
                 .connection_generator
                 .rederive_secret(&request.prepare.destination())
             {
+                if let Some(ref allowed_sources) = self.allowed_sources {
+                    let from = request.from.client_address();
+                    if !allowed_sources
+                        .iter()
+                        .any(|prefix| from.starts_with(prefix))
+                    {
+                        debug!(
+                            "Rejecting STREAM packet from account {} because its address is not allowed: {}",
+                            request.from.id(),
+                            request.from.client_address()
+                        );
+                        return Box::new(err(Reject::unreachable(
+                            to,
+                            "Source address is not allowed",
+                        )));
+                    }
+                }
                 {
-                    return Box::new(result(receive_money(&shared_secret, &to, request.prepare)));
+                    return Box::new(result(receive_money(
+                        &shared_secret,
+                        &to,
+                        request.prepare,
+                        self.max_frames_per_packet,
+                    )));
                 }
             }
         }
@@ -154,26 +289,61 @@ fn receive_money(
     shared_secret: &[u8; 32],
     client_address: &Address,
     prepare: Prepare,
+    max_frames: usize,
 ) -> Result<Fulfill, Reject> {
     // Generate fulfillment
     let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
     let condition = hash_sha256(&fulfillment);
-    let is_fulfillable = condition == prepare.execution_condition();
+    let is_fulfillable =
+        verify_slices_are_equal(&condition[..], prepare.execution_condition()).is_ok();
 
     // Parse STREAM packet
     // TODO avoid copying data
     let prepare_amount = prepare.amount();
-    let stream_packet =
-        StreamPacket::from_encrypted(shared_secret, prepare.into_data()).map_err(|_| {
-            debug!("Unable to parse data, rejecting Prepare packet");
-            RejectBuilder {
-                code: ErrorCode::F06_UNEXPECTED_PAYMENT,
-                message: b"Could not decrypt data",
+    // If the data cannot be authenticated, the packet was either tampered with or
+    // encrypted with a different shared secret so we cannot include a STREAM reply
+    let decrypted = decrypt(shared_secret, prepare.into_data()).map_err(|_| {
+        warn!("Unable to decrypt STREAM packet, rejecting Prepare packet");
+        RejectBuilder {
+            code: ErrorCode::F06_UNEXPECTED_PAYMENT,
+            message: b"Could not decrypt data",
+            triggered_by: Some(client_address),
+            data: &[],
+        }
+        .build()
+    })?;
+
+    // Check the number of frames before parsing them. Packets with an invalid
+    // header are rejected when they are parsed below
+    if let Ok(num_frames) = StreamPacket::num_frames_unencrypted(&decrypted[..]) {
+        if num_frames > max_frames as u64 {
+            warn!(
+                "Rejecting STREAM packet with {} frames, more than the maximum of {}",
+                num_frames, max_frames
+            );
+            return Err(RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: b"Too many STREAM frames",
                 triggered_by: Some(client_address),
                 data: &[],
             }
-            .build()
-        })?;
+            .build());
+        }
+    }
+
+    let stream_packet = StreamPacket::from_bytes_unencrypted(decrypted).map_err(|err| {
+        warn!(
+            "Unable to parse decrypted STREAM packet, rejecting Prepare packet: {:?}",
+            err
+        );
+        RejectBuilder {
+            code: ErrorCode::F99_APPLICATION_ERROR,
+            message: b"Invalid STREAM packet",
+            triggered_by: Some(client_address),
+            data: &[],
+        }
+        .build()
+    })?;
 
     let mut response_frames: Vec<Frame> = Vec::new();
 
@@ -284,6 +454,70 @@ mod connection_generator {
             .rederive_secret(&destination_account)
             .is_err());
     }
+
+    #[test]
+    fn same_token_derives_same_secret() {
+        let server_secret = Bytes::from(&[9; 32][..]);
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let token = [7; CONNECTION_TOKEN_LENGTH];
+        let (destination_account, shared_secret) = ConnectionGenerator::new(server_secret.clone())
+            .generate_address_and_secret_with_token(&receiver_address, &token);
+
+        // A separate generator with the same server secret, as on the receiving end
+        let receiver = ConnectionGenerator::new(server_secret);
+        assert_eq!(
+            receiver.generate_address_and_secret_with_token(&receiver_address, &token),
+            (destination_account.clone(), shared_secret)
+        );
+        assert_eq!(receiver.derive_secret(&token[..]), shared_secret);
+        assert_eq!(
+            receiver.rederive_secret(&destination_account).unwrap(),
+            shared_secret
+        );
+
+        let other = ConnectionGenerator::new(Bytes::from(&[8; 32][..]));
+        assert_ne!(other.derive_secret(&token[..]), shared_secret);
+    }
+
+    #[test]
+    fn rotation_uses_new_secret_for_new_connections() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let (old_destination, old_secret) =
+            connection_generator.generate_address_and_secret(&receiver_address);
+
+        // Clones share the rotated secret
+        connection_generator
+            .clone()
+            .rotate_server_secret(Bytes::from(&[8; 32][..]), Duration::from_secs(60));
+        let (new_destination, new_secret) =
+            connection_generator.generate_address_and_secret(&receiver_address);
+        assert_eq!(
+            ConnectionGenerator::new(Bytes::from(&[8; 32][..]))
+                .rederive_secret(&new_destination)
+                .unwrap(),
+            new_secret
+        );
+        assert_eq!(
+            connection_generator
+                .rederive_secret(&old_destination)
+                .unwrap(),
+            old_secret
+        );
+
+        // Rotating again ends the grace period of the first secret
+        connection_generator
+            .rotate_server_secret(Bytes::from(&[7; 32][..]), Duration::from_secs(60));
+        assert!(connection_generator
+            .rederive_secret(&old_destination)
+            .is_err());
+        assert_eq!(
+            connection_generator
+                .rederive_secret(&new_destination)
+                .unwrap(),
+            new_secret
+        );
+    }
 }
 
 #[cfg(test)]
@@ -331,7 +565,12 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let result = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        );
         assert!(result.is_ok());
     }
 
@@ -359,7 +598,12 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let result = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        );
         assert!(result.is_ok());
     }
 
@@ -388,10 +632,77 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let result = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        );
         assert!(result.is_err());
     }
 
+    #[test]
+    fn rejects_packet_encrypted_with_wrong_key() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, _shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let wrong_secret = [2; 32];
+        let data = test_stream_packet().into_encrypted(&wrong_secret[..]);
+        let execution_condition = generate_condition(&wrong_secret[..], &data);
+
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+
+        let shared_secret = connection_generator
+            .rederive_secret(&prepare.destination())
+            .unwrap();
+        let reject = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        )
+        .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F06_UNEXPECTED_PAYMENT);
+        assert_eq!(reject.message(), b"Could not decrypt data");
+        assert!(reject.data().is_empty());
+    }
+
+    #[test]
+    fn rejects_data_too_short_to_decrypt() {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: b"short",
+            execution_condition: &[0; 32],
+        }
+        .build();
+
+        let reject = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        )
+        .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F06_UNEXPECTED_PAYMENT);
+    }
+
     #[test]
     fn rejects_too_little_money() {
         let client_address = Address::from_str("example.destination").unwrap();
@@ -427,7 +738,12 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &client_address, prepare);
+        let result = receive_money(
+            &shared_secret,
+            &client_address,
+            prepare,
+            DEFAULT_MAX_FRAMES_PER_PACKET,
+        );
         assert!(result.is_err());
     }
 }
@@ -436,6 +752,7 @@ mod receiving_money {
 mod stream_receiver_service {
     use super::*;
     use crate::test_helpers::*;
+    use bytes::BytesMut;
     use futures::Future;
     use interledger_packet::PrepareBuilder;
     use interledger_service::outgoing_service_fn;
@@ -486,6 +803,7 @@ mod stream_receiver_service {
                 },
                 original_amount: prepare.amount(),
                 prepare,
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_ok());
@@ -537,11 +855,298 @@ mod stream_receiver_service {
                 },
                 original_amount: prepare.amount(),
                 prepare,
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
     }
 
+    fn request_from(
+        from_address: &str,
+        connection_generator: &ConnectionGenerator,
+    ) -> OutgoingRequest<TestAccount> {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&client_address);
+        let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+        OutgoingRequest {
+            from: TestAccount {
+                id: 0,
+                ilp_address: Address::from_str(from_address).unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            to: TestAccount {
+                id: 1,
+                ilp_address: client_address,
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+            },
+            original_amount: prepare.amount(),
+            prepare,
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn accepts_connections_from_previous_secret_during_grace_period() {
+        let mut service = StreamReceiverService::new(
+            Bytes::from(&[1; 32][..]),
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        let connection_generator = service.connection_generator().clone();
+        let old_connection = request_from("example.sender", &connection_generator);
+
+        connection_generator
+            .rotate_server_secret(Bytes::from(&[2; 32][..]), Duration::from_secs(60));
+        assert!(service.send_request(old_connection).wait().is_ok());
+        let new_connection = request_from(
+            "example.sender",
+            &ConnectionGenerator::new(Bytes::from(&[2; 32][..])),
+        );
+        assert!(service.send_request(new_connection).wait().is_ok());
+    }
+
+    #[test]
+    fn rejects_connections_from_previous_secret_after_grace_period() {
+        // Packets the receiver cannot rederive a secret for are passed on
+        let mut service = StreamReceiverService::new(
+            Bytes::from(&[1; 32][..]),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: None,
+                }
+                .build())
+            }),
+        );
+        let old_connection = request_from("example.sender", service.connection_generator());
+
+        service
+            .connection_generator()
+            .rotate_server_secret(Bytes::from(&[2; 32][..]), Duration::from_secs(0));
+        let reject = service.send_request(old_connection).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+    }
+
+    #[test]
+    fn accepts_allowed_source() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.allowed_sources(vec![Address::from_str("example.sender").unwrap()]);
+
+        let result = service
+            .send_request(request_from("example.sender.alice", &connection_generator))
+            .wait();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_disallowed_source() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.allowed_sources(vec![Address::from_str("example.sender").unwrap()]);
+
+        let reject = service
+            .send_request(request_from("example.other", &connection_generator))
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+        assert_eq!(
+            reject.triggered_by(),
+            Some(Address::from_str("example.destination").unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_sibling_of_allowed_source() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.allowed_sources(vec![Address::from_str("example.sender").unwrap()]);
+
+        let reject = service
+            .send_request(request_from("example.senderx", &connection_generator))
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+        assert!(service
+            .send_request(request_from("example.sender", &connection_generator))
+            .wait()
+            .is_ok());
+    }
+
+    fn request_with_frames(
+        num_frames: usize,
+        connection_token: u8,
+        connection_generator: &ConnectionGenerator,
+    ) -> OutgoingRequest<TestAccount> {
+        let client_address = Address::from_str("example.destination").unwrap();
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_token(
+                &client_address,
+                &[connection_token; CONNECTION_TOKEN_LENGTH],
+            );
+        let frames: Vec<Frame> = (0..num_frames)
+            .map(|i| {
+                Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: i as u64 + 1,
+                    shares: 1,
+                })
+            })
+            .collect();
+        let data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 1,
+            frames: &frames,
+        }
+        .build()
+        .into_encrypted(&shared_secret[..]);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+        let mut request = request_from("example.sender", connection_generator);
+        request.prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+        request
+    }
+
+    #[test]
+    fn rejects_packets_over_default_frame_limit() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+
+        let reject = service
+            .send_request(request_with_frames(
+                DEFAULT_MAX_FRAMES_PER_PACKET + 1,
+                1,
+                &connection_generator,
+            ))
+            .wait()
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+        assert!(service
+            .send_request(request_with_frames(
+                DEFAULT_MAX_FRAMES_PER_PACKET,
+                1,
+                &connection_generator
+            ))
+            .wait()
+            .is_ok());
+    }
+
+    #[test]
+    fn applies_configured_frame_limit_to_every_packet() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.max_frames_per_packet(2);
+
+        // Flood one connection with packets over the limit
+        for _ in 0..100 {
+            let reject = service
+                .send_request(request_with_frames(3, 1, &connection_generator))
+                .wait()
+                .unwrap_err();
+            assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+        }
+        // Packets within the limit are still handled, on that connection and others
+        assert!(service
+            .send_request(request_with_frames(2, 1, &connection_generator))
+            .wait()
+            .is_ok());
+        assert!(service
+            .send_request(request_with_frames(2, 2, &connection_generator))
+            .wait()
+            .is_ok());
+    }
+
+    #[test]
+    fn checks_frame_limit_before_parsing_frames() {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> BoxedIlpFuture {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.max_frames_per_packet(2);
+
+        // A packet that claims to have more frames than the limit, followed by bytes that are not valid frames.
+        // If the frames were parsed first, it would be rejected as an invalid packet instead
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_token(
+                &Address::from_str("example.destination").unwrap(),
+                &[1; CONNECTION_TOKEN_LENGTH],
+            );
+        let mut plaintext = BytesMut::new();
+        plaintext.extend_from_slice(&[1, IlpPacketType::Prepare as u8]);
+        // Sequence, prepare amount and number of frames, each as a one byte var uint
+        plaintext.extend_from_slice(&[1, 1, 1, 1, 1, 3]);
+        plaintext.extend_from_slice(&[0xff; 16]);
+        let data = encrypt(&shared_secret[..], plaintext);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+        let mut request = request_from("example.sender", &connection_generator);
+        request.prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+
+        let reject = service.send_request(request).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+    }
+
     #[test]
     fn passes_on_packets_not_for_it() {
         let client_address = Address::from_str("example.destination").unwrap();
@@ -594,6 +1199,7 @@ mod stream_receiver_service {
                     asset_scale: 9,
                 },
                 prepare,
+                correlation_id: None,
             })
             .wait();
         assert!(result.is_err());
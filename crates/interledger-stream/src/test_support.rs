@@ -0,0 +1,184 @@
+//! Helpers for testing code that sends STREAM payments, without a connector or network.
+//!
+//! Available in this crate's tests and, for other crates, with the `test_support` feature.
+
+use super::{send_money_with_target, DeliveryTarget, Error, StreamReceiverService};
+use bytes::Bytes;
+use futures::{future::err, Future};
+use interledger_ildcp::{IldcpAccount, IldcpService};
+use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_service::*;
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// An account of the sender or the receiver of a `StreamPair`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestAccount {
+    pub id: u64,
+    pub ilp_address: Address,
+    pub asset_code: String,
+    pub asset_scale: u8,
+}
+
+impl Account for TestAccount {
+    type AccountId = u64;
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl IldcpAccount for TestAccount {
+    fn asset_code(&self) -> &str {
+        self.asset_code.as_str()
+    }
+
+    fn asset_scale(&self) -> u8 {
+        self.asset_scale
+    }
+
+    fn client_address(&self) -> &Address {
+        &self.ilp_address
+    }
+}
+
+/// Rejects the packets that are not for the STREAM receiver
+#[derive(Clone)]
+pub struct Unreachable;
+
+impl OutgoingService<TestAccount> for Unreachable {
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<TestAccount>) -> Self::Future {
+        Box::new(err(RejectBuilder {
+            code: ErrorCode::F02_UNREACHABLE,
+            message: format!("No route to: {}", request.prepare.destination()).as_bytes(),
+            triggered_by: None,
+            data: &[],
+        }
+        .build()))
+    }
+}
+
+/// Passes every packet from the sender directly to the STREAM receiver, in place of a connector,
+/// and adds up the amounts of the packets the receiver fulfills
+#[derive(Clone)]
+pub struct LoopbackService {
+    receiver: StreamReceiverService<Unreachable, TestAccount>,
+    receiver_account: TestAccount,
+    received: Arc<AtomicU64>,
+}
+
+impl IncomingService<TestAccount> for LoopbackService {
+    type Future = BoxedIlpFuture;
+
+    fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> Self::Future {
+        let amount = request.prepare.amount();
+        let received = self.received.clone();
+        Box::new(
+            self.receiver
+                .send_request(request.into_outgoing(self.receiver_account.clone()))
+                .map(move |fulfill| {
+                    received.fetch_add(amount, Ordering::SeqCst);
+                    fulfill
+                }),
+        )
+    }
+}
+
+/// A STREAM sender and receiver connected in memory. See `stream_pair`.
+#[derive(Clone)]
+pub struct StreamPair {
+    /// The service the sender sends packets through. It answers ILDCP requests for the sender
+    pub service: IldcpService<LoopbackService, TestAccount>,
+    pub sender: TestAccount,
+    pub receiver: TestAccount,
+    /// The STREAM destination address and shared secret for sending to the receiver
+    pub destination_account: Address,
+    pub shared_secret: [u8; 32],
+    received: Arc<AtomicU64>,
+}
+
+impl StreamPair {
+    /// Send a payment from the sender to the receiver, resolving to the amount delivered
+    pub fn send_money(&self, source_amount: u64) -> impl Future<Item = u64, Error = Error> {
+        self.send_money_with_target(DeliveryTarget::Send(source_amount))
+    }
+
+    /// Send a payment from the sender to the receiver with the given target, resolving to the amount delivered
+    pub fn send_money_with_target(
+        &self,
+        target: DeliveryTarget,
+    ) -> impl Future<Item = u64, Error = Error> {
+        send_money_with_target(
+            self.service.clone(),
+            &self.sender,
+            self.destination_account.clone(),
+            &self.shared_secret[..],
+            target,
+        )
+        .map(|(delivered, _service)| delivered)
+    }
+
+    /// The total amount of the packets the receiver has fulfilled
+    pub fn received_amount(&self) -> u64 {
+        self.received.load(Ordering::SeqCst)
+    }
+}
+
+/// Create a STREAM sender (`example.sender`) and receiver (`example.receiver`), both with
+/// the asset `XYZ` and scale 9, connected directly to one another.
+pub fn stream_pair() -> StreamPair {
+    let sender = TestAccount {
+        id: 0,
+        ilp_address: Address::from_str("example.sender").unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+    };
+    let receiver_account = TestAccount {
+        id: 1,
+        ilp_address: Address::from_str("example.receiver").unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 9,
+    };
+    let server_secret = Bytes::from(&[0; 32][..]);
+    let (destination_account, shared_secret) =
+        super::ConnectionGenerator::new(server_secret.clone())
+            .generate_address_and_secret(&receiver_account.ilp_address);
+    let received = Arc::new(AtomicU64::new(0));
+    let service = IldcpService::new(LoopbackService {
+        receiver: StreamReceiverService::new(server_secret, Unreachable),
+        receiver_account: receiver_account.clone(),
+        received: received.clone(),
+    });
+    StreamPair {
+        service,
+        sender,
+        receiver: receiver_account,
+        destination_account,
+        shared_secret,
+        received,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn sends_payment_over_pair() {
+        let pair = stream_pair();
+        let delivered = Runtime::new()
+            .unwrap()
+            .block_on(pair.send_money(1000))
+            .unwrap();
+        assert_eq!(delivered, 1000);
+        assert_eq!(pair.received_amount(), 1000);
+    }
+}
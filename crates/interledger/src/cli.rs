@@ -1,6 +1,9 @@
 use base64;
 use bytes::Bytes;
-use futures::{future::ok, Future};
+use futures::{
+    future::{err, ok, Either},
+    Future,
+};
 use hyper::{
     header::{HeaderValue, ACCEPT},
     service::{service_fn, Service},
@@ -8,18 +11,17 @@ use hyper::{
 };
 use interledger_btp::{connect_client, create_open_signup_server, parse_btp_url};
 use interledger_http::{HttpClientService, HttpServerService};
-use interledger_ildcp::{get_ildcp_info, IldcpAccount, IldcpResponse, IldcpService};
-use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_ildcp::{get_ildcp_info, IldcpResponse, IldcpService};
+use interledger_packet::Address;
 use interledger_router::Router;
-use interledger_service::{incoming_service_fn, outgoing_service_fn, OutgoingRequest};
-use interledger_service_util::ValidatorService;
-use interledger_spsp::{pay, SpspResponder};
+use interledger_service::IncomingService;
+use interledger_service_util::{RejecterService, ValidatorService};
+use interledger_spsp::{format_amount, pay, SpspResponder};
 use interledger_store_memory::{Account, AccountBuilder, InMemoryStore};
 use interledger_stream::StreamReceiverService;
-use parking_lot::RwLock;
 use ring::rand::{SecureRandom, SystemRandom};
 use std::str::FromStr;
-use std::{convert::TryFrom, net::SocketAddr, str, sync::Arc, u64};
+use std::{net::SocketAddr, str};
 use url::Url;
 
 lazy_static! {
@@ -57,54 +59,25 @@ pub fn send_spsp_payment_btp(
     connect_client(
         vec![account.clone()],
         true,
-        outgoing_service_fn(|request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
-                code: ErrorCode::F02_UNREACHABLE,
-                message: &format!(
-                    "No route found for address: {:?}",
-                    request.from.client_address(),
-                )
-                .as_bytes(),
-                triggered_by: None,
-                data: &[],
-            }
-            .build())
-        }),
+        RejecterService::outgoing(LOCAL_ILP_ADDRESS.clone()),
     )
     .map_err(|err| {
         eprintln!("Error connecting to BTP server: {:?}", err);
         eprintln!("(Hint: is moneyd running?)");
     })
     .and_then(move |btp_service| {
-        let service = btp_service.handle_incoming(incoming_service_fn(|_| {
-            Err(RejectBuilder {
-                code: ErrorCode::F02_UNREACHABLE,
-                message: b"Not expecting incoming prepare packets",
-                triggered_by: None,
-                data: &[],
-            }
-            .build())
-        }));
+        let service =
+            btp_service.handle_incoming(RejecterService::incoming(LOCAL_ILP_ADDRESS.clone()));
         // TODO seems kind of janky to clone the btp_service just to
         // close it later. Is there some better way of making sure it closes?
         let btp_service = service.clone();
         let service = ValidatorService::outgoing(service);
         let store = InMemoryStore::from_accounts(vec![account.clone()]);
         let router = Router::new(store, service);
-        pay(router, account, &receiver, amount)
-            .map_err(|err| {
-                eprintln!("Error sending SPSP payment: {:?}", err);
-            })
-            .and_then(move |delivered| {
-                if !quiet {
-                    println!(
-                        "Sent: {}, delivered: {} (in the receiver's units)",
-                        amount, delivered
-                    );
-                }
-                btp_service.close();
-                Ok(())
-            })
+        pay_and_print(router, account, receiver, amount, quiet).and_then(move |_| {
+            btp_service.close();
+            Ok(())
+        })
     })
 }
 
@@ -117,49 +90,138 @@ pub fn send_spsp_payment_http(
 ) -> impl Future<Item = (), Error = ()> {
     let receiver = receiver.to_string();
     let url = Url::parse(http_server).expect("Cannot parse HTTP URL");
+    // The token is sent as a Bearer token, so it is removed from the endpoint URL
+    // (otherwise the HTTP client would send it using Basic auth instead)
+    let mut endpoint = url.clone();
+    endpoint.set_username("").unwrap();
+    endpoint.set_password(None).unwrap();
     let account = if let Some(token) = url.password() {
         AccountBuilder::new(LOCAL_ILP_ADDRESS.clone())
             .additional_routes(&[&b""[..]])
-            .http_endpoint(Url::parse(http_server).unwrap())
+            .http_endpoint(endpoint)
             .http_outgoing_token(token.to_string())
             .build()
     } else {
         AccountBuilder::new(LOCAL_ILP_ADDRESS.clone())
             .additional_routes(&[&b""[..]])
-            .http_endpoint(Url::parse(http_server).unwrap())
+            .http_endpoint(endpoint)
             .build()
     };
     let store = InMemoryStore::from_accounts(vec![account.clone()]);
     let service = HttpClientService::new(
         store.clone(),
-        outgoing_service_fn(|request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
-                code: ErrorCode::F02_UNREACHABLE,
-                message: &format!("No outgoing route for: {:?}", request.from.client_address())
-                    .as_bytes(),
-                triggered_by: None,
-                data: &[],
-            }
-            .build())
-        }),
+        RejecterService::outgoing(LOCAL_ILP_ADDRESS.clone()),
     );
     let service = ValidatorService::outgoing(service);
     let service = Router::new(store, service);
-    pay(service, account, &receiver, amount)
-        .map_err(|err| {
-            eprintln!("Error sending SPSP payment: {:?}", err);
-        })
-        .and_then(move |delivered| {
-            if !quiet {
-                println!(
-                    "Sent: {}, delivered: {} (in the receiver's units)",
-                    amount, delivered
-                );
+    pay_and_print(service, account, receiver, amount, quiet)
+}
+
+/// Send the payment and print the amount sent in our asset (as reported by ILDCP).
+/// If the upstream node does not answer the ILDCP request, the raw amount is printed instead.
+fn pay_and_print<S>(
+    service: S,
+    account: Account,
+    receiver: String,
+    amount: u64,
+    quiet: bool,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: IncomingService<Account> + Clone,
+{
+    get_ildcp_info(&mut service.clone(), account.clone())
+        .then(|result| {
+            if result.is_err() {
+                warn!("Error getting asset details from the upstream node, amounts will not be scaled");
             }
-            Ok(())
+            Ok(result.ok())
+        })
+        .and_then(move |info| {
+            pay(service, account, &receiver, amount)
+                .map_err(|err| {
+                    eprintln!("Error sending SPSP payment: {:?}", err);
+                })
+                .and_then(move |delivered| {
+                    if !quiet {
+                        let sent = match info {
+                            Some(info) => format_amount(
+                                amount,
+                                info.asset_scale(),
+                                str::from_utf8(info.asset_code()).unwrap_or(""),
+                            ),
+                            None => amount.to_string(),
+                        };
+                        println!(
+                            "Sent: {}, delivered: {} (in the receiver's units)",
+                            sent, delivered
+                        );
+                    }
+                    Ok(())
+                })
         })
 }
 
+#[doc(hidden)]
+#[derive(Debug, PartialEq)]
+pub enum SpspTransport {
+    Btp,
+    Http,
+}
+
+/// Determine whether to connect to the given server using BTP or ILP-over-HTTP
+/// based on its URL scheme. Payment pointers are treated as HTTPS URLs.
+#[doc(hidden)]
+pub fn detect_spsp_transport(server: &str) -> Option<SpspTransport> {
+    let server = server.to_lowercase();
+    if server.starts_with("btp+ws://")
+        || server.starts_with("btp+wss://")
+        || server.starts_with("ws://")
+        || server.starts_with("wss://")
+    {
+        Some(SpspTransport::Btp)
+    } else if server.starts_with("http://")
+        || server.starts_with("https://")
+        || server.starts_with('$')
+    {
+        Some(SpspTransport::Http)
+    } else {
+        None
+    }
+}
+
+/// Send an SPSP payment through the given server using either BTP or
+/// ILP-over-HTTP, depending on the server's URL scheme.
+#[doc(hidden)]
+pub fn send_spsp_payment(
+    server: &str,
+    receiver: &str,
+    amount: u64,
+    quiet: bool,
+) -> impl Future<Item = (), Error = ()> {
+    match detect_spsp_transport(server) {
+        Some(SpspTransport::Btp) => Either::A(Either::A(send_spsp_payment_btp(
+            server, receiver, amount, quiet,
+        ))),
+        Some(SpspTransport::Http) => {
+            let http_server = if server.starts_with('$') {
+                format!("https://{}", server.trim_start_matches('$'))
+            } else {
+                server.to_string()
+            };
+            Either::A(Either::B(send_spsp_payment_http(
+                &http_server,
+                receiver,
+                amount,
+                quiet,
+            )))
+        }
+        None => {
+            eprintln!("Unsupported server URL scheme: {}", server);
+            Either::B(err(()))
+        }
+    }
+}
+
 // TODO allow server secret to be specified
 #[doc(hidden)]
 pub fn run_spsp_server_btp(
@@ -168,7 +230,6 @@ pub fn run_spsp_server_btp(
     quiet: bool,
 ) -> impl Future<Item = (), Error = ()> {
     debug!("Starting SPSP server");
-    let ilp_address = Arc::new(RwLock::new(Bytes::new()));
     let btp_server = parse_btp_url(btp_server).unwrap();
     let incoming_account: Account = AccountBuilder::new(LOCAL_ILP_ADDRESS.clone())
         .additional_routes(&[b"peer."])
@@ -178,21 +239,10 @@ pub fn run_spsp_server_btp(
     let server_secret = Bytes::from(&random_secret()[..]);
     let store = InMemoryStore::from_accounts(vec![incoming_account.clone()]);
 
-    // Can we get better syntax than .read()[..] here? Doesn't seem too intuitive.
-    let ilp_addr = Address::try_from(&ilp_address.read()[..]).ok();
     connect_client(
         vec![incoming_account.clone()],
         true,
-        outgoing_service_fn(move |request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
-                code: ErrorCode::F02_UNREACHABLE,
-                message: &format!("No outgoing route for: {:?}", request.from.client_address(),)
-                    .as_bytes(),
-                triggered_by: ilp_addr.as_ref(),
-                data: &[],
-            }
-            .build())
-        }),
+        RejecterService::outgoing(LOCAL_ILP_ADDRESS.clone()),
     )
     .map_err(|err| {
         eprintln!("Error connecting to BTP server: {:?}", err);
@@ -209,8 +259,6 @@ pub fn run_spsp_server_btp(
         get_ildcp_info(&mut incoming_service, incoming_account.clone()).and_then(move |info| {
             debug!("SPSP server got ILDCP info: {:?}", info);
             let client_address = info.client_address();
-            // Update the ILP Address with the ildcp info request's address
-            *ilp_address.write() = client_address.to_bytes();
 
             let receiver_account = AccountBuilder::new(client_address.clone())
                 .asset_code(String::from_utf8(info.asset_code().to_vec()).unwrap_or_default())
@@ -235,6 +283,79 @@ pub fn run_spsp_server_btp(
     })
 }
 
+/// Run an SPSP server that accepts payments both over BTP, through the connection to the
+/// given BTP server, and over HTTP. Packets from both transports are handled by the same
+/// STREAM receiver, so the SPSP details it hands out can be paid over either one.
+#[doc(hidden)]
+pub fn run_spsp_server_btp_and_http(
+    btp_server: &str,
+    address: SocketAddr,
+    auth_token: String,
+    quiet: bool,
+) -> impl Future<Item = (), Error = ()> {
+    debug!("Starting SPSP server for BTP and ILP-over-HTTP");
+    let btp_server = parse_btp_url(btp_server).unwrap();
+    let btp_account: Account = AccountBuilder::new(LOCAL_ILP_ADDRESS.clone())
+        .additional_routes(&[b"peer."])
+        .btp_outgoing_token(btp_server.password().unwrap_or_default().to_string())
+        .btp_uri(btp_server)
+        .build();
+    let server_secret = Bytes::from(&random_secret()[..]);
+    let store = InMemoryStore::from_accounts(vec![btp_account.clone()]);
+
+    connect_client(
+        vec![btp_account.clone()],
+        true,
+        RejecterService::outgoing(LOCAL_ILP_ADDRESS.clone()),
+    )
+    .map_err(|err| {
+        eprintln!("Error connecting to BTP server: {:?}", err);
+        eprintln!("(Hint: is moneyd running?)");
+    })
+    .and_then(move |btp_service| {
+        let outgoing_service = ValidatorService::outgoing(btp_service.clone());
+        let outgoing_service = StreamReceiverService::new(server_secret.clone(), outgoing_service);
+        let router = Router::new(store.clone(), outgoing_service);
+        let mut btp_incoming_service = ValidatorService::incoming(router.clone());
+        btp_service.handle_incoming(btp_incoming_service.clone());
+
+        get_ildcp_info(&mut btp_incoming_service, btp_account).and_then(move |info| {
+            debug!("SPSP server got ILDCP info: {:?}", info);
+            let client_address = info.client_address();
+            let asset_code = String::from_utf8(info.asset_code().to_vec()).unwrap_or_default();
+
+            let receiver_account = AccountBuilder::new(client_address.clone())
+                .id(1)
+                .asset_code(asset_code.clone())
+                .asset_scale(info.asset_scale())
+                .additional_routes(&[&b""[..]])
+                .build();
+            store.add_account(receiver_account);
+            // HTTP senders get the same asset details as this server over ILDCP
+            let http_account = AccountBuilder::new(LOCAL_ILP_ADDRESS.clone())
+                .id(2)
+                .asset_code(asset_code)
+                .asset_scale(info.asset_scale())
+                .http_incoming_token(auth_token)
+                .build();
+            store.add_account(http_account);
+
+            let http_incoming_service = ValidatorService::incoming(IldcpService::new(router));
+            let http_service = HttpServerService::new(http_incoming_service, store);
+            let spsp_responder = SpspResponder::new(client_address.clone(), server_secret);
+
+            if !quiet {
+                println!("Listening on: {}", address);
+            }
+            debug!(
+                "SPSP server listening on {} with ILP address {}",
+                &address, client_address,
+            );
+            serve_spsp_and_ilp_over_http(address, spsp_responder, http_service)
+        })
+    })
+}
+
 #[doc(hidden)]
 pub fn run_spsp_server_http(
     ildcp_info: IldcpResponse,
@@ -253,22 +374,8 @@ pub fn run_spsp_server_http(
     let server_secret = Bytes::from(&random_secret()[..]);
     let store = InMemoryStore::from_accounts(vec![account.clone()]);
     let spsp_responder = SpspResponder::new(ilp_address.clone(), server_secret.clone());
-    let outgoing_handler = StreamReceiverService::new(
-        server_secret,
-        outgoing_service_fn(move |request: OutgoingRequest<Account>| {
-            Err(RejectBuilder {
-                code: ErrorCode::F02_UNREACHABLE,
-                message: &format!(
-                    "No handler configured for destination: {}",
-                    request.prepare.destination(),
-                )
-                .as_bytes(),
-                triggered_by: Some(&ilp_address),
-                data: &[],
-            }
-            .build())
-        }),
-    );
+    let outgoing_handler =
+        StreamReceiverService::new(server_secret, RejecterService::outgoing(ilp_address));
     let incoming_handler = Router::new(store.clone(), outgoing_handler);
     let incoming_handler = IldcpService::new(incoming_handler);
     let incoming_handler = ValidatorService::incoming(incoming_handler);
@@ -277,6 +384,18 @@ pub fn run_spsp_server_http(
     if !quiet {
         println!("Listening on: {}", address);
     }
+    serve_spsp_and_ilp_over_http(address, spsp_responder, http_service)
+}
+
+/// Serve SPSP queries and ILP packets sent over HTTP (on `/ilp`) from one HTTP server
+fn serve_spsp_and_ilp_over_http<S>(
+    address: SocketAddr,
+    spsp_responder: SpspResponder,
+    http_service: HttpServerService<S, InMemoryStore>,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: IncomingService<Account> + Clone + Send + Sync + 'static,
+{
     Server::bind(&address)
         .serve(move || {
             let mut spsp_responder = spsp_responder.clone();
@@ -317,15 +436,7 @@ pub fn run_moneyd_local(
     let store = InMemoryStore::default();
     // TODO this needs a reference to the BtpService so it can send outgoing packets
     println!("Listening on: {}", address);
-    let rejecter = outgoing_service_fn(move |_| {
-        Err(RejectBuilder {
-            code: ErrorCode::F02_UNREACHABLE,
-            message: b"No open connection for account",
-            triggered_by: Some(&ilp_address),
-            data: &[],
-        }
-        .build())
-    });
+    let rejecter = RejecterService::outgoing(ilp_address);
     create_open_signup_server(address, ildcp_info, store.clone(), rejecter).and_then(
         move |btp_service| {
             let service = Router::new(store, btp_service.clone());
@@ -336,3 +447,50 @@ pub fn run_moneyd_local(
         },
     )
 }
+
+#[cfg(test)]
+mod spsp_transport {
+    use super::*;
+
+    #[test]
+    fn detects_btp() {
+        assert_eq!(
+            detect_spsp_transport("btp+ws://:token@localhost:7768"),
+            Some(SpspTransport::Btp)
+        );
+        assert_eq!(
+            detect_spsp_transport("btp+wss://example.com/btp"),
+            Some(SpspTransport::Btp)
+        );
+        assert_eq!(
+            detect_spsp_transport("ws://localhost:7768"),
+            Some(SpspTransport::Btp)
+        );
+        assert_eq!(
+            detect_spsp_transport("WSS://example.com"),
+            Some(SpspTransport::Btp)
+        );
+    }
+
+    #[test]
+    fn detects_http() {
+        assert_eq!(
+            detect_spsp_transport("http://localhost:7770/ilp"),
+            Some(SpspTransport::Http)
+        );
+        assert_eq!(
+            detect_spsp_transport("https://:token@example.com/ilp"),
+            Some(SpspTransport::Http)
+        );
+        assert_eq!(
+            detect_spsp_transport("$example.com/ilp"),
+            Some(SpspTransport::Http)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_schemes() {
+        assert_eq!(detect_spsp_transport("ftp://example.com"), None);
+        assert_eq!(detect_spsp_transport("example.com"), None);
+    }
+}
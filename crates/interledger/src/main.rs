@@ -5,11 +5,13 @@ extern crate clap;
 use base64;
 use clap::{App, Arg, ArgGroup, SubCommand};
 use config;
+use futures::{Future, Stream};
 use hex;
 use interledger::{cli::*, node::*};
-use interledger_ildcp::IldcpResponseBuilder;
+use interledger_ildcp::{AssetScaleRange, IldcpResponseBuilder};
 use interledger_packet::Address;
-use std::str::FromStr;
+use log::{error, info};
+use std::{process, str::FromStr};
 use tokio;
 use url::Url;
 
@@ -44,6 +46,9 @@ pub fn main() {
                             Arg::with_name("ilp_over_http")
                                 .long("use_ilp_over_http")
                                 .help("Accept ILP packets sent over HTTP instead of connecting to a BTP server"),
+                            Arg::with_name("btp_and_http")
+                                .long("use_btp_and_ilp_over_http")
+                                .help("Accept ILP packets sent over HTTP in addition to those from the BTP server (Requires incoming_auth_token)"),
                             Arg::with_name("ilp_address")
                                 .long("ilp_address")
                                 .takes_value(true)
@@ -60,6 +65,10 @@ pub fn main() {
                     SubCommand::with_name("pay")
                         .about("Send an SPSP payment")
                         .args(&[
+                            Arg::with_name("server")
+                                .long("server")
+                                .takes_value(true)
+                                .help("BTP or HTTP URL of the node to pay from (the protocol is detected from the URL scheme)"),
                             Arg::with_name("btp_server")
                                 .long("btp_server")
                                 .takes_value(true)
@@ -189,6 +198,18 @@ pub fn main() {
                                 .long("amount_per_minute_limit")
                                 .help("Total amount of value this account can send per minute. Defaults to no limit")
                                 .takes_value(true),
+                            Arg::with_name("max_prepare_data_len")
+                                .long("max_prepare_data_len")
+                                .help("Largest Prepare data, in bytes, this account can send. Defaults to no limit")
+                                .takes_value(true),
+                            Arg::with_name("min_asset_scale")
+                                .long("min_asset_scale")
+                                .help("Smallest asset scale the node accepts for accounts. Should match the node's config. Defaults to 0")
+                                .takes_value(true),
+                            Arg::with_name("max_asset_scale")
+                                .long("max_asset_scale")
+                                .help("Largest asset scale the node accepts for accounts. Should match the node's config. Defaults to 18")
+                                .takes_value(true),
                         ]))),
         ]);
 
@@ -197,7 +218,18 @@ pub fn main() {
             ("server", Some(matches)) => {
                 let port = value_t!(matches, "port", u16).expect("Invalid port");
                 let quiet = matches.is_present("quiet");
-                if matches.is_present("ilp_over_http") {
+                if matches.is_present("btp_and_http") {
+                    let btp_server = value_t!(matches, "btp_server", String)
+                        .expect("BTP Server URL is required");
+                    let auth_token = value_t!(matches, "incoming_auth_token", String)
+                        .expect("incoming_auth_token is required");
+                    tokio::run(run_spsp_server_btp_and_http(
+                        &btp_server,
+                        ([0, 0, 0, 0], port).into(),
+                        auth_token,
+                        quiet,
+                    ));
+                } else if matches.is_present("ilp_over_http") {
                     let client_address =
                         value_t!(matches, "ilp_address", String).expect("ilp_address is required");
                     let client_address = Address::from_str(&client_address).unwrap();
@@ -231,7 +263,9 @@ pub fn main() {
                 let quiet = matches.is_present("quiet");
 
                 // Check for http_server first because btp_server has the default value of connecting to moneyd
-                if let Ok(http_server) = value_t!(matches, "http_server", String) {
+                if let Ok(server) = value_t!(matches, "server", String) {
+                    tokio::run(send_spsp_payment(&server, &receiver, amount, quiet));
+                } else if let Ok(http_server) = value_t!(matches, "http_server", String) {
                     tokio::run(send_spsp_payment_http(
                         &http_server,
                         &receiver,
@@ -338,31 +372,73 @@ pub fn main() {
                         .ok(),
                         amount_per_minute_limit: value_t!(matches, "amount_per_minute_limit", u64)
                             .ok(),
+                        max_prepare_data_len: value_t!(matches, "max_prepare_data_len", usize).ok(),
                         settlement_engine_url: None,
                         settlement_engine_asset_scale: None,
+                        settlement_engine_incoming_asset_scale: None,
+                        settlement_engine_outgoing_asset_scale: None,
                         settlement_engine_ilp_address: None,
+                        settlement_engine_auth_token: None,
+                        settlement_engine_peer_protocol_fulfillment: None,
+                        settlement_engine_message_timeout: None,
+                        enabled: None,
                     };
-                    tokio::run(insert_account_redis(redis_uri, &server_secret, account));
+                    let default_range = AssetScaleRange::default();
+                    let asset_scale_range = AssetScaleRange::new(
+                        value_t!(matches, "min_asset_scale", u8).unwrap_or(default_range.min()),
+                        value_t!(matches, "max_asset_scale", u8).unwrap_or(default_range.max()),
+                    )
+                    .expect("min_asset_scale must not be larger than max_asset_scale");
+                    tokio::run(insert_account_redis(
+                        redis_uri,
+                        &server_secret,
+                        asset_scale_range,
+                        account,
+                    ));
                 }
                 _ => app.print_help().unwrap(),
             },
             _ => {
-                let mut node_config = config::Config::new();
-                if let Some(config_path) = matches.value_of("config") {
-                    node_config
-                        .merge(config::File::with_name(config_path))
-                        .unwrap();
-                }
-                node_config
-                    .merge(config::Environment::with_prefix("ILP"))
-                    .unwrap();
-
-                let node: InterledgerNode = node_config
-                    .try_into()
+                let config_path = matches.value_of("config").map(String::from);
+                let node = load_node_config(config_path.as_deref())
                     .expect("Must provide config file name or config environment variables");
-                node.run();
+                let mut runtime =
+                    tokio::runtime::Runtime::new().expect("Unable to start the Tokio runtime");
+                let stopped = runtime.block_on(node.serve_until(
+                    Some(Box::new(move || load_node_config(config_path.as_deref()))),
+                    ctrl_c(),
+                ));
+                if stopped.is_err() {
+                    process::exit(1);
+                }
+                info!("Shutting down");
+                process::exit(0);
             }
         },
         _ => app.print_help().unwrap(),
     }
 }
+
+/// Resolves when the process is asked to stop with Ctrl-C
+fn ctrl_c() -> impl Future<Item = (), Error = ()> + Send {
+    tokio_signal::ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|(err, _)| error!("Error listening for Ctrl-C: {:?}", err))
+}
+
+fn load_node_config(config_path: Option<&str>) -> Result<InterledgerNode, String> {
+    let mut node_config = config::Config::new();
+    if let Some(config_path) = config_path {
+        node_config
+            .merge(config::File::with_name(config_path))
+            .map_err(|err| err.to_string())?;
+    }
+    node_config
+        .merge(config::Environment::with_prefix("ILP"))
+        .map_err(|err| err.to_string())?;
+    let node: InterledgerNode = node_config.try_into().map_err(|err| err.to_string())?;
+    node.validate()?;
+    Ok(node)
+}
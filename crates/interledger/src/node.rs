@@ -1,30 +1,41 @@
 use bytes::Bytes;
-use futures::{future::result, Future};
+use futures::{
+    future::{empty, err, join_all, ok, result, Either},
+    Future, Stream,
+};
 use hex::FromHex;
-use interledger_api::{NodeApi, NodeStore};
+use interledger_api::{AppliedConfig, NodeApi, NodeStore};
 use interledger_btp::{connect_client, create_server, BtpStore};
 use interledger_ccp::CcpRouteManagerBuilder;
 use interledger_http::HttpClientService;
-use interledger_ildcp::IldcpService;
+use interledger_ildcp::{AssetScaleRange, IldcpAccount, IldcpService};
 use interledger_packet::Address;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_router::Router;
 use interledger_service::{outgoing_service_fn, Account as AccountTrait, OutgoingRequest};
 use interledger_service_util::{
-    BalanceService, ExchangeRateService, ExpiryShortenerService, MaxPacketAmountService,
-    RateLimitService, ValidatorService,
+    AccountStatusService, BalanceService, ExchangeRateService, ExpiryShortenerService,
+    LearnedMaxPacketAmountService, LearnedMaxPacketAmounts, MaxPacketAmountService,
+    PrepareDataLimitService, RateLimitService, TriggeredByService, ValidatorService,
+};
+use interledger_settlement::{
+    SettlementBatcher, SettlementClient, SettlementMessageService, SettlementRetrier,
+    SettlementWebhook,
 };
-use interledger_settlement::SettlementMessageService;
 use interledger_store_redis::{Account, ConnectionInfo, IntoConnectionInfo, RedisStoreBuilder};
 use interledger_stream::StreamReceiverService;
 use ring::{digest, hmac};
 use serde::{de::Error as DeserializeError, Deserialize, Deserializer};
-use std::{net::SocketAddr, str};
+use std::{collections::HashMap, net::SocketAddr, str, time::Duration};
 use tokio::{self, net::TcpListener};
+#[cfg(unix)]
+use tokio_signal::unix::{Signal, SIGHUP};
 use url::Url;
 
 static REDIS_SECRET_GENERATION_STRING: &str = "ilp_redis_secret";
 static DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
+/// How often to retry delivering settlement messages the local settlement engine could not receive
+const SETTLEMENT_MESSAGE_REPLAY_INTERVAL: Duration = Duration::from_secs(10);
 
 fn default_http_address() -> SocketAddr {
     SocketAddr::from(([127, 0, 0, 1], 7770))
@@ -96,13 +107,152 @@ pub struct InterledgerNode {
     /// Interval, defined in milliseconds, on which the node will broadcast routing
     /// information to other nodes using CCP. Defaults to 30000ms (30 seconds).
     pub route_broadcast_interval: Option<u64>,
+    /// Whether to spread packets round-robin across the next hops of routes that several peers
+    /// announced with the same routing relation and path length. By default, the best route
+    /// in the routing table is always used.
+    #[serde(default)]
+    pub equal_cost_multipath: bool,
+    /// How long, in milliseconds, peer protocol messages such as settlement engine messages
+    /// are valid for. Defaults to 30000ms (30 seconds).
+    pub peer_protocol_expiry: Option<u64>,
+    /// Asset code of the connector itself. If this and `asset_scale` are set, they are served
+    /// to every child account that queries its address and asset details with ILDCP.
+    pub asset_code: Option<String>,
+    /// Asset scale of the connector itself. See `asset_code`.
+    pub asset_scale: Option<u8>,
+    /// Smallest asset scale that accounts and incoming settlements may use. Defaults to 0.
+    pub min_asset_scale: Option<u8>,
+    /// Largest asset scale that accounts and incoming settlements may use. Defaults to 18.
+    pub max_asset_scale: Option<u8>,
+    /// URL to POST to once an incoming settlement has been credited to an account.
+    /// Only used if `settlement_webhook_secret` is also set.
+    pub settlement_webhook_url: Option<String>,
+    /// Secret used to sign the requests sent to `settlement_webhook_url`
+    pub settlement_webhook_secret: Option<String>,
+    /// Whether to learn each peer's maximum packet amount from the `F08 Amount Too Large` rejects
+    /// it sends, and reject larger packets before forwarding them. Disabled by default.
+    #[serde(default)]
+    pub learn_max_packet_amounts: bool,
+    /// If set, the settlements triggered by fulfilled packets are collected for this many
+    /// milliseconds and each account's total is sent to its settlement engine in one request.
+    /// Whatever is still batched is settled when the shutdown future passed to
+    /// `serve_until` resolves.
+    pub settlement_batch_window: Option<u64>,
+    /// Static routes, mapping ILP address prefixes to the ids of the accounts to forward them to.
+    /// These are re-read from the config when the node receives SIGHUP (on Unix).
+    #[serde(default)]
+    pub static_routes: HashMap<String, u64>,
+    /// Accounts to create if no account with the same ILP address exists yet.
+    /// New entries are added when the node starts or receives SIGHUP, and accounts that were
+    /// created from the config and have since been removed from it are deleted, along with their balances.
+    #[serde(default)]
+    pub accounts: Vec<AccountDetails>,
 }
 
+/// Loads the latest version of the node's configuration, for example by re-reading the config file
+pub type ConfigLoader = Box<dyn Fn() -> Result<InterledgerNode, String> + Send>;
+
 impl InterledgerNode {
+    /// The asset code and scale of the connector, if both are configured
+    pub fn connector_asset(&self) -> Option<(&str, u8)> {
+        match (&self.asset_code, self.asset_scale) {
+            (Some(asset_code), Some(asset_scale)) => Some((asset_code.as_str(), asset_scale)),
+            _ => None,
+        }
+    }
+
+    /// The asset scales that accounts and incoming settlements may use
+    pub fn asset_scale_range(&self) -> Result<AssetScaleRange, String> {
+        let default = AssetScaleRange::default();
+        let min = self.min_asset_scale.unwrap_or(default.min());
+        let max = self.max_asset_scale.unwrap_or(default.max());
+        AssetScaleRange::new(min, max).ok_or_else(|| {
+            format!(
+                "min_asset_scale ({}) must not be larger than max_asset_scale ({})",
+                min, max
+            )
+        })
+    }
+
+    /// Check that the asset scale range and the accounts in the config are valid,
+    /// so that a config with accounts that could never be created is rejected when it is loaded
+    pub fn validate(&self) -> Result<(), String> {
+        let asset_scale_range = self.asset_scale_range()?;
+        for account in self.accounts.iter() {
+            account
+                .check_asset_scales(asset_scale_range)
+                .map_err(|err| format!("Invalid account {}: {}", account.ilp_address, err))?;
+        }
+        Ok(())
+    }
+
+    /// The webhook to notify of incoming settlements, if both its URL and secret are configured
+    pub fn settlement_webhook(&self) -> Option<SettlementWebhook> {
+        match (
+            &self.settlement_webhook_url,
+            &self.settlement_webhook_secret,
+        ) {
+            (Some(url), Some(secret)) => match Url::parse(url) {
+                Ok(url) => Some(SettlementWebhook::new(url, secret.as_bytes())),
+                Err(err) => {
+                    error!("Invalid settlement webhook URL {}: {:?}", url, err);
+                    None
+                }
+            },
+            (Some(_), None) => {
+                error!(
+                    "Settlement webhook URL is configured without a secret, so it will not be used"
+                );
+                None
+            }
+            _ => None,
+        }
+    }
+
     /// Returns a future that runs the Interledger Node
     // TODO when a BTP connection is made, insert a outgoing HTTP entry into the Store to tell other
     // connector instances to forward packets for that account to us
     pub fn serve(&self) -> impl Future<Item = (), Error = ()> {
+        self.serve_with_config_loader(None)
+    }
+
+    /// Returns a future that runs the Interledger Node and, if a `ConfigLoader` is given,
+    /// reloads the static routes and accounts whenever the process receives SIGHUP.
+    /// Reloading is only supported on Unix.
+    pub fn serve_with_config_loader(
+        &self,
+        config_loader: Option<ConfigLoader>,
+    ) -> impl Future<Item = (), Error = ()> {
+        self.start(config_loader, empty()).map(|stopped| {
+            tokio::spawn(stopped);
+        })
+    }
+
+    /// Like `serve_with_config_loader` but the returned future only resolves once `shutdown`
+    /// has resolved and any batched settlements have been sent to the settlement engines.
+    /// The node's other tasks keep running, so it is up to the caller to exit the process
+    /// or shut down the runtime afterwards.
+    pub fn serve_until<F>(
+        &self,
+        config_loader: Option<ConfigLoader>,
+        shutdown: F,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.start(config_loader, shutdown).flatten()
+    }
+
+    /// Starts the node's services and resolves to a future that resolves once `shutdown` has
+    /// resolved and the settlement batcher (if there is one) has been flushed
+    fn start<F>(
+        &self,
+        config_loader: Option<ConfigLoader>,
+        shutdown: F,
+    ) -> impl Future<Item = Box<dyn Future<Item = (), Error = ()> + Send>, Error = ()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
         debug!(
             "Starting Interledger node with ILP address: {}",
             str::from_utf8(self.ilp_address.as_ref()).unwrap_or("<not utf8>")
@@ -117,8 +267,32 @@ impl InterledgerNode {
         let default_spsp_account = self.default_spsp_account;
         let redis_addr = self.redis_connection.addr.clone();
         let route_broadcast_interval = self.route_broadcast_interval;
+        let learn_max_packet_amounts = self.learn_max_packet_amounts;
+        let equal_cost_multipath = self.equal_cost_multipath;
+        let peer_protocol_expiry = self.peer_protocol_expiry;
+        let asset_scale_range = match self.asset_scale_range() {
+            Ok(range) => range,
+            Err(message) => {
+                error!("Invalid config: {}", message);
+                return Either::A(err(()));
+            }
+        };
+        let settlement_webhook = self.settlement_webhook();
+        let connector_asset = self
+            .connector_asset()
+            .map(|(asset_code, asset_scale)| (asset_code.to_string(), asset_scale));
+        let initial_config = self.clone();
+        let settlement_batcher = self.settlement_batch_window.map(|ms| {
+            let mut batcher = SettlementBatcher::new(SettlementClient::new());
+            batcher.batch_window(Duration::from_millis(ms));
+            batcher
+        });
 
-        RedisStoreBuilder::new(self.redis_connection.clone(), redis_secret)
+        let mut store_builder = RedisStoreBuilder::new(self.redis_connection.clone(), redis_secret);
+        if let Some(batcher) = settlement_batcher.clone() {
+            store_builder.settlement_batcher(batcher);
+        }
+        Either::B(store_builder
         .connect()
         .map_err(move |err| error!("Error connecting to Redis: {:?} {:?}", redis_addr, err))
         .and_then(move |store| {
@@ -156,6 +330,15 @@ impl InterledgerNode {
                                     let outgoing_service =
                                         ValidatorService::outgoing(outgoing_service);
                                     let outgoing_service = HttpClientService::new(store.clone(), outgoing_service);
+                                    // Attribute the rejects the BTP and HTTP clients generate to this node,
+                                    // while passing the ones from the next node back unchanged
+                                    let outgoing_service = TriggeredByService::new(ilp_address.clone(), outgoing_service);
+                                    let mut outgoing_service = LearnedMaxPacketAmountService::new(
+                                        ilp_address.clone(),
+                                        LearnedMaxPacketAmounts::new(),
+                                        outgoing_service,
+                                    );
+                                    outgoing_service.enabled(learn_max_packet_amounts);
 
                                     // Note: the expiry shortener must come after the Validator so that the expiry duration
                                     // is shortened before we check whether there is enough time left
@@ -170,6 +353,11 @@ impl InterledgerNode {
                                         store.clone(),
                                         outgoing_service,
                                     );
+                                    // Reject packets to disabled accounts before their balance is touched
+                                    let outgoing_service = AccountStatusService::outgoing(
+                                        ilp_address.clone(),
+                                        outgoing_service,
+                                    );
                                     let outgoing_service = ExchangeRateService::new(
                                         ilp_address.clone(),
                                         store.clone(),
@@ -177,8 +365,9 @@ impl InterledgerNode {
                                     );
 
                                     // Set up the Router and Routing Manager
-                                    let incoming_service =
+                                    let mut incoming_service =
                                         Router::new(store.clone(), outgoing_service.clone());
+                                    incoming_service.equal_cost_multipath(equal_cost_multipath);
                                     let mut ccp_builder = CcpRouteManagerBuilder::new(
                                         ilp_address.clone(),
                                         store.clone(),
@@ -192,9 +381,17 @@ impl InterledgerNode {
                                     let incoming_service = ccp_builder.to_service();
 
                                     let incoming_service = SettlementMessageService::new(ilp_address.clone(), incoming_service);
-                                    let incoming_service = IldcpService::new(incoming_service);
+                                    tokio::spawn(incoming_service.replay_periodically(SETTLEMENT_MESSAGE_REPLAY_INTERVAL));
+                                    let mut incoming_service = IldcpService::new(incoming_service);
+                                    if let Some((asset_code, asset_scale)) = connector_asset.clone() {
+                                        incoming_service.connector_asset(asset_code, asset_scale);
+                                    }
                                     let incoming_service =
                                         MaxPacketAmountService::new(incoming_service);
+                                    let incoming_service = PrepareDataLimitService::new(
+                                        ilp_address.clone(),
+                                        incoming_service,
+                                    );
                                     let incoming_service =
                                         ValidatorService::incoming(incoming_service);
                                     let incoming_service = RateLimitService::new(
@@ -202,6 +399,10 @@ impl InterledgerNode {
                                         store.clone(),
                                         incoming_service,
                                     );
+                                    let incoming_service = AccountStatusService::incoming(
+                                        ilp_address.clone(),
+                                        incoming_service,
+                                    );
 
                                     // Handle incoming packets sent via BTP
                                     btp_server_service.handle_incoming(incoming_service.clone());
@@ -219,17 +420,51 @@ impl InterledgerNode {
                                     if let Some(account_id) = default_spsp_account {
                                         api.default_spsp_account(format!("{}", account_id));
                                     }
+                                    if let Some(ms) = peer_protocol_expiry {
+                                        api.peer_protocol_expiry(Duration::from_millis(ms));
+                                    }
+                                    api.ilp_address(ilp_address.clone());
+                                    api.asset_scale_range(asset_scale_range);
+                                    if let Some(webhook) = settlement_webhook {
+                                        api.settlement_webhook(webhook);
+                                    }
                                     let listener = TcpListener::bind(&http_address)
                                         .expect("Unable to bind to HTTP address");
                                     info!("Interledger node listening on: {}", http_address);
                                     tokio::spawn(api.serve(listener.incoming()));
-                                    Ok(())
+
+                                    // Keep retrying the settlements that could not be sent to the settlement engine
+                                    tokio::spawn(
+                                        SettlementRetrier::new(store.clone(), SettlementClient::new()).run(),
+                                    );
+
+                                    let stopped: Box<dyn Future<Item = (), Error = ()> + Send> =
+                                        match settlement_batcher {
+                                            Some(batcher) => Box::new(
+                                                batcher.run_until(store.clone(), shutdown).map(|_| {
+                                                    info!("Settled batched amounts");
+                                                }),
+                                            ),
+                                            None => Box::new(shutdown),
+                                        };
+
+                                    tokio::spawn(apply_config_changes(
+                                        store.clone(),
+                                        &initial_config,
+                                    ));
+                                    if let Some(config_loader) = config_loader {
+                                        tokio::spawn(reload_on_sighup(
+                                            store.clone(),
+                                            config_loader,
+                                        ));
+                                    }
+                                    Ok(stopped)
                                 },
                             )
                         },
                     )
                 })
-        })
+        }))
     }
 
     /// Run the node on the default Tokio runtime
@@ -237,8 +472,19 @@ impl InterledgerNode {
         tokio::run(self.serve());
     }
 
+    /// Run the node on the default Tokio runtime, reloading its config on SIGHUP
+    pub fn run_with_config_loader(&self, config_loader: ConfigLoader) {
+        tokio::run(self.serve_with_config_loader(Some(config_loader)));
+    }
+
     pub fn insert_account(&self, account: AccountDetails) -> impl Future<Item = (), Error = ()> {
-        insert_account_redis(self.redis_connection.clone(), &self.secret_seed, account)
+        let redis_connection = self.redis_connection.clone();
+        let secret_seed = self.secret_seed;
+        result(self.asset_scale_range())
+            .map_err(|message| error!("Invalid config: {}", message))
+            .and_then(move |asset_scale_range| {
+                insert_account_redis(redis_connection, &secret_seed, asset_scale_range, account)
+            })
     }
 }
 
@@ -248,25 +494,191 @@ pub use interledger_api::AccountDetails;
 pub fn insert_account_redis<R>(
     redis_uri: R,
     secret_seed: &[u8; 32],
+    asset_scale_range: AssetScaleRange,
     account: AccountDetails,
 ) -> impl Future<Item = (), Error = ()>
 where
     R: IntoConnectionInfo,
 {
     let redis_secret = generate_redis_secret(secret_seed);
-    result(redis_uri.into_connection_info())
-        .map_err(|err| error!("Invalid Redis connection details: {:?}", err))
-        .and_then(move |redis_uri| RedisStoreBuilder::new(redis_uri, redis_secret).connect())
-        .map_err(|err| error!("Error connecting to Redis: {:?}", err))
-        .and_then(move |store| {
-            store
-                .insert_account(account)
-                .map_err(|_| error!("Unable to create account"))
-                .and_then(|account| {
-                    debug!("Created account: {}", account.id());
-                    Ok(())
+    result(
+        account
+            .check_asset_scales(asset_scale_range)
+            .map_err(|err| error!("Cannot create account: {}", err))
+            .and_then(|_| {
+                account
+                    .resolve_secrets()
+                    .map_err(|err| error!("Unable to resolve account credentials: {}", err))
+            }),
+    )
+    .join(
+        result(redis_uri.into_connection_info())
+            .map_err(|err| error!("Invalid Redis connection details: {:?}", err)),
+    )
+    .and_then(move |(account, redis_uri)| {
+        RedisStoreBuilder::new(redis_uri, redis_secret)
+            .connect()
+            .map_err(|err| error!("Error connecting to Redis: {:?}", err))
+            .and_then(move |store| {
+                store
+                    .insert_account(account)
+                    .map_err(|_| error!("Unable to create account"))
+                    .and_then(|account| {
+                        debug!("Created account: {}", account.id());
+                        Ok(())
+                    })
+            })
+    })
+}
+
+#[cfg(unix)]
+fn reload_on_sighup<S>(store: S, config_loader: ConfigLoader) -> impl Future<Item = (), Error = ()>
+where
+    S: NodeStore,
+    S::Account: IldcpAccount + AccountTrait<AccountId = u64>,
+{
+    Signal::new(SIGHUP)
+        .flatten_stream()
+        .map_err(|err| error!("Error listening for SIGHUP: {:?}", err))
+        .for_each(move |_signal| {
+            info!("Received SIGHUP, reloading config");
+            match config_loader() {
+                Ok(new_config) => Box::new(
+                    // Keep listening for SIGHUP even if this config could not be applied
+                    apply_config_changes(store.clone(), &new_config).then(|_| Ok(())),
+                )
+                    as Box<dyn Future<Item = (), Error = ()> + Send>,
+                Err(err) => {
+                    error!("Unable to reload config, keeping the current one: {}", err);
+                    Box::new(ok(()))
+                }
+            }
+        })
+}
+
+#[cfg(not(unix))]
+fn reload_on_sighup<S>(
+    _store: S,
+    _config_loader: ConfigLoader,
+) -> impl Future<Item = (), Error = ()> {
+    warn!("Reloading the config on SIGHUP is only supported on Unix");
+    ok(())
+}
+
+/// Resolves to the ILP address and id of an account that should still be tracked
+/// as created from the config, if any
+type ConfigAccountUpdate = Box<dyn Future<Item = Option<(String, u64)>, Error = ()> + Send>;
+
+/// Apply the static routes and accounts from the node's config to the store.
+/// Nothing is applied if the config is invalid.
+/// The routes and accounts that were applied last time are loaded from the store, so that the ones
+/// that are no longer configured are deleted even if the node was restarted in between.
+/// Configured accounts are created if there is no account with the same ILP address.
+/// Accounts that were not created from the config and everything else in the store,
+/// including connected peers, are left as is.
+fn apply_config_changes<S>(
+    store: S,
+    new_config: &InterledgerNode,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: NodeStore,
+    S::Account: IldcpAccount + AccountTrait<AccountId = u64>,
+{
+    let configured_routes = new_config.static_routes.clone();
+    let configured_accounts = new_config.accounts.clone();
+
+    let store_clone = store.clone();
+    result(new_config.validate())
+        .map_err(|message| error!("Not applying invalid config: {}", message))
+        .and_then(move |_| store.get_applied_config().join(store.get_all_accounts()))
+        .and_then(move |(applied, existing_accounts)| {
+            let store = store_clone;
+            let AppliedConfig {
+                static_routes: applied_routes,
+                accounts: applied_accounts,
+            } = applied;
+            let mut updates: Vec<ConfigAccountUpdate> = Vec::new();
+            for details in configured_accounts.iter() {
+                let ilp_address = details.ilp_address.to_string();
+                if let Some(account) = existing_accounts
+                    .iter()
+                    .find(|account| account.client_address() == &details.ilp_address)
+                {
+                    if applied_accounts.contains_key(&ilp_address) {
+                        updates.push(Box::new(ok(Some((ilp_address, account.id())))));
+                    }
+                    continue;
+                }
+                let store = store.clone();
+                let ilp_address_clone = ilp_address.clone();
+                updates.push(Box::new(
+                    result(details.clone().resolve_secrets().map_err(move |err| {
+                        error!(
+                            "Unable to resolve credentials for account {}: {}",
+                            ilp_address_clone, err
+                        )
+                    }))
+                    .and_then(move |details| store.insert_account(details))
+                    .then(move |result| {
+                        Ok(result.ok().map(|account| {
+                            debug!("Created account from config: {}", account.id());
+                            (ilp_address, account.id())
+                        }))
+                    }),
+                ));
+            }
+            for (ilp_address, account_id) in applied_accounts {
+                let still_configured = configured_accounts
+                    .iter()
+                    .any(|details| details.ilp_address.to_string() == ilp_address);
+                let exists = existing_accounts
+                    .iter()
+                    .any(|account| account.id() == account_id);
+                if still_configured || !exists {
+                    continue;
+                }
+                info!(
+                    "Deleting account {} ({}) because it was removed from the config",
+                    account_id, ilp_address
+                );
+                // Keep tracking the account if it could not be deleted, so that it is tried again
+                updates.push(Box::new(store.delete_account(account_id).then(
+                    move |result| match result {
+                        Ok(_) => Ok(None),
+                        Err(_) => Ok(Some((ilp_address, account_id))),
+                    },
+                )));
+            }
+            join_all(updates).map(move |accounts| {
+                let accounts = accounts.into_iter().flatten().collect();
+                (store, applied_routes, accounts)
+            })
+        })
+        .and_then(move |(store, applied_routes, accounts)| {
+            let mut updates: Vec<Box<dyn Future<Item = (), Error = ()> + Send>> = Vec::new();
+            for prefix in applied_routes.keys() {
+                if !configured_routes.contains_key(prefix) {
+                    debug!("Removing static route for prefix: {}", prefix);
+                    updates.push(store.delete_static_route(prefix.clone()));
+                }
+            }
+            for (prefix, account_id) in configured_routes.iter() {
+                if applied_routes.get(prefix) != Some(account_id) {
+                    debug!(
+                        "Setting static route for prefix: {} to account: {}",
+                        prefix, account_id
+                    );
+                    updates.push(store.set_static_route(prefix.clone(), *account_id));
+                }
+            }
+            join_all(updates).and_then(move |_| {
+                store.set_applied_config(AppliedConfig {
+                    static_routes: configured_routes,
+                    accounts,
                 })
+            })
         })
+        .map_err(|_| error!("Error applying config changes"))
 }
 
 fn generate_redis_secret(secret_seed: &[u8; 32]) -> [u8; 32] {
@@ -278,3 +690,280 @@ fn generate_redis_secret(secret_seed: &[u8; 32]) -> [u8; 32] {
     redis_secret.copy_from_slice(sig.as_ref());
     redis_secret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::err;
+    use interledger_router::RouteEntry;
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::{str::FromStr, sync::Arc};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        ilp_address: Address,
+    }
+
+    impl AccountTrait for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &Address {
+            &self.ilp_address
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestStore {
+        accounts: Arc<Mutex<Vec<TestAccount>>>,
+        static_routes: Arc<Mutex<HashMap<String, u64>>>,
+        applied_config: Arc<Mutex<AppliedConfig<u64>>>,
+    }
+
+    impl NodeStore for TestStore {
+        type Account = TestAccount;
+
+        fn insert_account(
+            &self,
+            account: AccountDetails,
+        ) -> Box<dyn Future<Item = TestAccount, Error = ()> + Send> {
+            let mut accounts = self.accounts.lock();
+            let account = TestAccount {
+                id: accounts
+                    .iter()
+                    .map(|account| account.id + 1)
+                    .max()
+                    .unwrap_or(0),
+                ilp_address: account.ilp_address,
+            };
+            accounts.push(account.clone());
+            Box::new(ok(account))
+        }
+
+        fn get_all_accounts(&self) -> Box<dyn Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+            Box::new(ok(self.accounts.lock().clone()))
+        }
+
+        fn set_rates<R>(&self, _rates: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+        where
+            R: IntoIterator<Item = (String, f64)>,
+        {
+            Box::new(err(()))
+        }
+
+        fn set_static_routes<R>(&self, _routes: R) -> Box<dyn Future<Item = (), Error = ()> + Send>
+        where
+            R: IntoIterator<Item = (String, u64)>,
+        {
+            // Reloading should only apply the individual changes
+            Box::new(err(()))
+        }
+
+        fn set_static_route(
+            &self,
+            prefix: String,
+            account_id: u64,
+        ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            self.static_routes.lock().insert(prefix, account_id);
+            Box::new(ok(()))
+        }
+
+        fn delete_static_route(
+            &self,
+            prefix: String,
+        ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            self.static_routes.lock().remove(&prefix);
+            Box::new(ok(()))
+        }
+
+        fn import_routes(
+            &self,
+            _routes: Vec<RouteEntry<u64>>,
+        ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            Box::new(err(()))
+        }
+
+        fn set_account_enabled(
+            &self,
+            _account_id: u64,
+            _enabled: bool,
+        ) -> Box<dyn Future<Item = TestAccount, Error = ()> + Send> {
+            Box::new(err(()))
+        }
+
+        fn delete_account(&self, account_id: u64) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            self.accounts
+                .lock()
+                .retain(|account| account.id != account_id);
+            Box::new(ok(()))
+        }
+
+        fn get_applied_config(
+            &self,
+        ) -> Box<dyn Future<Item = AppliedConfig<u64>, Error = ()> + Send> {
+            Box::new(ok(self.applied_config.lock().clone()))
+        }
+
+        fn set_applied_config(
+            &self,
+            config: AppliedConfig<u64>,
+        ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            *self.applied_config.lock() = config;
+            Box::new(ok(()))
+        }
+    }
+
+    fn node_config(static_routes: serde_json::Value) -> InterledgerNode {
+        serde_json::from_value(json!({
+            "ilp_address": "example.node",
+            "secret_seed": "0000000000000000000000000000000000000000000000000000000000000000",
+            "admin_auth_token": "admin",
+            "static_routes": static_routes,
+            "accounts": [{
+                "ilp_address": "example.node.peer",
+                "asset_code": "XYZ",
+                "asset_scale": 9,
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn connector_asset_requires_code_and_scale() {
+        let mut node = node_config(json!({}));
+        assert_eq!(node.connector_asset(), None);
+        node.asset_code = Some("XYZ".to_string());
+        assert_eq!(node.connector_asset(), None);
+        node.asset_scale = Some(9);
+        assert_eq!(node.connector_asset(), Some(("XYZ", 9)));
+    }
+
+    #[test]
+    fn rejects_config_accounts_with_unsupported_asset_scale() {
+        let store = TestStore::default();
+        let mut config = node_config(json!({}));
+        let mut invalid = config.accounts[0].clone();
+        invalid.ilp_address = Address::from_str("example.node.invalid").unwrap();
+        invalid.asset_scale = 30;
+        config.accounts.push(invalid);
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "Invalid account example.node.invalid: Asset scale 30 is outside the accepted range of 0 to 18"
+        );
+        assert!(apply_config_changes(store.clone(), &config).wait().is_err());
+        assert!(store.accounts.lock().is_empty());
+    }
+
+    #[test]
+    fn asset_scale_range_defaults_to_0_to_18() {
+        let mut config = node_config(json!({}));
+        assert_eq!(config.asset_scale_range(), Ok(AssetScaleRange::default()));
+        config.max_asset_scale = Some(9);
+        assert_eq!(
+            config.asset_scale_range(),
+            Ok(AssetScaleRange::new(0, 9).unwrap())
+        );
+        config.min_asset_scale = Some(10);
+        assert!(config.asset_scale_range().is_err());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn settlement_webhook_requires_url_and_secret() {
+        let mut config = node_config(json!({}));
+        assert!(config.settlement_webhook().is_none());
+        config.settlement_webhook_url = Some("http://localhost:3000/settlements".to_string());
+        assert!(config.settlement_webhook().is_none());
+        config.settlement_webhook_secret = Some("webhook secret".to_string());
+        assert!(config.settlement_webhook().is_some());
+        config.settlement_webhook_url = Some("not a url".to_string());
+        assert!(config.settlement_webhook().is_none());
+    }
+
+    #[test]
+    fn reload_adds_and_removes_routes() {
+        let store = TestStore::default();
+        let first = node_config(json!({"example.a": 0, "example.b": 0}));
+        apply_config_changes(store.clone(), &first).wait().unwrap();
+        assert_eq!(store.accounts.lock().len(), 1);
+        assert_eq!(store.static_routes.lock().len(), 2);
+
+        let second = node_config(json!({"example.b": 0, "example.c": 0}));
+        apply_config_changes(store.clone(), &second).wait().unwrap();
+        let routes = store.static_routes.lock();
+        assert!(!routes.contains_key("example.a"));
+        assert_eq!(routes.get("example.b"), Some(&0));
+        assert_eq!(routes.get("example.c"), Some(&0));
+        // The account that was already created is not added again
+        assert_eq!(store.accounts.lock().len(), 1);
+        assert_eq!(
+            store.applied_config.lock().static_routes,
+            second.static_routes
+        );
+    }
+
+    #[test]
+    fn removes_routes_applied_before_a_restart() {
+        let store = TestStore::default();
+        store
+            .applied_config
+            .lock()
+            .static_routes
+            .insert("example.a".to_string(), 0);
+        store
+            .static_routes
+            .lock()
+            .insert("example.a".to_string(), 0);
+        apply_config_changes(store.clone(), &node_config(json!({})))
+            .wait()
+            .unwrap();
+        assert!(store.static_routes.lock().is_empty());
+    }
+
+    #[test]
+    fn reload_deletes_accounts_removed_from_config() {
+        let store = TestStore::default();
+        let first = node_config(json!({}));
+        apply_config_changes(store.clone(), &first).wait().unwrap();
+        assert_eq!(store.accounts.lock().len(), 1);
+
+        let mut second = first.clone();
+        second.accounts.clear();
+        apply_config_changes(store.clone(), &second).wait().unwrap();
+        assert!(store.accounts.lock().is_empty());
+        assert!(store.applied_config.lock().accounts.is_empty());
+    }
+
+    #[test]
+    fn does_not_delete_accounts_not_created_from_config() {
+        let store = TestStore::default();
+        let config = node_config(json!({}));
+        store
+            .insert_account(config.accounts[0].clone())
+            .wait()
+            .unwrap();
+        // The account already exists, so it is not tracked as created from the config
+        apply_config_changes(store.clone(), &config).wait().unwrap();
+        assert!(store.applied_config.lock().accounts.is_empty());
+
+        let mut second = config.clone();
+        second.accounts.clear();
+        apply_config_changes(store.clone(), &second).wait().unwrap();
+        assert_eq!(store.accounts.lock().len(), 1);
+    }
+}
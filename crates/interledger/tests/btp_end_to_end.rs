@@ -12,6 +12,7 @@ use interledger::{
     node::{AccountDetails, InterledgerNode},
 };
 use interledger_packet::Address;
+use std::collections::HashMap;
 use std::str::FromStr;
 use tokio::runtime::Runtime;
 
@@ -33,6 +34,18 @@ fn btp_end_to_end() {
         http_address: ([127, 0, 0, 1], http_port).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        equal_cost_multipath: false,
+        peer_protocol_expiry: None,
+        asset_code: None,
+        asset_scale: None,
+        min_asset_scale: None,
+        max_asset_scale: None,
+        settlement_webhook_url: None,
+        settlement_webhook_secret: None,
+        learn_max_packet_amounts: false,
+        settlement_batch_window: None,
+        static_routes: HashMap::new(),
+        accounts: Vec::new(),
     };
     let run = ok(()).and_then(move |_| {
         let spawn_connector = ok(tokio::spawn(node.serve())).and_then(move |_| {
@@ -55,10 +68,17 @@ fn btp_end_to_end() {
                     routing_relation: None,
                     round_trip_time: None,
                     packets_per_minute_limit: None,
+                    max_prepare_data_len: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
                     settlement_engine_asset_scale: None,
+                    settlement_engine_incoming_asset_scale: None,
+                    settlement_engine_outgoing_asset_scale: None,
                     settlement_engine_ilp_address: None,
+                    settlement_engine_auth_token: None,
+                    settlement_engine_peer_protocol_fulfillment: None,
+                    settlement_engine_message_timeout: None,
+                    enabled: None,
                 }),
                 node.insert_account(AccountDetails {
                     ilp_address: Address::from_str("example.node.two").unwrap(),
@@ -78,10 +98,17 @@ fn btp_end_to_end() {
                     routing_relation: None,
                     round_trip_time: None,
                     packets_per_minute_limit: None,
+                    max_prepare_data_len: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
                     settlement_engine_asset_scale: None,
+                    settlement_engine_incoming_asset_scale: None,
+                    settlement_engine_outgoing_asset_scale: None,
                     settlement_engine_ilp_address: None,
+                    settlement_engine_auth_token: None,
+                    settlement_engine_peer_protocol_fulfillment: None,
+                    settlement_engine_message_timeout: None,
+                    enabled: None,
                 }),
             ])
         });
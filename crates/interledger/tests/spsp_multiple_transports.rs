@@ -0,0 +1,82 @@
+use futures::future::{loop_fn, Either, Loop};
+use futures::Future;
+use interledger::cli;
+use interledger_ildcp::IldcpResponseBuilder;
+use interledger_packet::Address;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::{net::TcpStream, runtime::Runtime, timer::Delay};
+
+fn get_open_port() -> u16 {
+    let listener = net2::TcpBuilder::new_v4().unwrap();
+    listener.reuse_address(true).unwrap();
+    let listener = listener.bind("127.0.0.1:0").unwrap();
+    listener.listen(1).unwrap().local_addr().unwrap().port()
+}
+
+/// Resolves once the server on the given port accepts connections, which the SPSP server
+/// only does after it has connected to its BTP server and received its ILDCP info
+fn listening_on(port: u16) -> impl Future<Item = (), Error = ()> {
+    let address = ([127, 0, 0, 1], port).into();
+    loop_fn(0, move |attempts| {
+        TcpStream::connect(&address).then(move |result| match result {
+            Ok(_) => Either::A(futures::future::ok(Loop::Break(()))),
+            Err(err) if attempts >= 500 => panic!("Server never started listening: {:?}", err),
+            Err(_) => Either::B(
+                Delay::new(Instant::now() + Duration::from_millis(10))
+                    .map_err(|err| panic!("{:?}", err))
+                    .map(move |_| Loop::Continue(attempts + 1)),
+            ),
+        })
+    })
+}
+
+#[test]
+fn spsp_server_accepts_btp_and_http() {
+    let _ = env_logger::try_init();
+    let moneyd_port = get_open_port();
+    let spsp_port = get_open_port();
+    let mut runtime = Runtime::new().unwrap();
+
+    let ildcp_info = IldcpResponseBuilder {
+        client_address: &Address::from_str("example.moneyd").unwrap(),
+        asset_code: "XYZ",
+        asset_scale: 9,
+    }
+    .build();
+    // The moneyd future resolves once it is listening
+    runtime
+        .block_on(cli::run_moneyd_local(
+            ([127, 0, 0, 1], moneyd_port).into(),
+            ildcp_info,
+        ))
+        .unwrap();
+    let moneyd = format!("btp+ws://:receiver@127.0.0.1:{}", moneyd_port);
+    runtime.spawn(cli::run_spsp_server_btp_and_http(
+        &moneyd,
+        ([127, 0, 0, 1], spsp_port).into(),
+        "http_token".to_string(),
+        true,
+    ));
+    runtime.block_on(listening_on(spsp_port)).unwrap();
+
+    let receiver = format!("http://127.0.0.1:{}", spsp_port);
+    let receiver_clone = receiver.clone();
+    let pay_over_btp = cli::send_spsp_payment_btp(
+        &format!("btp+ws://:sender@127.0.0.1:{}", moneyd_port),
+        &receiver,
+        1000,
+        true,
+    );
+    let pay_over_http = pay_over_btp.and_then(move |_| {
+        cli::send_spsp_payment_http(
+            &format!("http://:http_token@127.0.0.1:{}/ilp", spsp_port),
+            &receiver_clone,
+            1000,
+            true,
+        )
+    });
+    // Both payments are received by the same STREAM receiver, so they only
+    // succeed if packets from both transports reach it
+    runtime.block_on(pay_over_http).unwrap();
+}
@@ -12,6 +12,7 @@ use interledger::{
     node::{AccountDetails, InterledgerNode},
 };
 use interledger_packet::Address;
+use std::collections::HashMap;
 use std::str;
 use std::str::FromStr;
 use tokio::runtime::Builder as RuntimeBuilder;
@@ -52,6 +53,18 @@ fn three_nodes() {
         http_address: ([127, 0, 0, 1], node1_http).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        equal_cost_multipath: false,
+        peer_protocol_expiry: None,
+        asset_code: None,
+        asset_scale: None,
+        min_asset_scale: None,
+        max_asset_scale: None,
+        settlement_webhook_url: None,
+        settlement_webhook_secret: None,
+        learn_max_packet_amounts: false,
+        settlement_batch_window: None,
+        static_routes: HashMap::new(),
+        accounts: Vec::new(),
     };
     let node1_clone = node1.clone();
     runtime.spawn(
@@ -75,10 +88,17 @@ fn three_nodes() {
                 routing_relation: None,
                 round_trip_time: None,
                 packets_per_minute_limit: None,
+                max_prepare_data_len: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
                 settlement_engine_asset_scale: None,
+                settlement_engine_incoming_asset_scale: None,
+                settlement_engine_outgoing_asset_scale: None,
                 settlement_engine_ilp_address: None,
+                settlement_engine_auth_token: None,
+                settlement_engine_peer_protocol_fulfillment: None,
+                settlement_engine_message_timeout: None,
+                enabled: None,
             })
             .and_then(move |_|
         // TODO insert the accounts via HTTP request
@@ -101,10 +121,17 @@ fn three_nodes() {
                 routing_relation: Some("Peer".to_string()),
                 round_trip_time: None,
                 packets_per_minute_limit: None,
+                max_prepare_data_len: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
                 settlement_engine_asset_scale: None,
+                settlement_engine_incoming_asset_scale: None,
+                settlement_engine_outgoing_asset_scale: None,
                                 settlement_engine_ilp_address: None,
+                                settlement_engine_auth_token: None,
+                                settlement_engine_peer_protocol_fulfillment: None,
+                                settlement_engine_message_timeout: None,
+                                enabled: None,
             }))
             .and_then(move |_| node1.serve()),
     );
@@ -118,6 +145,18 @@ fn three_nodes() {
         http_address: ([127, 0, 0, 1], node2_http).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        equal_cost_multipath: false,
+        peer_protocol_expiry: None,
+        asset_code: None,
+        asset_scale: None,
+        min_asset_scale: None,
+        max_asset_scale: None,
+        settlement_webhook_url: None,
+        settlement_webhook_secret: None,
+        learn_max_packet_amounts: false,
+        settlement_batch_window: None,
+        static_routes: HashMap::new(),
+        accounts: Vec::new(),
     };
     runtime.spawn(
         join_all(vec![
@@ -139,10 +178,17 @@ fn three_nodes() {
                 routing_relation: Some("Peer".to_string()),
                 round_trip_time: None,
                 packets_per_minute_limit: None,
+                max_prepare_data_len: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
                 settlement_engine_asset_scale: None,
+                settlement_engine_incoming_asset_scale: None,
+                settlement_engine_outgoing_asset_scale: None,
                 settlement_engine_ilp_address: None,
+                settlement_engine_auth_token: None,
+                settlement_engine_peer_protocol_fulfillment: None,
+                settlement_engine_message_timeout: None,
+                enabled: None,
             }),
             node2.insert_account(AccountDetails {
                 ilp_address: Address::from_str("example.two.three").unwrap(),
@@ -162,10 +208,17 @@ fn three_nodes() {
                 routing_relation: Some("Child".to_string()),
                 round_trip_time: None,
                 packets_per_minute_limit: None,
+                max_prepare_data_len: None,
                 amount_per_minute_limit: None,
                 settlement_engine_url: None,
                 settlement_engine_asset_scale: None,
+                settlement_engine_incoming_asset_scale: None,
+                settlement_engine_outgoing_asset_scale: None,
                 settlement_engine_ilp_address: None,
+                settlement_engine_auth_token: None,
+                settlement_engine_peer_protocol_fulfillment: None,
+                settlement_engine_message_timeout: None,
+                enabled: None,
             }),
         ])
         .and_then(move |_| node2.serve())
@@ -194,6 +247,18 @@ fn three_nodes() {
         http_address: ([127, 0, 0, 1], node3_http).into(),
         secret_seed: cli::random_secret(),
         route_broadcast_interval: Some(200),
+        equal_cost_multipath: false,
+        peer_protocol_expiry: None,
+        asset_code: None,
+        asset_scale: None,
+        min_asset_scale: None,
+        max_asset_scale: None,
+        settlement_webhook_url: None,
+        settlement_webhook_secret: None,
+        learn_max_packet_amounts: false,
+        settlement_batch_window: None,
+        static_routes: HashMap::new(),
+        accounts: Vec::new(),
     };
     let node3_clone = node3.clone();
     runtime.spawn(
@@ -218,10 +283,17 @@ fn three_nodes() {
                     routing_relation: None,
                     round_trip_time: None,
                     packets_per_minute_limit: None,
+                    max_prepare_data_len: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
                     settlement_engine_asset_scale: None,
+                    settlement_engine_incoming_asset_scale: None,
+                    settlement_engine_outgoing_asset_scale: None,
                     settlement_engine_ilp_address: None,
+                    settlement_engine_auth_token: None,
+                    settlement_engine_peer_protocol_fulfillment: None,
+                    settlement_engine_message_timeout: None,
+                    enabled: None,
                 }),
                 node3_clone.insert_account(AccountDetails {
                     ilp_address: Address::from_str("example.two").unwrap(),
@@ -241,10 +313,17 @@ fn three_nodes() {
                     routing_relation: Some("Parent".to_string()),
                     round_trip_time: None,
                     packets_per_minute_limit: None,
+                    max_prepare_data_len: None,
                     amount_per_minute_limit: None,
                     settlement_engine_url: None,
                     settlement_engine_asset_scale: None,
+                    settlement_engine_incoming_asset_scale: None,
+                    settlement_engine_outgoing_asset_scale: None,
                     settlement_engine_ilp_address: None,
+                    settlement_engine_auth_token: None,
+                    settlement_engine_peer_protocol_fulfillment: None,
+                    settlement_engine_message_timeout: None,
+                    enabled: None,
                 }),
             ])
             .and_then(move |_| node3.serve())